@@ -25,10 +25,16 @@ export interface CloudErrorResponse {
 
 export interface CloudAuthResponse {
   token: string;
+  refresh_token: string;
   user: CloudUser;
   workspaces: WorkspaceSummary[];
 }
 
+export interface CloudTokenResponse {
+  token: string;
+  refresh_token: string;
+}
+
 export interface AuthSession {
   token: string;
   user: CloudUser;
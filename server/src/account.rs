@@ -0,0 +1,175 @@
+//! Personal-data export and account deletion.
+//!
+//! The export is a tar archive with one JSON file per kind of record plus
+//! the files of every workspace the user owns and of their published site.
+//! Deletion follows the ownership rules of [`db::delete_user_account`] and
+//! then wipes the data directories nobody else owns.
+
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::audit::{self, AuditEvent};
+use crate::dav;
+use crate::db;
+use crate::error::AppError;
+use crate::state::AppState;
+
+const ARCHIVE_ROOT: &str = "lumina-export";
+/// Sections that hold at most one row and are exported as an object.
+const SINGLE_ROW_SECTIONS: &[&str] = &["profile", "published_site"];
+
+/// Build the export archive for `user_id`. The returned file is already
+/// unlinked from disk, so it disappears once the download is done.
+pub async fn export_archive(state: &AppState, user_id: &str) -> Result<File, AppError> {
+    let sections = db::export_user_data(&state.pool, user_id).await?;
+    let owned_workspaces: Vec<(String, PathBuf)> = sections
+        .iter()
+        .filter(|(name, _)| *name == "workspaces")
+        .flat_map(|(_, rows)| rows.iter())
+        .filter(|row| row["owner_id"] == user_id)
+        .filter_map(|row| row["id"].as_str())
+        .map(|id| (id.to_string(), dav::workspace_root(state, id)))
+        .collect();
+    let site_dir = dav::site_root(state, user_id);
+    let exports_dir = PathBuf::from(&state.config.data_dir).join("exports");
+
+    tokio::task::spawn_blocking(move || {
+        std::fs::create_dir_all(&exports_dir)
+            .map_err(|e| AppError::Internal(format!("create exports dir: {}", e)))?;
+        let path = exports_dir.join(format!("{}.tar", Uuid::new_v4()));
+        let result = write_archive(&path, &sections, &owned_workspaces, &site_dir).and_then(|_| {
+            File::open(&path).map_err(|e| AppError::Internal(format!("open export: {}", e)))
+        });
+        let _ = std::fs::remove_file(&path);
+        result
+    })
+    .await
+    .map_err(|e| AppError::Internal(format!("export task: {}", e)))?
+}
+
+fn write_archive(
+    path: &Path,
+    sections: &[(&'static str, Vec<serde_json::Value>)],
+    workspaces: &[(String, PathBuf)],
+    site_dir: &Path,
+) -> Result<(), AppError> {
+    let io_err = |e: std::io::Error| AppError::Internal(format!("write export: {}", e));
+    let file = File::create(path).map_err(io_err)?;
+    let mut builder = tar::Builder::new(file);
+    builder.follow_symlinks(false);
+
+    let now = chrono::Utc::now().timestamp() as u64;
+    for (name, rows) in sections {
+        let value = if SINGLE_ROW_SECTIONS.contains(name) {
+            rows.first().cloned().unwrap_or(serde_json::Value::Null)
+        } else {
+            serde_json::Value::Array(rows.clone())
+        };
+        let json = serde_json::to_vec_pretty(&value)
+            .map_err(|e| AppError::Internal(format!("serialize export: {}", e)))?;
+        let mut header = tar::Header::new_gnu();
+        header.set_size(json.len() as u64);
+        header.set_mode(0o644);
+        header.set_mtime(now);
+        builder
+            .append_data(
+                &mut header,
+                format!("{}/{}.json", ARCHIVE_ROOT, name),
+                json.as_slice(),
+            )
+            .map_err(io_err)?;
+    }
+
+    for (workspace_id, root) in workspaces {
+        if root.is_dir() {
+            builder
+                .append_dir_all(
+                    format!("{}/workspaces/{}", ARCHIVE_ROOT, workspace_id),
+                    root,
+                )
+                .map_err(io_err)?;
+        }
+    }
+    if site_dir.is_dir() {
+        builder
+            .append_dir_all(format!("{}/site", ARCHIVE_ROOT), site_dir)
+            .map_err(io_err)?;
+    }
+
+    builder.into_inner().map_err(io_err)?;
+    Ok(())
+}
+
+/// Bytes on disk in the workspaces `user_id` owns and their published site.
+pub async fn storage_usage(state: &AppState, user_id: &str) -> Result<u64, AppError> {
+    let mut dirs: Vec<PathBuf> = db::owned_workspace_ids(&state.pool, user_id)
+        .await?
+        .iter()
+        .map(|id| dav::workspace_root(state, id))
+        .collect();
+    dirs.push(dav::site_root(state, user_id));
+    tokio::task::spawn_blocking(move || dirs.iter().map(|dir| dir_size(dir)).sum())
+        .await
+        .map_err(|e| AppError::Internal(format!("storage usage task: {}", e)))
+}
+
+fn dir_size(dir: &Path) -> u64 {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return 0;
+    };
+    entries
+        .flatten()
+        .map(|entry| match entry.file_type() {
+            Ok(kind) if kind.is_dir() => dir_size(&entry.path()),
+            Ok(kind) if kind.is_file() => entry.metadata().map(|m| m.len()).unwrap_or(0),
+            _ => 0,
+        })
+        .sum()
+}
+
+/// Delete the account and every file that was only theirs.
+pub async fn delete_account(
+    state: &AppState,
+    user_id: &str,
+    ip: Option<&str>,
+) -> Result<(), AppError> {
+    let deleted = db::delete_user_account(&state.pool, user_id).await?;
+    state.collab.discard_docs(&deleted.collab_docs).await;
+    for workspace_id in &deleted.workspaces {
+        audit::record(
+            state,
+            AuditEvent::new(audit::WORKSPACE_DELETED, "workspace", workspace_id)
+                .actor(user_id)
+                .ip(ip)
+                .details(json!({ "reason": "account_deleted" })),
+        )
+        .await;
+    }
+    audit::record(
+        state,
+        AuditEvent::new(audit::ACCOUNT_DELETED, "user", user_id)
+            .actor(user_id)
+            .ip(ip),
+    )
+    .await;
+
+    let mut dirs: Vec<PathBuf> = deleted
+        .workspaces
+        .iter()
+        .map(|id| dav::workspace_root(state, id))
+        .collect();
+    dirs.push(dav::site_root(state, user_id));
+    for dir in dirs {
+        if dir.exists() {
+            // The account is already gone; a leftover directory is logged
+            // rather than reported as a failed deletion.
+            if let Err(err) = tokio::fs::remove_dir_all(&dir).await {
+                tracing::warn!(path = %dir.display(), error = %err, "failed to remove user data");
+            }
+        }
+    }
+    Ok(())
+}
@@ -1,17 +1,33 @@
+use crate::audit::{self, AuditEvent};
 use crate::config::Config;
+use crate::db;
 use crate::error::AppError;
+use crate::keys::TokenKind;
+use crate::state::AppState;
 use argon2::{Argon2, PasswordHash, PasswordHasher, PasswordVerifier};
-use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
+use axum::http::HeaderMap;
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+use base64::Engine as _;
 use password_hash::SaltString;
 use rand::rngs::OsRng;
+use rand::RngCore;
 use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::{Digest, Sha256};
 
-const TOKEN_TTL_SECS: i64 = 60 * 60 * 24 * 7;
+/// Access tokens, the only kind accepted as proof of identity.
+pub const ACCESS_TOKEN: TokenKind = TokenKind {
+    typ: "at+jwt",
+    aud: "lumina-api",
+};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Claims {
     pub sub: String,
     pub exp: usize,
+    /// Session the access token was issued for; revoking the session
+    /// invalidates the token before it expires.
+    pub sid: String,
 }
 
 pub fn hash_password(password: &str) -> Result<String, AppError> {
@@ -31,29 +47,571 @@ pub fn verify_password(password: &str, hash: &str) -> Result<bool, AppError> {
     Ok(argon2.verify_password(password.as_bytes(), &parsed).is_ok())
 }
 
-pub fn create_token(user_id: &str, config: &Config) -> Result<String, AppError> {
+/// Create a short-lived access token bound to `session_id`.
+pub fn create_token(state: &AppState, user_id: &str, session_id: &str) -> Result<String, AppError> {
     let exp = chrono::Utc::now()
-        .checked_add_signed(chrono::Duration::seconds(TOKEN_TTL_SECS))
+        .checked_add_signed(chrono::Duration::seconds(
+            state.config.access_token_ttl_secs,
+        ))
         .ok_or_else(|| AppError::Internal("token expiry overflow".to_string()))?
         .timestamp();
     let claims = Claims {
         sub: user_id.to_string(),
         exp: exp as usize,
+        sid: session_id.to_string(),
     };
-    encode(
-        &Header::default(),
-        &claims,
-        &EncodingKey::from_secret(config.jwt_secret.as_bytes()),
+    state.keys.sign(ACCESS_TOKEN, &claims)
+}
+
+pub async fn decode_token(state: &AppState, token: &str) -> Result<Claims, AppError> {
+    state.keys.verify(ACCESS_TOKEN, token).await
+}
+
+/// How long a retired signing key must keep verifying: the lifetime of the
+/// longest-lived token signed with it.
+pub fn signing_key_grace_secs(config: &Config) -> i64 {
+    config.access_token_ttl_secs.max(MFA_CHALLENGE_TTL_SECS)
+}
+
+// ── Two-factor login challenge ──────────────────────────────────────
+
+/// The short-lived token handed out after the password step of a two-factor
+/// login. It is signed with the same keys as access tokens, so its own `typ`
+/// and `aud` keep it from ever being accepted as one.
+const MFA_CHALLENGE: TokenKind = TokenKind {
+    typ: "lumina-mfa+jwt",
+    aud: "lumina-mfa-challenge",
+};
+const MFA_CHALLENGE_TTL_SECS: i64 = 300;
+
+/// How stale a session's `last_used_at` may get before a request bumps it.
+const SESSION_TOUCH_INTERVAL_SECS: i64 = 60;
+const SESSION_CLEANUP_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60 * 60);
+/// Revoked and expired sessions are kept this long before being deleted.
+const ENDED_SESSION_RETENTION_SECS: i64 = 30 * 24 * 60 * 60;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct MfaChallengeClaims {
+    sub: String,
+    exp: usize,
+}
+
+pub fn create_mfa_challenge(state: &AppState, user_id: &str) -> Result<String, AppError> {
+    let exp = chrono::Utc::now().timestamp() + MFA_CHALLENGE_TTL_SECS;
+    let claims = MfaChallengeClaims {
+        sub: user_id.to_string(),
+        exp: exp as usize,
+    };
+    state.keys.sign(MFA_CHALLENGE, &claims)
+}
+
+/// Validate a challenge token and return the user id it was issued for.
+pub async fn decode_mfa_challenge(state: &AppState, token: &str) -> Result<String, AppError> {
+    let claims: MfaChallengeClaims = state.keys.verify(MFA_CHALLENGE, token).await?;
+    Ok(claims.sub)
+}
+
+/// Check a TOTP code for `user_id`, rejecting codes whose time step was
+/// already consumed.
+pub async fn verify_totp_code(state: &AppState, user_id: &str, code: &str) -> Result<(), AppError> {
+    let totp = db::get_totp_state(&state.pool, user_id).await?;
+    let secret = totp.secret.ok_or(AppError::Unauthorized)?;
+    let step = crate::totp::verify(&secret, code, chrono::Utc::now().timestamp())
+        .ok_or(AppError::Unauthorized)?;
+    if !db::consume_totp_step(&state.pool, user_id, step).await? {
+        return Err(AppError::Unauthorized);
+    }
+    Ok(())
+}
+
+const RECOVERY_CODE_COUNT: usize = 10;
+
+/// Generate a fresh set of recovery codes, store their hashes and return the
+/// plaintext codes for one-time display.
+pub async fn issue_recovery_codes(
+    state: &AppState,
+    user_id: &str,
+) -> Result<Vec<String>, AppError> {
+    let codes: Vec<String> = (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let mut bytes = [0u8; 5];
+            OsRng.fill_bytes(&mut bytes);
+            let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+            format!("{}-{}", &hex[..5], &hex[5..])
+        })
+        .collect();
+    let hashes = codes
+        .iter()
+        .map(|code| hash_password(code))
+        .collect::<Result<Vec<_>, _>>()?;
+    db::replace_recovery_codes(&state.pool, user_id, &hashes).await?;
+    Ok(codes)
+}
+
+/// Consume a recovery code for `user_id`. Each code works once.
+pub async fn redeem_recovery_code(
+    state: &AppState,
+    user_id: &str,
+    code: &str,
+) -> Result<(), AppError> {
+    let code = code.trim().to_lowercase();
+    for (id, hash) in db::list_unused_recovery_codes(&state.pool, user_id).await? {
+        if verify_password(&code, &hash)? && db::use_recovery_code(&state.pool, &id).await? {
+            return Ok(());
+        }
+    }
+    Err(AppError::Unauthorized)
+}
+
+// ── Sessions & refresh tokens ───────────────────────────────────────
+
+/// Access and refresh token pair handed out at login and on refresh.
+pub struct SessionTokens {
+    pub access_token: String,
+    pub refresh_token: String,
+}
+
+/// Refresh and emailed tokens are opaque random strings. They carry enough
+/// entropy that a fast digest is sufficient for storage; argon2 would only
+/// slow refreshes.
+pub fn hash_opaque_token(token: &str) -> String {
+    let digest = Sha256::digest(token.as_bytes());
+    digest.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+pub fn generate_opaque_token() -> String {
+    let mut secret = [0u8; 32];
+    OsRng.fill_bytes(&mut secret);
+    URL_SAFE_NO_PAD.encode(secret)
+}
+
+/// Start a new session for `user_id` and issue its first token pair.
+pub async fn start_session(
+    state: &AppState,
+    user_id: &str,
+    device_name: &str,
+    ip: Option<&str>,
+) -> Result<SessionTokens, AppError> {
+    ensure_user_enabled(state, user_id).await?;
+    let expires_at = chrono::Utc::now().timestamp() + state.config.refresh_token_ttl_secs;
+    let session_id = db::create_session(&state.pool, user_id, device_name, ip, expires_at).await?;
+    let refresh_token = generate_opaque_token();
+    db::insert_refresh_token(&state.pool, &hash_opaque_token(&refresh_token), &session_id).await?;
+    let access_token = create_token(state, user_id, &session_id)?;
+    Ok(SessionTokens {
+        access_token,
+        refresh_token,
+    })
+}
+
+/// Exchange a refresh token for a new pair. Each refresh token is single-use:
+/// presenting one that was already rotated means it leaked, so the whole
+/// session is revoked.
+pub async fn rotate_refresh_token(
+    state: &AppState,
+    refresh_token: &str,
+    ip: Option<&str>,
+) -> Result<SessionTokens, AppError> {
+    let token_hash = hash_opaque_token(refresh_token);
+    let (session_id, used_at) = db::get_refresh_token(&state.pool, &token_hash)
+        .await?
+        .ok_or(AppError::Unauthorized)?;
+    let session = db::get_session(&state.pool, &session_id)
+        .await?
+        .ok_or(AppError::Unauthorized)?;
+    let now = chrono::Utc::now().timestamp();
+    if session.revoked_at.is_some() || session.expires_at <= now {
+        return Err(AppError::Unauthorized);
+    }
+    ensure_user_enabled(state, &session.user_id).await?;
+
+    let first_use =
+        used_at.is_none() && db::mark_refresh_token_used(&state.pool, &token_hash).await?;
+    if !first_use {
+        tracing::warn!(
+            session_id = %session_id,
+            user_id = %session.user_id,
+            "refresh token reuse detected; revoking session"
+        );
+        db::revoke_session(&state.pool, &session_id).await?;
+        audit::record(
+            state,
+            AuditEvent::new(audit::TOKEN_REUSED, "session", &session_id)
+                .actor(&session.user_id)
+                .ip(ip),
+        )
+        .await;
+        return Err(AppError::Unauthorized);
+    }
+
+    let new_refresh = generate_opaque_token();
+    db::insert_refresh_token(&state.pool, &hash_opaque_token(&new_refresh), &session_id).await?;
+    db::touch_session(
+        &state.pool,
+        &session_id,
+        ip,
+        now + state.config.refresh_token_ttl_secs,
+    )
+    .await?;
+    let access_token = create_token(state, &session.user_id, &session_id)?;
+    audit::record(
+        state,
+        AuditEvent::new(audit::TOKEN_REFRESHED, "session", &session_id)
+            .actor(&session.user_id)
+            .ip(ip),
+    )
+    .await;
+    Ok(SessionTokens {
+        access_token,
+        refresh_token: new_refresh,
+    })
+}
+
+/// Decode an access token and check that its session is still active.
+pub async fn authenticate_access_token(state: &AppState, token: &str) -> Result<Claims, AppError> {
+    let claims = decode_token(state, token).await?;
+    let session = db::get_session(&state.pool, &claims.sid)
+        .await?
+        .ok_or(AppError::Unauthorized)?;
+    let now = chrono::Utc::now().timestamp();
+    if session.revoked_at.is_some() || session.user_id != claims.sub || session.expires_at <= now {
+        return Err(AppError::Unauthorized);
+    }
+    ensure_user_enabled(state, &claims.sub).await?;
+    // Keep "last active" in the sessions list current without a write on
+    // every request.
+    if now - session.last_used_at >= SESSION_TOUCH_INTERVAL_SECS {
+        if let Err(err) = db::mark_session_used(&state.pool, &session.id, now).await {
+            tracing::warn!(session_id = %session.id, error = %err, "failed to record session use");
+        }
+    }
+    Ok(claims)
+}
+
+/// Delete sessions that ended more than `ENDED_SESSION_RETENTION_SECS` ago
+/// and the refresh tokens of every ended session, once an hour.
+pub fn spawn_session_cleanup_task(state: AppState) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(SESSION_CLEANUP_INTERVAL);
+        loop {
+            interval.tick().await;
+            let now = chrono::Utc::now().timestamp();
+            match db::purge_ended_sessions(&state.pool, now, now - ENDED_SESSION_RETENTION_SECS)
+                .await
+            {
+                Ok(0) => {}
+                Ok(count) => tracing::info!(count, "purged ended sessions"),
+                Err(err) => tracing::warn!(error = %err, "failed to purge ended sessions"),
+            }
+        }
+    });
+}
+
+/// Refuse accounts an instance admin has disabled. Checked on every way
+/// in: new sessions, refreshes, access tokens, app tokens and Basic auth.
+pub async fn ensure_user_enabled(state: &AppState, user_id: &str) -> Result<(), AppError> {
+    if db::is_user_disabled(&state.pool, user_id).await? {
+        return Err(AppError::AccountDisabled);
+    }
+    Ok(())
+}
+
+/// Human-readable device label for the sessions list: an explicit
+/// `X-Lumina-Device` header, else the user agent.
+pub fn device_name_from_headers(headers: &HeaderMap) -> String {
+    headers
+        .get("x-lumina-device")
+        .or_else(|| headers.get(axum::http::header::USER_AGENT))
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.trim().chars().take(120).collect::<String>())
+        .filter(|v| !v.is_empty())
+        .unwrap_or_else(|| "Unknown device".to_string())
+}
+
+// ── Emailed tokens ──────────────────────────────────────────────────
+
+/// Create a single-use token for `purpose` (see `db::EMAIL_TOKEN_*`) and
+/// return the plaintext to embed in the email.
+pub async fn issue_email_token(
+    state: &AppState,
+    user_id: &str,
+    purpose: &str,
+    ttl_secs: i64,
+) -> Result<String, AppError> {
+    let token = generate_opaque_token();
+    let expires_at = chrono::Utc::now().timestamp() + ttl_secs;
+    db::create_email_token(
+        &state.pool,
+        &hash_opaque_token(&token),
+        user_id,
+        purpose,
+        expires_at,
     )
-    .map_err(|e| AppError::Internal(format!("encode token: {}", e)))
+    .await?;
+    Ok(token)
+}
+
+/// Redeem an emailed token, returning the user it was issued to.
+pub async fn redeem_email_token(
+    state: &AppState,
+    token: &str,
+    purpose: &str,
+) -> Result<String, AppError> {
+    db::consume_email_token(&state.pool, &hash_opaque_token(token.trim()), purpose)
+        .await?
+        .ok_or_else(|| AppError::BadRequest("invalid or expired token".to_string()))
 }
 
-pub fn decode_token(token: &str, config: &Config) -> Result<Claims, AppError> {
-    let data = decode::<Claims>(
-        token,
-        &DecodingKey::from_secret(config.jwt_secret.as_bytes()),
-        &Validation::default(),
+// ── App passwords & personal access tokens ──────────────────────────
+
+pub const SCOPE_DAV_READ: &str = "dav:read";
+pub const SCOPE_DAV_WRITE: &str = "dav:write";
+pub const SCOPE_API: &str = "api";
+pub const SCOPE_RELAY: &str = "relay";
+pub const TOKEN_SCOPES: &[&str] = &[SCOPE_DAV_READ, SCOPE_DAV_WRITE, SCOPE_API, SCOPE_RELAY];
+
+pub const KIND_APP_PASSWORD: &str = "app_password";
+pub const KIND_PERSONAL_ACCESS_TOKEN: &str = "personal_access_token";
+
+const APP_TOKEN_PREFIX: &str = "lum_";
+
+/// Generate a new app token for `token_id`. The id is embedded so the stored
+/// hash can be looked up directly instead of verifying against every token.
+pub fn generate_app_token(token_id: &str) -> String {
+    let mut secret = [0u8; 32];
+    OsRng.fill_bytes(&mut secret);
+    format!(
+        "{}{}_{}",
+        APP_TOKEN_PREFIX,
+        token_id,
+        URL_SAFE_NO_PAD.encode(secret)
     )
-    .map_err(|_| AppError::Unauthorized)?;
-    Ok(data.claims)
+}
+
+/// Split a presented app token into its id. Returns `None` for anything that
+/// is not shaped like an app token (e.g. an account password or JWT).
+pub fn app_token_id(raw: &str) -> Option<&str> {
+    let rest = raw.strip_prefix(APP_TOKEN_PREFIX)?;
+    let (id, secret) = rest.split_once('_')?;
+    if id.is_empty() || secret.is_empty() {
+        return None;
+    }
+    Some(id)
+}
+
+/// Look up a presented app token and check its secret. Returns `None` when
+/// the id is unknown or the secret does not match.
+async fn find_app_token(state: &AppState, raw: &str) -> Result<Option<db::AppTokenRow>, AppError> {
+    let Some(token_id) = app_token_id(raw) else {
+        return Ok(None);
+    };
+    let Some(token) = db::get_app_token(&state.pool, token_id).await? else {
+        return Ok(None);
+    };
+    if !verify_password(raw, &token.token_hash)? {
+        return Ok(None);
+    }
+    Ok(Some(token))
+}
+
+/// Check that an app token whose secret matched is of the given `kind`,
+/// unexpired, unrevoked and carries `scope`, then record the last-used time
+/// and IP. Returns its owner's user id.
+async fn authorize_app_token(
+    state: &AppState,
+    token: db::AppTokenRow,
+    kind: &str,
+    scope: &str,
+    ip: Option<&str>,
+) -> Result<String, AppError> {
+    let now = chrono::Utc::now().timestamp();
+    if token.kind != kind
+        || token.revoked_at.is_some()
+        || token.expires_at.is_some_and(|exp| exp <= now)
+    {
+        return Err(AppError::Unauthorized);
+    }
+    ensure_user_enabled(state, &token.user_id).await?;
+    if !token.scopes.iter().any(|s| s == scope) {
+        return Err(AppError::Forbidden);
+    }
+    db::touch_app_token(&state.pool, &token.id, ip).await?;
+    Ok(token.user_id)
+}
+
+/// Verify an app token of the given `kind` and return its owner's user id.
+async fn verify_app_token(
+    state: &AppState,
+    raw: &str,
+    kind: &str,
+    scope: &str,
+    ip: Option<&str>,
+) -> Result<String, AppError> {
+    let token = find_app_token(state, raw)
+        .await?
+        .ok_or(AppError::Unauthorized)?;
+    authorize_app_token(state, token, kind, scope, ip).await
+}
+
+/// Authenticate a bearer credential: a session JWT, or — when `scope` is
+/// given — a personal access token carrying that scope.
+pub async fn authenticate_bearer(
+    state: &AppState,
+    token: &str,
+    scope: Option<&str>,
+    ip: Option<&str>,
+) -> Result<String, AppError> {
+    if app_token_id(token).is_some() {
+        let scope = scope.ok_or(AppError::Unauthorized)?;
+        return verify_app_token(state, token, KIND_PERSONAL_ACCESS_TOKEN, scope, ip).await;
+    }
+    let claims = authenticate_access_token(state, token).await?;
+    Ok(claims.sub)
+}
+
+/// Authenticate HTTP Basic credentials (`email:password`). The password may
+/// be an app password carrying `scope`, or the account password unless the
+/// instance requires app passwords for WebDAV, password login is disabled, or
+/// the account has two-factor authentication enabled (Basic auth has no way
+/// to prompt for a code). Wrong credentials count towards the account's
+/// lockout.
+pub async fn authenticate_basic(
+    state: &AppState,
+    email: &str,
+    password: &str,
+    scope: &str,
+    ip: Option<&str>,
+) -> Result<String, AppError> {
+    check_login_guard(state, email)?;
+    let user = db::find_user_by_email(&state.pool, email).await?;
+    let Some((user_id, password_hash)) = user else {
+        return Err(login_failed(state, email, ip, "basic").await);
+    };
+
+    if app_token_id(password).is_some() {
+        // Only a wrong secret counts as a guess: a device still retrying a
+        // revoked or expired app password must not lock the account.
+        let token = match find_app_token(state, password).await? {
+            Some(token) if token.user_id == user_id => token,
+            _ => return Err(login_failed(state, email, ip, "app_password").await),
+        };
+        let owner = authorize_app_token(state, token, KIND_APP_PASSWORD, scope, ip).await?;
+        login_succeeded(state, email);
+        return Ok(owner);
+    }
+
+    let is_dav = scope == SCOPE_DAV_READ || scope == SCOPE_DAV_WRITE;
+    if is_dav && state.config.dav_require_app_password {
+        return Err(AppError::Unauthorized);
+    }
+    if !state.config.password_login_enabled
+        || db::get_totp_state(&state.pool, &user_id).await?.enabled
+    {
+        return Err(AppError::Unauthorized);
+    }
+    if state.config.require_email_verification
+        && !db::is_email_verified(&state.pool, &user_id).await?
+    {
+        return Err(AppError::Unauthorized);
+    }
+    if !verify_password(password, &password_hash)? {
+        return Err(login_failed(state, email, ip, "basic").await);
+    }
+    ensure_user_enabled(state, &user_id).await?;
+    login_succeeded(state, email);
+    Ok(user_id)
+}
+
+// ── Per-account lockout ─────────────────────────────────────────────
+
+/// Reject attempts on an account that is locked after repeated failures.
+pub fn check_login_guard(state: &AppState, email: &str) -> Result<(), AppError> {
+    state
+        .login_guard
+        .check(email)
+        .map_err(AppError::RateLimited)
+}
+
+/// Record a failed credential check for `email` and return the error to
+/// hand back to the client.
+pub async fn login_failed(
+    state: &AppState,
+    email: &str,
+    ip: Option<&str>,
+    method: &str,
+) -> AppError {
+    let locked = state.login_guard.record_failure(email);
+    if locked {
+        state
+            .metrics
+            .auth_lockouts
+            .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        tracing::warn!(email = %email, "account temporarily locked after failed logins");
+    }
+    audit::record(
+        state,
+        AuditEvent::new(audit::LOGIN_FAILED, "account", email)
+            .ip(ip)
+            .details(json!({ "method": method, "locked": locked })),
+    )
+    .await;
+    AppError::Unauthorized
+}
+
+pub fn login_succeeded(state: &AppState, email: &str) {
+    state.login_guard.record_success(email);
+}
+
+/// Authenticate an `Authorization` header carrying either Bearer or Basic
+/// credentials for `scope`, as used by the WebDAV and relay endpoints.
+pub async fn authorize_header(
+    state: &AppState,
+    headers: &HeaderMap,
+    scope: &str,
+) -> Result<String, AppError> {
+    let header = headers
+        .get(axum::http::header::AUTHORIZATION)
+        .ok_or(AppError::Unauthorized)?;
+    let value = header.to_str().map_err(|_| AppError::Unauthorized)?;
+    let ip = crate::rate_limit::client_ip(headers);
+
+    if let Some(token) = value.strip_prefix("Bearer ") {
+        return authenticate_bearer(state, token.trim(), Some(scope), ip.as_deref()).await;
+    }
+
+    if let Some(encoded) = value.strip_prefix("Basic ") {
+        let decoded = STANDARD
+            .decode(encoded.trim().as_bytes())
+            .map_err(|_| AppError::Unauthorized)?;
+        let decoded = String::from_utf8(decoded).map_err(|_| AppError::Unauthorized)?;
+        let mut parts = decoded.splitn(2, ':');
+        let email = parts.next().unwrap_or("").trim().to_lowercase();
+        let password = parts.next().unwrap_or("").to_string();
+        if email.is_empty() || password.is_empty() {
+            return Err(AppError::Unauthorized);
+        }
+        return authenticate_basic(state, &email, &password, scope, ip.as_deref()).await;
+    }
+
+    Err(AppError::Unauthorized)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn app_token_id_round_trips() {
+        let token = generate_app_token("abc123");
+        assert!(token.starts_with("lum_abc123_"));
+        assert_eq!(app_token_id(&token), Some("abc123"));
+    }
+
+    #[test]
+    fn app_token_id_rejects_other_credentials() {
+        assert_eq!(app_token_id("correct horse battery staple"), None);
+        assert_eq!(app_token_id("eyJhbGciOiJIUzI1NiJ9.e30.sig"), None);
+        assert_eq!(app_token_id("lum_"), None);
+        assert_eq!(app_token_id("lum_abc_"), None);
+    }
 }
@@ -34,6 +34,8 @@ pub struct CollabRoom {
     awareness: RwLock<HashMap<String, Vec<u8>>>,
     /// Set to true when the doc has been modified since last save.
     dirty: AtomicBool,
+    /// Set when the document was deleted; its state must not be saved again.
+    discarded: AtomicBool,
 }
 
 impl CollabRoom {
@@ -81,6 +83,9 @@ fn collab_path(data_dir: &str, doc_id: &str) -> PathBuf {
 
 /// Save room state to disk using atomic write (tmp + rename).
 async fn save_room_state(room: &CollabRoom, data_dir: &str, doc_id: &str) {
+    if room.discarded.load(Ordering::Relaxed) {
+        return;
+    }
     let state = room.encode_state();
     let path = collab_path(data_dir, doc_id);
     let tmp_path = path.with_extension("bin.tmp");
@@ -167,6 +172,7 @@ impl CollabHub {
             peers: Arc::new(RwLock::new(HashMap::new())),
             awareness: RwLock::new(HashMap::new()),
             dirty: AtomicBool::new(false),
+            discarded: AtomicBool::new(false),
         });
         rooms.insert(doc_id.to_string(), room.clone());
         room
@@ -177,6 +183,25 @@ impl CollabHub {
         self.rooms.write().await.remove(doc_id);
     }
 
+    /// Forget deleted documents: disconnect their peers and remove their
+    /// persisted state.
+    pub async fn discard_docs(&self, doc_ids: &[String]) {
+        for doc_id in doc_ids {
+            if let Some(room) = self.rooms.write().await.remove(doc_id) {
+                room.discarded.store(true, Ordering::Relaxed);
+                for sender in room.peers.read().await.values() {
+                    let _ = sender.send(Message::Close(None));
+                }
+            }
+            let path = collab_path(&self.data_dir, doc_id);
+            if let Err(e) = tokio::fs::remove_file(&path).await {
+                if e.kind() != std::io::ErrorKind::NotFound {
+                    tracing::warn!(doc_id = %doc_id, error = %e, "failed to remove collab state file");
+                }
+            }
+        }
+    }
+
     /// Spawn a background task that periodically flushes dirty rooms to disk.
     pub fn spawn_flush_task(&self) {
         let hub = self.clone();
@@ -208,15 +233,30 @@ pub async fn collab_handler(
     AxumPath(doc_id): AxumPath<String>,
     Query(query): Query<CollabQuery>,
 ) -> Result<Response, AppError> {
-    // Validate JWT token
-    let _claims = crate::auth::decode_token(&query.token, &state.config)?;
+    // Validate the access token and its session
+    let claims = crate::auth::authenticate_access_token(&state, &query.token).await?;
+
+    // Registered documents follow their project's roles; viewers join
+    // read-only.
+    let read_only = match crate::db::get_doc_project(&state.pool, &doc_id).await? {
+        Some(project_id) => {
+            let project = crate::db::get_project(&state.pool, &project_id)
+                .await?
+                .ok_or(AppError::NotFound)?;
+            let role = crate::projects::role(&state, &project, &claims.sub)
+                .await?
+                .ok_or(AppError::Forbidden)?;
+            !crate::projects::allows(&role, crate::projects::CONTRIBUTOR)
+        }
+        None => false,
+    };
 
     let hub = state.collab.clone();
     let room = hub.get_or_create_room(&doc_id).await;
     let data_dir = state.config.data_dir.clone();
 
     Ok(ws.on_upgrade(move |socket| async move {
-        handle_collab_socket(socket, room, hub, doc_id, data_dir).await;
+        handle_collab_socket(socket, room, hub, doc_id, data_dir, read_only).await;
     }))
 }
 
@@ -227,6 +267,7 @@ async fn handle_collab_socket(
     hub: CollabHub,
     doc_id: String,
     data_dir: String,
+    read_only: bool,
 ) {
     let (mut ws_tx, mut ws_rx) = socket.split();
     let (tx, mut rx) = mpsc::unbounded_channel::<Message>();
@@ -275,6 +316,9 @@ async fn handle_collab_socket(
                 }
 
                 match data[0] {
+                    MSG_SYNC if read_only => {
+                        tracing::debug!(doc_id = %doc_id, "dropped update from read-only peer");
+                    }
                     MSG_SYNC => {
                         let payload = &data[1..];
                         let applied = room.apply_update_v1(payload);
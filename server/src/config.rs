@@ -5,10 +5,58 @@ pub struct Config {
     pub bind: String,
     pub db_url: String,
     pub data_dir: String,
-    pub jwt_secret: String,
     pub auth_rate_limit_burst: u32,
     pub auth_rate_limit_window_secs: u64,
+    /// Failed logins for one account before it is temporarily locked.
+    pub login_lockout_threshold: u32,
+    /// First lockout duration; doubles with every further failure.
+    pub login_lockout_base_secs: u64,
+    pub login_lockout_max_secs: u64,
     pub trusted_proxy_hops: u32,
+    /// Reject the account password on WebDAV Basic auth; clients must use an
+    /// app password instead.
+    pub dav_require_app_password: bool,
+    pub access_token_ttl_secs: i64,
+    pub refresh_token_ttl_secs: i64,
+    /// Externally reachable base URL, used for links in emails.
+    pub public_url: String,
+    /// SMTP relay URL; when unset, mail is written to `<data_dir>/outbox`.
+    pub smtp_url: Option<String>,
+    pub mail_from: String,
+    /// Refuse to sign in accounts that have not confirmed their email.
+    pub require_email_verification: bool,
+    /// Single sign-on provider; enabled when `LUMINA_OIDC_ISSUER` is set.
+    pub oidc: Option<OidcConfig>,
+    /// Allow signing in and registering with an email and password. Turn off
+    /// to make single sign-on the only way in.
+    pub password_login_enabled: bool,
+    /// Instance admins, by email. The address must be verified before it
+    /// grants admin access.
+    pub admin_emails: Vec<String>,
+    /// Default registration mode (`open`, `closed`, `domains` or `invite`)
+    /// until an instance admin sets one.
+    pub registration_mode: String,
+    /// Email domains allowed to register in `domains` mode.
+    pub registration_domains: Vec<String>,
+    /// How long a deleted organization can be restored before it is purged.
+    pub org_deletion_grace_secs: i64,
+    /// How long before a task's due date its assignee is reminded, largest
+    /// first. Each window sends one reminder; overdue tasks get one more.
+    pub due_reminder_windows_secs: Vec<i64>,
+}
+
+#[derive(Clone, Debug)]
+pub struct OidcConfig {
+    pub issuer: String,
+    pub client_id: String,
+    pub client_secret: Option<String>,
+    pub redirect_url: String,
+    pub scopes: String,
+    /// Label for the login button.
+    pub provider_name: String,
+    /// Create an account on first sign-in instead of requiring an existing
+    /// user with the same (verified) email.
+    pub auto_provision: bool,
 }
 
 impl Config {
@@ -17,8 +65,6 @@ impl Config {
         let db_url =
             env::var("LUMINA_DB_URL").unwrap_or_else(|_| "sqlite://data/lumina.db".to_string());
         let data_dir = env::var("LUMINA_DATA_DIR").unwrap_or_else(|_| "data".to_string());
-        let jwt_secret =
-            env::var("LUMINA_JWT_SECRET").unwrap_or_else(|_| "dev-secret-change-me".to_string());
         let auth_rate_limit_burst = env::var("LUMINA_AUTH_RATE_BURST")
             .ok()
             .and_then(|v| v.parse().ok())
@@ -29,24 +75,141 @@ impl Config {
             .and_then(|v| v.parse().ok())
             .unwrap_or(60)
             .max(1);
+        let login_lockout_threshold = env::var("LUMINA_LOGIN_LOCKOUT_THRESHOLD")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(5)
+            .max(1);
+        let login_lockout_base_secs = env::var("LUMINA_LOGIN_LOCKOUT_BASE_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(30)
+            .max(1);
+        let login_lockout_max_secs = env::var("LUMINA_LOGIN_LOCKOUT_MAX_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(15 * 60)
+            .max(login_lockout_base_secs);
 
         let trusted_proxy_hops = env::var("LUMINA_TRUSTED_PROXY_HOPS")
             .ok()
             .and_then(|v| v.parse().ok())
             .unwrap_or(0);
 
+        let dav_require_app_password = env_flag("LUMINA_DAV_REQUIRE_APP_PASSWORD");
+        let access_token_ttl_secs = env::var("LUMINA_ACCESS_TOKEN_TTL_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(15 * 60)
+            .max(60);
+        let refresh_token_ttl_secs = env::var("LUMINA_REFRESH_TOKEN_TTL_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(60 * 60 * 24 * 30)
+            .max(access_token_ttl_secs);
+
+        let public_url = env::var("LUMINA_PUBLIC_URL")
+            .unwrap_or_else(|_| format!("http://{}", bind))
+            .trim_end_matches('/')
+            .to_string();
+        let smtp_url = env::var("LUMINA_SMTP_URL")
+            .ok()
+            .filter(|v| !v.trim().is_empty());
+        let mail_from = env::var("LUMINA_MAIL_FROM")
+            .unwrap_or_else(|_| "Lumina <no-reply@localhost>".to_string());
+        let require_email_verification = env_flag("LUMINA_REQUIRE_EMAIL_VERIFICATION");
+
+        let oidc = env::var("LUMINA_OIDC_ISSUER")
+            .ok()
+            .filter(|v| !v.trim().is_empty())
+            .map(|issuer| OidcConfig {
+                issuer: issuer.trim().trim_end_matches('/').to_string(),
+                client_id: env::var("LUMINA_OIDC_CLIENT_ID").unwrap_or_default(),
+                client_secret: env::var("LUMINA_OIDC_CLIENT_SECRET")
+                    .ok()
+                    .filter(|v| !v.is_empty()),
+                redirect_url: env::var("LUMINA_OIDC_REDIRECT_URL")
+                    .unwrap_or_else(|_| format!("{}/auth/oidc/callback", public_url)),
+                scopes: env::var("LUMINA_OIDC_SCOPES")
+                    .unwrap_or_else(|_| "openid email profile".to_string()),
+                provider_name: env::var("LUMINA_OIDC_PROVIDER_NAME")
+                    .unwrap_or_else(|_| "Single sign-on".to_string()),
+                auto_provision: env_flag("LUMINA_OIDC_AUTO_PROVISION"),
+            });
+        let password_login_enabled = !env_flag("LUMINA_DISABLE_PASSWORD_LOGIN");
+        let admin_emails = env::var("LUMINA_ADMIN_EMAILS")
+            .unwrap_or_default()
+            .split(',')
+            .map(|email| email.trim().to_lowercase())
+            .filter(|email| !email.is_empty())
+            .collect();
+        let registration_mode = env::var("LUMINA_REGISTRATION_MODE")
+            .map(|mode| mode.trim().to_lowercase())
+            .unwrap_or_else(|_| "open".to_string());
+        let org_deletion_grace_secs = env::var("LUMINA_ORG_DELETION_GRACE_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(60 * 60 * 24 * 30)
+            .max(0);
+        let due_reminder_windows_secs = parse_windows(
+            &env::var("LUMINA_DUE_REMINDER_WINDOWS").unwrap_or_else(|_| "86400,3600".to_string()),
+        );
+        let registration_domains = crate::registration::parse_domains(
+            &env::var("LUMINA_REGISTRATION_DOMAINS").unwrap_or_default(),
+        );
+
         Self {
             bind,
             db_url,
             data_dir,
-            jwt_secret,
             auth_rate_limit_burst,
             auth_rate_limit_window_secs,
+            login_lockout_threshold,
+            login_lockout_base_secs,
+            login_lockout_max_secs,
             trusted_proxy_hops,
+            dav_require_app_password,
+            access_token_ttl_secs,
+            refresh_token_ttl_secs,
+            public_url,
+            smtp_url,
+            mail_from,
+            require_email_verification,
+            oidc,
+            password_login_enabled,
+            admin_emails,
+            registration_mode,
+            registration_domains,
+            org_deletion_grace_secs,
+            due_reminder_windows_secs,
         }
     }
 }
 
+/// Parse a comma-separated list of positive durations in seconds, largest
+/// first.
+fn parse_windows(value: &str) -> Vec<i64> {
+    let mut windows: Vec<i64> = value
+        .split(',')
+        .filter_map(|v| v.trim().parse().ok())
+        .filter(|secs| *secs > 0)
+        .collect();
+    windows.sort_unstable_by(|a, b| b.cmp(a));
+    windows.dedup();
+    windows
+}
+
+fn env_flag(name: &str) -> bool {
+    env::var(name)
+        .map(|v| {
+            matches!(
+                v.trim().to_ascii_lowercase().as_str(),
+                "1" | "true" | "yes" | "on"
+            )
+        })
+        .unwrap_or(false)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -72,4 +235,10 @@ mod tests {
         std::env::remove_var("LUMINA_AUTH_RATE_WINDOW_SECS");
         assert!(config.auth_rate_limit_window_secs >= 1);
     }
+
+    #[test]
+    fn reminder_windows_sort_largest_first() {
+        assert_eq!(parse_windows("3600, 86400,x,0,3600"), [86400, 3600]);
+        assert!(parse_windows("").is_empty());
+    }
 }
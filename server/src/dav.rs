@@ -4,16 +4,17 @@ use std::time::SystemTime;
 use axum::body::Body;
 use axum::extract::{Path as AxumPath, State};
 use axum::http::{HeaderMap, Request, Response, StatusCode};
-use base64::{engine::general_purpose::STANDARD, Engine as _};
 use httpdate::fmt_http_date;
 use hyper::body::HttpBody;
 use mime_guess::MimeGuess;
+use serde_json::json;
 use tokio::io::AsyncWriteExt;
 use tokio_util::io::ReaderStream;
 use urlencoding::encode;
 use uuid::Uuid;
 
-use crate::auth::{decode_token, verify_password};
+use crate::audit::{self, AuditEvent};
+use crate::auth::{authorize_header, SCOPE_DAV_READ, SCOPE_DAV_WRITE};
 use crate::db;
 use crate::error::AppError;
 use crate::state::{AppState, ServerMetrics};
@@ -52,7 +53,7 @@ async fn handle_dav_request(
         workspace_id = %workspace_id
     );
     Uuid::parse_str(&workspace_id).map_err(|_| AppError::NotFound)?;
-    let user_id = match authorize_request(&state, req.headers()).await {
+    let user_id = match authorize_header(&state, req.headers(), dav_scope(&method)).await {
         Ok(user_id) => user_id,
         Err(AppError::Unauthorized) => {
             let response = Response::builder()
@@ -76,6 +77,9 @@ async fn handle_dav_request(
         .map_err(|e| AppError::Internal(format!("create workspace dir: {}", e)))?;
 
     let relative = sanitize_path(&path)?;
+    if db::workspace_is_e2ee(&state.pool, &workspace_id).await? {
+        ensure_encrypted_path(&relative)?;
+    }
     let absolute = workspace_root.join(&relative);
 
     let result = match req.method().as_str() {
@@ -85,7 +89,17 @@ async fn handle_dav_request(
         "HEAD" => respond_head(&absolute).await,
         "PUT" => respond_put(&absolute, req, &state.metrics).await,
         "MKCOL" => respond_mkcol(&absolute).await,
-        "DELETE" => respond_delete(&absolute).await,
+        "DELETE" => {
+            let response = respond_delete(&absolute).await;
+            let event = AuditEvent::new(
+                audit::DAV_DELETED,
+                "workspace_file",
+                &format!("{}/{}", workspace_id, relative.display()),
+            )
+            .details(json!({ "workspace_id": workspace_id, "path": relative }));
+            audit_delete(&state, &user_id, req.headers(), event, &response).await;
+            response
+        }
         _ => Ok(Response::builder()
             .status(StatusCode::METHOD_NOT_ALLOWED)
             .body(Body::empty())
@@ -349,43 +363,15 @@ pub(crate) async fn respond_delete(absolute: &Path) -> Result<Response<Body>, Ap
         .map_err(|e| AppError::Internal(format!("build response: {}", e)))
 }
 
-pub(crate) async fn authorize_request(
-    state: &AppState,
-    headers: &HeaderMap,
-) -> Result<String, AppError> {
-    let header = headers
-        .get(axum::http::header::AUTHORIZATION)
-        .ok_or(AppError::Unauthorized)?;
-    let value = header.to_str().map_err(|_| AppError::Unauthorized)?;
-
-    if let Some(token) = value.strip_prefix("Bearer ") {
-        let claims = decode_token(token.trim(), &state.config)?;
-        return Ok(claims.sub);
+/// WebDAV methods that only read; everything else needs the write scope.
+fn dav_scope(method: &axum::http::Method) -> &'static str {
+    match method.as_str() {
+        "OPTIONS" | "PROPFIND" | "GET" | "HEAD" => SCOPE_DAV_READ,
+        _ => SCOPE_DAV_WRITE,
     }
-
-    if let Some(encoded) = value.strip_prefix("Basic ") {
-        let decoded = STANDARD
-            .decode(encoded.trim().as_bytes())
-            .map_err(|_| AppError::Unauthorized)?;
-        let decoded = String::from_utf8(decoded).map_err(|_| AppError::Unauthorized)?;
-        let mut parts = decoded.splitn(2, ':');
-        let email = parts.next().unwrap_or("").trim().to_lowercase();
-        let password = parts.next().unwrap_or("").to_string();
-        if email.is_empty() || password.is_empty() {
-            return Err(AppError::Unauthorized);
-        }
-        let user = db::find_user_by_email(&state.pool, &email).await?;
-        let (user_id, password_hash) = user.ok_or(AppError::Unauthorized)?;
-        if !verify_password(&password, &password_hash)? {
-            return Err(AppError::Unauthorized);
-        }
-        return Ok(user_id);
-    }
-
-    Err(AppError::Unauthorized)
 }
 
-fn workspace_root(state: &AppState, workspace_id: &str) -> PathBuf {
+pub(crate) fn workspace_root(state: &AppState, workspace_id: &str) -> PathBuf {
     PathBuf::from(&state.config.data_dir)
         .join("workspaces")
         .join(workspace_id)
@@ -410,6 +396,28 @@ pub(crate) fn sanitize_path(path: &str) -> Result<PathBuf, AppError> {
     Ok(cleaned.to_path_buf())
 }
 
+/// E2EE workspaces only hold encrypted filenames, which clients encode as
+/// unpadded or padded base64url. Anything else is likely a plaintext name
+/// leaking through a misconfigured client.
+fn ensure_encrypted_path(relative: &Path) -> Result<(), AppError> {
+    for component in relative.components() {
+        if let Component::Normal(segment) = component {
+            let valid = segment.to_str().is_some_and(|name| {
+                !name.is_empty()
+                    && name
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '='))
+            });
+            if !valid {
+                return Err(AppError::BadRequest(
+                    "end-to-end encrypted workspaces require encrypted filenames".to_string(),
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
 pub async fn handle_site_dav_root(
     State(state): State<AppState>,
     req: Request<Body>,
@@ -430,7 +438,10 @@ async fn handle_site_dav(
     path: String,
     req: Request<Body>,
 ) -> Result<Response<Body>, AppError> {
-    let user_id = authorize_request(&state, req.headers()).await?;
+    let user_id = authorize_header(&state, req.headers(), SCOPE_DAV_WRITE).await?;
+    if matches!(req.method().as_str(), "PUT" | "MKCOL" | "DELETE") {
+        db::mark_site_pending(&state.pool, &user_id).await?;
+    }
 
     let root = site_root(&state, &user_id);
     tokio::fs::create_dir_all(&root)
@@ -444,7 +455,17 @@ async fn handle_site_dav(
         "OPTIONS" => respond_options(),
         "PUT" => respond_put(&absolute, req, &state.metrics).await,
         "MKCOL" => respond_mkcol(&absolute).await,
-        "DELETE" => respond_delete(&absolute).await,
+        "DELETE" => {
+            let response = respond_delete(&absolute).await;
+            let event = AuditEvent::new(
+                audit::DAV_DELETED,
+                "site_file",
+                &format!("{}/{}", user_id, relative.display()),
+            )
+            .details(json!({ "path": relative }));
+            audit_delete(&state, &user_id, req.headers(), event, &response).await;
+            response
+        }
         _ => Ok(Response::builder()
             .status(StatusCode::METHOD_NOT_ALLOWED)
             .body(Body::empty())
@@ -452,6 +473,20 @@ async fn handle_site_dav(
     }
 }
 
+/// Record a DAV delete in the audit log if it removed something.
+async fn audit_delete(
+    state: &AppState,
+    user_id: &str,
+    headers: &HeaderMap,
+    event: AuditEvent,
+    response: &Result<Response<Body>, AppError>,
+) {
+    if matches!(response, Ok(response) if response.status().is_success()) {
+        let ip = crate::rate_limit::client_ip(headers);
+        audit::record(state, event.actor(user_id).ip(ip.as_deref())).await;
+    }
+}
+
 #[derive(Debug)]
 struct PropEntry {
     href: String,
@@ -1,6 +1,8 @@
 use crate::error::AppError;
 use chrono::Utc;
+use serde_json::json;
 use sqlx::{Row, SqlitePool};
+use std::collections::HashMap;
 use uuid::Uuid;
 
 pub async fn init_db(pool: &SqlitePool) -> Result<(), AppError> {
@@ -17,6 +19,23 @@ pub async fn init_db(pool: &SqlitePool) -> Result<(), AppError> {
     .execute(pool)
     .await
     .map_err(|e| AppError::Internal(format!("create users table: {}", e)))?;
+    ensure_column(pool, "users", "totp_secret", "TEXT").await?;
+    ensure_column(pool, "users", "totp_enabled", "INTEGER NOT NULL DEFAULT 0").await?;
+    ensure_column(pool, "users", "totp_last_step", "INTEGER").await?;
+    ensure_column(pool, "users", "is_admin", "INTEGER NOT NULL DEFAULT 0").await?;
+    ensure_column(pool, "users", "disabled_at", "INTEGER").await?;
+    // Cleared for accounts provisioned through single sign-on, whose stored
+    // hash is of a random password nobody knows. Existing accounts count as
+    // having one, so they keep being asked for it.
+    ensure_column(pool, "users", "password_set", "INTEGER NOT NULL DEFAULT 1").await?;
+    if ensure_column(pool, "users", "email_verified_at", "INTEGER").await? {
+        // Accounts created before verification existed are trusted as-is so
+        // turning on LUMINA_REQUIRE_EMAIL_VERIFICATION does not lock them out.
+        sqlx::query("UPDATE users SET email_verified_at = created_at")
+            .execute(pool)
+            .await
+            .map_err(|e| AppError::Internal(format!("backfill email_verified_at: {}", e)))?;
+    }
 
     sqlx::query(
         r#"
@@ -24,13 +43,15 @@ pub async fn init_db(pool: &SqlitePool) -> Result<(), AppError> {
             id TEXT PRIMARY KEY,
             name TEXT NOT NULL,
             owner_id TEXT NOT NULL,
-            created_at INTEGER NOT NULL
+            created_at INTEGER NOT NULL,
+            e2ee INTEGER NOT NULL DEFAULT 0
         );
         "#,
     )
     .execute(pool)
     .await
     .map_err(|e| AppError::Internal(format!("create workspaces table: {}", e)))?;
+    ensure_column(pool, "workspaces", "e2ee", "INTEGER NOT NULL DEFAULT 0").await?;
 
     sqlx::query(
         r#"
@@ -60,6 +81,8 @@ pub async fn init_db(pool: &SqlitePool) -> Result<(), AppError> {
     .execute(pool)
     .await
     .map_err(|e| AppError::Internal(format!("create organizations table: {}", e)))?;
+    ensure_column(pool, "organizations", "deleted_at", "INTEGER").await?;
+    ensure_column(pool, "organizations", "deleted_by", "TEXT").await?;
 
     sqlx::query(
         r#"
@@ -76,6 +99,33 @@ pub async fn init_db(pool: &SqlitePool) -> Result<(), AppError> {
     .await
     .map_err(|e| AppError::Internal(format!("create org_members table: {}", e)))?;
 
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS org_invitations (
+            id          TEXT PRIMARY KEY,
+            org_id      TEXT NOT NULL,
+            email       TEXT,
+            role        TEXT NOT NULL CHECK(role IN ('admin','member','guest')),
+            token_hash  TEXT NOT NULL UNIQUE,
+            invited_by  TEXT NOT NULL,
+            created_at  INTEGER NOT NULL,
+            expires_at  INTEGER NOT NULL,
+            accepted_at INTEGER,
+            accepted_by TEXT,
+            declined_at INTEGER,
+            revoked_at  INTEGER
+        );
+        "#,
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| AppError::Internal(format!("create org_invitations table: {}", e)))?;
+
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_org_invitations_email ON org_invitations(email);")
+        .execute(pool)
+        .await
+        .map_err(|e| AppError::Internal(format!("create org_invitations index: {}", e)))?;
+
     sqlx::query(
         r#"
         CREATE TABLE IF NOT EXISTS projects (
@@ -90,6 +140,56 @@ pub async fn init_db(pool: &SqlitePool) -> Result<(), AppError> {
     .execute(pool)
     .await
     .map_err(|e| AppError::Internal(format!("create projects table: {}", e)))?;
+    ensure_column(pool, "projects", "archived_at", "INTEGER").await?;
+
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS project_statuses (
+            project_id  TEXT NOT NULL,
+            key         TEXT NOT NULL,
+            name        TEXT NOT NULL,
+            category    TEXT NOT NULL CHECK (category IN ('backlog', 'active', 'done')),
+            position    INTEGER NOT NULL,
+            transitions TEXT,
+            PRIMARY KEY (project_id, key)
+        );
+        "#,
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| AppError::Internal(format!("create project_statuses table: {}", e)))?;
+
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS project_priorities (
+            project_id TEXT NOT NULL,
+            key        TEXT NOT NULL,
+            name       TEXT NOT NULL,
+            position   INTEGER NOT NULL,
+            is_default INTEGER NOT NULL DEFAULT 0,
+            PRIMARY KEY (project_id, key)
+        );
+        "#,
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| AppError::Internal(format!("create project_priorities table: {}", e)))?;
+
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS project_members (
+            project_id TEXT NOT NULL,
+            user_id    TEXT NOT NULL,
+            role       TEXT NOT NULL CHECK(role IN ('manager','contributor','viewer')),
+            added_by   TEXT NOT NULL,
+            added_at   INTEGER NOT NULL,
+            PRIMARY KEY (project_id, user_id)
+        );
+        "#,
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| AppError::Internal(format!("create project_members table: {}", e)))?;
 
     sqlx::query(
         r#"
@@ -113,6 +213,22 @@ pub async fn init_db(pool: &SqlitePool) -> Result<(), AppError> {
     .execute(pool)
     .await
     .map_err(|e| AppError::Internal(format!("create tasks table: {}", e)))?;
+    ensure_column(pool, "tasks", "parent_id", "TEXT").await?;
+
+    // `blocker_id` blocks `blocked_id`: the latter cannot be finished first.
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS task_dependencies (
+            blocker_id TEXT NOT NULL,
+            blocked_id TEXT NOT NULL,
+            created_at INTEGER NOT NULL,
+            PRIMARY KEY (blocker_id, blocked_id)
+        );
+        "#,
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| AppError::Internal(format!("create task_dependencies table: {}", e)))?;
 
     sqlx::query(
         r#"
@@ -127,6 +243,94 @@ pub async fn init_db(pool: &SqlitePool) -> Result<(), AppError> {
     .await
     .map_err(|e| AppError::Internal(format!("create task_labels table: {}", e)))?;
 
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS project_labels (
+            id         TEXT PRIMARY KEY,
+            project_id TEXT NOT NULL,
+            name       TEXT NOT NULL,
+            color      TEXT NOT NULL,
+            created_at INTEGER NOT NULL,
+            UNIQUE(project_id, name)
+        );
+        "#,
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| AppError::Internal(format!("create project_labels table: {}", e)))?;
+
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS task_comments (
+            id         TEXT PRIMARY KEY,
+            task_id    TEXT NOT NULL,
+            parent_id  TEXT,
+            user_id    TEXT NOT NULL,
+            body       TEXT NOT NULL,
+            created_at INTEGER NOT NULL
+        );
+        "#,
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| AppError::Internal(format!("create task_comments table: {}", e)))?;
+
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS task_activity (
+            id         INTEGER PRIMARY KEY AUTOINCREMENT,
+            task_id    TEXT NOT NULL,
+            actor_id   TEXT,
+            field      TEXT NOT NULL,
+            old_value  TEXT,
+            new_value  TEXT,
+            created_at INTEGER NOT NULL
+        );
+        "#,
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| AppError::Internal(format!("create task_activity table: {}", e)))?;
+
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS task_watchers (
+            task_id    TEXT NOT NULL,
+            user_id    TEXT NOT NULL,
+            created_at INTEGER NOT NULL,
+            PRIMARY KEY (task_id, user_id)
+        );
+        "#,
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| AppError::Internal(format!("create task_watchers table: {}", e)))?;
+
+    for (name, sql) in [
+        (
+            "task_comments",
+            "CREATE INDEX IF NOT EXISTS idx_task_comments_task ON task_comments (task_id)",
+        ),
+        (
+            "task_activity",
+            "CREATE INDEX IF NOT EXISTS idx_task_activity_task ON task_activity (task_id, id)",
+        ),
+        (
+            "tasks",
+            "CREATE INDEX IF NOT EXISTS idx_tasks_parent ON tasks (parent_id)",
+        ),
+        (
+            "task_dependencies",
+            "CREATE INDEX IF NOT EXISTS idx_task_dependencies_blocked
+             ON task_dependencies (blocked_id)",
+        ),
+    ] {
+        sqlx::query(sql)
+            .execute(pool)
+            .await
+            .map_err(|e| AppError::Internal(format!("create {} index: {}", name, e)))?;
+    }
+
     sqlx::query(
         r#"
         CREATE TABLE IF NOT EXISTS annotations (
@@ -145,6 +349,7 @@ pub async fn init_db(pool: &SqlitePool) -> Result<(), AppError> {
     .execute(pool)
     .await
     .map_err(|e| AppError::Internal(format!("create annotations table: {}", e)))?;
+    ensure_column(pool, "annotations", "project_id", "TEXT").await?;
 
     sqlx::query(
         r#"
@@ -180,6 +385,39 @@ pub async fn init_db(pool: &SqlitePool) -> Result<(), AppError> {
     .await
     .map_err(|e| AppError::Internal(format!("create notifications table: {}", e)))?;
 
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS notification_preferences (
+            user_id    TEXT NOT NULL,
+            type       TEXT NOT NULL,
+            enabled    INTEGER NOT NULL,
+            updated_at INTEGER NOT NULL,
+            PRIMARY KEY (user_id, type)
+        );
+        "#,
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| AppError::Internal(format!("create notification_preferences table: {}", e)))?;
+
+    // One row per reminder sent, so restarts never repeat one. Keyed by the
+    // due date too: moving it re-arms the reminders.
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS task_reminders (
+            task_id  TEXT NOT NULL,
+            user_id  TEXT NOT NULL,
+            kind     TEXT NOT NULL,
+            due_date INTEGER NOT NULL,
+            sent_at  INTEGER NOT NULL,
+            PRIMARY KEY (task_id, user_id, kind, due_date)
+        );
+        "#,
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| AppError::Internal(format!("create task_reminders table: {}", e)))?;
+
     sqlx::query(
         "CREATE TABLE IF NOT EXISTS published_sites (
             user_id     TEXT PRIMARY KEY,
@@ -191,6 +429,16 @@ pub async fn init_db(pool: &SqlitePool) -> Result<(), AppError> {
     .execute(pool)
     .await
     .map_err(|e| AppError::Internal(format!("create published_sites table: {}", e)))?;
+    // The workspace the site was last confirmed for, and whether files were
+    // uploaded since; a pending site is not served until it is confirmed.
+    ensure_column(pool, "published_sites", "workspace_id", "TEXT").await?;
+    ensure_column(
+        pool,
+        "published_sites",
+        "pending",
+        "INTEGER NOT NULL DEFAULT 0",
+    )
+    .await?;
 
     sqlx::query(
         r#"
@@ -208,10 +456,266 @@ pub async fn init_db(pool: &SqlitePool) -> Result<(), AppError> {
     .execute(pool)
     .await
     .map_err(|e| AppError::Internal(format!("create document_registry table: {}", e)))?;
+    // Annotations made before they had a project belong to the project their
+    // document is registered in, where that is unambiguous.
+    sqlx::query(
+        r#"
+        UPDATE annotations SET project_id = (
+            SELECT d.project_id FROM document_registry d JOIN projects p ON d.project_id = p.id
+            WHERE d.rel_path = annotations.doc_path AND p.org_id = annotations.org_id
+        )
+        WHERE project_id IS NULL AND (
+            SELECT COUNT(*) FROM document_registry d JOIN projects p ON d.project_id = p.id
+            WHERE d.rel_path = annotations.doc_path AND p.org_id = annotations.org_id
+        ) = 1;
+        "#,
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| AppError::Internal(format!("backfill annotation projects: {}", e)))?;
+
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS workspace_device_keys (
+            workspace_id TEXT NOT NULL,
+            device_id    TEXT NOT NULL,
+            user_id      TEXT NOT NULL,
+            device_name  TEXT NOT NULL,
+            public_key   TEXT NOT NULL,
+            wrapped_key  TEXT,
+            enrolled_by  TEXT,
+            created_at   INTEGER NOT NULL,
+            enrolled_at  INTEGER,
+            PRIMARY KEY (workspace_id, device_id)
+        );
+        "#,
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| AppError::Internal(format!("create workspace_device_keys table: {}", e)))?;
+
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS app_tokens (
+            id            TEXT PRIMARY KEY,
+            user_id       TEXT NOT NULL,
+            name          TEXT NOT NULL,
+            kind          TEXT NOT NULL CHECK(kind IN ('app_password','personal_access_token')),
+            token_hash    TEXT NOT NULL,
+            scopes        TEXT NOT NULL,
+            expires_at    INTEGER,
+            revoked_at    INTEGER,
+            last_used_at  INTEGER,
+            last_used_ip  TEXT,
+            created_at    INTEGER NOT NULL
+        );
+        "#,
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| AppError::Internal(format!("create app_tokens table: {}", e)))?;
+
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS sessions (
+            id            TEXT PRIMARY KEY,
+            user_id       TEXT NOT NULL,
+            device_name   TEXT NOT NULL,
+            ip            TEXT,
+            created_at    INTEGER NOT NULL,
+            last_used_at  INTEGER NOT NULL,
+            expires_at    INTEGER NOT NULL,
+            revoked_at    INTEGER
+        );
+        "#,
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| AppError::Internal(format!("create sessions table: {}", e)))?;
+
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS refresh_tokens (
+            token_hash  TEXT PRIMARY KEY,
+            session_id  TEXT NOT NULL,
+            created_at  INTEGER NOT NULL,
+            used_at     INTEGER
+        );
+        "#,
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| AppError::Internal(format!("create refresh_tokens table: {}", e)))?;
+
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS recovery_codes (
+            id          TEXT PRIMARY KEY,
+            user_id     TEXT NOT NULL,
+            code_hash   TEXT NOT NULL,
+            used_at     INTEGER,
+            created_at  INTEGER NOT NULL
+        );
+        "#,
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| AppError::Internal(format!("create recovery_codes table: {}", e)))?;
+
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS email_tokens (
+            token_hash  TEXT PRIMARY KEY,
+            user_id     TEXT NOT NULL,
+            purpose     TEXT NOT NULL CHECK(purpose IN ('verify_email', 'password_reset')),
+            expires_at  INTEGER NOT NULL,
+            used_at     INTEGER,
+            created_at  INTEGER NOT NULL
+        );
+        "#,
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| AppError::Internal(format!("create email_tokens table: {}", e)))?;
+
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS oidc_states (
+            state           TEXT PRIMARY KEY,
+            code_verifier   TEXT NOT NULL,
+            nonce           TEXT NOT NULL,
+            created_at      INTEGER NOT NULL
+        );
+        "#,
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| AppError::Internal(format!("create oidc_states table: {}", e)))?;
+
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS user_identities (
+            issuer          TEXT NOT NULL,
+            subject         TEXT NOT NULL,
+            user_id         TEXT NOT NULL,
+            email           TEXT,
+            created_at      INTEGER NOT NULL,
+            last_login_at   INTEGER NOT NULL,
+            PRIMARY KEY (issuer, subject)
+        );
+        "#,
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| AppError::Internal(format!("create user_identities table: {}", e)))?;
+
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS signing_keys (
+            kid         TEXT PRIMARY KEY,
+            algorithm   TEXT NOT NULL,
+            private_key TEXT NOT NULL,
+            public_key  TEXT NOT NULL,
+            created_at  INTEGER NOT NULL,
+            retired_at  INTEGER
+        );
+        "#,
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| AppError::Internal(format!("create signing_keys table: {}", e)))?;
+
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS instance_settings (
+            key         TEXT PRIMARY KEY,
+            value       TEXT NOT NULL,
+            updated_at  INTEGER NOT NULL
+        );
+        "#,
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| AppError::Internal(format!("create instance_settings table: {}", e)))?;
+
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS invite_codes (
+            id          TEXT PRIMARY KEY,
+            code_hash   TEXT NOT NULL UNIQUE,
+            created_by  TEXT NOT NULL,
+            org_id      TEXT,
+            role        TEXT,
+            created_at  INTEGER NOT NULL,
+            expires_at  INTEGER,
+            used_at     INTEGER,
+            used_by     TEXT
+        );
+        "#,
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| AppError::Internal(format!("create invite_codes table: {}", e)))?;
+
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS audit_log (
+            id          INTEGER PRIMARY KEY AUTOINCREMENT,
+            created_at  INTEGER NOT NULL,
+            action      TEXT NOT NULL,
+            actor_id    TEXT,
+            ip          TEXT,
+            org_id      TEXT,
+            target_type TEXT NOT NULL,
+            target_id   TEXT NOT NULL,
+            details     TEXT NOT NULL DEFAULT 'null'
+        );
+        "#,
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| AppError::Internal(format!("create audit_log table: {}", e)))?;
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_audit_log_org ON audit_log (org_id, id)")
+        .execute(pool)
+        .await
+        .map_err(|e| AppError::Internal(format!("create audit_log index: {}", e)))?;
+
+    migrate_task_workflows(pool).await?;
 
     Ok(())
 }
 
+/// Add `column` to `table` when an older database was created without it.
+/// `CREATE TABLE IF NOT EXISTS` never alters existing tables, so new columns
+/// must also be declared here. Returns whether the column was added.
+async fn ensure_column(
+    pool: &SqlitePool,
+    table: &str,
+    column: &str,
+    definition: &str,
+) -> Result<bool, AppError> {
+    let rows = sqlx::query(&format!("PRAGMA table_info({})", table))
+        .fetch_all(pool)
+        .await
+        .map_err(|e| AppError::Internal(format!("inspect {} table: {}", table, e)))?;
+    if rows
+        .iter()
+        .any(|row| row.get::<String, _>("name") == column)
+    {
+        return Ok(false);
+    }
+
+    sqlx::query(&format!(
+        "ALTER TABLE {} ADD COLUMN {} {}",
+        table, column, definition
+    ))
+    .execute(pool)
+    .await
+    .map_err(|e| AppError::Internal(format!("add {}.{} column: {}", table, column, e)))?;
+
+    Ok(true)
+}
+
 // ---------------------------------------------------------------------------
 // Struct definitions for team collaboration
 // ---------------------------------------------------------------------------
@@ -220,6 +724,7 @@ pub async fn init_db(pool: &SqlitePool) -> Result<(), AppError> {
 pub struct TaskRow {
     pub id: String,
     pub project_id: String,
+    pub parent_id: Option<String>,
     pub title: String,
     pub description: String,
     pub status: String,
@@ -238,6 +743,7 @@ pub struct AnnotationRow {
     pub id: String,
     pub doc_path: String,
     pub org_id: String,
+    pub project_id: Option<String>,
     pub user_id: String,
     pub range_start: i64,
     pub range_end: i64,
@@ -318,7 +824,6 @@ pub async fn find_user_by_email(
     }))
 }
 
-#[allow(dead_code)]
 pub async fn get_user_by_id(pool: &SqlitePool, user_id: &str) -> Result<Option<String>, AppError> {
     let row = sqlx::query(
         r#"
@@ -339,20 +844,22 @@ pub async fn create_workspace(
     pool: &SqlitePool,
     owner_id: &str,
     name: &str,
+    e2ee: bool,
 ) -> Result<String, AppError> {
     let workspace_id = Uuid::new_v4().to_string();
     let now = Utc::now().timestamp();
 
     sqlx::query(
         r#"
-        INSERT INTO workspaces (id, name, owner_id, created_at)
-        VALUES (?1, ?2, ?3, ?4);
+        INSERT INTO workspaces (id, name, owner_id, created_at, e2ee)
+        VALUES (?1, ?2, ?3, ?4, ?5);
         "#,
     )
     .bind(&workspace_id)
     .bind(name)
     .bind(owner_id)
     .bind(now)
+    .bind(e2ee)
     .execute(pool)
     .await
     .map_err(|e| AppError::Internal(format!("create workspace: {}", e)))?;
@@ -376,10 +883,10 @@ pub async fn create_workspace(
 pub async fn list_workspaces(
     pool: &SqlitePool,
     user_id: &str,
-) -> Result<Vec<(String, String)>, AppError> {
+) -> Result<Vec<(String, String, bool)>, AppError> {
     let rows = sqlx::query(
         r#"
-        SELECT w.id, w.name
+        SELECT w.id, w.name, w.e2ee
         FROM workspaces w
         JOIN workspace_members m
           ON w.id = m.workspace_id
@@ -394,7 +901,13 @@ pub async fn list_workspaces(
 
     Ok(rows
         .into_iter()
-        .map(|row| (row.get::<String, _>("id"), row.get::<String, _>("name")))
+        .map(|row| {
+            (
+                row.get::<String, _>("id"),
+                row.get::<String, _>("name"),
+                row.get::<i32, _>("e2ee") != 0,
+            )
+        })
         .collect())
 }
 
@@ -420,935 +933,5236 @@ pub async fn user_has_workspace(
     Ok(row.is_some())
 }
 
-// ---------------------------------------------------------------------------
-// Organization CRUD
-// ---------------------------------------------------------------------------
-
-pub async fn create_organization(
-    pool: &SqlitePool,
-    owner_id: &str,
-    name: &str,
-) -> Result<String, AppError> {
-    let org_id = Uuid::new_v4().to_string();
-    let now = Utc::now().timestamp();
-
-    sqlx::query(
+/// Whether the workspace stores only client-side encrypted content.
+/// Unknown workspaces report `false`.
+pub async fn workspace_is_e2ee(pool: &SqlitePool, workspace_id: &str) -> Result<bool, AppError> {
+    let row = sqlx::query(
         r#"
-        INSERT INTO organizations (id, name, owner_id, created_at)
-        VALUES (?1, ?2, ?3, ?4);
+        SELECT e2ee
+        FROM workspaces
+        WHERE id = ?1;
         "#,
     )
-    .bind(&org_id)
-    .bind(name)
-    .bind(owner_id)
-    .bind(now)
-    .execute(pool)
+    .bind(workspace_id)
+    .fetch_optional(pool)
     .await
-    .map_err(|e| AppError::Internal(format!("create organization: {}", e)))?;
+    .map_err(|e| AppError::Internal(format!("get workspace e2ee flag: {}", e)))?;
 
-    sqlx::query(
+    Ok(row
+        .map(|row| row.get::<i32, _>("e2ee") != 0)
+        .unwrap_or(false))
+}
+
+// ---------------------------------------------------------------------------
+// E2EE device keys
+// ---------------------------------------------------------------------------
+
+pub struct DeviceKeyRow {
+    pub device_id: String,
+    pub user_id: String,
+    pub device_name: String,
+    pub public_key: String,
+    pub wrapped_key: Option<String>,
+    pub enrolled_by: Option<String>,
+    pub created_at: i64,
+    pub enrolled_at: Option<i64>,
+}
+
+fn device_key_from_row(row: &sqlx::sqlite::SqliteRow) -> DeviceKeyRow {
+    DeviceKeyRow {
+        device_id: row.get::<String, _>("device_id"),
+        user_id: row.get::<String, _>("user_id"),
+        device_name: row.get::<String, _>("device_name"),
+        public_key: row.get::<String, _>("public_key"),
+        wrapped_key: row.get::<Option<String>, _>("wrapped_key"),
+        enrolled_by: row.get::<Option<String>, _>("enrolled_by"),
+        created_at: row.get::<i64, _>("created_at"),
+        enrolled_at: row.get::<Option<i64>, _>("enrolled_at"),
+    }
+}
+
+/// Register a device for an E2EE workspace. `wrapped_key` is only set when the
+/// device bootstraps the workspace key itself; otherwise the device stays
+/// pending until an enrolled device wraps the key for it. Bootstrapping is
+/// checked and recorded in one statement, so of two devices racing to do it
+/// only one succeeds and the other gets a conflict.
+pub async fn register_device_key(
+    pool: &SqlitePool,
+    workspace_id: &str,
+    device_id: &str,
+    user_id: &str,
+    device_name: &str,
+    public_key: &str,
+    wrapped_key: Option<&str>,
+) -> Result<(), AppError> {
+    let now = Utc::now().timestamp();
+    let enrolled_at = wrapped_key.map(|_| now);
+    let enrolled_by = wrapped_key.map(|_| device_id);
+
+    let result = sqlx::query(
         r#"
-        INSERT INTO org_members (org_id, user_id, role, joined_at)
-        VALUES (?1, ?2, 'admin', ?3);
+        INSERT INTO workspace_device_keys
+            (workspace_id, device_id, user_id, device_name, public_key, wrapped_key, enrolled_by, created_at, enrolled_at)
+        SELECT ?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9
+        WHERE ?6 IS NULL OR NOT EXISTS (
+            SELECT 1 FROM workspace_device_keys
+            WHERE workspace_id = ?1 AND wrapped_key IS NOT NULL
+        );
         "#,
     )
-    .bind(&org_id)
-    .bind(owner_id)
+    .bind(workspace_id)
+    .bind(device_id)
+    .bind(user_id)
+    .bind(device_name)
+    .bind(public_key)
+    .bind(wrapped_key)
+    .bind(enrolled_by)
     .bind(now)
+    .bind(enrolled_at)
     .execute(pool)
-    .await
-    .map_err(|e| AppError::Internal(format!("insert org owner member: {}", e)))?;
+    .await;
 
-    Ok(org_id)
+    match result {
+        Ok(done) if done.rows_affected() == 0 => Err(AppError::Conflict(
+            "workspace key already exists; enroll through an existing device".to_string(),
+        )),
+        Ok(_) => Ok(()),
+        Err(err) if err.to_string().contains("UNIQUE") => {
+            Err(AppError::Conflict("device already registered".to_string()))
+        }
+        Err(err) => Err(AppError::Internal(format!("register device key: {}", err))),
+    }
 }
 
-pub async fn list_user_organizations(
+pub async fn list_device_keys(
     pool: &SqlitePool,
-    user_id: &str,
-) -> Result<Vec<(String, String, String)>, AppError> {
+    workspace_id: &str,
+) -> Result<Vec<DeviceKeyRow>, AppError> {
     let rows = sqlx::query(
         r#"
-        SELECT o.id, o.name, m.role
-        FROM organizations o
-        JOIN org_members m
-          ON o.id = m.org_id
-        WHERE m.user_id = ?1
-        ORDER BY o.created_at DESC;
+        SELECT device_id, user_id, device_name, public_key, wrapped_key, enrolled_by, created_at, enrolled_at
+        FROM workspace_device_keys
+        WHERE workspace_id = ?1
+        ORDER BY created_at ASC;
         "#,
     )
-    .bind(user_id)
+    .bind(workspace_id)
     .fetch_all(pool)
     .await
-    .map_err(|e| AppError::Internal(format!("list user organizations: {}", e)))?;
+    .map_err(|e| AppError::Internal(format!("list device keys: {}", e)))?;
 
-    Ok(rows
-        .into_iter()
-        .map(|row| {
-            (
-                row.get::<String, _>("id"),
-                row.get::<String, _>("name"),
-                row.get::<String, _>("role"),
-            )
-        })
-        .collect())
+    Ok(rows.iter().map(device_key_from_row).collect())
 }
 
-pub async fn get_organization(
+pub async fn get_device_key(
     pool: &SqlitePool,
-    org_id: &str,
-) -> Result<Option<(String, String, String)>, AppError> {
+    workspace_id: &str,
+    device_id: &str,
+) -> Result<Option<DeviceKeyRow>, AppError> {
     let row = sqlx::query(
         r#"
-        SELECT id, name, owner_id
-        FROM organizations
-        WHERE id = ?1;
+        SELECT device_id, user_id, device_name, public_key, wrapped_key, enrolled_by, created_at, enrolled_at
+        FROM workspace_device_keys
+        WHERE workspace_id = ?1 AND device_id = ?2;
         "#,
     )
-    .bind(org_id)
+    .bind(workspace_id)
+    .bind(device_id)
     .fetch_optional(pool)
     .await
-    .map_err(|e| AppError::Internal(format!("get organization: {}", e)))?;
+    .map_err(|e| AppError::Internal(format!("get device key: {}", e)))?;
 
-    Ok(row.map(|row| {
-        (
-            row.get::<String, _>("id"),
-            row.get::<String, _>("name"),
-            row.get::<String, _>("owner_id"),
-        )
-    }))
+    Ok(row.as_ref().map(device_key_from_row))
 }
 
-pub async fn update_organization_name(
+/// Store key material wrapped for a pending device by an enrolled one.
+pub async fn enroll_device_key(
     pool: &SqlitePool,
-    org_id: &str,
-    name: &str,
+    workspace_id: &str,
+    device_id: &str,
+    wrapped_key: &str,
+    enrolled_by: &str,
 ) -> Result<(), AppError> {
+    let now = Utc::now().timestamp();
     sqlx::query(
         r#"
-        UPDATE organizations
-        SET name = ?1
-        WHERE id = ?2;
+        UPDATE workspace_device_keys
+        SET wrapped_key = ?1, enrolled_by = ?2, enrolled_at = ?3
+        WHERE workspace_id = ?4 AND device_id = ?5;
         "#,
     )
-    .bind(name)
-    .bind(org_id)
+    .bind(wrapped_key)
+    .bind(enrolled_by)
+    .bind(now)
+    .bind(workspace_id)
+    .bind(device_id)
     .execute(pool)
     .await
-    .map_err(|e| AppError::Internal(format!("update organization name: {}", e)))?;
+    .map_err(|e| AppError::Internal(format!("enroll device key: {}", e)))?;
 
     Ok(())
 }
 
-pub async fn add_org_member(
+pub async fn delete_device_key(
     pool: &SqlitePool,
-    org_id: &str,
-    user_id: &str,
-    role: &str,
+    workspace_id: &str,
+    device_id: &str,
 ) -> Result<(), AppError> {
-    let now = Utc::now().timestamp();
-
-    let result = sqlx::query(
+    sqlx::query(
         r#"
-        INSERT INTO org_members (org_id, user_id, role, joined_at)
-        VALUES (?1, ?2, ?3, ?4);
+        DELETE FROM workspace_device_keys
+        WHERE workspace_id = ?1 AND device_id = ?2;
         "#,
     )
-    .bind(org_id)
-    .bind(user_id)
-    .bind(role)
-    .bind(now)
+    .bind(workspace_id)
+    .bind(device_id)
     .execute(pool)
-    .await;
-
-    if let Err(err) = result {
-        let message = err.to_string();
-        if message.contains("UNIQUE") {
-            return Err(AppError::Conflict("member already exists".to_string()));
-        }
-        return Err(AppError::Internal(format!("add org member: {}", err)));
-    }
+    .await
+    .map_err(|e| AppError::Internal(format!("delete device key: {}", e)))?;
 
     Ok(())
 }
 
-pub async fn remove_org_member(
+// ---------------------------------------------------------------------------
+// App passwords & personal access tokens
+// ---------------------------------------------------------------------------
+
+pub struct AppTokenRow {
+    pub id: String,
+    pub user_id: String,
+    pub name: String,
+    pub kind: String,
+    pub token_hash: String,
+    pub scopes: Vec<String>,
+    pub expires_at: Option<i64>,
+    pub revoked_at: Option<i64>,
+    pub last_used_at: Option<i64>,
+    pub last_used_ip: Option<String>,
+    pub created_at: i64,
+}
+
+fn app_token_from_row(row: &sqlx::sqlite::SqliteRow) -> AppTokenRow {
+    AppTokenRow {
+        id: row.get::<String, _>("id"),
+        user_id: row.get::<String, _>("user_id"),
+        name: row.get::<String, _>("name"),
+        kind: row.get::<String, _>("kind"),
+        token_hash: row.get::<String, _>("token_hash"),
+        scopes: row
+            .get::<String, _>("scopes")
+            .split_whitespace()
+            .map(|s| s.to_string())
+            .collect(),
+        expires_at: row.get::<Option<i64>, _>("expires_at"),
+        revoked_at: row.get::<Option<i64>, _>("revoked_at"),
+        last_used_at: row.get::<Option<i64>, _>("last_used_at"),
+        last_used_ip: row.get::<Option<String>, _>("last_used_ip"),
+        created_at: row.get::<i64, _>("created_at"),
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub async fn create_app_token(
     pool: &SqlitePool,
-    org_id: &str,
+    token_id: &str,
     user_id: &str,
+    name: &str,
+    kind: &str,
+    token_hash: &str,
+    scopes: &[String],
+    expires_at: Option<i64>,
 ) -> Result<(), AppError> {
+    let now = Utc::now().timestamp();
     sqlx::query(
         r#"
-        DELETE FROM org_members
-        WHERE org_id = ?1 AND user_id = ?2;
+        INSERT INTO app_tokens (id, user_id, name, kind, token_hash, scopes, expires_at, created_at)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8);
         "#,
     )
-    .bind(org_id)
+    .bind(token_id)
     .bind(user_id)
+    .bind(name)
+    .bind(kind)
+    .bind(token_hash)
+    .bind(scopes.join(" "))
+    .bind(expires_at)
+    .bind(now)
     .execute(pool)
     .await
-    .map_err(|e| AppError::Internal(format!("remove org member: {}", e)))?;
+    .map_err(|e| AppError::Internal(format!("create app token: {}", e)))?;
 
     Ok(())
 }
 
-pub async fn list_org_members(
+pub async fn get_app_token(
     pool: &SqlitePool,
-    org_id: &str,
-) -> Result<Vec<(String, String, String)>, AppError> {
+    token_id: &str,
+) -> Result<Option<AppTokenRow>, AppError> {
+    let row = sqlx::query(
+        r#"
+        SELECT id, user_id, name, kind, token_hash, scopes, expires_at, revoked_at,
+               last_used_at, last_used_ip, created_at
+        FROM app_tokens
+        WHERE id = ?1;
+        "#,
+    )
+    .bind(token_id)
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| AppError::Internal(format!("get app token: {}", e)))?;
+
+    Ok(row.as_ref().map(app_token_from_row))
+}
+
+pub async fn list_app_tokens(
+    pool: &SqlitePool,
+    user_id: &str,
+) -> Result<Vec<AppTokenRow>, AppError> {
     let rows = sqlx::query(
         r#"
-        SELECT m.user_id, u.email, m.role
-        FROM org_members m
-        JOIN users u
-          ON m.user_id = u.id
-        WHERE m.org_id = ?1
-        ORDER BY m.joined_at ASC;
+        SELECT id, user_id, name, kind, token_hash, scopes, expires_at, revoked_at,
+               last_used_at, last_used_ip, created_at
+        FROM app_tokens
+        WHERE user_id = ?1
+        ORDER BY created_at DESC;
         "#,
     )
-    .bind(org_id)
+    .bind(user_id)
     .fetch_all(pool)
     .await
-    .map_err(|e| AppError::Internal(format!("list org members: {}", e)))?;
+    .map_err(|e| AppError::Internal(format!("list app tokens: {}", e)))?;
 
-    Ok(rows
-        .into_iter()
-        .map(|row| {
-            (
-                row.get::<String, _>("user_id"),
-                row.get::<String, _>("email"),
-                row.get::<String, _>("role"),
-            )
-        })
-        .collect())
+    Ok(rows.iter().map(app_token_from_row).collect())
 }
 
-pub async fn get_org_member_role(
+/// Revoke a token owned by `user_id`. Returns `false` if no such token exists.
+pub async fn revoke_app_token(
     pool: &SqlitePool,
-    org_id: &str,
+    token_id: &str,
     user_id: &str,
-) -> Result<Option<String>, AppError> {
-    let row = sqlx::query(
+) -> Result<bool, AppError> {
+    let now = Utc::now().timestamp();
+    let result = sqlx::query(
         r#"
-        SELECT role
-        FROM org_members
-        WHERE org_id = ?1 AND user_id = ?2;
+        UPDATE app_tokens
+        SET revoked_at = COALESCE(revoked_at, ?1)
+        WHERE id = ?2 AND user_id = ?3;
         "#,
     )
-    .bind(org_id)
+    .bind(now)
+    .bind(token_id)
     .bind(user_id)
-    .fetch_optional(pool)
+    .execute(pool)
     .await
-    .map_err(|e| AppError::Internal(format!("get org member role: {}", e)))?;
+    .map_err(|e| AppError::Internal(format!("revoke app token: {}", e)))?;
 
-    Ok(row.map(|row| row.get::<String, _>("role")))
+    Ok(result.rows_affected() > 0)
 }
 
-// ---------------------------------------------------------------------------
-// Project CRUD
-// ---------------------------------------------------------------------------
+/// Revoke every token of `user_id`.
+pub async fn revoke_user_app_tokens(pool: &SqlitePool, user_id: &str) -> Result<(), AppError> {
+    let now = Utc::now().timestamp();
+    sqlx::query("UPDATE app_tokens SET revoked_at = ?1 WHERE user_id = ?2 AND revoked_at IS NULL")
+        .bind(now)
+        .bind(user_id)
+        .execute(pool)
+        .await
+        .map_err(|e| AppError::Internal(format!("revoke user app tokens: {}", e)))?;
 
-pub async fn create_project(
+    Ok(())
+}
+
+pub async fn touch_app_token(
     pool: &SqlitePool,
-    org_id: &str,
-    name: &str,
-    description: &str,
-) -> Result<String, AppError> {
-    let project_id = Uuid::new_v4().to_string();
+    token_id: &str,
+    ip: Option<&str>,
+) -> Result<(), AppError> {
     let now = Utc::now().timestamp();
-
     sqlx::query(
         r#"
-        INSERT INTO projects (id, org_id, name, description, created_at)
-        VALUES (?1, ?2, ?3, ?4, ?5);
+        UPDATE app_tokens
+        SET last_used_at = ?1, last_used_ip = COALESCE(?2, last_used_ip)
+        WHERE id = ?3;
         "#,
     )
-    .bind(&project_id)
-    .bind(org_id)
-    .bind(name)
-    .bind(description)
     .bind(now)
+    .bind(ip)
+    .bind(token_id)
     .execute(pool)
     .await
-    .map_err(|e| AppError::Internal(format!("create project: {}", e)))?;
+    .map_err(|e| AppError::Internal(format!("touch app token: {}", e)))?;
 
-    Ok(project_id)
+    Ok(())
 }
 
-pub async fn list_projects(
+pub async fn update_user_password(
     pool: &SqlitePool,
-    org_id: &str,
-) -> Result<Vec<(String, String, String)>, AppError> {
-    let rows = sqlx::query(
+    user_id: &str,
+    password_hash: &str,
+) -> Result<(), AppError> {
+    sqlx::query(
         r#"
-        SELECT id, name, description
-        FROM projects
-        WHERE org_id = ?1
-        ORDER BY created_at DESC;
+        UPDATE users
+        SET password_hash = ?1, password_set = 1
+        WHERE id = ?2;
         "#,
     )
-    .bind(org_id)
-    .fetch_all(pool)
+    .bind(password_hash)
+    .bind(user_id)
+    .execute(pool)
     .await
-    .map_err(|e| AppError::Internal(format!("list projects: {}", e)))?;
+    .map_err(|e| AppError::Internal(format!("update password: {}", e)))?;
 
-    Ok(rows
-        .into_iter()
-        .map(|row| {
-            (
-                row.get::<String, _>("id"),
-                row.get::<String, _>("name"),
-                row.get::<String, _>("description"),
-            )
-        })
-        .collect())
+    Ok(())
 }
 
-pub async fn get_project(
-    pool: &SqlitePool,
-    project_id: &str,
-) -> Result<Option<(String, String, String, String)>, AppError> {
-    let row = sqlx::query(
-        r#"
-        SELECT id, org_id, name, description
-        FROM projects
-        WHERE id = ?1;
-        "#,
-    )
-    .bind(project_id)
-    .fetch_optional(pool)
-    .await
-    .map_err(|e| AppError::Internal(format!("get project: {}", e)))?;
+/// Record that the account has no password of its own yet.
+pub async fn mark_password_unset(pool: &SqlitePool, user_id: &str) -> Result<(), AppError> {
+    sqlx::query("UPDATE users SET password_set = 0 WHERE id = ?1")
+        .bind(user_id)
+        .execute(pool)
+        .await
+        .map_err(|e| AppError::Internal(format!("mark password unset: {}", e)))?;
+    Ok(())
+}
 
-    Ok(row.map(|row| {
-        (
-            row.get::<String, _>("id"),
-            row.get::<String, _>("org_id"),
-            row.get::<String, _>("name"),
-            row.get::<String, _>("description"),
-        )
-    }))
+/// Whether the user chose a password, as opposed to only signing in
+/// through single sign-on.
+pub async fn user_has_password(pool: &SqlitePool, user_id: &str) -> Result<bool, AppError> {
+    let set: Option<i64> = sqlx::query_scalar("SELECT password_set FROM users WHERE id = ?1")
+        .bind(user_id)
+        .fetch_optional(pool)
+        .await
+        .map_err(|e| AppError::Internal(format!("check password set: {}", e)))?;
+    Ok(set.is_some_and(|set| set != 0))
 }
 
-#[allow(dead_code)]
-pub async fn delete_project(pool: &SqlitePool, project_id: &str) -> Result<(), AppError> {
+pub async fn is_email_verified(pool: &SqlitePool, user_id: &str) -> Result<bool, AppError> {
+    let row = sqlx::query("SELECT email_verified_at FROM users WHERE id = ?1")
+        .bind(user_id)
+        .fetch_optional(pool)
+        .await
+        .map_err(|e| AppError::Internal(format!("query email verification: {}", e)))?
+        .ok_or(AppError::NotFound)?;
+
+    Ok(row.get::<Option<i64>, _>("email_verified_at").is_some())
+}
+
+pub async fn mark_email_verified(pool: &SqlitePool, user_id: &str) -> Result<(), AppError> {
+    let now = Utc::now().timestamp();
     sqlx::query(
         r#"
-        DELETE FROM projects
-        WHERE id = ?1;
+        UPDATE users
+        SET email_verified_at = ?1
+        WHERE id = ?2 AND email_verified_at IS NULL;
         "#,
     )
-    .bind(project_id)
+    .bind(now)
+    .bind(user_id)
     .execute(pool)
     .await
-    .map_err(|e| AppError::Internal(format!("delete project: {}", e)))?;
+    .map_err(|e| AppError::Internal(format!("mark email verified: {}", e)))?;
 
     Ok(())
 }
 
 // ---------------------------------------------------------------------------
-// Task CRUD
+// Email tokens (verification & password reset)
 // ---------------------------------------------------------------------------
 
-#[allow(clippy::too_many_arguments)]
-pub async fn create_task(
+pub const EMAIL_TOKEN_VERIFY: &str = "verify_email";
+pub const EMAIL_TOKEN_RESET: &str = "password_reset";
+
+/// Store a new emailed token. Earlier unused tokens for the same purpose are
+/// invalidated so only the most recent email works.
+pub async fn create_email_token(
     pool: &SqlitePool,
-    project_id: &str,
-    title: &str,
-    description: &str,
-    status: &str,
-    priority: &str,
-    assignee_id: Option<&str>,
-    due_date: Option<i64>,
-    start_date: Option<i64>,
-    created_by: &str,
-) -> Result<String, AppError> {
-    let task_id = Uuid::new_v4().to_string();
+    token_hash: &str,
+    user_id: &str,
+    purpose: &str,
+    expires_at: i64,
+) -> Result<(), AppError> {
     let now = Utc::now().timestamp();
-
     sqlx::query(
         r#"
-        INSERT INTO tasks (id, project_id, title, description, status, priority, assignee_id, due_date, start_date, position, created_by, created_at, updated_at)
-        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13);
+        UPDATE email_tokens
+        SET used_at = ?1
+        WHERE user_id = ?2 AND purpose = ?3 AND used_at IS NULL;
         "#,
     )
-    .bind(&task_id)
-    .bind(project_id)
-    .bind(title)
-    .bind(description)
-    .bind(status)
-    .bind(priority)
-    .bind(assignee_id)
-    .bind(due_date)
-    .bind(start_date)
-    .bind(0.0_f64)
-    .bind(created_by)
     .bind(now)
+    .bind(user_id)
+    .bind(purpose)
+    .execute(pool)
+    .await
+    .map_err(|e| AppError::Internal(format!("invalidate email tokens: {}", e)))?;
+
+    sqlx::query(
+        r#"
+        INSERT INTO email_tokens (token_hash, user_id, purpose, expires_at, created_at)
+        VALUES (?1, ?2, ?3, ?4, ?5);
+        "#,
+    )
+    .bind(token_hash)
+    .bind(user_id)
+    .bind(purpose)
+    .bind(expires_at)
     .bind(now)
     .execute(pool)
     .await
-    .map_err(|e| AppError::Internal(format!("create task: {}", e)))?;
+    .map_err(|e| AppError::Internal(format!("insert email token: {}", e)))?;
 
-    Ok(task_id)
+    Ok(())
 }
 
-#[allow(clippy::too_many_arguments)]
-pub async fn update_task(
+/// Mark a token as used and return its user. `None` if the token is unknown,
+/// expired, already used or issued for a different purpose.
+pub async fn consume_email_token(
     pool: &SqlitePool,
-    task_id: &str,
-    title: Option<&str>,
-    description: Option<&str>,
-    status: Option<&str>,
-    priority: Option<&str>,
-    assignee_id: Option<Option<&str>>,
-    due_date: Option<Option<i64>>,
-    start_date: Option<Option<i64>>,
-    position: Option<f64>,
-) -> Result<(), AppError> {
+    token_hash: &str,
+    purpose: &str,
+) -> Result<Option<String>, AppError> {
     let now = Utc::now().timestamp();
-    // Fetch current task, then do a full UPDATE with merged values.
-    let current = get_task(pool, task_id).await?;
-    let current = current.ok_or(AppError::NotFound)?;
-
-    let final_title = title.unwrap_or(&current.title);
-    let final_description = description.unwrap_or(&current.description);
-    let final_status = status.unwrap_or(&current.status);
-    let final_priority = priority.unwrap_or(&current.priority);
-    let final_assignee_id: Option<String> = match assignee_id {
-        Some(v) => v.map(|s| s.to_string()),
-        None => current.assignee_id,
-    };
-    let final_due_date: Option<i64> = match due_date {
-        Some(v) => v,
-        None => current.due_date,
-    };
-    let final_start_date: Option<i64> = match start_date {
-        Some(v) => v,
-        None => current.start_date,
-    };
-    let final_position = position.unwrap_or(current.position);
-
-    sqlx::query(
+    let row = sqlx::query(
         r#"
-        UPDATE tasks
-        SET title = ?1, description = ?2, status = ?3, priority = ?4,
-            assignee_id = ?5, due_date = ?6, start_date = ?7, position = ?8,
-            updated_at = ?9
-        WHERE id = ?10;
+        UPDATE email_tokens
+        SET used_at = ?1
+        WHERE token_hash = ?2 AND purpose = ?3 AND used_at IS NULL AND expires_at > ?1
+        RETURNING user_id;
         "#,
     )
-    .bind(final_title)
-    .bind(final_description)
-    .bind(final_status)
-    .bind(final_priority)
-    .bind(&final_assignee_id)
-    .bind(final_due_date)
-    .bind(final_start_date)
-    .bind(final_position)
     .bind(now)
-    .bind(task_id)
-    .execute(pool)
+    .bind(token_hash)
+    .bind(purpose)
+    .fetch_optional(pool)
     .await
-    .map_err(|e| AppError::Internal(format!("update task: {}", e)))?;
+    .map_err(|e| AppError::Internal(format!("consume email token: {}", e)))?;
 
-    Ok(())
+    Ok(row.map(|row| row.get::<String, _>("user_id")))
+}
+
+// ---------------------------------------------------------------------------
+// Token signing keys
+// ---------------------------------------------------------------------------
+
+pub struct SigningKeyRow {
+    pub kid: String,
+    pub algorithm: String,
+    pub private_key: String,
+    pub public_key: String,
+    pub created_at: i64,
+    pub retired_at: Option<i64>,
 }
 
-pub async fn list_tasks(pool: &SqlitePool, project_id: &str) -> Result<Vec<TaskRow>, AppError> {
+/// Keys that are active or were retired after `retired_after`, newest first.
+pub async fn list_signing_keys(
+    pool: &SqlitePool,
+    retired_after: i64,
+) -> Result<Vec<SigningKeyRow>, AppError> {
     let rows = sqlx::query(
         r#"
-        SELECT id, project_id, title, description, status, priority,
-               assignee_id, due_date, start_date, position, created_by,
-               created_at, updated_at
-        FROM tasks
-        WHERE project_id = ?1
-        ORDER BY position ASC, created_at ASC;
+        SELECT kid, algorithm, private_key, public_key, created_at, retired_at
+        FROM signing_keys
+        WHERE retired_at IS NULL OR retired_at > ?1
+        ORDER BY created_at DESC;
         "#,
     )
-    .bind(project_id)
+    .bind(retired_after)
     .fetch_all(pool)
     .await
-    .map_err(|e| AppError::Internal(format!("list tasks: {}", e)))?;
+    .map_err(|e| AppError::Internal(format!("list signing keys: {}", e)))?;
 
     Ok(rows
         .into_iter()
-        .map(|row| TaskRow {
-            id: row.get::<String, _>("id"),
-            project_id: row.get::<String, _>("project_id"),
-            title: row.get::<String, _>("title"),
-            description: row.get::<String, _>("description"),
-            status: row.get::<String, _>("status"),
-            priority: row.get::<String, _>("priority"),
-            assignee_id: row.get::<Option<String>, _>("assignee_id"),
-            due_date: row.get::<Option<i64>, _>("due_date"),
-            start_date: row.get::<Option<i64>, _>("start_date"),
-            position: row.get::<f64, _>("position"),
-            created_by: row.get::<String, _>("created_by"),
+        .map(|row| SigningKeyRow {
+            kid: row.get::<String, _>("kid"),
+            algorithm: row.get::<String, _>("algorithm"),
+            private_key: row.get::<String, _>("private_key"),
+            public_key: row.get::<String, _>("public_key"),
             created_at: row.get::<i64, _>("created_at"),
-            updated_at: row.get::<i64, _>("updated_at"),
+            retired_at: row.get::<Option<i64>, _>("retired_at"),
         })
         .collect())
 }
 
-pub async fn get_task(pool: &SqlitePool, task_id: &str) -> Result<Option<TaskRow>, AppError> {
-    let row = sqlx::query(
-        r#"
-        SELECT id, project_id, title, description, status, priority,
-               assignee_id, due_date, start_date, position, created_by,
-               created_at, updated_at
-        FROM tasks
-        WHERE id = ?1;
-        "#,
-    )
-    .bind(task_id)
-    .fetch_optional(pool)
-    .await
-    .map_err(|e| AppError::Internal(format!("get task: {}", e)))?;
-
-    Ok(row.map(|row| TaskRow {
-        id: row.get::<String, _>("id"),
-        project_id: row.get::<String, _>("project_id"),
-        title: row.get::<String, _>("title"),
-        description: row.get::<String, _>("description"),
-        status: row.get::<String, _>("status"),
-        priority: row.get::<String, _>("priority"),
-        assignee_id: row.get::<Option<String>, _>("assignee_id"),
-        due_date: row.get::<Option<i64>, _>("due_date"),
-        start_date: row.get::<Option<i64>, _>("start_date"),
-        position: row.get::<f64, _>("position"),
-        created_by: row.get::<String, _>("created_by"),
-        created_at: row.get::<i64, _>("created_at"),
-        updated_at: row.get::<i64, _>("updated_at"),
-    }))
-}
-
-pub async fn delete_task(pool: &SqlitePool, task_id: &str) -> Result<(), AppError> {
+/// Make `key` the only active signing key. Previously active keys are
+/// retired, and keys retired before `purge_before` are deleted.
+pub async fn rotate_signing_key(
+    pool: &SqlitePool,
+    key: &SigningKeyRow,
+    purge_before: i64,
+) -> Result<(), AppError> {
+    let now = Utc::now().timestamp();
+    let mut tx = pool
+        .begin()
+        .await
+        .map_err(|e| AppError::Internal(format!("begin signing key tx: {}", e)))?;
+    sqlx::query("UPDATE signing_keys SET retired_at = ?1 WHERE retired_at IS NULL")
+        .bind(now)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| AppError::Internal(format!("retire signing keys: {}", e)))?;
+    sqlx::query("DELETE FROM signing_keys WHERE retired_at IS NOT NULL AND retired_at <= ?1")
+        .bind(purge_before)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| AppError::Internal(format!("purge signing keys: {}", e)))?;
     sqlx::query(
         r#"
-        DELETE FROM tasks
-        WHERE id = ?1;
+        INSERT INTO signing_keys (kid, algorithm, private_key, public_key, created_at)
+        VALUES (?1, ?2, ?3, ?4, ?5);
         "#,
     )
-    .bind(task_id)
-    .execute(pool)
+    .bind(&key.kid)
+    .bind(&key.algorithm)
+    .bind(&key.private_key)
+    .bind(&key.public_key)
+    .bind(key.created_at)
+    .execute(&mut *tx)
     .await
-    .map_err(|e| AppError::Internal(format!("delete task: {}", e)))?;
+    .map_err(|e| AppError::Internal(format!("insert signing key: {}", e)))?;
+    tx.commit()
+        .await
+        .map_err(|e| AppError::Internal(format!("commit signing key: {}", e)))?;
 
     Ok(())
 }
 
 // ---------------------------------------------------------------------------
-// Annotation CRUD
+// Single sign-on (OIDC)
 // ---------------------------------------------------------------------------
 
-pub async fn create_annotation(
+/// Remember a started OIDC login. Stale entries from abandoned logins are
+/// cleaned up on the way.
+pub async fn insert_oidc_state(
     pool: &SqlitePool,
-    doc_path: &str,
-    org_id: &str,
-    user_id: &str,
-    range_start: i64,
-    range_end: i64,
-    content: &str,
-) -> Result<String, AppError> {
-    let annotation_id = Uuid::new_v4().to_string();
+    state: &str,
+    code_verifier: &str,
+    nonce: &str,
+    ttl_secs: i64,
+) -> Result<(), AppError> {
     let now = Utc::now().timestamp();
+    sqlx::query("DELETE FROM oidc_states WHERE created_at <= ?1")
+        .bind(now - ttl_secs)
+        .execute(pool)
+        .await
+        .map_err(|e| AppError::Internal(format!("purge oidc states: {}", e)))?;
 
     sqlx::query(
         r#"
-        INSERT INTO annotations (id, doc_path, org_id, user_id, range_start, range_end, content, resolved, created_at)
-        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, 0, ?8);
+        INSERT INTO oidc_states (state, code_verifier, nonce, created_at)
+        VALUES (?1, ?2, ?3, ?4);
         "#,
     )
-    .bind(&annotation_id)
-    .bind(doc_path)
-    .bind(org_id)
-    .bind(user_id)
-    .bind(range_start)
-    .bind(range_end)
-    .bind(content)
+    .bind(state)
+    .bind(code_verifier)
+    .bind(nonce)
     .bind(now)
     .execute(pool)
     .await
-    .map_err(|e| AppError::Internal(format!("create annotation: {}", e)))?;
+    .map_err(|e| AppError::Internal(format!("insert oidc state: {}", e)))?;
 
-    Ok(annotation_id)
+    Ok(())
 }
 
-pub async fn list_annotations(
+/// Remove and return `(code_verifier, nonce)` for an unexpired login state.
+pub async fn take_oidc_state(
     pool: &SqlitePool,
-    doc_path: &str,
-    org_id: &str,
-) -> Result<Vec<AnnotationRow>, AppError> {
-    let rows = sqlx::query(
+    state: &str,
+    ttl_secs: i64,
+) -> Result<Option<(String, String)>, AppError> {
+    let now = Utc::now().timestamp();
+    let row = sqlx::query(
         r#"
-        SELECT id, doc_path, org_id, user_id, range_start, range_end, content, resolved, created_at
-        FROM annotations
-        WHERE doc_path = ?1 AND org_id = ?2
-        ORDER BY range_start ASC, created_at ASC;
+        DELETE FROM oidc_states
+        WHERE state = ?1 AND created_at > ?2
+        RETURNING code_verifier, nonce;
         "#,
     )
-    .bind(doc_path)
-    .bind(org_id)
-    .fetch_all(pool)
+    .bind(state)
+    .bind(now - ttl_secs)
+    .fetch_optional(pool)
     .await
-    .map_err(|e| AppError::Internal(format!("list annotations: {}", e)))?;
+    .map_err(|e| AppError::Internal(format!("take oidc state: {}", e)))?;
 
-    Ok(rows
-        .into_iter()
-        .map(|row| AnnotationRow {
-            id: row.get::<String, _>("id"),
-            doc_path: row.get::<String, _>("doc_path"),
-            org_id: row.get::<String, _>("org_id"),
-            user_id: row.get::<String, _>("user_id"),
-            range_start: row.get::<i64, _>("range_start"),
-            range_end: row.get::<i64, _>("range_end"),
-            content: row.get::<String, _>("content"),
-            resolved: row.get::<i32, _>("resolved") != 0,
-            created_at: row.get::<i64, _>("created_at"),
-        })
-        .collect())
+    Ok(row.map(|row| {
+        (
+            row.get::<String, _>("code_verifier"),
+            row.get::<String, _>("nonce"),
+        )
+    }))
 }
 
-pub async fn create_annotation_reply(
+/// User linked to an identity at `issuer`, recording the login time.
+pub async fn find_identity_user(
     pool: &SqlitePool,
-    annotation_id: &str,
-    user_id: &str,
-    content: &str,
-) -> Result<String, AppError> {
-    let reply_id = Uuid::new_v4().to_string();
+    issuer: &str,
+    subject: &str,
+) -> Result<Option<String>, AppError> {
     let now = Utc::now().timestamp();
-
-    sqlx::query(
+    let row = sqlx::query(
         r#"
-        INSERT INTO annotation_replies (id, annotation_id, user_id, content, created_at)
-        VALUES (?1, ?2, ?3, ?4, ?5);
+        UPDATE user_identities
+        SET last_login_at = ?1
+        WHERE issuer = ?2 AND subject = ?3
+        RETURNING user_id;
         "#,
     )
-    .bind(&reply_id)
-    .bind(annotation_id)
-    .bind(user_id)
-    .bind(content)
     .bind(now)
-    .execute(pool)
+    .bind(issuer)
+    .bind(subject)
+    .fetch_optional(pool)
     .await
-    .map_err(|e| AppError::Internal(format!("create annotation reply: {}", e)))?;
+    .map_err(|e| AppError::Internal(format!("find identity: {}", e)))?;
 
-    Ok(reply_id)
+    Ok(row.map(|row| row.get::<String, _>("user_id")))
 }
 
-pub async fn list_annotation_replies(
+pub async fn link_identity(
     pool: &SqlitePool,
-    annotation_id: &str,
-) -> Result<Vec<(String, String, String, i64)>, AppError> {
-    let rows = sqlx::query(
+    issuer: &str,
+    subject: &str,
+    user_id: &str,
+    email: Option<&str>,
+) -> Result<(), AppError> {
+    let now = Utc::now().timestamp();
+    sqlx::query(
         r#"
-        SELECT id, user_id, content, created_at
-        FROM annotation_replies
-        WHERE annotation_id = ?1
-        ORDER BY created_at ASC;
+        INSERT INTO user_identities (issuer, subject, user_id, email, created_at, last_login_at)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?5);
         "#,
     )
-    .bind(annotation_id)
-    .fetch_all(pool)
+    .bind(issuer)
+    .bind(subject)
+    .bind(user_id)
+    .bind(email)
+    .bind(now)
+    .execute(pool)
     .await
-    .map_err(|e| AppError::Internal(format!("list annotation replies: {}", e)))?;
+    .map_err(|e| AppError::Internal(format!("link identity: {}", e)))?;
 
-    Ok(rows
-        .into_iter()
-        .map(|row| {
-            (
-                row.get::<String, _>("id"),
-                row.get::<String, _>("user_id"),
-                row.get::<String, _>("content"),
-                row.get::<i64, _>("created_at"),
-            )
-        })
-        .collect())
+    Ok(())
 }
 
-pub async fn get_annotation(
-    pool: &SqlitePool,
-    annotation_id: &str,
-) -> Result<Option<AnnotationRow>, AppError> {
+pub async fn user_has_identity(pool: &SqlitePool, user_id: &str) -> Result<bool, AppError> {
     let row = sqlx::query(
         r#"
-        SELECT id, doc_path, org_id, user_id, range_start, range_end, content, resolved, created_at
-        FROM annotations
-        WHERE id = ?1;
+        SELECT 1
+        FROM user_identities
+        WHERE user_id = ?1
+        LIMIT 1;
         "#,
     )
-    .bind(annotation_id)
+    .bind(user_id)
     .fetch_optional(pool)
     .await
-    .map_err(|e| AppError::Internal(format!("get annotation: {}", e)))?;
+    .map_err(|e| AppError::Internal(format!("check identity: {}", e)))?;
 
-    Ok(row.map(|row| AnnotationRow {
-        id: row.get::<String, _>("id"),
-        doc_path: row.get::<String, _>("doc_path"),
-        org_id: row.get::<String, _>("org_id"),
-        user_id: row.get::<String, _>("user_id"),
-        range_start: row.get::<i64, _>("range_start"),
-        range_end: row.get::<i64, _>("range_end"),
-        content: row.get::<String, _>("content"),
-        resolved: row.get::<i32, _>("resolved") != 0,
-        created_at: row.get::<i64, _>("created_at"),
-    }))
+    Ok(row.is_some())
 }
 
-pub async fn resolve_annotation(pool: &SqlitePool, annotation_id: &str) -> Result<(), AppError> {
-    sqlx::query(
+// ---------------------------------------------------------------------------
+// Two-factor authentication
+// ---------------------------------------------------------------------------
+
+pub struct TotpState {
+    pub secret: Option<String>,
+    pub enabled: bool,
+}
+
+pub async fn get_totp_state(pool: &SqlitePool, user_id: &str) -> Result<TotpState, AppError> {
+    let row = sqlx::query(
         r#"
-        UPDATE annotations
-        SET resolved = 1
+        SELECT totp_secret, totp_enabled
+        FROM users
         WHERE id = ?1;
         "#,
     )
-    .bind(annotation_id)
-    .execute(pool)
+    .bind(user_id)
+    .fetch_optional(pool)
     .await
-    .map_err(|e| AppError::Internal(format!("resolve annotation: {}", e)))?;
+    .map_err(|e| AppError::Internal(format!("get totp state: {}", e)))?
+    .ok_or(AppError::NotFound)?;
 
-    Ok(())
+    Ok(TotpState {
+        secret: row.get::<Option<String>, _>("totp_secret"),
+        enabled: row.get::<i32, _>("totp_enabled") != 0,
+    })
 }
 
-// ---------------------------------------------------------------------------
-// Notification CRUD
-// ---------------------------------------------------------------------------
-
-pub async fn create_notification(
+/// Store a new, not yet confirmed secret. Enrollment only takes effect once
+/// [`enable_totp`] is called after the user proves they can generate codes.
+pub async fn set_pending_totp_secret(
     pool: &SqlitePool,
     user_id: &str,
-    org_id: &str,
-    ntype: &str,
-    title: &str,
-    body: &str,
-    ref_id: &str,
-) -> Result<String, AppError> {
-    let notification_id = Uuid::new_v4().to_string();
-    let now = Utc::now().timestamp();
-
+    secret: &str,
+) -> Result<(), AppError> {
     sqlx::query(
         r#"
-        INSERT INTO notifications (id, user_id, org_id, type, title, body, ref_id, read, created_at)
-        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, 0, ?8);
+        UPDATE users
+        SET totp_secret = ?1, totp_enabled = 0, totp_last_step = NULL
+        WHERE id = ?2 AND totp_enabled = 0;
         "#,
     )
-    .bind(&notification_id)
+    .bind(secret)
     .bind(user_id)
-    .bind(org_id)
-    .bind(ntype)
-    .bind(title)
-    .bind(body)
-    .bind(ref_id)
-    .bind(now)
     .execute(pool)
     .await
-    .map_err(|e| AppError::Internal(format!("create notification: {}", e)))?;
+    .map_err(|e| AppError::Internal(format!("set totp secret: {}", e)))?;
 
-    Ok(notification_id)
+    Ok(())
 }
 
-pub async fn list_notifications(
-    pool: &SqlitePool,
-    user_id: &str,
-    limit: i64,
-) -> Result<Vec<NotificationRow>, AppError> {
-    let rows = sqlx::query(
+pub async fn enable_totp(pool: &SqlitePool, user_id: &str, step: i64) -> Result<(), AppError> {
+    sqlx::query(
         r#"
-        SELECT id, user_id, org_id, type, title, body, ref_id, read, created_at
-        FROM notifications
-        WHERE user_id = ?1
-        ORDER BY created_at DESC
-        LIMIT ?2;
+        UPDATE users
+        SET totp_enabled = 1, totp_last_step = ?1
+        WHERE id = ?2 AND totp_secret IS NOT NULL;
         "#,
     )
+    .bind(step)
     .bind(user_id)
-    .bind(limit)
-    .fetch_all(pool)
+    .execute(pool)
     .await
-    .map_err(|e| AppError::Internal(format!("list notifications: {}", e)))?;
+    .map_err(|e| AppError::Internal(format!("enable totp: {}", e)))?;
 
-    Ok(rows
-        .into_iter()
-        .map(|row| NotificationRow {
-            id: row.get::<String, _>("id"),
-            user_id: row.get::<String, _>("user_id"),
-            org_id: row.get::<String, _>("org_id"),
-            ntype: row.get::<String, _>("type"),
-            title: row.get::<String, _>("title"),
-            body: row.get::<String, _>("body"),
-            ref_id: row.get::<String, _>("ref_id"),
-            read: row.get::<i32, _>("read") != 0,
-            created_at: row.get::<i64, _>("created_at"),
-        })
-        .collect())
+    Ok(())
 }
 
-pub async fn mark_notification_read(
-    pool: &SqlitePool,
-    notification_id: &str,
-    user_id: &str,
-) -> Result<(), AppError> {
+pub async fn disable_totp(pool: &SqlitePool, user_id: &str) -> Result<(), AppError> {
     sqlx::query(
         r#"
-        UPDATE notifications
-        SET read = 1
-        WHERE id = ?1 AND user_id = ?2;
+        UPDATE users
+        SET totp_secret = NULL, totp_enabled = 0, totp_last_step = NULL
+        WHERE id = ?1;
         "#,
     )
-    .bind(notification_id)
     .bind(user_id)
     .execute(pool)
     .await
-    .map_err(|e| AppError::Internal(format!("mark notification read: {}", e)))?;
+    .map_err(|e| AppError::Internal(format!("disable totp: {}", e)))?;
+
+    sqlx::query("DELETE FROM recovery_codes WHERE user_id = ?1")
+        .bind(user_id)
+        .execute(pool)
+        .await
+        .map_err(|e| AppError::Internal(format!("delete recovery codes: {}", e)))?;
 
     Ok(())
 }
 
-pub async fn mark_all_notifications_read(pool: &SqlitePool, user_id: &str) -> Result<(), AppError> {
-    sqlx::query(
+/// Record the time step of an accepted code. Returns `false` when the step
+/// was already used, i.e. the code is being replayed.
+pub async fn consume_totp_step(
+    pool: &SqlitePool,
+    user_id: &str,
+    step: i64,
+) -> Result<bool, AppError> {
+    let result = sqlx::query(
         r#"
-        UPDATE notifications
-        SET read = 1
-        WHERE user_id = ?1 AND read = 0;
+        UPDATE users
+        SET totp_last_step = ?1
+        WHERE id = ?2 AND (totp_last_step IS NULL OR totp_last_step < ?1);
         "#,
     )
+    .bind(step)
     .bind(user_id)
     .execute(pool)
     .await
-    .map_err(|e| AppError::Internal(format!("mark all notifications read: {}", e)))?;
+    .map_err(|e| AppError::Internal(format!("consume totp step: {}", e)))?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Replace all recovery codes of a user with freshly hashed ones.
+pub async fn replace_recovery_codes(
+    pool: &SqlitePool,
+    user_id: &str,
+    code_hashes: &[String],
+) -> Result<(), AppError> {
+    let now = Utc::now().timestamp();
+    let mut tx = pool
+        .begin()
+        .await
+        .map_err(|e| AppError::Internal(format!("begin recovery codes tx: {}", e)))?;
+    sqlx::query("DELETE FROM recovery_codes WHERE user_id = ?1")
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| AppError::Internal(format!("delete recovery codes: {}", e)))?;
+    for hash in code_hashes {
+        sqlx::query(
+            r#"
+            INSERT INTO recovery_codes (id, user_id, code_hash, created_at)
+            VALUES (?1, ?2, ?3, ?4);
+            "#,
+        )
+        .bind(Uuid::new_v4().to_string())
+        .bind(user_id)
+        .bind(hash)
+        .bind(now)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| AppError::Internal(format!("insert recovery code: {}", e)))?;
+    }
+    tx.commit()
+        .await
+        .map_err(|e| AppError::Internal(format!("commit recovery codes: {}", e)))?;
 
     Ok(())
 }
 
-pub async fn count_unread_notifications(pool: &SqlitePool, user_id: &str) -> Result<i64, AppError> {
-    let row = sqlx::query(
+/// Unused recovery codes as `(id, code_hash)`.
+pub async fn list_unused_recovery_codes(
+    pool: &SqlitePool,
+    user_id: &str,
+) -> Result<Vec<(String, String)>, AppError> {
+    let rows = sqlx::query(
         r#"
-        SELECT COUNT(*) as cnt
-        FROM notifications
-        WHERE user_id = ?1 AND read = 0;
+        SELECT id, code_hash
+        FROM recovery_codes
+        WHERE user_id = ?1 AND used_at IS NULL;
         "#,
     )
     .bind(user_id)
-    .fetch_one(pool)
+    .fetch_all(pool)
     .await
-    .map_err(|e| AppError::Internal(format!("count unread notifications: {}", e)))?;
+    .map_err(|e| AppError::Internal(format!("list recovery codes: {}", e)))?;
 
-    Ok(row.get::<i64, _>("cnt"))
+    Ok(rows
+        .into_iter()
+        .map(|row| {
+            (
+                row.get::<String, _>("id"),
+                row.get::<String, _>("code_hash"),
+            )
+        })
+        .collect())
+}
+
+/// Mark a recovery code as used. Returns `false` if it was already used.
+pub async fn use_recovery_code(pool: &SqlitePool, code_id: &str) -> Result<bool, AppError> {
+    let now = Utc::now().timestamp();
+    let result = sqlx::query(
+        r#"
+        UPDATE recovery_codes
+        SET used_at = ?1
+        WHERE id = ?2 AND used_at IS NULL;
+        "#,
+    )
+    .bind(now)
+    .bind(code_id)
+    .execute(pool)
+    .await
+    .map_err(|e| AppError::Internal(format!("use recovery code: {}", e)))?;
+
+    Ok(result.rows_affected() > 0)
 }
 
 // ---------------------------------------------------------------------------
-// Document Registry
+// Sessions & refresh tokens
 // ---------------------------------------------------------------------------
 
-/// Return existing doc_id for (project_id, rel_path), or create a new one.
-pub async fn resolve_or_create_doc(
-    pool: &SqlitePool,
-    project_id: &str,
-    rel_path: &str,
-    user_id: &str,
-) -> Result<String, AppError> {
-    // Fast path: lookup existing
-    let existing =
-        sqlx::query("SELECT id FROM document_registry WHERE project_id = ?1 AND rel_path = ?2")
-            .bind(project_id)
-            .bind(rel_path)
-            .fetch_optional(pool)
-            .await
-            .map_err(|e| AppError::Internal(format!("lookup document_registry: {}", e)))?;
+pub struct SessionRow {
+    pub id: String,
+    pub user_id: String,
+    pub device_name: String,
+    pub ip: Option<String>,
+    pub created_at: i64,
+    pub last_used_at: i64,
+    pub expires_at: i64,
+    pub revoked_at: Option<i64>,
+}
 
-    if let Some(row) = existing {
-        return Ok(row.get::<String, _>("id"));
+fn session_from_row(row: &sqlx::sqlite::SqliteRow) -> SessionRow {
+    SessionRow {
+        id: row.get::<String, _>("id"),
+        user_id: row.get::<String, _>("user_id"),
+        device_name: row.get::<String, _>("device_name"),
+        ip: row.get::<Option<String>, _>("ip"),
+        created_at: row.get::<i64, _>("created_at"),
+        last_used_at: row.get::<i64, _>("last_used_at"),
+        expires_at: row.get::<i64, _>("expires_at"),
+        revoked_at: row.get::<Option<i64>, _>("revoked_at"),
     }
+}
 
-    // Insert new
-    let doc_id = Uuid::new_v4().to_string();
+pub async fn create_session(
+    pool: &SqlitePool,
+    user_id: &str,
+    device_name: &str,
+    ip: Option<&str>,
+    expires_at: i64,
+) -> Result<String, AppError> {
+    let session_id = Uuid::new_v4().to_string();
     let now = Utc::now().timestamp();
-
-    let result = sqlx::query(
+    sqlx::query(
         r#"
-        INSERT INTO document_registry (id, project_id, rel_path, created_by, created_at)
-        VALUES (?1, ?2, ?3, ?4, ?5)
+        INSERT INTO sessions (id, user_id, device_name, ip, created_at, last_used_at, expires_at)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?5, ?6);
         "#,
     )
-    .bind(&doc_id)
-    .bind(project_id)
-    .bind(rel_path)
+    .bind(&session_id)
     .bind(user_id)
+    .bind(device_name)
+    .bind(ip)
     .bind(now)
+    .bind(expires_at)
     .execute(pool)
-    .await;
+    .await
+    .map_err(|e| AppError::Internal(format!("create session: {}", e)))?;
 
-    match result {
-        Ok(_) => Ok(doc_id),
-        Err(err) => {
-            // Race condition: another request inserted between our SELECT and INSERT.
-            // Re-query to get the winner's id.
-            if err.to_string().contains("UNIQUE") {
-                let row = sqlx::query(
-                    "SELECT id FROM document_registry WHERE project_id = ?1 AND rel_path = ?2",
-                )
-                .bind(project_id)
-                .bind(rel_path)
-                .fetch_one(pool)
-                .await
-                .map_err(|e| AppError::Internal(format!("re-lookup document_registry: {}", e)))?;
-                Ok(row.get::<String, _>("id"))
-            } else {
-                Err(AppError::Internal(format!(
-                    "insert document_registry: {}",
-                    err
-                )))
-            }
-        }
-    }
+    Ok(session_id)
 }
 
-// ---------------------------------------------------------------------------
-// Published Sites CRUD
-// ---------------------------------------------------------------------------
+pub async fn get_session(
+    pool: &SqlitePool,
+    session_id: &str,
+) -> Result<Option<SessionRow>, AppError> {
+    let row = sqlx::query(
+        r#"
+        SELECT id, user_id, device_name, ip, created_at, last_used_at, expires_at, revoked_at
+        FROM sessions
+        WHERE id = ?1;
+        "#,
+    )
+    .bind(session_id)
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| AppError::Internal(format!("get session: {}", e)))?;
 
-pub async fn upsert_published_site(
+    Ok(row.as_ref().map(session_from_row))
+}
+
+/// Active (unrevoked, unexpired) sessions of a user, most recently used first.
+pub async fn list_active_sessions(
     pool: &SqlitePool,
     user_id: &str,
-    site_url: &str,
+) -> Result<Vec<SessionRow>, AppError> {
+    let now = Utc::now().timestamp();
+    let rows = sqlx::query(
+        r#"
+        SELECT id, user_id, device_name, ip, created_at, last_used_at, expires_at, revoked_at
+        FROM sessions
+        WHERE user_id = ?1 AND revoked_at IS NULL AND expires_at > ?2
+        ORDER BY last_used_at DESC;
+        "#,
+    )
+    .bind(user_id)
+    .bind(now)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| AppError::Internal(format!("list sessions: {}", e)))?;
+
+    Ok(rows.iter().map(session_from_row).collect())
+}
+
+/// Record a refresh: bump last use, remember the IP and slide the expiry.
+pub async fn touch_session(
+    pool: &SqlitePool,
+    session_id: &str,
+    ip: Option<&str>,
+    expires_at: i64,
 ) -> Result<(), AppError> {
-    let now = chrono::Utc::now().timestamp();
+    let now = Utc::now().timestamp();
     sqlx::query(
-        "INSERT INTO published_sites (user_id, site_url, published_at, updated_at)
-         VALUES (?, ?, ?, ?)
-         ON CONFLICT(user_id) DO UPDATE SET site_url = ?, updated_at = ?",
+        r#"
+        UPDATE sessions
+        SET last_used_at = ?1, ip = COALESCE(?2, ip), expires_at = ?3
+        WHERE id = ?4;
+        "#,
     )
-    .bind(user_id)
-    .bind(site_url)
     .bind(now)
+    .bind(ip)
+    .bind(expires_at)
+    .bind(session_id)
+    .execute(pool)
+    .await
+    .map_err(|e| AppError::Internal(format!("touch session: {}", e)))?;
+
+    Ok(())
+}
+
+/// Record that an access token of the session was used.
+pub async fn mark_session_used(
+    pool: &SqlitePool,
+    session_id: &str,
+    now: i64,
+) -> Result<(), AppError> {
+    sqlx::query("UPDATE sessions SET last_used_at = ?1 WHERE id = ?2 AND last_used_at < ?1")
+        .bind(now)
+        .bind(session_id)
+        .execute(pool)
+        .await
+        .map_err(|e| AppError::Internal(format!("mark session used: {}", e)))?;
+
+    Ok(())
+}
+
+/// Delete the refresh tokens of sessions that were revoked or expired by
+/// `now`, and the sessions themselves once they ended before `cutoff`.
+/// Returns how many sessions were deleted.
+pub async fn purge_ended_sessions(
+    pool: &SqlitePool,
+    now: i64,
+    cutoff: i64,
+) -> Result<u64, AppError> {
+    let mut tx = pool
+        .begin()
+        .await
+        .map_err(|e| AppError::Internal(format!("begin purge sessions tx: {}", e)))?;
+    sqlx::query(
+        r#"
+        DELETE FROM refresh_tokens
+        WHERE session_id NOT IN (
+            SELECT id FROM sessions WHERE revoked_at IS NULL AND expires_at > ?1
+        );
+        "#,
+    )
     .bind(now)
-    .bind(site_url)
+    .execute(&mut *tx)
+    .await
+    .map_err(|e| AppError::Internal(format!("purge refresh tokens: {}", e)))?;
+    let deleted = sqlx::query(
+        r#"
+        DELETE FROM sessions
+        WHERE revoked_at <= ?1 OR expires_at <= ?1;
+        "#,
+    )
+    .bind(cutoff)
+    .execute(&mut *tx)
+    .await
+    .map_err(|e| AppError::Internal(format!("purge sessions: {}", e)))?
+    .rows_affected();
+    tx.commit()
+        .await
+        .map_err(|e| AppError::Internal(format!("commit purge sessions: {}", e)))?;
+
+    Ok(deleted)
+}
+
+pub async fn revoke_session(pool: &SqlitePool, session_id: &str) -> Result<(), AppError> {
+    let now = Utc::now().timestamp();
+    sqlx::query(
+        r#"
+        UPDATE sessions
+        SET revoked_at = COALESCE(revoked_at, ?1)
+        WHERE id = ?2;
+        "#,
+    )
     .bind(now)
+    .bind(session_id)
     .execute(pool)
     .await
-    .map_err(|e| AppError::Internal(format!("upsert published site: {}", e)))?;
+    .map_err(|e| AppError::Internal(format!("revoke session: {}", e)))?;
+
     Ok(())
 }
 
-pub async fn get_published_site(
+/// Revoke every session of `user_id`, optionally keeping `except_session`.
+pub async fn revoke_user_sessions(
     pool: &SqlitePool,
     user_id: &str,
-) -> Result<Option<(String, i64, i64)>, AppError> {
-    let row: Option<(String, i64, i64)> = sqlx::query_as(
-        "SELECT site_url, published_at, updated_at FROM published_sites WHERE user_id = ?",
+    except_session: Option<&str>,
+) -> Result<(), AppError> {
+    let now = Utc::now().timestamp();
+    sqlx::query(
+        r#"
+        UPDATE sessions
+        SET revoked_at = ?1
+        WHERE user_id = ?2 AND revoked_at IS NULL AND id IS NOT ?3;
+        "#,
     )
+    .bind(now)
     .bind(user_id)
-    .fetch_optional(pool)
+    .bind(except_session)
+    .execute(pool)
     .await
-    .map_err(|e| AppError::Internal(format!("get published site: {}", e)))?;
-    Ok(row)
+    .map_err(|e| AppError::Internal(format!("revoke user sessions: {}", e)))?;
+
+    Ok(())
 }
 
-pub async fn delete_published_site(pool: &SqlitePool, user_id: &str) -> Result<(), AppError> {
-    sqlx::query("DELETE FROM published_sites WHERE user_id = ?")
-        .bind(user_id)
-        .execute(pool)
-        .await
-        .map_err(|e| AppError::Internal(format!("delete published site: {}", e)))?;
+pub async fn insert_refresh_token(
+    pool: &SqlitePool,
+    token_hash: &str,
+    session_id: &str,
+) -> Result<(), AppError> {
+    let now = Utc::now().timestamp();
+    sqlx::query(
+        r#"
+        INSERT INTO refresh_tokens (token_hash, session_id, created_at)
+        VALUES (?1, ?2, ?3);
+        "#,
+    )
+    .bind(token_hash)
+    .bind(session_id)
+    .bind(now)
+    .execute(pool)
+    .await
+    .map_err(|e| AppError::Internal(format!("insert refresh token: {}", e)))?;
+
     Ok(())
 }
+
+/// Look up a refresh token by hash, returning `(session_id, used_at)`.
+pub async fn get_refresh_token(
+    pool: &SqlitePool,
+    token_hash: &str,
+) -> Result<Option<(String, Option<i64>)>, AppError> {
+    let row = sqlx::query(
+        r#"
+        SELECT session_id, used_at
+        FROM refresh_tokens
+        WHERE token_hash = ?1;
+        "#,
+    )
+    .bind(token_hash)
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| AppError::Internal(format!("get refresh token: {}", e)))?;
+
+    Ok(row.map(|row| {
+        (
+            row.get::<String, _>("session_id"),
+            row.get::<Option<i64>, _>("used_at"),
+        )
+    }))
+}
+
+/// Mark a refresh token as consumed. Returns `false` if another request
+/// consumed it first.
+pub async fn mark_refresh_token_used(
+    pool: &SqlitePool,
+    token_hash: &str,
+) -> Result<bool, AppError> {
+    let now = Utc::now().timestamp();
+    let result = sqlx::query(
+        r#"
+        UPDATE refresh_tokens
+        SET used_at = ?1
+        WHERE token_hash = ?2 AND used_at IS NULL;
+        "#,
+    )
+    .bind(now)
+    .bind(token_hash)
+    .execute(pool)
+    .await
+    .map_err(|e| AppError::Internal(format!("mark refresh token used: {}", e)))?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+// ---------------------------------------------------------------------------
+// Organization CRUD
+// ---------------------------------------------------------------------------
+
+pub async fn create_organization(
+    pool: &SqlitePool,
+    owner_id: &str,
+    name: &str,
+) -> Result<String, AppError> {
+    let org_id = Uuid::new_v4().to_string();
+    let now = Utc::now().timestamp();
+
+    sqlx::query(
+        r#"
+        INSERT INTO organizations (id, name, owner_id, created_at)
+        VALUES (?1, ?2, ?3, ?4);
+        "#,
+    )
+    .bind(&org_id)
+    .bind(name)
+    .bind(owner_id)
+    .bind(now)
+    .execute(pool)
+    .await
+    .map_err(|e| AppError::Internal(format!("create organization: {}", e)))?;
+
+    sqlx::query(
+        r#"
+        INSERT INTO org_members (org_id, user_id, role, joined_at)
+        VALUES (?1, ?2, 'admin', ?3);
+        "#,
+    )
+    .bind(&org_id)
+    .bind(owner_id)
+    .bind(now)
+    .execute(pool)
+    .await
+    .map_err(|e| AppError::Internal(format!("insert org owner member: {}", e)))?;
+
+    Ok(org_id)
+}
+
+pub async fn list_user_organizations(
+    pool: &SqlitePool,
+    user_id: &str,
+) -> Result<Vec<(String, String, String)>, AppError> {
+    let rows = sqlx::query(
+        r#"
+        SELECT o.id, o.name, m.role
+        FROM organizations o
+        JOIN org_members m
+          ON o.id = m.org_id
+        WHERE m.user_id = ?1 AND o.deleted_at IS NULL
+        ORDER BY o.created_at DESC;
+        "#,
+    )
+    .bind(user_id)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| AppError::Internal(format!("list user organizations: {}", e)))?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| {
+            (
+                row.get::<String, _>("id"),
+                row.get::<String, _>("name"),
+                row.get::<String, _>("role"),
+            )
+        })
+        .collect())
+}
+
+pub async fn get_organization(
+    pool: &SqlitePool,
+    org_id: &str,
+) -> Result<Option<(String, String, String)>, AppError> {
+    let row = sqlx::query(
+        r#"
+        SELECT id, name, owner_id
+        FROM organizations
+        WHERE id = ?1 AND deleted_at IS NULL;
+        "#,
+    )
+    .bind(org_id)
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| AppError::Internal(format!("get organization: {}", e)))?;
+
+    Ok(row.map(|row| {
+        (
+            row.get::<String, _>("id"),
+            row.get::<String, _>("name"),
+            row.get::<String, _>("owner_id"),
+        )
+    }))
+}
+
+pub async fn update_organization_name(
+    pool: &SqlitePool,
+    org_id: &str,
+    name: &str,
+) -> Result<(), AppError> {
+    sqlx::query(
+        r#"
+        UPDATE organizations
+        SET name = ?1
+        WHERE id = ?2;
+        "#,
+    )
+    .bind(name)
+    .bind(org_id)
+    .execute(pool)
+    .await
+    .map_err(|e| AppError::Internal(format!("update organization name: {}", e)))?;
+
+    Ok(())
+}
+
+pub async fn add_org_member(
+    pool: &SqlitePool,
+    org_id: &str,
+    user_id: &str,
+    role: &str,
+) -> Result<(), AppError> {
+    let now = Utc::now().timestamp();
+
+    let result = sqlx::query(
+        r#"
+        INSERT INTO org_members (org_id, user_id, role, joined_at)
+        VALUES (?1, ?2, ?3, ?4);
+        "#,
+    )
+    .bind(org_id)
+    .bind(user_id)
+    .bind(role)
+    .bind(now)
+    .execute(pool)
+    .await;
+
+    if let Err(err) = result {
+        let message = err.to_string();
+        if message.contains("UNIQUE") {
+            return Err(AppError::Conflict("member already exists".to_string()));
+        }
+        return Err(AppError::Internal(format!("add org member: {}", err)));
+    }
+
+    Ok(())
+}
+
+/// Check that changing `user_id`'s membership to `new_role` (`None` to
+/// remove them) keeps the organization's invariants: the owner stays an
+/// admin and there is always at least one admin. Returns the current role.
+async fn check_member_change(
+    conn: &mut sqlx::SqliteConnection,
+    org_id: &str,
+    user_id: &str,
+    new_role: Option<&str>,
+) -> Result<String, AppError> {
+    let row = sqlx::query(
+        r#"
+        SELECT m.role, o.owner_id,
+               (SELECT COUNT(*) FROM org_members a
+                WHERE a.org_id = m.org_id AND a.role = 'admin') AS admin_count
+        FROM org_members m
+        JOIN organizations o ON o.id = m.org_id
+        WHERE m.org_id = ?1 AND m.user_id = ?2;
+        "#,
+    )
+    .bind(org_id)
+    .bind(user_id)
+    .fetch_optional(&mut *conn)
+    .await
+    .map_err(|e| AppError::Internal(format!("get org membership: {}", e)))?
+    .ok_or(AppError::NotFound)?;
+    let role = row.get::<String, _>("role");
+    if new_role == Some(role.as_str()) {
+        return Ok(role);
+    }
+    if row.get::<String, _>("owner_id") == user_id {
+        return Err(AppError::Conflict(
+            "the owner must stay an admin; transfer ownership first".to_string(),
+        ));
+    }
+    if role == "admin" && row.get::<i64, _>("admin_count") <= 1 {
+        return Err(AppError::Conflict(
+            "an organization needs at least one admin".to_string(),
+        ));
+    }
+    Ok(role)
+}
+
+/// Remove a member, along with their roles in the org's projects. Refuses
+/// to remove the owner or the last admin.
+pub async fn remove_org_member(
+    pool: &SqlitePool,
+    org_id: &str,
+    user_id: &str,
+) -> Result<(), AppError> {
+    let mut tx = pool
+        .begin()
+        .await
+        .map_err(|e| AppError::Internal(format!("begin remove member tx: {}", e)))?;
+    check_member_change(&mut tx, org_id, user_id, None).await?;
+    sqlx::query(
+        r#"
+        DELETE FROM org_members
+        WHERE org_id = ?1 AND user_id = ?2;
+        "#,
+    )
+    .bind(org_id)
+    .bind(user_id)
+    .execute(&mut *tx)
+    .await
+    .map_err(|e| AppError::Internal(format!("remove org member: {}", e)))?;
+    sqlx::query(
+        r#"
+        DELETE FROM project_members
+        WHERE user_id = ?2 AND project_id IN (SELECT id FROM projects WHERE org_id = ?1);
+        "#,
+    )
+    .bind(org_id)
+    .bind(user_id)
+    .execute(&mut *tx)
+    .await
+    .map_err(|e| AppError::Internal(format!("remove project memberships: {}", e)))?;
+    tx.commit()
+        .await
+        .map_err(|e| AppError::Internal(format!("commit remove member: {}", e)))?;
+
+    Ok(())
+}
+
+/// Change a member's role and return the previous one. Refuses to demote
+/// the owner or the last admin.
+pub async fn update_org_member_role(
+    pool: &SqlitePool,
+    org_id: &str,
+    user_id: &str,
+    role: &str,
+) -> Result<String, AppError> {
+    let mut tx = pool
+        .begin()
+        .await
+        .map_err(|e| AppError::Internal(format!("begin role change tx: {}", e)))?;
+    let previous = check_member_change(&mut tx, org_id, user_id, Some(role)).await?;
+    sqlx::query("UPDATE org_members SET role = ?1 WHERE org_id = ?2 AND user_id = ?3")
+        .bind(role)
+        .bind(org_id)
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| AppError::Internal(format!("update org member role: {}", e)))?;
+    tx.commit()
+        .await
+        .map_err(|e| AppError::Internal(format!("commit role change: {}", e)))?;
+
+    Ok(previous)
+}
+
+/// Make `new_owner_id`, who must already be a member, the owner and an
+/// admin. The previous owner stays an admin.
+pub async fn transfer_org_ownership(
+    pool: &SqlitePool,
+    org_id: &str,
+    new_owner_id: &str,
+) -> Result<(), AppError> {
+    let mut tx = pool
+        .begin()
+        .await
+        .map_err(|e| AppError::Internal(format!("begin ownership transfer tx: {}", e)))?;
+    let promoted =
+        sqlx::query("UPDATE org_members SET role = 'admin' WHERE org_id = ?1 AND user_id = ?2")
+            .bind(org_id)
+            .bind(new_owner_id)
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| AppError::Internal(format!("promote new owner: {}", e)))?;
+    if promoted.rows_affected() == 0 {
+        return Err(AppError::BadRequest(
+            "the new owner must be a member".to_string(),
+        ));
+    }
+    sqlx::query("UPDATE organizations SET owner_id = ?1 WHERE id = ?2")
+        .bind(new_owner_id)
+        .bind(org_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| AppError::Internal(format!("transfer organization: {}", e)))?;
+    tx.commit()
+        .await
+        .map_err(|e| AppError::Internal(format!("commit ownership transfer: {}", e)))?;
+
+    Ok(())
+}
+
+pub async fn list_org_members(
+    pool: &SqlitePool,
+    org_id: &str,
+) -> Result<Vec<(String, String, String)>, AppError> {
+    let rows = sqlx::query(
+        r#"
+        SELECT m.user_id, u.email, m.role
+        FROM org_members m
+        JOIN users u
+          ON m.user_id = u.id
+        WHERE m.org_id = ?1
+        ORDER BY m.joined_at ASC;
+        "#,
+    )
+    .bind(org_id)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| AppError::Internal(format!("list org members: {}", e)))?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| {
+            (
+                row.get::<String, _>("user_id"),
+                row.get::<String, _>("email"),
+                row.get::<String, _>("role"),
+            )
+        })
+        .collect())
+}
+
+pub async fn get_org_member_role(
+    pool: &SqlitePool,
+    org_id: &str,
+    user_id: &str,
+) -> Result<Option<String>, AppError> {
+    let row = sqlx::query(
+        r#"
+        SELECT m.role
+        FROM org_members m
+        JOIN organizations o ON o.id = m.org_id
+        WHERE m.org_id = ?1 AND m.user_id = ?2 AND o.deleted_at IS NULL;
+        "#,
+    )
+    .bind(org_id)
+    .bind(user_id)
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| AppError::Internal(format!("get org member role: {}", e)))?;
+
+    Ok(row.map(|row| row.get::<String, _>("role")))
+}
+
+// ---------------------------------------------------------------------------
+// Org deletion
+// ---------------------------------------------------------------------------
+
+/// Mark an organization as deleted. It disappears for its members at once
+/// and is purged after the grace period unless restored. Returns whether
+/// the organization existed and was not already deleted.
+pub async fn soft_delete_org(
+    pool: &SqlitePool,
+    org_id: &str,
+    deleted_by: &str,
+) -> Result<bool, AppError> {
+    let result = sqlx::query(
+        "UPDATE organizations SET deleted_at = ?1, deleted_by = ?2
+         WHERE id = ?3 AND deleted_at IS NULL",
+    )
+    .bind(Utc::now().timestamp())
+    .bind(deleted_by)
+    .bind(org_id)
+    .execute(pool)
+    .await
+    .map_err(|e| AppError::Internal(format!("soft delete organization: {}", e)))?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+pub async fn restore_org(pool: &SqlitePool, org_id: &str) -> Result<bool, AppError> {
+    let result = sqlx::query(
+        "UPDATE organizations SET deleted_at = NULL, deleted_by = NULL
+         WHERE id = ?1 AND deleted_at IS NOT NULL",
+    )
+    .bind(org_id)
+    .execute(pool)
+    .await
+    .map_err(|e| AppError::Internal(format!("restore organization: {}", e)))?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// The caller's role in an organization that is pending deletion.
+pub async fn deleted_org_role(
+    pool: &SqlitePool,
+    org_id: &str,
+    user_id: &str,
+) -> Result<Option<String>, AppError> {
+    sqlx::query_scalar(
+        r#"
+        SELECT m.role
+        FROM org_members m
+        JOIN organizations o ON o.id = m.org_id
+        WHERE m.org_id = ?1 AND m.user_id = ?2 AND o.deleted_at IS NOT NULL;
+        "#,
+    )
+    .bind(org_id)
+    .bind(user_id)
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| AppError::Internal(format!("get deleted org role: {}", e)))
+}
+
+/// Organizations pending deletion that `user_id` administers, as
+/// `(id, name, deleted_at)`, most recently deleted first.
+pub async fn list_deleted_orgs_for_admin(
+    pool: &SqlitePool,
+    user_id: &str,
+) -> Result<Vec<(String, String, i64)>, AppError> {
+    let rows = sqlx::query(
+        r#"
+        SELECT o.id, o.name, o.deleted_at
+        FROM organizations o
+        JOIN org_members m ON o.id = m.org_id
+        WHERE m.user_id = ?1 AND m.role = 'admin' AND o.deleted_at IS NOT NULL
+        ORDER BY o.deleted_at DESC;
+        "#,
+    )
+    .bind(user_id)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| AppError::Internal(format!("list deleted organizations: {}", e)))?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| {
+            (
+                row.get::<String, _>("id"),
+                row.get::<String, _>("name"),
+                row.get::<i64, _>("deleted_at"),
+            )
+        })
+        .collect())
+}
+
+/// Organizations deleted at or before `cutoff`.
+pub async fn orgs_due_for_purge(pool: &SqlitePool, cutoff: i64) -> Result<Vec<String>, AppError> {
+    sqlx::query_scalar("SELECT id FROM organizations WHERE deleted_at <= ?1")
+        .bind(cutoff)
+        .fetch_all(pool)
+        .await
+        .map_err(|e| AppError::Internal(format!("list organizations to purge: {}", e)))
+}
+
+/// Purge an organization in one transaction and return the ids of its
+/// collaborative documents, whose state files are the caller's to remove.
+pub async fn purge_deleted_org(pool: &SqlitePool, org_id: &str) -> Result<Vec<String>, AppError> {
+    let mut tx = pool
+        .begin()
+        .await
+        .map_err(|e| AppError::Internal(format!("begin org purge tx: {}", e)))?;
+    let doc_ids = purge_org(&mut tx, org_id).await?;
+    tx.commit()
+        .await
+        .map_err(|e| AppError::Internal(format!("commit org purge: {}", e)))?;
+    Ok(doc_ids)
+}
+
+// ---------------------------------------------------------------------------
+// Org invitations
+// ---------------------------------------------------------------------------
+
+/// A pending or settled invitation. `email` is `None` for a shareable join
+/// link, which any signed-in user can use until it expires or is revoked.
+pub struct OrgInvitationRow {
+    pub id: String,
+    pub org_id: String,
+    pub org_name: String,
+    pub email: Option<String>,
+    pub role: String,
+    pub invited_by: String,
+    pub created_at: i64,
+    pub expires_at: i64,
+    pub accepted_at: Option<i64>,
+    pub accepted_by: Option<String>,
+    pub declined_at: Option<i64>,
+    pub revoked_at: Option<i64>,
+}
+
+impl OrgInvitationRow {
+    /// `pending`, `accepted`, `declined`, `revoked` or `expired`.
+    pub fn status(&self, now: i64) -> &'static str {
+        if self.revoked_at.is_some() {
+            "revoked"
+        } else if self.accepted_at.is_some() {
+            "accepted"
+        } else if self.declined_at.is_some() {
+            "declined"
+        } else if self.expires_at <= now {
+            "expired"
+        } else {
+            "pending"
+        }
+    }
+}
+
+const ORG_INVITATION_COLUMNS: &str = r#"
+    SELECT i.id, i.org_id, o.name AS org_name, i.email, i.role, i.invited_by, i.created_at,
+           i.expires_at, i.accepted_at, i.accepted_by, i.declined_at, i.revoked_at
+    FROM org_invitations i
+    JOIN organizations o ON o.id = i.org_id
+"#;
+
+/// Condition for invitations that can still be used; binds `now` as `?1`.
+const ORG_INVITATION_PENDING: &str = "i.accepted_at IS NULL AND i.declined_at IS NULL \
+     AND i.revoked_at IS NULL AND i.expires_at > ?1";
+
+fn org_invitation_from_row(row: &sqlx::sqlite::SqliteRow) -> OrgInvitationRow {
+    OrgInvitationRow {
+        id: row.get::<String, _>("id"),
+        org_id: row.get::<String, _>("org_id"),
+        org_name: row.get::<String, _>("org_name"),
+        email: row.get::<Option<String>, _>("email"),
+        role: row.get::<String, _>("role"),
+        invited_by: row.get::<String, _>("invited_by"),
+        created_at: row.get::<i64, _>("created_at"),
+        expires_at: row.get::<i64, _>("expires_at"),
+        accepted_at: row.get::<Option<i64>, _>("accepted_at"),
+        accepted_by: row.get::<Option<String>, _>("accepted_by"),
+        declined_at: row.get::<Option<i64>, _>("declined_at"),
+        revoked_at: row.get::<Option<i64>, _>("revoked_at"),
+    }
+}
+
+pub async fn create_org_invitation(
+    pool: &SqlitePool,
+    org_id: &str,
+    email: Option<&str>,
+    role: &str,
+    token_hash: &str,
+    invited_by: &str,
+    expires_at: i64,
+) -> Result<String, AppError> {
+    let invitation_id = Uuid::new_v4().to_string();
+    sqlx::query(
+        r#"
+        INSERT INTO org_invitations
+            (id, org_id, email, role, token_hash, invited_by, created_at, expires_at)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8);
+        "#,
+    )
+    .bind(&invitation_id)
+    .bind(org_id)
+    .bind(email)
+    .bind(role)
+    .bind(token_hash)
+    .bind(invited_by)
+    .bind(Utc::now().timestamp())
+    .bind(expires_at)
+    .execute(pool)
+    .await
+    .map_err(|e| AppError::Internal(format!("create org invitation: {}", e)))?;
+
+    Ok(invitation_id)
+}
+
+/// All invitations of an organization, newest first.
+pub async fn list_org_invitations(
+    pool: &SqlitePool,
+    org_id: &str,
+) -> Result<Vec<OrgInvitationRow>, AppError> {
+    let rows = sqlx::query(&format!(
+        "{} WHERE i.org_id = ?1 ORDER BY i.created_at DESC",
+        ORG_INVITATION_COLUMNS
+    ))
+    .bind(org_id)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| AppError::Internal(format!("list org invitations: {}", e)))?;
+
+    Ok(rows.iter().map(org_invitation_from_row).collect())
+}
+
+/// Pending invitations addressed to `email`, oldest first.
+pub async fn list_pending_invitations_for_email(
+    pool: &SqlitePool,
+    email: &str,
+) -> Result<Vec<OrgInvitationRow>, AppError> {
+    let rows = sqlx::query(&format!(
+        "{} WHERE i.email = ?2 AND o.deleted_at IS NULL AND {} ORDER BY i.created_at ASC",
+        ORG_INVITATION_COLUMNS, ORG_INVITATION_PENDING
+    ))
+    .bind(Utc::now().timestamp())
+    .bind(email)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| AppError::Internal(format!("list pending invitations: {}", e)))?;
+
+    Ok(rows.iter().map(org_invitation_from_row).collect())
+}
+
+pub async fn get_org_invitation(
+    pool: &SqlitePool,
+    invitation_id: &str,
+) -> Result<Option<OrgInvitationRow>, AppError> {
+    let row = sqlx::query(&format!("{} WHERE i.id = ?1", ORG_INVITATION_COLUMNS))
+        .bind(invitation_id)
+        .fetch_optional(pool)
+        .await
+        .map_err(|e| AppError::Internal(format!("get org invitation: {}", e)))?;
+
+    Ok(row.as_ref().map(org_invitation_from_row))
+}
+
+/// The pending invitation a join token belongs to.
+pub async fn find_pending_invitation_by_token(
+    pool: &SqlitePool,
+    token_hash: &str,
+) -> Result<Option<OrgInvitationRow>, AppError> {
+    let row = sqlx::query(&format!(
+        "{} WHERE i.token_hash = ?2 AND o.deleted_at IS NULL AND {}",
+        ORG_INVITATION_COLUMNS, ORG_INVITATION_PENDING
+    ))
+    .bind(Utc::now().timestamp())
+    .bind(token_hash)
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| AppError::Internal(format!("find org invitation: {}", e)))?;
+
+    Ok(row.as_ref().map(org_invitation_from_row))
+}
+
+/// Settle a pending email invitation as accepted by `user_id`, or declined
+/// when `user_id` is `None`. Returns whether it was still pending.
+pub async fn settle_org_invitation(
+    pool: &SqlitePool,
+    invitation_id: &str,
+    accepted_by: Option<&str>,
+) -> Result<bool, AppError> {
+    let column = if accepted_by.is_some() {
+        "accepted_at"
+    } else {
+        "declined_at"
+    };
+    let result = sqlx::query(&format!(
+        "UPDATE org_invitations AS i SET {} = ?1, accepted_by = ?2
+         WHERE i.id = ?3 AND i.email IS NOT NULL AND {}",
+        column, ORG_INVITATION_PENDING
+    ))
+    .bind(Utc::now().timestamp())
+    .bind(accepted_by)
+    .bind(invitation_id)
+    .execute(pool)
+    .await
+    .map_err(|e| AppError::Internal(format!("settle org invitation: {}", e)))?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Returns whether a pending invitation was revoked.
+pub async fn revoke_org_invitation(
+    pool: &SqlitePool,
+    org_id: &str,
+    invitation_id: &str,
+) -> Result<bool, AppError> {
+    let result = sqlx::query(&format!(
+        "UPDATE org_invitations AS i SET revoked_at = ?1
+         WHERE i.id = ?2 AND i.org_id = ?3 AND {}",
+        ORG_INVITATION_PENDING
+    ))
+    .bind(Utc::now().timestamp())
+    .bind(invitation_id)
+    .bind(org_id)
+    .execute(pool)
+    .await
+    .map_err(|e| AppError::Internal(format!("revoke org invitation: {}", e)))?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+// ---------------------------------------------------------------------------
+// Project CRUD
+// ---------------------------------------------------------------------------
+
+pub async fn create_project(
+    pool: &SqlitePool,
+    org_id: &str,
+    name: &str,
+    description: &str,
+) -> Result<String, AppError> {
+    let project_id = Uuid::new_v4().to_string();
+    let now = Utc::now().timestamp();
+
+    let mut tx = pool
+        .begin()
+        .await
+        .map_err(|e| AppError::Internal(format!("begin create project tx: {}", e)))?;
+    sqlx::query(
+        r#"
+        INSERT INTO projects (id, org_id, name, description, created_at)
+        VALUES (?1, ?2, ?3, ?4, ?5);
+        "#,
+    )
+    .bind(&project_id)
+    .bind(org_id)
+    .bind(name)
+    .bind(description)
+    .bind(now)
+    .execute(&mut *tx)
+    .await
+    .map_err(|e| AppError::Internal(format!("create project: {}", e)))?;
+    write_workflow(&mut tx, &project_id, &crate::workflows::default_workflow()).await?;
+    tx.commit()
+        .await
+        .map_err(|e| AppError::Internal(format!("commit create project: {}", e)))?;
+
+    Ok(project_id)
+}
+
+pub struct ProjectRow {
+    pub id: String,
+    pub org_id: String,
+    pub name: String,
+    pub description: String,
+    pub archived_at: Option<i64>,
+}
+
+fn project_from_row(row: &sqlx::sqlite::SqliteRow) -> ProjectRow {
+    ProjectRow {
+        id: row.get::<String, _>("id"),
+        org_id: row.get::<String, _>("org_id"),
+        name: row.get::<String, _>("name"),
+        description: row.get::<String, _>("description"),
+        archived_at: row.get::<Option<i64>, _>("archived_at"),
+    }
+}
+
+/// Projects of an organization, newest first. Archived projects are left
+/// out unless `include_archived` is set, and with `member_id` only projects
+/// that user was added to are listed.
+pub async fn list_projects(
+    pool: &SqlitePool,
+    org_id: &str,
+    include_archived: bool,
+    member_id: Option<&str>,
+) -> Result<Vec<ProjectRow>, AppError> {
+    let rows = sqlx::query(
+        r#"
+        SELECT id, org_id, name, description, archived_at
+        FROM projects
+        WHERE org_id = ?1 AND (?2 OR archived_at IS NULL)
+          AND (?3 IS NULL OR id IN (SELECT project_id FROM project_members WHERE user_id = ?3))
+        ORDER BY created_at DESC;
+        "#,
+    )
+    .bind(org_id)
+    .bind(include_archived)
+    .bind(member_id)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| AppError::Internal(format!("list projects: {}", e)))?;
+
+    Ok(rows.iter().map(project_from_row).collect())
+}
+
+pub async fn get_project(
+    pool: &SqlitePool,
+    project_id: &str,
+) -> Result<Option<ProjectRow>, AppError> {
+    let row = sqlx::query(
+        r#"
+        SELECT id, org_id, name, description, archived_at
+        FROM projects
+        WHERE id = ?1;
+        "#,
+    )
+    .bind(project_id)
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| AppError::Internal(format!("get project: {}", e)))?;
+
+    Ok(row.as_ref().map(project_from_row))
+}
+
+/// Update the given fields; `archived` sets or clears `archived_at`.
+pub async fn update_project(
+    pool: &SqlitePool,
+    project_id: &str,
+    name: Option<&str>,
+    description: Option<&str>,
+    archived: Option<bool>,
+) -> Result<(), AppError> {
+    sqlx::query(
+        r#"
+        UPDATE projects
+        SET name = COALESCE(?1, name),
+            description = COALESCE(?2, description),
+            archived_at = CASE
+                WHEN ?3 IS NULL THEN archived_at
+                WHEN ?3 THEN COALESCE(archived_at, ?4)
+                ELSE NULL
+            END
+        WHERE id = ?5;
+        "#,
+    )
+    .bind(name)
+    .bind(description)
+    .bind(archived)
+    .bind(Utc::now().timestamp())
+    .bind(project_id)
+    .execute(pool)
+    .await
+    .map_err(|e| AppError::Internal(format!("update project: {}", e)))?;
+
+    Ok(())
+}
+
+/// Delete a project with its members, tasks, labels, annotations and
+/// documents in one transaction.
+/// Returns the ids of the deleted documents, whose collab state lives on
+/// disk.
+pub async fn delete_project(pool: &SqlitePool, project_id: &str) -> Result<Vec<String>, AppError> {
+    let mut tx = pool
+        .begin()
+        .await
+        .map_err(|e| AppError::Internal(format!("begin project deletion tx: {}", e)))?;
+    let doc_ids: Vec<String> =
+        sqlx::query_scalar("SELECT id FROM document_registry WHERE project_id = ?1")
+            .bind(project_id)
+            .fetch_all(&mut *tx)
+            .await
+            .map_err(|e| AppError::Internal(format!("list project documents: {}", e)))?;
+    let statements = [
+        (
+            "DELETE FROM task_labels
+             WHERE task_id IN (SELECT id FROM tasks WHERE project_id = ?1)",
+            "delete project task labels",
+        ),
+        (
+            "DELETE FROM task_comments
+             WHERE task_id IN (SELECT id FROM tasks WHERE project_id = ?1)",
+            "delete project task comments",
+        ),
+        (
+            "DELETE FROM task_activity
+             WHERE task_id IN (SELECT id FROM tasks WHERE project_id = ?1)",
+            "delete project task activity",
+        ),
+        (
+            "DELETE FROM task_watchers
+             WHERE task_id IN (SELECT id FROM tasks WHERE project_id = ?1)",
+            "delete project task watchers",
+        ),
+        (
+            "DELETE FROM task_reminders
+             WHERE task_id IN (SELECT id FROM tasks WHERE project_id = ?1)",
+            "delete project task reminders",
+        ),
+        (
+            "DELETE FROM task_dependencies
+             WHERE blocker_id IN (SELECT id FROM tasks WHERE project_id = ?1)
+                OR blocked_id IN (SELECT id FROM tasks WHERE project_id = ?1)",
+            "delete project task dependencies",
+        ),
+        (
+            "DELETE FROM tasks WHERE project_id = ?1",
+            "delete project tasks",
+        ),
+        (
+            "DELETE FROM project_labels WHERE project_id = ?1",
+            "delete project labels",
+        ),
+        (
+            "DELETE FROM project_statuses WHERE project_id = ?1",
+            "delete project statuses",
+        ),
+        (
+            "DELETE FROM project_priorities WHERE project_id = ?1",
+            "delete project priorities",
+        ),
+        (
+            "DELETE FROM document_registry WHERE project_id = ?1",
+            "delete project documents",
+        ),
+        (
+            "DELETE FROM annotation_replies
+             WHERE annotation_id IN (SELECT id FROM annotations WHERE project_id = ?1)",
+            "delete project annotation replies",
+        ),
+        (
+            "DELETE FROM annotations WHERE project_id = ?1",
+            "delete project annotations",
+        ),
+        (
+            "DELETE FROM project_members WHERE project_id = ?1",
+            "delete project members",
+        ),
+        ("DELETE FROM projects WHERE id = ?1", "delete project"),
+    ];
+    for (sql, what) in statements {
+        execute_for(&mut tx, sql, project_id, what).await?;
+    }
+    tx.commit()
+        .await
+        .map_err(|e| AppError::Internal(format!("commit project deletion: {}", e)))?;
+
+    Ok(doc_ids)
+}
+
+// ---------------------------------------------------------------------------
+// Project members
+// ---------------------------------------------------------------------------
+
+pub async fn get_project_member_role(
+    pool: &SqlitePool,
+    project_id: &str,
+    user_id: &str,
+) -> Result<Option<String>, AppError> {
+    sqlx::query_scalar("SELECT role FROM project_members WHERE project_id = ?1 AND user_id = ?2")
+        .bind(project_id)
+        .bind(user_id)
+        .fetch_optional(pool)
+        .await
+        .map_err(|e| AppError::Internal(format!("get project member role: {}", e)))
+}
+
+/// Users with an explicit role in a project, as `(user_id, email, role)`.
+pub async fn list_project_members(
+    pool: &SqlitePool,
+    project_id: &str,
+) -> Result<Vec<(String, String, String)>, AppError> {
+    let rows = sqlx::query(
+        r#"
+        SELECT pm.user_id, u.email, pm.role
+        FROM project_members pm
+        JOIN users u ON u.id = pm.user_id
+        WHERE pm.project_id = ?1
+        ORDER BY pm.added_at ASC;
+        "#,
+    )
+    .bind(project_id)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| AppError::Internal(format!("list project members: {}", e)))?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| {
+            (
+                row.get::<String, _>("user_id"),
+                row.get::<String, _>("email"),
+                row.get::<String, _>("role"),
+            )
+        })
+        .collect())
+}
+
+/// Add a user to a project or change their role there.
+pub async fn set_project_member(
+    pool: &SqlitePool,
+    project_id: &str,
+    user_id: &str,
+    role: &str,
+    added_by: &str,
+) -> Result<(), AppError> {
+    sqlx::query(
+        r#"
+        INSERT INTO project_members (project_id, user_id, role, added_by, added_at)
+        VALUES (?1, ?2, ?3, ?4, ?5)
+        ON CONFLICT(project_id, user_id) DO UPDATE SET role = excluded.role;
+        "#,
+    )
+    .bind(project_id)
+    .bind(user_id)
+    .bind(role)
+    .bind(added_by)
+    .bind(Utc::now().timestamp())
+    .execute(pool)
+    .await
+    .map_err(|e| AppError::Internal(format!("set project member: {}", e)))?;
+
+    Ok(())
+}
+
+/// Returns false if the user had no explicit role in the project.
+pub async fn remove_project_member(
+    pool: &SqlitePool,
+    project_id: &str,
+    user_id: &str,
+) -> Result<bool, AppError> {
+    let result = sqlx::query("DELETE FROM project_members WHERE project_id = ?1 AND user_id = ?2")
+        .bind(project_id)
+        .bind(user_id)
+        .execute(pool)
+        .await
+        .map_err(|e| AppError::Internal(format!("remove project member: {}", e)))?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+// ---------------------------------------------------------------------------
+// Task CRUD
+// ---------------------------------------------------------------------------
+
+#[allow(clippy::too_many_arguments)]
+pub async fn create_task(
+    pool: &SqlitePool,
+    project_id: &str,
+    parent_id: Option<&str>,
+    title: &str,
+    description: &str,
+    status: &str,
+    priority: &str,
+    assignee_id: Option<&str>,
+    due_date: Option<i64>,
+    start_date: Option<i64>,
+    created_by: &str,
+) -> Result<String, AppError> {
+    let task_id = Uuid::new_v4().to_string();
+    let now = Utc::now().timestamp();
+
+    let mut tx = pool
+        .begin()
+        .await
+        .map_err(|e| AppError::Internal(format!("begin create task tx: {}", e)))?;
+    sqlx::query(
+        r#"
+        INSERT INTO tasks (id, project_id, parent_id, title, description, status, priority, assignee_id, due_date, start_date, position, created_by, created_at, updated_at)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14);
+        "#,
+    )
+    .bind(&task_id)
+    .bind(project_id)
+    .bind(parent_id)
+    .bind(title)
+    .bind(description)
+    .bind(status)
+    .bind(priority)
+    .bind(assignee_id)
+    .bind(due_date)
+    .bind(start_date)
+    .bind(0.0_f64)
+    .bind(created_by)
+    .bind(now)
+    .bind(now)
+    .execute(&mut *tx)
+    .await
+    .map_err(|e| AppError::Internal(format!("create task: {}", e)))?;
+    let created = TaskChange {
+        field: "created",
+        old: serde_json::Value::Null,
+        new: serde_json::Value::from(title),
+    };
+    insert_task_activity(&mut tx, &task_id, created_by, &created, now).await?;
+    sqlx::query(
+        "INSERT OR IGNORE INTO task_watchers (task_id, user_id, created_at) VALUES (?1, ?2, ?3)",
+    )
+    .bind(&task_id)
+    .bind(created_by)
+    .bind(now)
+    .execute(&mut *tx)
+    .await
+    .map_err(|e| AppError::Internal(format!("watch created task: {}", e)))?;
+    tx.commit()
+        .await
+        .map_err(|e| AppError::Internal(format!("commit create task: {}", e)))?;
+
+    Ok(task_id)
+}
+
+/// One field of a task changed by [`update_task`]; values are JSON.
+#[derive(Debug, Clone)]
+pub struct TaskChange {
+    pub field: &'static str,
+    pub old: serde_json::Value,
+    pub new: serde_json::Value,
+}
+
+async fn insert_task_activity(
+    conn: &mut sqlx::SqliteConnection,
+    task_id: &str,
+    actor_id: &str,
+    change: &TaskChange,
+    now: i64,
+) -> Result<(), AppError> {
+    sqlx::query(
+        r#"
+        INSERT INTO task_activity (task_id, actor_id, field, old_value, new_value, created_at)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6);
+        "#,
+    )
+    .bind(task_id)
+    .bind(actor_id)
+    .bind(change.field)
+    .bind(change.old.to_string())
+    .bind(change.new.to_string())
+    .bind(now)
+    .execute(conn)
+    .await
+    .map_err(|e| AppError::Internal(format!("record task activity: {}", e)))?;
+    Ok(())
+}
+
+/// Update the given fields and record changes to the parent, title, status,
+/// priority, assignee and dates in the task's activity. Returns those
+/// changes. Like [`move_task`], the new status and priority are checked
+/// against `workflow` once the task is locked.
+#[allow(clippy::too_many_arguments)]
+pub async fn update_task(
+    pool: &SqlitePool,
+    task_id: &str,
+    actor_id: &str,
+    workflow: &crate::models::Workflow,
+    parent_id: Option<Option<&str>>,
+    title: Option<&str>,
+    description: Option<&str>,
+    status: Option<&str>,
+    priority: Option<&str>,
+    assignee_id: Option<Option<&str>>,
+    due_date: Option<Option<i64>>,
+    start_date: Option<Option<i64>>,
+) -> Result<Vec<TaskChange>, AppError> {
+    let now = Utc::now().timestamp();
+    let mut tx = pool
+        .begin()
+        .await
+        .map_err(|e| AppError::Internal(format!("begin update task tx: {}", e)))?;
+    // Lock the task before reading it, so the status checked below is the
+    // one this update replaces.
+    let touched = sqlx::query("UPDATE tasks SET updated_at = ?1 WHERE id = ?2")
+        .bind(now)
+        .bind(task_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| AppError::Internal(format!("lock task: {}", e)))?;
+    if touched.rows_affected() == 0 {
+        return Err(AppError::NotFound);
+    }
+    // Fetch current task, then do a full UPDATE with merged values.
+    let current = sqlx::query(
+        r#"
+        SELECT id, project_id, parent_id, title, description, status, priority,
+               assignee_id, due_date, start_date, position, created_by,
+               created_at, updated_at
+        FROM tasks
+        WHERE id = ?1;
+        "#,
+    )
+    .bind(task_id)
+    .fetch_optional(&mut *tx)
+    .await
+    .map_err(|e| AppError::Internal(format!("get task: {}", e)))?
+    .as_ref()
+    .map(task_from_row)
+    .ok_or(AppError::NotFound)?;
+    crate::workflows::check_task(workflow, Some(&current.status), status, priority)?;
+    let mut changes = Vec::new();
+    if let Some(parent_id) = parent_id {
+        changes.extend(set_task_parent(&mut tx, task_id, actor_id, parent_id, now).await?);
+    }
+
+    let final_title = title.unwrap_or(&current.title);
+    let final_description = description.unwrap_or(&current.description);
+    let final_status = status.unwrap_or(&current.status);
+    let final_priority = priority.unwrap_or(&current.priority);
+    let final_assignee_id: Option<String> = match assignee_id {
+        Some(v) => v.map(|s| s.to_string()),
+        None => current.assignee_id.clone(),
+    };
+    let final_due_date: Option<i64> = match due_date {
+        Some(v) => v,
+        None => current.due_date,
+    };
+    let final_start_date: Option<i64> = match start_date {
+        Some(v) => v,
+        None => current.start_date,
+    };
+
+    sqlx::query(
+        r#"
+        UPDATE tasks
+        SET title = ?1, description = ?2, status = ?3, priority = ?4,
+            assignee_id = ?5, due_date = ?6, start_date = ?7, updated_at = ?8
+        WHERE id = ?9;
+        "#,
+    )
+    .bind(final_title)
+    .bind(final_description)
+    .bind(final_status)
+    .bind(final_priority)
+    .bind(&final_assignee_id)
+    .bind(final_due_date)
+    .bind(final_start_date)
+    .bind(now)
+    .bind(task_id)
+    .execute(&mut *tx)
+    .await
+    .map_err(|e| AppError::Internal(format!("update task: {}", e)))?;
+
+    let candidates = [
+        ("title", json!(current.title), json!(final_title)),
+        ("status", json!(current.status), json!(final_status)),
+        ("priority", json!(current.priority), json!(final_priority)),
+        (
+            "assignee_id",
+            json!(current.assignee_id),
+            json!(final_assignee_id),
+        ),
+        ("due_date", json!(current.due_date), json!(final_due_date)),
+        (
+            "start_date",
+            json!(current.start_date),
+            json!(final_start_date),
+        ),
+    ];
+    for (field, old, new) in candidates {
+        if old != new {
+            let change = TaskChange { field, old, new };
+            insert_task_activity(&mut tx, task_id, actor_id, &change, now).await?;
+            changes.push(change);
+        }
+    }
+    tx.commit()
+        .await
+        .map_err(|e| AppError::Internal(format!("commit update task: {}", e)))?;
+
+    Ok(changes)
+}
+
+/// Distance between neighbouring tasks after a column is rebalanced.
+const POSITION_STEP: f64 = 1024.0;
+/// Rebalance a column instead of moving a task into a smaller gap.
+const MIN_POSITION_GAP: f64 = 1e-6;
+
+/// Move a task into `status` (or its current one), right after `after_id`
+/// and/or before `before_id`, or to the end of the column without either.
+/// When the gap there is too small the whole column is renumbered. The move
+/// is checked against `workflow` from the status the task has once locked.
+/// Returns the status change, if any, as recorded in the task's activity.
+pub async fn move_task(
+    pool: &SqlitePool,
+    task_id: &str,
+    actor_id: &str,
+    workflow: &crate::models::Workflow,
+    status: Option<&str>,
+    after_id: Option<&str>,
+    before_id: Option<&str>,
+) -> Result<Vec<TaskChange>, AppError> {
+    let now = Utc::now().timestamp();
+    let mut tx = pool
+        .begin()
+        .await
+        .map_err(|e| AppError::Internal(format!("begin move task tx: {}", e)))?;
+    // Write first, so concurrent moves queue up for the lock instead of
+    // computing positions from the same snapshot.
+    let touched = sqlx::query("UPDATE tasks SET updated_at = ?1 WHERE id = ?2")
+        .bind(now)
+        .bind(task_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| AppError::Internal(format!("lock task: {}", e)))?;
+    if touched.rows_affected() == 0 {
+        return Err(AppError::NotFound);
+    }
+    let (project_id, current): (String, String) =
+        sqlx::query_as("SELECT project_id, status FROM tasks WHERE id = ?1")
+            .bind(task_id)
+            .fetch_one(&mut *tx)
+            .await
+            .map_err(|e| AppError::Internal(format!("get task: {}", e)))?;
+    crate::workflows::check_task(workflow, Some(&current), status, None)?;
+    let status = status.unwrap_or(&current);
+
+    let column: Vec<(String, f64)> = sqlx::query_as(
+        r#"
+        SELECT id, position FROM tasks
+        WHERE project_id = ?1 AND status = ?2 AND id != ?3
+        ORDER BY position, created_at, id;
+        "#,
+    )
+    .bind(&project_id)
+    .bind(status)
+    .bind(task_id)
+    .fetch_all(&mut *tx)
+    .await
+    .map_err(|e| AppError::Internal(format!("list column tasks: {}", e)))?;
+    let index_of = |neighbour: &str| {
+        column
+            .iter()
+            .position(|(id, _)| id == neighbour)
+            .ok_or_else(|| AppError::BadRequest(format!("{} is not in this column", neighbour)))
+    };
+    let index = match (after_id, before_id) {
+        (Some(after), Some(before)) => {
+            let index = index_of(after)? + 1;
+            if index_of(before)? != index {
+                return Err(AppError::BadRequest(
+                    "after_id and before_id are not next to each other".to_string(),
+                ));
+            }
+            index
+        }
+        (Some(after), None) => index_of(after)? + 1,
+        (None, Some(before)) => index_of(before)?,
+        (None, None) => column.len(),
+    };
+
+    let lower = index.checked_sub(1).map(|i| column[i].1);
+    let upper = column.get(index).map(|(_, position)| *position);
+    let position = match (lower, upper) {
+        (None, None) => Some(0.0),
+        (Some(lower), None) => Some(lower + POSITION_STEP),
+        (None, Some(upper)) => Some(upper - POSITION_STEP),
+        (Some(lower), Some(upper)) if upper - lower >= MIN_POSITION_GAP => {
+            Some(lower + (upper - lower) / 2.0)
+        }
+        _ => None,
+    };
+    match position {
+        Some(position) => {
+            sqlx::query("UPDATE tasks SET status = ?1, position = ?2 WHERE id = ?3")
+                .bind(status)
+                .bind(position)
+                .bind(task_id)
+                .execute(&mut *tx)
+                .await
+                .map_err(|e| AppError::Internal(format!("move task: {}", e)))?;
+        }
+        None => {
+            let mut order: Vec<&str> = column.iter().map(|(id, _)| id.as_str()).collect();
+            order.insert(index, task_id);
+            for (i, id) in order.into_iter().enumerate() {
+                sqlx::query("UPDATE tasks SET status = ?1, position = ?2 WHERE id = ?3")
+                    .bind(status)
+                    .bind((i + 1) as f64 * POSITION_STEP)
+                    .bind(id)
+                    .execute(&mut *tx)
+                    .await
+                    .map_err(|e| AppError::Internal(format!("rebalance column: {}", e)))?;
+            }
+        }
+    }
+
+    let mut changes = Vec::new();
+    if status != current {
+        let change = TaskChange {
+            field: "status",
+            old: json!(current),
+            new: json!(status),
+        };
+        insert_task_activity(&mut tx, task_id, actor_id, &change, now).await?;
+        changes.push(change);
+    }
+    tx.commit()
+        .await
+        .map_err(|e| AppError::Internal(format!("commit move task: {}", e)))?;
+
+    Ok(changes)
+}
+
+fn task_from_row(row: &sqlx::sqlite::SqliteRow) -> TaskRow {
+    TaskRow {
+        id: row.get::<String, _>("id"),
+        project_id: row.get::<String, _>("project_id"),
+        parent_id: row.get::<Option<String>, _>("parent_id"),
+        title: row.get::<String, _>("title"),
+        description: row.get::<String, _>("description"),
+        status: row.get::<String, _>("status"),
+        priority: row.get::<String, _>("priority"),
+        assignee_id: row.get::<Option<String>, _>("assignee_id"),
+        due_date: row.get::<Option<i64>, _>("due_date"),
+        start_date: row.get::<Option<i64>, _>("start_date"),
+        position: row.get::<f64, _>("position"),
+        created_by: row.get::<String, _>("created_by"),
+        created_at: row.get::<i64, _>("created_at"),
+        updated_at: row.get::<i64, _>("updated_at"),
+    }
+}
+
+/// Orders for [`list_tasks`]. Ties are broken by creation time, then id.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum TaskSort {
+    #[default]
+    Position,
+    DueDate,
+    Priority,
+    CreatedAt,
+    UpdatedAt,
+    Title,
+}
+
+impl TaskSort {
+    pub fn parse(value: &str) -> Option<Self> {
+        Some(match value {
+            "position" => Self::Position,
+            "due_date" => Self::DueDate,
+            "priority" => Self::Priority,
+            "created_at" => Self::CreatedAt,
+            "updated_at" => Self::UpdatedAt,
+            "title" => Self::Title,
+            _ => return None,
+        })
+    }
+
+    /// The SQL expression tasks are ordered by. Tasks without a due date
+    /// sort after all others; priorities in their project's order, most
+    /// urgent first, and unknown ones last.
+    fn key_sql(self) -> &'static str {
+        match self {
+            Self::Position => "CAST(t.position AS REAL)",
+            Self::DueDate => "CAST(COALESCE(t.due_date, 253402300799) AS REAL)",
+            Self::Priority => {
+                "CAST(COALESCE((SELECT pp.position FROM project_priorities pp
+                                WHERE pp.project_id = t.project_id AND pp.key = t.priority),
+                               1000000) AS REAL)"
+            }
+            Self::CreatedAt => "CAST(t.created_at AS REAL)",
+            Self::UpdatedAt => "CAST(t.updated_at AS REAL)",
+            Self::Title => "LOWER(t.title)",
+        }
+    }
+}
+
+/// A task's value for the current [`TaskSort`]: text for titles, a number
+/// otherwise.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(untagged)]
+pub enum TaskSortKey {
+    Number(f64),
+    Text(String),
+}
+
+/// Where a page of tasks ends; the next page starts after it.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct TaskCursor {
+    pub key: TaskSortKey,
+    pub created_at: i64,
+    pub id: String,
+}
+
+/// Filters for [`list_tasks`]; `None` matches everything. `statuses` and
+/// `priorities` are comma-separated lists. `visible_to` limits the result
+/// to projects that user can see in orgs that still exist.
+#[derive(Debug, Default)]
+pub struct TaskFilter {
+    pub project_id: Option<String>,
+    pub visible_to: Option<String>,
+    pub statuses: Option<String>,
+    pub priorities: Option<String>,
+    pub assignee_id: Option<String>,
+    pub unassigned: bool,
+    pub label_id: Option<String>,
+    pub due_after: Option<i64>,
+    pub due_before: Option<i64>,
+    pub search: Option<String>,
+    pub sort: TaskSort,
+    pub descending: bool,
+    pub after: Option<TaskCursor>,
+    pub limit: i64,
+}
+
+/// One page of tasks, each with its cursor.
+pub async fn list_tasks(
+    pool: &SqlitePool,
+    filter: &TaskFilter,
+) -> Result<Vec<(TaskRow, TaskCursor)>, AppError> {
+    let (key, cmp, dir) = (
+        filter.sort.key_sql(),
+        if filter.descending { "<" } else { ">" },
+        if filter.descending { "DESC" } else { "ASC" },
+    );
+    let sql = format!(
+        r#"
+        SELECT t.id, t.project_id, t.parent_id, t.title, t.description, t.status, t.priority,
+               t.assignee_id, t.due_date, t.start_date, t.position, t.created_by,
+               t.created_at, t.updated_at, {key} AS sort_key
+        FROM tasks t
+        WHERE (?1 IS NULL OR t.project_id = ?1)
+          AND (?2 IS NULL OR t.project_id IN (
+                SELECT p.id FROM projects p
+                JOIN organizations o ON o.id = p.org_id AND o.deleted_at IS NULL
+                JOIN org_members m ON m.org_id = p.org_id AND m.user_id = ?2
+                WHERE m.role != 'guest'
+                   OR p.id IN (SELECT project_id FROM project_members WHERE user_id = ?2)))
+          AND (?3 IS NULL OR instr(',' || ?3 || ',', ',' || t.status || ',') > 0)
+          AND (?4 IS NULL OR instr(',' || ?4 || ',', ',' || t.priority || ',') > 0)
+          AND (?5 IS NULL OR t.assignee_id = ?5)
+          AND (NOT ?6 OR t.assignee_id IS NULL)
+          AND (?7 IS NULL OR t.id IN (SELECT task_id FROM task_labels WHERE label = ?7))
+          AND (?8 IS NULL OR t.due_date >= ?8)
+          AND (?9 IS NULL OR t.due_date < ?9)
+          AND (?10 IS NULL OR t.title LIKE ?10 ESCAPE '\' OR t.description LIKE ?10 ESCAPE '\')
+          AND (?13 IS NULL OR ({key}, t.created_at, t.id) {cmp} (?11, ?12, ?13))
+        ORDER BY sort_key {dir}, t.created_at {dir}, t.id {dir}
+        LIMIT ?14;
+        "#
+    );
+    let search = filter.search.as_deref().map(|search| {
+        let escaped = search
+            .replace('\\', "\\\\")
+            .replace('%', "\\%")
+            .replace('_', "\\_");
+        format!("%{}%", escaped)
+    });
+    let query = sqlx::query(&sql)
+        .bind(&filter.project_id)
+        .bind(&filter.visible_to)
+        .bind(&filter.statuses)
+        .bind(&filter.priorities)
+        .bind(&filter.assignee_id)
+        .bind(filter.unassigned)
+        .bind(&filter.label_id)
+        .bind(filter.due_after)
+        .bind(filter.due_before)
+        .bind(search);
+    let query = match filter.after.as_ref().map(|cursor| &cursor.key) {
+        Some(TaskSortKey::Text(key)) => query.bind(key.clone()),
+        Some(TaskSortKey::Number(key)) => query.bind(*key),
+        None => query.bind(None::<f64>),
+    };
+    let rows = query
+        .bind(filter.after.as_ref().map(|cursor| cursor.created_at))
+        .bind(filter.after.as_ref().map(|cursor| cursor.id.clone()))
+        .bind(filter.limit)
+        .fetch_all(pool)
+        .await
+        .map_err(|e| AppError::Internal(format!("list tasks: {}", e)))?;
+
+    Ok(rows
+        .iter()
+        .map(|row| {
+            let task = task_from_row(row);
+            let key = match filter.sort {
+                TaskSort::Title => TaskSortKey::Text(row.get::<String, _>("sort_key")),
+                _ => TaskSortKey::Number(row.get::<f64, _>("sort_key")),
+            };
+            let cursor = TaskCursor {
+                key,
+                created_at: task.created_at,
+                id: task.id.clone(),
+            };
+            (task, cursor)
+        })
+        .collect())
+}
+
+pub async fn get_task(pool: &SqlitePool, task_id: &str) -> Result<Option<TaskRow>, AppError> {
+    let row = sqlx::query(
+        r#"
+        SELECT id, project_id, parent_id, title, description, status, priority,
+               assignee_id, due_date, start_date, position, created_by,
+               created_at, updated_at
+        FROM tasks
+        WHERE id = ?1;
+        "#,
+    )
+    .bind(task_id)
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| AppError::Internal(format!("get task: {}", e)))?;
+
+    Ok(row.as_ref().map(task_from_row))
+}
+
+pub async fn delete_task(pool: &SqlitePool, task_id: &str) -> Result<(), AppError> {
+    let mut tx = pool
+        .begin()
+        .await
+        .map_err(|e| AppError::Internal(format!("begin task deletion tx: {}", e)))?;
+    let statements = [
+        (
+            "DELETE FROM task_labels WHERE task_id = ?1",
+            "delete task labels",
+        ),
+        (
+            "DELETE FROM task_comments WHERE task_id = ?1",
+            "delete task comments",
+        ),
+        (
+            "DELETE FROM task_activity WHERE task_id = ?1",
+            "delete task activity",
+        ),
+        (
+            "DELETE FROM task_watchers WHERE task_id = ?1",
+            "delete task watchers",
+        ),
+        (
+            "DELETE FROM task_reminders WHERE task_id = ?1",
+            "delete task reminders",
+        ),
+        (
+            "DELETE FROM task_dependencies WHERE blocker_id = ?1 OR blocked_id = ?1",
+            "delete task dependencies",
+        ),
+        (
+            "UPDATE tasks SET parent_id = NULL WHERE parent_id = ?1",
+            "detach subtasks",
+        ),
+        ("DELETE FROM tasks WHERE id = ?1", "delete task"),
+    ];
+    for (sql, what) in statements {
+        execute_for(&mut tx, sql, task_id, what).await?;
+    }
+    tx.commit()
+        .await
+        .map_err(|e| AppError::Internal(format!("commit task deletion: {}", e)))?;
+
+    Ok(())
+}
+
+// ---------------------------------------------------------------------------
+// Task comments, activity & watchers
+// ---------------------------------------------------------------------------
+
+pub struct TaskCommentRow {
+    pub id: String,
+    pub parent_id: Option<String>,
+    pub user_id: String,
+    pub body: String,
+    pub created_at: i64,
+}
+
+/// Add a comment and make its author a watcher of the task.
+pub async fn create_task_comment(
+    pool: &SqlitePool,
+    task_id: &str,
+    parent_id: Option<&str>,
+    user_id: &str,
+    body: &str,
+) -> Result<String, AppError> {
+    let comment_id = Uuid::new_v4().to_string();
+    let now = Utc::now().timestamp();
+    let mut tx = pool
+        .begin()
+        .await
+        .map_err(|e| AppError::Internal(format!("begin task comment tx: {}", e)))?;
+    sqlx::query(
+        r#"
+        INSERT INTO task_comments (id, task_id, parent_id, user_id, body, created_at)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6);
+        "#,
+    )
+    .bind(&comment_id)
+    .bind(task_id)
+    .bind(parent_id)
+    .bind(user_id)
+    .bind(body)
+    .bind(now)
+    .execute(&mut *tx)
+    .await
+    .map_err(|e| AppError::Internal(format!("create task comment: {}", e)))?;
+    sqlx::query(
+        "INSERT OR IGNORE INTO task_watchers (task_id, user_id, created_at) VALUES (?1, ?2, ?3)",
+    )
+    .bind(task_id)
+    .bind(user_id)
+    .bind(now)
+    .execute(&mut *tx)
+    .await
+    .map_err(|e| AppError::Internal(format!("watch commented task: {}", e)))?;
+    tx.commit()
+        .await
+        .map_err(|e| AppError::Internal(format!("commit task comment: {}", e)))?;
+
+    Ok(comment_id)
+}
+
+/// The task a comment belongs to.
+pub async fn get_task_comment_task(
+    pool: &SqlitePool,
+    comment_id: &str,
+) -> Result<Option<String>, AppError> {
+    sqlx::query_scalar("SELECT task_id FROM task_comments WHERE id = ?1")
+        .bind(comment_id)
+        .fetch_optional(pool)
+        .await
+        .map_err(|e| AppError::Internal(format!("get task comment: {}", e)))
+}
+
+/// Comments on a task, oldest first; replies point at their parent.
+pub async fn list_task_comments(
+    pool: &SqlitePool,
+    task_id: &str,
+) -> Result<Vec<TaskCommentRow>, AppError> {
+    let rows = sqlx::query(
+        r#"
+        SELECT id, parent_id, user_id, body, created_at
+        FROM task_comments
+        WHERE task_id = ?1
+        ORDER BY created_at ASC, rowid ASC;
+        "#,
+    )
+    .bind(task_id)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| AppError::Internal(format!("list task comments: {}", e)))?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| TaskCommentRow {
+            id: row.get::<String, _>("id"),
+            parent_id: row.get::<Option<String>, _>("parent_id"),
+            user_id: row.get::<String, _>("user_id"),
+            body: row.get::<String, _>("body"),
+            created_at: row.get::<i64, _>("created_at"),
+        })
+        .collect())
+}
+
+pub struct TaskActivityRow {
+    pub id: i64,
+    pub actor_id: Option<String>,
+    pub field: String,
+    pub old_value: serde_json::Value,
+    pub new_value: serde_json::Value,
+    pub created_at: i64,
+}
+
+/// Activity of a task, oldest first.
+pub async fn list_task_activity(
+    pool: &SqlitePool,
+    task_id: &str,
+) -> Result<Vec<TaskActivityRow>, AppError> {
+    let rows = sqlx::query(
+        r#"
+        SELECT id, actor_id, field, old_value, new_value, created_at
+        FROM task_activity
+        WHERE task_id = ?1
+        ORDER BY id ASC;
+        "#,
+    )
+    .bind(task_id)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| AppError::Internal(format!("list task activity: {}", e)))?;
+
+    let parse = |value: Option<String>| {
+        value
+            .and_then(|value| serde_json::from_str(&value).ok())
+            .unwrap_or(serde_json::Value::Null)
+    };
+    Ok(rows
+        .into_iter()
+        .map(|row| TaskActivityRow {
+            id: row.get::<i64, _>("id"),
+            actor_id: row.get::<Option<String>, _>("actor_id"),
+            field: row.get::<String, _>("field"),
+            old_value: parse(row.get::<Option<String>, _>("old_value")),
+            new_value: parse(row.get::<Option<String>, _>("new_value")),
+            created_at: row.get::<i64, _>("created_at"),
+        })
+        .collect())
+}
+
+pub async fn list_task_watchers(pool: &SqlitePool, task_id: &str) -> Result<Vec<String>, AppError> {
+    sqlx::query_scalar("SELECT user_id FROM task_watchers WHERE task_id = ?1 ORDER BY created_at")
+        .bind(task_id)
+        .fetch_all(pool)
+        .await
+        .map_err(|e| AppError::Internal(format!("list task watchers: {}", e)))
+}
+
+pub async fn set_task_watch(
+    pool: &SqlitePool,
+    task_id: &str,
+    user_id: &str,
+    watching: bool,
+) -> Result<(), AppError> {
+    let query = if watching {
+        sqlx::query(
+            "INSERT OR IGNORE INTO task_watchers (task_id, user_id, created_at)
+             VALUES (?1, ?2, ?3)",
+        )
+        .bind(task_id)
+        .bind(user_id)
+        .bind(Utc::now().timestamp())
+    } else {
+        sqlx::query("DELETE FROM task_watchers WHERE task_id = ?1 AND user_id = ?2")
+            .bind(task_id)
+            .bind(user_id)
+    };
+    query
+        .execute(pool)
+        .await
+        .map_err(|e| AppError::Internal(format!("set task watch: {}", e)))?;
+
+    Ok(())
+}
+
+// ---------------------------------------------------------------------------
+// Workflows
+// ---------------------------------------------------------------------------
+
+/// Replace the stored statuses and priorities of a project.
+async fn write_workflow(
+    conn: &mut sqlx::SqliteConnection,
+    project_id: &str,
+    workflow: &crate::models::Workflow,
+) -> Result<(), AppError> {
+    execute_for(
+        &mut *conn,
+        "DELETE FROM project_statuses WHERE project_id = ?1",
+        project_id,
+        "clear project statuses",
+    )
+    .await?;
+    execute_for(
+        &mut *conn,
+        "DELETE FROM project_priorities WHERE project_id = ?1",
+        project_id,
+        "clear project priorities",
+    )
+    .await?;
+    for (position, status) in workflow.statuses.iter().enumerate() {
+        sqlx::query(
+            r#"
+            INSERT INTO project_statuses (project_id, key, name, category, position, transitions)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6);
+            "#,
+        )
+        .bind(project_id)
+        .bind(&status.key)
+        .bind(&status.name)
+        .bind(&status.category)
+        .bind(position as i64)
+        .bind(status.transitions.as_ref().map(|t| json!(t).to_string()))
+        .execute(&mut *conn)
+        .await
+        .map_err(|e| AppError::Internal(format!("insert project status: {}", e)))?;
+    }
+    for (position, priority) in workflow.priorities.iter().enumerate() {
+        sqlx::query(
+            r#"
+            INSERT INTO project_priorities (project_id, key, name, position, is_default)
+            VALUES (?1, ?2, ?3, ?4, ?5);
+            "#,
+        )
+        .bind(project_id)
+        .bind(&priority.key)
+        .bind(&priority.name)
+        .bind(position as i64)
+        .bind((priority.key == workflow.default_priority) as i32)
+        .execute(&mut *conn)
+        .await
+        .map_err(|e| AppError::Internal(format!("insert project priority: {}", e)))?;
+    }
+    Ok(())
+}
+
+/// Rename the status or priority values of a project's tasks. Every task
+/// is renamed from its value before the call, in one statement, so swaps
+/// and chains like `a -> b, b -> c` rename each task once.
+async fn remap_tasks(
+    conn: &mut sqlx::SqliteConnection,
+    project_id: &str,
+    column: &str,
+    map: &HashMap<String, String>,
+) -> Result<(), AppError> {
+    if map.is_empty() {
+        return Ok(());
+    }
+    let mut renames: Vec<_> = map.iter().collect();
+    renames.sort();
+    let cases = " WHEN ? THEN ?".repeat(renames.len());
+    let sources = vec!["?"; renames.len()].join(", ");
+    let sql = format!(
+        "UPDATE tasks SET {column} = CASE {column}{cases} END
+         WHERE project_id = ? AND {column} IN ({sources})"
+    );
+    let mut query = sqlx::query(&sql);
+    for (from, to) in &renames {
+        query = query.bind(*from).bind(*to);
+    }
+    query = query.bind(project_id);
+    for (from, _) in &renames {
+        query = query.bind(*from);
+    }
+    query
+        .execute(&mut *conn)
+        .await
+        .map_err(|e| AppError::Internal(format!("remap task {}: {}", column, e)))?;
+    Ok(())
+}
+
+async fn distinct_task_values(
+    conn: &mut sqlx::SqliteConnection,
+    project_id: &str,
+    column: &str,
+) -> Result<Vec<String>, AppError> {
+    sqlx::query_scalar(&format!(
+        "SELECT DISTINCT {column} FROM tasks WHERE project_id = ?1 ORDER BY {column}"
+    ))
+    .bind(project_id)
+    .fetch_all(&mut *conn)
+    .await
+    .map_err(|e| AppError::Internal(format!("list task {} values: {}", column, e)))
+}
+
+/// Give every project without a workflow one that fits its tasks, mapping
+/// their free-form statuses and priorities onto it.
+async fn migrate_task_workflows(pool: &SqlitePool) -> Result<(), AppError> {
+    let project_ids: Vec<String> = sqlx::query_scalar(
+        "SELECT id FROM projects
+         WHERE id NOT IN (SELECT DISTINCT project_id FROM project_statuses)",
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(|e| AppError::Internal(format!("list projects without workflow: {}", e)))?;
+    for project_id in project_ids {
+        let mut tx = pool
+            .begin()
+            .await
+            .map_err(|e| AppError::Internal(format!("begin workflow migration tx: {}", e)))?;
+        let statuses = distinct_task_values(&mut tx, &project_id, "status").await?;
+        let priorities = distinct_task_values(&mut tx, &project_id, "priority").await?;
+        let (workflow, status_map, priority_map) =
+            crate::workflows::legacy_workflow(&statuses, &priorities);
+        write_workflow(&mut tx, &project_id, &workflow).await?;
+        remap_tasks(&mut tx, &project_id, "status", &status_map).await?;
+        remap_tasks(&mut tx, &project_id, "priority", &priority_map).await?;
+        tx.commit()
+            .await
+            .map_err(|e| AppError::Internal(format!("commit workflow migration: {}", e)))?;
+    }
+    Ok(())
+}
+
+pub async fn get_workflow(
+    pool: &SqlitePool,
+    project_id: &str,
+) -> Result<crate::models::Workflow, AppError> {
+    use crate::models::{Workflow, WorkflowPriority, WorkflowStatus};
+
+    let statuses = sqlx::query(
+        "SELECT key, name, category, transitions FROM project_statuses
+         WHERE project_id = ?1 ORDER BY position",
+    )
+    .bind(project_id)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| AppError::Internal(format!("list project statuses: {}", e)))?;
+    let priorities = sqlx::query(
+        "SELECT key, name, is_default FROM project_priorities
+         WHERE project_id = ?1 ORDER BY position",
+    )
+    .bind(project_id)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| AppError::Internal(format!("list project priorities: {}", e)))?;
+    if statuses.is_empty() || priorities.is_empty() {
+        return Ok(crate::workflows::default_workflow());
+    }
+
+    let default_priority = priorities
+        .iter()
+        .find(|row| row.get::<i32, _>("is_default") != 0)
+        .unwrap_or(&priorities[0])
+        .get::<String, _>("key");
+    Ok(Workflow {
+        statuses: statuses
+            .iter()
+            .map(|row| WorkflowStatus {
+                key: row.get("key"),
+                name: row.get("name"),
+                category: row.get("category"),
+                transitions: row
+                    .get::<Option<String>, _>("transitions")
+                    .and_then(|t| serde_json::from_str(&t).ok()),
+            })
+            .collect(),
+        priorities: priorities
+            .iter()
+            .map(|row| WorkflowPriority {
+                key: row.get("key"),
+                name: row.get("name"),
+            })
+            .collect(),
+        default_priority,
+    })
+}
+
+/// Replace a project's workflow, moving its tasks with the given maps. Fails
+/// with a conflict if tasks would be left in a status or priority that no
+/// longer exists.
+pub async fn replace_workflow(
+    pool: &SqlitePool,
+    project_id: &str,
+    workflow: &crate::models::Workflow,
+    status_map: &HashMap<String, String>,
+    priority_map: &HashMap<String, String>,
+) -> Result<(), AppError> {
+    let mut tx = pool
+        .begin()
+        .await
+        .map_err(|e| AppError::Internal(format!("begin replace workflow tx: {}", e)))?;
+    remap_tasks(&mut tx, project_id, "status", status_map).await?;
+    remap_tasks(&mut tx, project_id, "priority", priority_map).await?;
+    for status in distinct_task_values(&mut tx, project_id, "status").await? {
+        if !workflow.statuses.iter().any(|s| s.key == status) {
+            return Err(AppError::Conflict(format!(
+                "tasks still use status {}",
+                status
+            )));
+        }
+    }
+    for priority in distinct_task_values(&mut tx, project_id, "priority").await? {
+        if !workflow.priorities.iter().any(|p| p.key == priority) {
+            return Err(AppError::Conflict(format!(
+                "tasks still use priority {}",
+                priority
+            )));
+        }
+    }
+    write_workflow(&mut tx, project_id, workflow).await?;
+    tx.commit()
+        .await
+        .map_err(|e| AppError::Internal(format!("commit replace workflow: {}", e)))?;
+
+    Ok(())
+}
+
+// ---------------------------------------------------------------------------
+// Subtasks and dependencies
+// ---------------------------------------------------------------------------
+
+/// Move a task under `parent_id`, or to the top level with `None`, and
+/// record it in the task's activity. The parent must be in the same project
+/// and not the task itself or one of its subtasks.
+async fn set_task_parent(
+    conn: &mut sqlx::SqliteConnection,
+    task_id: &str,
+    actor_id: &str,
+    parent_id: Option<&str>,
+    now: i64,
+) -> Result<Option<TaskChange>, AppError> {
+    let (project_id, current): (String, Option<String>) =
+        sqlx::query_as("SELECT project_id, parent_id FROM tasks WHERE id = ?1")
+            .bind(task_id)
+            .fetch_optional(&mut *conn)
+            .await
+            .map_err(|e| AppError::Internal(format!("get task parent: {}", e)))?
+            .ok_or(AppError::NotFound)?;
+    if current.as_deref() == parent_id {
+        return Ok(None);
+    }
+    if let Some(parent_id) = parent_id {
+        let parent_project: Option<String> =
+            sqlx::query_scalar("SELECT project_id FROM tasks WHERE id = ?1")
+                .bind(parent_id)
+                .fetch_optional(&mut *conn)
+                .await
+                .map_err(|e| AppError::Internal(format!("get parent task: {}", e)))?;
+        if parent_project.as_deref() != Some(project_id.as_str()) {
+            return Err(AppError::BadRequest(
+                "parent task is not in this project".to_string(),
+            ));
+        }
+        let cycle: Option<i64> = sqlx::query_scalar(
+            r#"
+            WITH RECURSIVE ancestors(id) AS (
+                SELECT ?1
+                UNION
+                SELECT t.parent_id FROM tasks t JOIN ancestors a ON t.id = a.id
+                WHERE t.parent_id IS NOT NULL
+            )
+            SELECT 1 FROM ancestors WHERE id = ?2;
+            "#,
+        )
+        .bind(parent_id)
+        .bind(task_id)
+        .fetch_optional(&mut *conn)
+        .await
+        .map_err(|e| AppError::Internal(format!("check task parent cycle: {}", e)))?;
+        if cycle.is_some() {
+            return Err(AppError::BadRequest(
+                "a task cannot be a subtask of itself or its subtasks".to_string(),
+            ));
+        }
+    }
+
+    sqlx::query("UPDATE tasks SET parent_id = ?1, updated_at = ?2 WHERE id = ?3")
+        .bind(parent_id)
+        .bind(now)
+        .bind(task_id)
+        .execute(&mut *conn)
+        .await
+        .map_err(|e| AppError::Internal(format!("set task parent: {}", e)))?;
+    let change = TaskChange {
+        field: "parent_id",
+        old: json!(current),
+        new: json!(parent_id),
+    };
+    insert_task_activity(conn, task_id, actor_id, &change, now).await?;
+    Ok(Some(change))
+}
+
+/// Direct subtasks of `parent_id`, in board order.
+pub async fn list_subtasks(pool: &SqlitePool, parent_id: &str) -> Result<Vec<TaskRow>, AppError> {
+    let rows = sqlx::query(
+        r#"
+        SELECT id, project_id, parent_id, title, description, status, priority,
+               assignee_id, due_date, start_date, position, created_by,
+               created_at, updated_at
+        FROM tasks
+        WHERE parent_id = ?1
+        ORDER BY position, created_at, id;
+        "#,
+    )
+    .bind(parent_id)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| AppError::Internal(format!("list subtasks: {}", e)))?;
+
+    Ok(rows.iter().map(task_from_row).collect())
+}
+
+/// Subtask counts of each of `task_ids`, as `(done, total)`. Tasks without
+/// subtasks are left out.
+pub async fn subtask_progress(
+    pool: &SqlitePool,
+    task_ids: &[String],
+) -> Result<HashMap<String, (i64, i64)>, AppError> {
+    let rows = sqlx::query(
+        r#"
+        SELECT t.parent_id, SUM(s.category = 'done') AS done, COUNT(*) AS total
+        FROM tasks t
+        LEFT JOIN project_statuses s ON s.project_id = t.project_id AND s.key = t.status
+        WHERE t.parent_id IN (SELECT value FROM json_each(?1))
+        GROUP BY t.parent_id;
+        "#,
+    )
+    .bind(json!(task_ids).to_string())
+    .fetch_all(pool)
+    .await
+    .map_err(|e| AppError::Internal(format!("count subtasks: {}", e)))?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| {
+            (
+                row.get::<String, _>("parent_id"),
+                (row.get::<i64, _>("done"), row.get::<i64, _>("total")),
+            )
+        })
+        .collect())
+}
+
+/// Dependencies touching any of `task_ids`, as `(blocker_id, blocked_id)`.
+pub async fn list_task_dependencies(
+    pool: &SqlitePool,
+    task_ids: &[String],
+) -> Result<Vec<(String, String)>, AppError> {
+    sqlx::query_as(
+        r#"
+        SELECT blocker_id, blocked_id
+        FROM task_dependencies
+        WHERE blocker_id IN (SELECT value FROM json_each(?1))
+           OR blocked_id IN (SELECT value FROM json_each(?1))
+        ORDER BY created_at, blocker_id, blocked_id;
+        "#,
+    )
+    .bind(json!(task_ids).to_string())
+    .fetch_all(pool)
+    .await
+    .map_err(|e| AppError::Internal(format!("list task dependencies: {}", e)))
+}
+
+/// Record that `blocker_id` blocks `blocked_id`, unless that would close a
+/// cycle. Returns false if the dependency already existed.
+pub async fn add_task_dependency(
+    pool: &SqlitePool,
+    blocker_id: &str,
+    blocked_id: &str,
+) -> Result<bool, AppError> {
+    let mut tx = pool
+        .begin()
+        .await
+        .map_err(|e| AppError::Internal(format!("begin add dependency tx: {}", e)))?;
+    // A cycle exists if the blocker is itself blocked, directly or not, by
+    // the task it would block.
+    let cycle: Option<i64> = sqlx::query_scalar(
+        r#"
+        WITH RECURSIVE blockers(id) AS (
+            SELECT ?1
+            UNION
+            SELECT d.blocker_id FROM task_dependencies d JOIN blockers b ON d.blocked_id = b.id
+        )
+        SELECT 1 FROM blockers WHERE id = ?2;
+        "#,
+    )
+    .bind(blocker_id)
+    .bind(blocked_id)
+    .fetch_optional(&mut *tx)
+    .await
+    .map_err(|e| AppError::Internal(format!("check dependency cycle: {}", e)))?;
+    if cycle.is_some() {
+        return Err(AppError::BadRequest(
+            "dependency would create a cycle".to_string(),
+        ));
+    }
+    let result = sqlx::query(
+        "INSERT OR IGNORE INTO task_dependencies (blocker_id, blocked_id, created_at)
+         VALUES (?1, ?2, ?3)",
+    )
+    .bind(blocker_id)
+    .bind(blocked_id)
+    .bind(Utc::now().timestamp())
+    .execute(&mut *tx)
+    .await
+    .map_err(|e| AppError::Internal(format!("add task dependency: {}", e)))?;
+    tx.commit()
+        .await
+        .map_err(|e| AppError::Internal(format!("commit add dependency: {}", e)))?;
+
+    Ok(result.rows_affected() == 1)
+}
+
+pub async fn remove_task_dependency(
+    pool: &SqlitePool,
+    blocker_id: &str,
+    blocked_id: &str,
+) -> Result<bool, AppError> {
+    let result =
+        sqlx::query("DELETE FROM task_dependencies WHERE blocker_id = ?1 AND blocked_id = ?2")
+            .bind(blocker_id)
+            .bind(blocked_id)
+            .execute(pool)
+            .await
+            .map_err(|e| AppError::Internal(format!("remove task dependency: {}", e)))?;
+
+    Ok(result.rows_affected() == 1)
+}
+
+// ---------------------------------------------------------------------------
+// Labels
+// ---------------------------------------------------------------------------
+
+/// A label definition. `task_labels.label` holds label ids.
+pub struct LabelRow {
+    pub id: String,
+    pub project_id: String,
+    pub name: String,
+    pub color: String,
+}
+
+fn label_from_row(row: &sqlx::sqlite::SqliteRow) -> LabelRow {
+    LabelRow {
+        id: row.get::<String, _>("id"),
+        project_id: row.get::<String, _>("project_id"),
+        name: row.get::<String, _>("name"),
+        color: row.get::<String, _>("color"),
+    }
+}
+
+fn label_write_error(err: sqlx::Error, what: &str) -> AppError {
+    if err.to_string().contains("UNIQUE") {
+        return AppError::Conflict("label already exists".to_string());
+    }
+    AppError::Internal(format!("{}: {}", what, err))
+}
+
+pub async fn create_label(
+    pool: &SqlitePool,
+    project_id: &str,
+    name: &str,
+    color: &str,
+) -> Result<String, AppError> {
+    let label_id = Uuid::new_v4().to_string();
+    sqlx::query(
+        r#"
+        INSERT INTO project_labels (id, project_id, name, color, created_at)
+        VALUES (?1, ?2, ?3, ?4, ?5);
+        "#,
+    )
+    .bind(&label_id)
+    .bind(project_id)
+    .bind(name)
+    .bind(color)
+    .bind(Utc::now().timestamp())
+    .execute(pool)
+    .await
+    .map_err(|e| label_write_error(e, "create label"))?;
+
+    Ok(label_id)
+}
+
+pub async fn list_labels(pool: &SqlitePool, project_id: &str) -> Result<Vec<LabelRow>, AppError> {
+    let rows = sqlx::query(
+        r#"
+        SELECT id, project_id, name, color
+        FROM project_labels
+        WHERE project_id = ?1
+        ORDER BY name COLLATE NOCASE ASC;
+        "#,
+    )
+    .bind(project_id)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| AppError::Internal(format!("list labels: {}", e)))?;
+
+    Ok(rows.iter().map(label_from_row).collect())
+}
+
+pub async fn get_label(pool: &SqlitePool, label_id: &str) -> Result<Option<LabelRow>, AppError> {
+    let row = sqlx::query("SELECT id, project_id, name, color FROM project_labels WHERE id = ?1")
+        .bind(label_id)
+        .fetch_optional(pool)
+        .await
+        .map_err(|e| AppError::Internal(format!("get label: {}", e)))?;
+
+    Ok(row.as_ref().map(label_from_row))
+}
+
+pub async fn update_label(
+    pool: &SqlitePool,
+    label_id: &str,
+    name: Option<&str>,
+    color: Option<&str>,
+) -> Result<(), AppError> {
+    sqlx::query(
+        r#"
+        UPDATE project_labels
+        SET name = COALESCE(?1, name), color = COALESCE(?2, color)
+        WHERE id = ?3;
+        "#,
+    )
+    .bind(name)
+    .bind(color)
+    .bind(label_id)
+    .execute(pool)
+    .await
+    .map_err(|e| label_write_error(e, "update label"))?;
+
+    Ok(())
+}
+
+/// Delete a label and take it off every task.
+pub async fn delete_label(pool: &SqlitePool, label_id: &str) -> Result<(), AppError> {
+    let mut tx = pool
+        .begin()
+        .await
+        .map_err(|e| AppError::Internal(format!("begin label deletion tx: {}", e)))?;
+    let statements = [
+        (
+            "DELETE FROM task_labels WHERE label = ?1",
+            "detach deleted label",
+        ),
+        ("DELETE FROM project_labels WHERE id = ?1", "delete label"),
+    ];
+    for (sql, what) in statements {
+        execute_for(&mut tx, sql, label_id, what).await?;
+    }
+    tx.commit()
+        .await
+        .map_err(|e| AppError::Internal(format!("commit label deletion: {}", e)))?;
+
+    Ok(())
+}
+
+pub async fn list_task_labels(pool: &SqlitePool, task_id: &str) -> Result<Vec<LabelRow>, AppError> {
+    let rows = sqlx::query(
+        r#"
+        SELECT l.id, l.project_id, l.name, l.color
+        FROM task_labels tl
+        JOIN project_labels l ON l.id = tl.label
+        WHERE tl.task_id = ?1
+        ORDER BY l.name COLLATE NOCASE ASC;
+        "#,
+    )
+    .bind(task_id)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| AppError::Internal(format!("list task labels: {}", e)))?;
+
+    Ok(rows.iter().map(label_from_row).collect())
+}
+
+/// Attach a label to a task; attaching it twice is a no-op.
+pub async fn attach_task_label(
+    pool: &SqlitePool,
+    task_id: &str,
+    label_id: &str,
+) -> Result<(), AppError> {
+    sqlx::query("INSERT OR IGNORE INTO task_labels (task_id, label) VALUES (?1, ?2)")
+        .bind(task_id)
+        .bind(label_id)
+        .execute(pool)
+        .await
+        .map_err(|e| AppError::Internal(format!("attach task label: {}", e)))?;
+
+    Ok(())
+}
+
+/// Returns false if the task did not carry the label.
+pub async fn detach_task_label(
+    pool: &SqlitePool,
+    task_id: &str,
+    label_id: &str,
+) -> Result<bool, AppError> {
+    let result = sqlx::query("DELETE FROM task_labels WHERE task_id = ?1 AND label = ?2")
+        .bind(task_id)
+        .bind(label_id)
+        .execute(pool)
+        .await
+        .map_err(|e| AppError::Internal(format!("detach task label: {}", e)))?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+// ---------------------------------------------------------------------------
+// Annotation CRUD
+// ---------------------------------------------------------------------------
+
+#[allow(clippy::too_many_arguments)]
+pub async fn create_annotation(
+    pool: &SqlitePool,
+    doc_path: &str,
+    org_id: &str,
+    project_id: Option<&str>,
+    user_id: &str,
+    range_start: i64,
+    range_end: i64,
+    content: &str,
+) -> Result<String, AppError> {
+    let annotation_id = Uuid::new_v4().to_string();
+    let now = Utc::now().timestamp();
+
+    sqlx::query(
+        r#"
+        INSERT INTO annotations (id, doc_path, org_id, project_id, user_id, range_start, range_end, content, resolved, created_at)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, 0, ?9);
+        "#,
+    )
+    .bind(&annotation_id)
+    .bind(doc_path)
+    .bind(org_id)
+    .bind(project_id)
+    .bind(user_id)
+    .bind(range_start)
+    .bind(range_end)
+    .bind(content)
+    .bind(now)
+    .execute(pool)
+    .await
+    .map_err(|e| AppError::Internal(format!("create annotation: {}", e)))?;
+
+    Ok(annotation_id)
+}
+
+/// Annotations on a document. Annotations made in a project are kept apart
+/// from org-level ones on the same path.
+pub async fn list_annotations(
+    pool: &SqlitePool,
+    doc_path: &str,
+    org_id: &str,
+    project_id: Option<&str>,
+) -> Result<Vec<AnnotationRow>, AppError> {
+    let rows = sqlx::query(
+        r#"
+        SELECT id, doc_path, org_id, project_id, user_id, range_start, range_end, content, resolved, created_at
+        FROM annotations
+        WHERE doc_path = ?1 AND org_id = ?2 AND project_id IS ?3
+        ORDER BY range_start ASC, created_at ASC;
+        "#,
+    )
+    .bind(doc_path)
+    .bind(org_id)
+    .bind(project_id)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| AppError::Internal(format!("list annotations: {}", e)))?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| AnnotationRow {
+            id: row.get::<String, _>("id"),
+            doc_path: row.get::<String, _>("doc_path"),
+            org_id: row.get::<String, _>("org_id"),
+            project_id: row.get::<Option<String>, _>("project_id"),
+            user_id: row.get::<String, _>("user_id"),
+            range_start: row.get::<i64, _>("range_start"),
+            range_end: row.get::<i64, _>("range_end"),
+            content: row.get::<String, _>("content"),
+            resolved: row.get::<i32, _>("resolved") != 0,
+            created_at: row.get::<i64, _>("created_at"),
+        })
+        .collect())
+}
+
+pub async fn create_annotation_reply(
+    pool: &SqlitePool,
+    annotation_id: &str,
+    user_id: &str,
+    content: &str,
+) -> Result<String, AppError> {
+    let reply_id = Uuid::new_v4().to_string();
+    let now = Utc::now().timestamp();
+
+    sqlx::query(
+        r#"
+        INSERT INTO annotation_replies (id, annotation_id, user_id, content, created_at)
+        VALUES (?1, ?2, ?3, ?4, ?5);
+        "#,
+    )
+    .bind(&reply_id)
+    .bind(annotation_id)
+    .bind(user_id)
+    .bind(content)
+    .bind(now)
+    .execute(pool)
+    .await
+    .map_err(|e| AppError::Internal(format!("create annotation reply: {}", e)))?;
+
+    Ok(reply_id)
+}
+
+pub async fn list_annotation_replies(
+    pool: &SqlitePool,
+    annotation_id: &str,
+) -> Result<Vec<(String, String, String, i64)>, AppError> {
+    let rows = sqlx::query(
+        r#"
+        SELECT id, user_id, content, created_at
+        FROM annotation_replies
+        WHERE annotation_id = ?1
+        ORDER BY created_at ASC;
+        "#,
+    )
+    .bind(annotation_id)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| AppError::Internal(format!("list annotation replies: {}", e)))?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| {
+            (
+                row.get::<String, _>("id"),
+                row.get::<String, _>("user_id"),
+                row.get::<String, _>("content"),
+                row.get::<i64, _>("created_at"),
+            )
+        })
+        .collect())
+}
+
+pub async fn get_annotation(
+    pool: &SqlitePool,
+    annotation_id: &str,
+) -> Result<Option<AnnotationRow>, AppError> {
+    let row = sqlx::query(
+        r#"
+        SELECT id, doc_path, org_id, project_id, user_id, range_start, range_end, content, resolved, created_at
+        FROM annotations
+        WHERE id = ?1;
+        "#,
+    )
+    .bind(annotation_id)
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| AppError::Internal(format!("get annotation: {}", e)))?;
+
+    Ok(row.map(|row| AnnotationRow {
+        id: row.get::<String, _>("id"),
+        doc_path: row.get::<String, _>("doc_path"),
+        org_id: row.get::<String, _>("org_id"),
+        project_id: row.get::<Option<String>, _>("project_id"),
+        user_id: row.get::<String, _>("user_id"),
+        range_start: row.get::<i64, _>("range_start"),
+        range_end: row.get::<i64, _>("range_end"),
+        content: row.get::<String, _>("content"),
+        resolved: row.get::<i32, _>("resolved") != 0,
+        created_at: row.get::<i64, _>("created_at"),
+    }))
+}
+
+pub async fn resolve_annotation(pool: &SqlitePool, annotation_id: &str) -> Result<(), AppError> {
+    sqlx::query(
+        r#"
+        UPDATE annotations
+        SET resolved = 1
+        WHERE id = ?1;
+        "#,
+    )
+    .bind(annotation_id)
+    .execute(pool)
+    .await
+    .map_err(|e| AppError::Internal(format!("resolve annotation: {}", e)))?;
+
+    Ok(())
+}
+
+// ---------------------------------------------------------------------------
+// Notification CRUD
+// ---------------------------------------------------------------------------
+
+pub async fn create_notification(
+    pool: &SqlitePool,
+    user_id: &str,
+    org_id: &str,
+    ntype: &str,
+    title: &str,
+    body: &str,
+    ref_id: &str,
+) -> Result<String, AppError> {
+    let notification_id = Uuid::new_v4().to_string();
+    let now = Utc::now().timestamp();
+
+    sqlx::query(
+        r#"
+        INSERT INTO notifications (id, user_id, org_id, type, title, body, ref_id, read, created_at)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, 0, ?8);
+        "#,
+    )
+    .bind(&notification_id)
+    .bind(user_id)
+    .bind(org_id)
+    .bind(ntype)
+    .bind(title)
+    .bind(body)
+    .bind(ref_id)
+    .bind(now)
+    .execute(pool)
+    .await
+    .map_err(|e| AppError::Internal(format!("create notification: {}", e)))?;
+
+    Ok(notification_id)
+}
+
+/// Whether `user_id` already has an unread notification identical to this one.
+pub async fn has_unread_notification(
+    pool: &SqlitePool,
+    user_id: &str,
+    ntype: &str,
+    ref_id: &str,
+    title: &str,
+    body: &str,
+) -> Result<bool, AppError> {
+    let row = sqlx::query(
+        r#"
+        SELECT 1
+        FROM notifications
+        WHERE user_id = ?1 AND type = ?2 AND ref_id = ?3 AND title = ?4 AND body = ?5 AND read = 0
+        LIMIT 1;
+        "#,
+    )
+    .bind(user_id)
+    .bind(ntype)
+    .bind(ref_id)
+    .bind(title)
+    .bind(body)
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| AppError::Internal(format!("query notification: {}", e)))?;
+
+    Ok(row.is_some())
+}
+
+/// The notification types `user_id` has turned off or back on, as
+/// `(type, enabled)` pairs. Types without a row are on.
+pub async fn list_notification_preferences(
+    pool: &SqlitePool,
+    user_id: &str,
+) -> Result<Vec<(String, bool)>, AppError> {
+    let rows = sqlx::query(
+        "SELECT type, enabled FROM notification_preferences WHERE user_id = ?1 ORDER BY type",
+    )
+    .bind(user_id)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| AppError::Internal(format!("list notification preferences: {}", e)))?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| {
+            (
+                row.get::<String, _>("type"),
+                row.get::<i32, _>("enabled") != 0,
+            )
+        })
+        .collect())
+}
+
+pub async fn set_notification_preference(
+    pool: &SqlitePool,
+    user_id: &str,
+    ntype: &str,
+    enabled: bool,
+) -> Result<(), AppError> {
+    sqlx::query(
+        r#"
+        INSERT INTO notification_preferences (user_id, type, enabled, updated_at)
+        VALUES (?1, ?2, ?3, ?4)
+        ON CONFLICT(user_id, type) DO UPDATE SET enabled = ?3, updated_at = ?4;
+        "#,
+    )
+    .bind(user_id)
+    .bind(ntype)
+    .bind(enabled as i32)
+    .bind(Utc::now().timestamp())
+    .execute(pool)
+    .await
+    .map_err(|e| AppError::Internal(format!("set notification preference: {}", e)))?;
+
+    Ok(())
+}
+
+pub async fn notification_enabled(
+    pool: &SqlitePool,
+    user_id: &str,
+    ntype: &str,
+) -> Result<bool, AppError> {
+    let enabled: Option<i32> = sqlx::query_scalar(
+        "SELECT enabled FROM notification_preferences WHERE user_id = ?1 AND type = ?2",
+    )
+    .bind(user_id)
+    .bind(ntype)
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| AppError::Internal(format!("query notification preference: {}", e)))?;
+
+    Ok(enabled.is_none_or(|enabled| enabled != 0))
+}
+
+/// An assigned, unfinished task with a due date, in a live project.
+pub struct DueTaskRow {
+    pub id: String,
+    pub project_id: String,
+    pub org_id: String,
+    pub title: String,
+    pub assignee_id: String,
+    pub due_date: i64,
+}
+
+/// Assigned tasks that are not done and fall due after `since` and by
+/// `until`, leaving out those whose assignee already got the overdue
+/// reminder. Tasks in archived projects or deleted orgs are left out too.
+pub async fn list_due_tasks(
+    pool: &SqlitePool,
+    since: i64,
+    until: i64,
+) -> Result<Vec<DueTaskRow>, AppError> {
+    let rows = sqlx::query(
+        r#"
+        SELECT t.id, t.project_id, p.org_id, t.title, t.assignee_id, t.due_date
+        FROM tasks t
+        JOIN projects p ON t.project_id = p.id
+        JOIN organizations o ON p.org_id = o.id
+        WHERE t.assignee_id IS NOT NULL
+          AND t.due_date > ?1
+          AND t.due_date <= ?2
+          AND t.status NOT IN (SELECT key FROM project_statuses
+                               WHERE project_id = t.project_id AND category = 'done')
+          AND NOT EXISTS (SELECT 1 FROM task_reminders r
+                          WHERE r.task_id = t.id AND r.user_id = t.assignee_id
+                            AND r.kind = 'overdue' AND r.due_date = t.due_date)
+          AND p.archived_at IS NULL
+          AND o.deleted_at IS NULL
+        ORDER BY t.due_date, t.id;
+        "#,
+    )
+    .bind(since)
+    .bind(until)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| AppError::Internal(format!("list due tasks: {}", e)))?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| DueTaskRow {
+            id: row.get("id"),
+            project_id: row.get("project_id"),
+            org_id: row.get("org_id"),
+            title: row.get("title"),
+            assignee_id: row.get("assignee_id"),
+            due_date: row.get("due_date"),
+        })
+        .collect())
+}
+
+/// Record that the `kind` reminder for this due date went to `user_id`.
+/// Returns false if it already had, so each reminder is sent once.
+pub async fn record_task_reminder(
+    pool: &SqlitePool,
+    task_id: &str,
+    user_id: &str,
+    kind: &str,
+    due_date: i64,
+    now: i64,
+) -> Result<bool, AppError> {
+    let result = sqlx::query(
+        r#"
+        INSERT OR IGNORE INTO task_reminders (task_id, user_id, kind, due_date, sent_at)
+        VALUES (?1, ?2, ?3, ?4, ?5);
+        "#,
+    )
+    .bind(task_id)
+    .bind(user_id)
+    .bind(kind)
+    .bind(due_date)
+    .bind(now)
+    .execute(pool)
+    .await
+    .map_err(|e| AppError::Internal(format!("record task reminder: {}", e)))?;
+
+    Ok(result.rows_affected() == 1)
+}
+
+pub async fn list_notifications(
+    pool: &SqlitePool,
+    user_id: &str,
+    limit: i64,
+) -> Result<Vec<NotificationRow>, AppError> {
+    let rows = sqlx::query(
+        r#"
+        SELECT id, user_id, org_id, type, title, body, ref_id, read, created_at
+        FROM notifications
+        WHERE user_id = ?1
+        ORDER BY created_at DESC
+        LIMIT ?2;
+        "#,
+    )
+    .bind(user_id)
+    .bind(limit)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| AppError::Internal(format!("list notifications: {}", e)))?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| NotificationRow {
+            id: row.get::<String, _>("id"),
+            user_id: row.get::<String, _>("user_id"),
+            org_id: row.get::<String, _>("org_id"),
+            ntype: row.get::<String, _>("type"),
+            title: row.get::<String, _>("title"),
+            body: row.get::<String, _>("body"),
+            ref_id: row.get::<String, _>("ref_id"),
+            read: row.get::<i32, _>("read") != 0,
+            created_at: row.get::<i64, _>("created_at"),
+        })
+        .collect())
+}
+
+pub async fn mark_notification_read(
+    pool: &SqlitePool,
+    notification_id: &str,
+    user_id: &str,
+) -> Result<(), AppError> {
+    sqlx::query(
+        r#"
+        UPDATE notifications
+        SET read = 1
+        WHERE id = ?1 AND user_id = ?2;
+        "#,
+    )
+    .bind(notification_id)
+    .bind(user_id)
+    .execute(pool)
+    .await
+    .map_err(|e| AppError::Internal(format!("mark notification read: {}", e)))?;
+
+    Ok(())
+}
+
+pub async fn mark_all_notifications_read(pool: &SqlitePool, user_id: &str) -> Result<(), AppError> {
+    sqlx::query(
+        r#"
+        UPDATE notifications
+        SET read = 1
+        WHERE user_id = ?1 AND read = 0;
+        "#,
+    )
+    .bind(user_id)
+    .execute(pool)
+    .await
+    .map_err(|e| AppError::Internal(format!("mark all notifications read: {}", e)))?;
+
+    Ok(())
+}
+
+pub async fn count_unread_notifications(pool: &SqlitePool, user_id: &str) -> Result<i64, AppError> {
+    let row = sqlx::query(
+        r#"
+        SELECT COUNT(*) as cnt
+        FROM notifications
+        WHERE user_id = ?1 AND read = 0;
+        "#,
+    )
+    .bind(user_id)
+    .fetch_one(pool)
+    .await
+    .map_err(|e| AppError::Internal(format!("count unread notifications: {}", e)))?;
+
+    Ok(row.get::<i64, _>("cnt"))
+}
+
+// ---------------------------------------------------------------------------
+// Document Registry
+// ---------------------------------------------------------------------------
+
+/// The project a registered document belongs to.
+pub async fn get_doc_project(pool: &SqlitePool, doc_id: &str) -> Result<Option<String>, AppError> {
+    sqlx::query_scalar("SELECT project_id FROM document_registry WHERE id = ?1")
+        .bind(doc_id)
+        .fetch_optional(pool)
+        .await
+        .map_err(|e| AppError::Internal(format!("get document project: {}", e)))
+}
+
+/// The projects in `org_id` that have a document registered at `rel_path`.
+pub async fn doc_path_projects(
+    pool: &SqlitePool,
+    org_id: &str,
+    rel_path: &str,
+) -> Result<Vec<String>, AppError> {
+    sqlx::query_scalar(
+        "SELECT d.project_id FROM document_registry d JOIN projects p ON d.project_id = p.id
+         WHERE p.org_id = ?1 AND d.rel_path = ?2",
+    )
+    .bind(org_id)
+    .bind(rel_path)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| AppError::Internal(format!("list document path projects: {}", e)))
+}
+
+/// Return existing doc_id for (project_id, rel_path), or create a new one.
+pub async fn resolve_or_create_doc(
+    pool: &SqlitePool,
+    project_id: &str,
+    rel_path: &str,
+    user_id: &str,
+) -> Result<String, AppError> {
+    // Fast path: lookup existing
+    let existing =
+        sqlx::query("SELECT id FROM document_registry WHERE project_id = ?1 AND rel_path = ?2")
+            .bind(project_id)
+            .bind(rel_path)
+            .fetch_optional(pool)
+            .await
+            .map_err(|e| AppError::Internal(format!("lookup document_registry: {}", e)))?;
+
+    if let Some(row) = existing {
+        return Ok(row.get::<String, _>("id"));
+    }
+
+    // Insert new
+    let doc_id = Uuid::new_v4().to_string();
+    let now = Utc::now().timestamp();
+
+    let result = sqlx::query(
+        r#"
+        INSERT INTO document_registry (id, project_id, rel_path, created_by, created_at)
+        VALUES (?1, ?2, ?3, ?4, ?5)
+        "#,
+    )
+    .bind(&doc_id)
+    .bind(project_id)
+    .bind(rel_path)
+    .bind(user_id)
+    .bind(now)
+    .execute(pool)
+    .await;
+
+    match result {
+        Ok(_) => Ok(doc_id),
+        Err(err) => {
+            // Race condition: another request inserted between our SELECT and INSERT.
+            // Re-query to get the winner's id.
+            if err.to_string().contains("UNIQUE") {
+                let row = sqlx::query(
+                    "SELECT id FROM document_registry WHERE project_id = ?1 AND rel_path = ?2",
+                )
+                .bind(project_id)
+                .bind(rel_path)
+                .fetch_one(pool)
+                .await
+                .map_err(|e| AppError::Internal(format!("re-lookup document_registry: {}", e)))?;
+                Ok(row.get::<String, _>("id"))
+            } else {
+                Err(AppError::Internal(format!(
+                    "insert document_registry: {}",
+                    err
+                )))
+            }
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Published Sites CRUD
+// ---------------------------------------------------------------------------
+
+/// Record the user's site as published from `workspace_id`, which puts
+/// the uploaded files online.
+pub async fn upsert_published_site(
+    pool: &SqlitePool,
+    user_id: &str,
+    site_url: &str,
+    workspace_id: &str,
+) -> Result<(), AppError> {
+    let now = chrono::Utc::now().timestamp();
+    sqlx::query(
+        "INSERT INTO published_sites (user_id, site_url, published_at, updated_at, workspace_id)
+         VALUES (?1, ?2, ?3, ?3, ?4)
+         ON CONFLICT(user_id) DO UPDATE
+         SET site_url = ?2, updated_at = ?3, workspace_id = ?4, pending = 0",
+    )
+    .bind(user_id)
+    .bind(site_url)
+    .bind(now)
+    .bind(workspace_id)
+    .execute(pool)
+    .await
+    .map_err(|e| AppError::Internal(format!("upsert published site: {}", e)))?;
+    Ok(())
+}
+
+pub async fn get_published_site(
+    pool: &SqlitePool,
+    user_id: &str,
+) -> Result<Option<(String, i64, i64)>, AppError> {
+    let row: Option<(String, i64, i64)> = sqlx::query_as(
+        "SELECT site_url, published_at, updated_at FROM published_sites WHERE user_id = ?",
+    )
+    .bind(user_id)
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| AppError::Internal(format!("get published site: {}", e)))?;
+    Ok(row)
+}
+
+/// Take the user's site offline until it is confirmed again, because its
+/// files are being changed.
+pub async fn mark_site_pending(pool: &SqlitePool, user_id: &str) -> Result<(), AppError> {
+    sqlx::query("UPDATE published_sites SET pending = 1 WHERE user_id = ?1")
+        .bind(user_id)
+        .execute(pool)
+        .await
+        .map_err(|e| AppError::Internal(format!("mark site pending: {}", e)))?;
+    Ok(())
+}
+
+/// Whether the user's site is confirmed and may be served.
+pub async fn site_is_live(pool: &SqlitePool, user_id: &str) -> Result<bool, AppError> {
+    let pending: Option<i64> =
+        sqlx::query_scalar("SELECT pending FROM published_sites WHERE user_id = ?1")
+            .bind(user_id)
+            .fetch_optional(pool)
+            .await
+            .map_err(|e| AppError::Internal(format!("check site live: {}", e)))?;
+    Ok(pending == Some(0))
+}
+
+pub async fn delete_published_site(pool: &SqlitePool, user_id: &str) -> Result<(), AppError> {
+    sqlx::query("DELETE FROM published_sites WHERE user_id = ?")
+        .bind(user_id)
+        .execute(pool)
+        .await
+        .map_err(|e| AppError::Internal(format!("delete published site: {}", e)))?;
+    Ok(())
+}
+
+// ---------------------------------------------------------------------------
+// Instance administration
+// ---------------------------------------------------------------------------
+
+/// `instance_settings` keys for the registration policy; see
+/// `crate::registration`.
+pub const SETTING_REGISTRATION_MODE: &str = "registration_mode";
+pub const SETTING_REGISTRATION_DOMAINS: &str = "registration_domains";
+
+pub struct AdminUserRow {
+    pub id: String,
+    pub email: String,
+    pub created_at: i64,
+    pub email_verified: bool,
+    pub is_admin: bool,
+    pub disabled_at: Option<i64>,
+    pub workspace_count: i64,
+    pub last_seen_at: Option<i64>,
+}
+
+const ADMIN_USER_COLUMNS: &str = r#"
+    SELECT u.id, u.email, u.created_at, u.email_verified_at, u.is_admin, u.disabled_at,
+           (SELECT COUNT(*) FROM workspaces w WHERE w.owner_id = u.id) AS workspace_count,
+           (SELECT MAX(s.last_used_at) FROM sessions s WHERE s.user_id = u.id) AS last_seen_at
+    FROM users u
+"#;
+
+fn admin_user_from_row(row: &sqlx::sqlite::SqliteRow) -> AdminUserRow {
+    AdminUserRow {
+        id: row.get::<String, _>("id"),
+        email: row.get::<String, _>("email"),
+        created_at: row.get::<i64, _>("created_at"),
+        email_verified: row.get::<Option<i64>, _>("email_verified_at").is_some(),
+        is_admin: row.get::<i32, _>("is_admin") != 0,
+        disabled_at: row.get::<Option<i64>, _>("disabled_at"),
+        workspace_count: row.get::<i64, _>("workspace_count"),
+        last_seen_at: row.get::<Option<i64>, _>("last_seen_at"),
+    }
+}
+
+/// Users whose email contains `search` (all users when `None`), oldest
+/// first, with the total number of matches.
+pub async fn list_users(
+    pool: &SqlitePool,
+    search: Option<&str>,
+    limit: i64,
+    offset: i64,
+) -> Result<(Vec<AdminUserRow>, i64), AppError> {
+    let rows = sqlx::query(&format!(
+        "{} WHERE (?1 IS NULL OR instr(u.email, ?1) > 0)
+         ORDER BY u.created_at ASC, u.id ASC
+         LIMIT ?2 OFFSET ?3",
+        ADMIN_USER_COLUMNS
+    ))
+    .bind(search)
+    .bind(limit)
+    .bind(offset)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| AppError::Internal(format!("list users: {}", e)))?;
+    let total: i64 =
+        sqlx::query_scalar("SELECT COUNT(*) FROM users WHERE (?1 IS NULL OR instr(email, ?1) > 0)")
+            .bind(search)
+            .fetch_one(pool)
+            .await
+            .map_err(|e| AppError::Internal(format!("count users: {}", e)))?;
+
+    Ok((rows.iter().map(admin_user_from_row).collect(), total))
+}
+
+pub async fn get_admin_user(
+    pool: &SqlitePool,
+    user_id: &str,
+) -> Result<Option<AdminUserRow>, AppError> {
+    let row = sqlx::query(&format!("{} WHERE u.id = ?1", ADMIN_USER_COLUMNS))
+        .bind(user_id)
+        .fetch_optional(pool)
+        .await
+        .map_err(|e| AppError::Internal(format!("get user: {}", e)))?;
+
+    Ok(row.as_ref().map(admin_user_from_row))
+}
+
+pub async fn is_user_admin(pool: &SqlitePool, user_id: &str) -> Result<bool, AppError> {
+    let row = sqlx::query("SELECT is_admin FROM users WHERE id = ?1")
+        .bind(user_id)
+        .fetch_optional(pool)
+        .await
+        .map_err(|e| AppError::Internal(format!("check admin flag: {}", e)))?;
+
+    Ok(row
+        .map(|row| row.get::<i32, _>("is_admin") != 0)
+        .unwrap_or(false))
+}
+
+/// Returns whether the user exists.
+pub async fn set_user_admin(
+    pool: &SqlitePool,
+    user_id: &str,
+    is_admin: bool,
+) -> Result<bool, AppError> {
+    let result = sqlx::query("UPDATE users SET is_admin = ?1 WHERE id = ?2")
+        .bind(is_admin)
+        .bind(user_id)
+        .execute(pool)
+        .await
+        .map_err(|e| AppError::Internal(format!("set admin flag: {}", e)))?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+pub async fn is_user_disabled(pool: &SqlitePool, user_id: &str) -> Result<bool, AppError> {
+    let row = sqlx::query("SELECT disabled_at FROM users WHERE id = ?1")
+        .bind(user_id)
+        .fetch_optional(pool)
+        .await
+        .map_err(|e| AppError::Internal(format!("check disabled flag: {}", e)))?;
+
+    Ok(row
+        .map(|row| row.get::<Option<i64>, _>("disabled_at").is_some())
+        .unwrap_or(false))
+}
+
+/// Returns whether the user exists.
+pub async fn set_user_disabled(
+    pool: &SqlitePool,
+    user_id: &str,
+    disabled: bool,
+) -> Result<bool, AppError> {
+    let disabled_at = disabled.then(|| Utc::now().timestamp());
+    let result = sqlx::query("UPDATE users SET disabled_at = ?1 WHERE id = ?2")
+        .bind(disabled_at)
+        .bind(user_id)
+        .execute(pool)
+        .await
+        .map_err(|e| AppError::Internal(format!("set disabled flag: {}", e)))?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+pub async fn owned_workspace_ids(
+    pool: &SqlitePool,
+    user_id: &str,
+) -> Result<Vec<String>, AppError> {
+    sqlx::query_scalar("SELECT id FROM workspaces WHERE owner_id = ?1")
+        .bind(user_id)
+        .fetch_all(pool)
+        .await
+        .map_err(|e| AppError::Internal(format!("list owned workspaces: {}", e)))
+}
+
+pub async fn get_setting(pool: &SqlitePool, key: &str) -> Result<Option<String>, AppError> {
+    sqlx::query_scalar("SELECT value FROM instance_settings WHERE key = ?1")
+        .bind(key)
+        .fetch_optional(pool)
+        .await
+        .map_err(|e| AppError::Internal(format!("get setting {}: {}", key, e)))
+}
+
+pub async fn set_setting(pool: &SqlitePool, key: &str, value: &str) -> Result<(), AppError> {
+    sqlx::query(
+        r#"
+        INSERT INTO instance_settings (key, value, updated_at)
+        VALUES (?1, ?2, ?3)
+        ON CONFLICT(key) DO UPDATE SET value = excluded.value, updated_at = excluded.updated_at;
+        "#,
+    )
+    .bind(key)
+    .bind(value)
+    .bind(Utc::now().timestamp())
+    .execute(pool)
+    .await
+    .map_err(|e| AppError::Internal(format!("set setting {}: {}", key, e)))?;
+
+    Ok(())
+}
+
+// ---------------------------------------------------------------------------
+// Invite codes
+// ---------------------------------------------------------------------------
+
+pub struct InviteCodeRow {
+    pub id: String,
+    pub created_by: String,
+    pub org_id: Option<String>,
+    pub role: Option<String>,
+    pub created_at: i64,
+    pub expires_at: Option<i64>,
+    pub used_at: Option<i64>,
+    pub used_by: Option<String>,
+}
+
+const INVITE_CODE_COLUMNS: &str =
+    "id, created_by, org_id, role, created_at, expires_at, used_at, used_by";
+
+fn invite_code_from_row(row: &sqlx::sqlite::SqliteRow) -> InviteCodeRow {
+    InviteCodeRow {
+        id: row.get::<String, _>("id"),
+        created_by: row.get::<String, _>("created_by"),
+        org_id: row.get::<Option<String>, _>("org_id"),
+        role: row.get::<Option<String>, _>("role"),
+        created_at: row.get::<i64, _>("created_at"),
+        expires_at: row.get::<Option<i64>, _>("expires_at"),
+        used_at: row.get::<Option<i64>, _>("used_at"),
+        used_by: row.get::<Option<String>, _>("used_by"),
+    }
+}
+
+pub async fn create_invite_code(
+    pool: &SqlitePool,
+    code_hash: &str,
+    created_by: &str,
+    org_id: Option<&str>,
+    role: Option<&str>,
+    expires_at: Option<i64>,
+) -> Result<InviteCodeRow, AppError> {
+    let row = sqlx::query(&format!(
+        r#"
+        INSERT INTO invite_codes (id, code_hash, created_by, org_id, role, created_at, expires_at)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+        RETURNING {};
+        "#,
+        INVITE_CODE_COLUMNS
+    ))
+    .bind(Uuid::new_v4().to_string())
+    .bind(code_hash)
+    .bind(created_by)
+    .bind(org_id)
+    .bind(role)
+    .bind(Utc::now().timestamp())
+    .bind(expires_at)
+    .fetch_one(pool)
+    .await
+    .map_err(|e| AppError::Internal(format!("create invite code: {}", e)))?;
+
+    Ok(invite_code_from_row(&row))
+}
+
+/// Invite codes for `org_id`, or all of them when `None`, newest first.
+pub async fn list_invite_codes(
+    pool: &SqlitePool,
+    org_id: Option<&str>,
+) -> Result<Vec<InviteCodeRow>, AppError> {
+    let rows = sqlx::query(&format!(
+        "SELECT {} FROM invite_codes
+         WHERE (?1 IS NULL OR org_id = ?1)
+         ORDER BY created_at DESC",
+        INVITE_CODE_COLUMNS
+    ))
+    .bind(org_id)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| AppError::Internal(format!("list invite codes: {}", e)))?;
+
+    Ok(rows.iter().map(invite_code_from_row).collect())
+}
+
+pub async fn get_invite_code(
+    pool: &SqlitePool,
+    invite_id: &str,
+) -> Result<Option<InviteCodeRow>, AppError> {
+    let row = sqlx::query(&format!(
+        "SELECT {} FROM invite_codes WHERE id = ?1",
+        INVITE_CODE_COLUMNS
+    ))
+    .bind(invite_id)
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| AppError::Internal(format!("get invite code: {}", e)))?;
+
+    Ok(row.as_ref().map(invite_code_from_row))
+}
+
+pub async fn delete_invite_code(pool: &SqlitePool, invite_id: &str) -> Result<(), AppError> {
+    sqlx::query("DELETE FROM invite_codes WHERE id = ?1")
+        .bind(invite_id)
+        .execute(pool)
+        .await
+        .map_err(|e| AppError::Internal(format!("delete invite code: {}", e)))?;
+
+    Ok(())
+}
+
+/// Mark an unused, unexpired code as used and return it. `None` if the
+/// code is unknown, expired or already taken.
+pub async fn claim_invite_code(
+    pool: &SqlitePool,
+    code_hash: &str,
+) -> Result<Option<InviteCodeRow>, AppError> {
+    let row = sqlx::query(&format!(
+        r#"
+        UPDATE invite_codes
+        SET used_at = ?1
+        WHERE code_hash = ?2 AND used_at IS NULL AND (expires_at IS NULL OR expires_at > ?1)
+        RETURNING {};
+        "#,
+        INVITE_CODE_COLUMNS
+    ))
+    .bind(Utc::now().timestamp())
+    .bind(code_hash)
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| AppError::Internal(format!("claim invite code: {}", e)))?;
+
+    Ok(row.as_ref().map(invite_code_from_row))
+}
+
+/// Record who redeemed a claimed code, or hand it back (`user_id` `None`)
+/// when the registration it was claimed for failed.
+pub async fn finish_invite_code(
+    pool: &SqlitePool,
+    invite_id: &str,
+    user_id: Option<&str>,
+) -> Result<(), AppError> {
+    sqlx::query(
+        r#"
+        UPDATE invite_codes
+        SET used_by = ?1, used_at = CASE WHEN ?1 IS NULL THEN NULL ELSE used_at END
+        WHERE id = ?2;
+        "#,
+    )
+    .bind(user_id)
+    .bind(invite_id)
+    .execute(pool)
+    .await
+    .map_err(|e| AppError::Internal(format!("finish invite code: {}", e)))?;
+
+    Ok(())
+}
+
+// ---------------------------------------------------------------------------
+// Audit log
+// ---------------------------------------------------------------------------
+
+pub struct AuditRow {
+    pub id: i64,
+    pub created_at: i64,
+    pub action: String,
+    pub actor_id: Option<String>,
+    pub actor_email: Option<String>,
+    pub ip: Option<String>,
+    pub org_id: Option<String>,
+    pub target_type: String,
+    pub target_id: String,
+    pub details: serde_json::Value,
+}
+
+/// Filters for [`list_audit_events`]; `None` matches everything. Entries
+/// come newest first, and `before` continues from the previous page's last
+/// id.
+#[derive(Debug, Default)]
+pub struct AuditFilter {
+    pub org_id: Option<String>,
+    pub action: Option<String>,
+    pub actor_id: Option<String>,
+    pub target_type: Option<String>,
+    pub target_id: Option<String>,
+    pub since: Option<i64>,
+    pub until: Option<i64>,
+    pub before: Option<i64>,
+    pub limit: i64,
+}
+
+pub async fn insert_audit_event(
+    pool: &SqlitePool,
+    event: &crate::audit::AuditEvent,
+) -> Result<(), AppError> {
+    sqlx::query(
+        r#"
+        INSERT INTO audit_log
+            (created_at, action, actor_id, ip, org_id, target_type, target_id, details)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8);
+        "#,
+    )
+    .bind(Utc::now().timestamp())
+    .bind(event.action)
+    .bind(&event.actor_id)
+    .bind(&event.ip)
+    .bind(&event.org_id)
+    .bind(event.target_type)
+    .bind(&event.target_id)
+    .bind(event.details.to_string())
+    .execute(pool)
+    .await
+    .map_err(|e| AppError::Internal(format!("insert audit event: {}", e)))?;
+
+    Ok(())
+}
+
+pub async fn list_audit_events(
+    pool: &SqlitePool,
+    filter: &AuditFilter,
+) -> Result<Vec<AuditRow>, AppError> {
+    let rows = sqlx::query(
+        r#"
+        SELECT a.id, a.created_at, a.action, a.actor_id, u.email AS actor_email, a.ip,
+               a.org_id, a.target_type, a.target_id, a.details
+        FROM audit_log a
+        LEFT JOIN users u
+          ON a.actor_id = u.id
+        WHERE (?1 IS NULL OR a.org_id = ?1)
+          AND (?2 IS NULL OR a.action = ?2)
+          AND (?3 IS NULL OR a.actor_id = ?3)
+          AND (?4 IS NULL OR a.target_type = ?4)
+          AND (?5 IS NULL OR a.target_id = ?5)
+          AND (?6 IS NULL OR a.created_at >= ?6)
+          AND (?7 IS NULL OR a.created_at < ?7)
+          AND (?8 IS NULL OR a.id < ?8)
+        ORDER BY a.id DESC
+        LIMIT ?9;
+        "#,
+    )
+    .bind(&filter.org_id)
+    .bind(&filter.action)
+    .bind(&filter.actor_id)
+    .bind(&filter.target_type)
+    .bind(&filter.target_id)
+    .bind(filter.since)
+    .bind(filter.until)
+    .bind(filter.before)
+    .bind(filter.limit)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| AppError::Internal(format!("list audit events: {}", e)))?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| AuditRow {
+            id: row.get::<i64, _>("id"),
+            created_at: row.get::<i64, _>("created_at"),
+            action: row.get::<String, _>("action"),
+            actor_id: row.get::<Option<String>, _>("actor_id"),
+            actor_email: row.get::<Option<String>, _>("actor_email"),
+            ip: row.get::<Option<String>, _>("ip"),
+            org_id: row.get::<Option<String>, _>("org_id"),
+            target_type: row.get::<String, _>("target_type"),
+            target_id: row.get::<String, _>("target_id"),
+            details: serde_json::from_str(&row.get::<String, _>("details"))
+                .unwrap_or(serde_json::Value::Null),
+        })
+        .collect())
+}
+
+// ---------------------------------------------------------------------------
+// Account export & deletion
+// ---------------------------------------------------------------------------
+
+/// Queries for the personal-data export, one archive file each. Every query
+/// binds the user id as `?1`. Secrets (password, TOTP and token hashes,
+/// wrapped workspace keys) are left out.
+const EXPORT_QUERIES: &[(&str, &str)] = &[
+    (
+        "profile",
+        "SELECT id, email, created_at, email_verified_at, totp_enabled
+         FROM users WHERE id = ?1",
+    ),
+    (
+        "identities",
+        "SELECT issuer, subject, email, created_at, last_login_at
+         FROM user_identities WHERE user_id = ?1",
+    ),
+    (
+        "sessions",
+        "SELECT id, device_name, ip, created_at, last_used_at, expires_at, revoked_at
+         FROM sessions WHERE user_id = ?1 ORDER BY created_at",
+    ),
+    (
+        "app_tokens",
+        "SELECT id, name, kind, scopes, expires_at, revoked_at, last_used_at, last_used_ip,
+                created_at
+         FROM app_tokens WHERE user_id = ?1 ORDER BY created_at",
+    ),
+    (
+        "workspaces",
+        "SELECT w.id, w.name, w.owner_id, w.e2ee, w.created_at, m.role
+         FROM workspaces w JOIN workspace_members m ON w.id = m.workspace_id
+         WHERE m.user_id = ?1 ORDER BY w.created_at",
+    ),
+    (
+        "device_keys",
+        "SELECT workspace_id, device_id, device_name, public_key, created_at, enrolled_at
+         FROM workspace_device_keys WHERE user_id = ?1 ORDER BY created_at",
+    ),
+    (
+        "organizations",
+        "SELECT o.id, o.name, o.owner_id, o.created_at, m.role, m.joined_at
+         FROM organizations o JOIN org_members m ON o.id = m.org_id
+         WHERE m.user_id = ?1 ORDER BY m.joined_at",
+    ),
+    (
+        "project_members",
+        "SELECT project_id, role, added_at FROM project_members WHERE user_id = ?1
+         ORDER BY added_at",
+    ),
+    (
+        "tasks",
+        "SELECT * FROM tasks WHERE created_by = ?1 OR assignee_id = ?1 ORDER BY created_at",
+    ),
+    (
+        "task_comments",
+        "SELECT * FROM task_comments WHERE user_id = ?1 ORDER BY created_at",
+    ),
+    (
+        "task_activity",
+        "SELECT * FROM task_activity WHERE actor_id = ?1 ORDER BY id",
+    ),
+    (
+        "annotations",
+        "SELECT * FROM annotations WHERE user_id = ?1 ORDER BY created_at",
+    ),
+    (
+        "annotation_replies",
+        "SELECT * FROM annotation_replies WHERE user_id = ?1 ORDER BY created_at",
+    ),
+    (
+        "notifications",
+        "SELECT * FROM notifications WHERE user_id = ?1 ORDER BY created_at",
+    ),
+    (
+        "notification_preferences",
+        "SELECT type, enabled, updated_at FROM notification_preferences WHERE user_id = ?1
+         ORDER BY type",
+    ),
+    (
+        "published_site",
+        "SELECT site_url, published_at, updated_at FROM published_sites WHERE user_id = ?1",
+    ),
+    (
+        "invite_codes",
+        "SELECT id, org_id, role, created_at, expires_at, used_at
+         FROM invite_codes WHERE created_by = ?1 ORDER BY created_at",
+    ),
+    (
+        "org_invitations",
+        "SELECT id, org_id, email, role, created_at, expires_at, accepted_at, declined_at,
+                revoked_at
+         FROM org_invitations WHERE invited_by = ?1 OR accepted_by = ?1 ORDER BY created_at",
+    ),
+];
+
+fn row_to_json(row: &sqlx::sqlite::SqliteRow) -> serde_json::Value {
+    use sqlx::{Column, TypeInfo, ValueRef};
+
+    let mut object = serde_json::Map::new();
+    for column in row.columns() {
+        let index = column.ordinal();
+        let kind = match row.try_get_raw(index) {
+            Ok(raw) if !raw.is_null() => raw.type_info().name().to_string(),
+            _ => "NULL".to_string(),
+        };
+        let value = match kind.as_str() {
+            "INTEGER" => serde_json::Value::from(row.get::<i64, _>(index)),
+            "REAL" => serde_json::Value::from(row.get::<f64, _>(index)),
+            "TEXT" => serde_json::Value::from(row.get::<String, _>(index)),
+            _ => serde_json::Value::Null,
+        };
+        object.insert(column.name().to_string(), value);
+    }
+    serde_json::Value::Object(object)
+}
+
+/// Everything stored about a user, as `(section, rows)` pairs.
+pub async fn export_user_data(
+    pool: &SqlitePool,
+    user_id: &str,
+) -> Result<Vec<(&'static str, Vec<serde_json::Value>)>, AppError> {
+    let mut sections = Vec::with_capacity(EXPORT_QUERIES.len());
+    for (name, sql) in EXPORT_QUERIES {
+        let rows = sqlx::query(sql)
+            .bind(user_id)
+            .fetch_all(pool)
+            .await
+            .map_err(|e| AppError::Internal(format!("export {}: {}", name, e)))?;
+        sections.push((*name, rows.iter().map(row_to_json).collect()));
+    }
+    Ok(sections)
+}
+
+async fn execute_for(
+    conn: &mut sqlx::SqliteConnection,
+    sql: &str,
+    id: &str,
+    what: &str,
+) -> Result<(), AppError> {
+    sqlx::query(sql)
+        .bind(id)
+        .execute(conn)
+        .await
+        .map_err(|e| AppError::Internal(format!("{}: {}", what, e)))?;
+    Ok(())
+}
+
+/// Delete an organization and everything in it. Returns the ids of the
+/// deleted documents, whose collab state lives on disk.
+pub async fn purge_org(
+    conn: &mut sqlx::SqliteConnection,
+    org_id: &str,
+) -> Result<Vec<String>, AppError> {
+    let doc_ids: Vec<String> = sqlx::query_scalar(
+        "SELECT d.id FROM document_registry d JOIN projects p ON d.project_id = p.id
+         WHERE p.org_id = ?1",
+    )
+    .bind(org_id)
+    .fetch_all(&mut *conn)
+    .await
+    .map_err(|e| AppError::Internal(format!("list org documents: {}", e)))?;
+    let statements = [
+        (
+            "DELETE FROM task_labels WHERE task_id IN
+               (SELECT t.id FROM tasks t JOIN projects p ON t.project_id = p.id
+                WHERE p.org_id = ?1)",
+            "purge org task labels",
+        ),
+        (
+            "DELETE FROM task_comments WHERE task_id IN
+               (SELECT t.id FROM tasks t JOIN projects p ON t.project_id = p.id
+                WHERE p.org_id = ?1)",
+            "purge org task comments",
+        ),
+        (
+            "DELETE FROM task_activity WHERE task_id IN
+               (SELECT t.id FROM tasks t JOIN projects p ON t.project_id = p.id
+                WHERE p.org_id = ?1)",
+            "purge org task activity",
+        ),
+        (
+            "DELETE FROM task_watchers WHERE task_id IN
+               (SELECT t.id FROM tasks t JOIN projects p ON t.project_id = p.id
+                WHERE p.org_id = ?1)",
+            "purge org task watchers",
+        ),
+        (
+            "DELETE FROM task_reminders WHERE task_id IN
+               (SELECT t.id FROM tasks t JOIN projects p ON t.project_id = p.id
+                WHERE p.org_id = ?1)",
+            "purge org task reminders",
+        ),
+        (
+            "DELETE FROM task_dependencies WHERE blocker_id IN
+               (SELECT t.id FROM tasks t JOIN projects p ON t.project_id = p.id
+                WHERE p.org_id = ?1)",
+            "purge org task dependencies",
+        ),
+        (
+            "DELETE FROM tasks WHERE project_id IN (SELECT id FROM projects WHERE org_id = ?1)",
+            "purge org tasks",
+        ),
+        (
+            "DELETE FROM project_labels
+             WHERE project_id IN (SELECT id FROM projects WHERE org_id = ?1)",
+            "purge org labels",
+        ),
+        (
+            "DELETE FROM project_statuses
+             WHERE project_id IN (SELECT id FROM projects WHERE org_id = ?1)",
+            "purge org statuses",
+        ),
+        (
+            "DELETE FROM project_priorities
+             WHERE project_id IN (SELECT id FROM projects WHERE org_id = ?1)",
+            "purge org priorities",
+        ),
+        (
+            "DELETE FROM document_registry
+             WHERE project_id IN (SELECT id FROM projects WHERE org_id = ?1)",
+            "purge org documents",
+        ),
+        (
+            "DELETE FROM project_members
+             WHERE project_id IN (SELECT id FROM projects WHERE org_id = ?1)",
+            "purge org project members",
+        ),
+        (
+            "DELETE FROM projects WHERE org_id = ?1",
+            "purge org projects",
+        ),
+        (
+            "DELETE FROM annotation_replies
+             WHERE annotation_id IN (SELECT id FROM annotations WHERE org_id = ?1)",
+            "purge org annotation replies",
+        ),
+        (
+            "DELETE FROM annotations WHERE org_id = ?1",
+            "purge org annotations",
+        ),
+        (
+            "DELETE FROM notifications WHERE org_id = ?1",
+            "purge org notifications",
+        ),
+        (
+            "DELETE FROM invite_codes WHERE org_id = ?1",
+            "purge org invite codes",
+        ),
+        (
+            "DELETE FROM org_invitations WHERE org_id = ?1",
+            "purge org invitations",
+        ),
+        (
+            "DELETE FROM org_members WHERE org_id = ?1",
+            "purge org members",
+        ),
+        (
+            "DELETE FROM organizations WHERE id = ?1",
+            "purge organization",
+        ),
+    ];
+    for (sql, what) in statements {
+        execute_for(conn, sql, org_id, what).await?;
+    }
+    Ok(doc_ids)
+}
+
+/// What went with a deleted account and still has files on disk for the
+/// caller to remove.
+pub struct DeletedAccount {
+    pub workspaces: Vec<String>,
+    pub collab_docs: Vec<String>,
+}
+
+/// Delete a user account in one transaction and return the workspaces and
+/// collaborative documents that were deleted with it.
+///
+/// Owned workspaces and organizations go to the longest-standing other
+/// member — for organizations an admin if there is one, else a non-guest
+/// member who is promoted to admin — and are deleted when there is nobody
+/// to take them over. Tasks and documents the user created stay with their
+/// organization; assignments are cleared, the user's annotations, replies
+/// and task comments are deleted, and task activity no longer names them.
+pub async fn delete_user_account(
+    pool: &SqlitePool,
+    user_id: &str,
+) -> Result<DeletedAccount, AppError> {
+    let mut tx = pool
+        .begin()
+        .await
+        .map_err(|e| AppError::Internal(format!("begin account deletion tx: {}", e)))?;
+
+    let owned_workspaces: Vec<String> =
+        sqlx::query_scalar("SELECT id FROM workspaces WHERE owner_id = ?1")
+            .bind(user_id)
+            .fetch_all(&mut *tx)
+            .await
+            .map_err(|e| AppError::Internal(format!("list owned workspaces: {}", e)))?;
+    let mut deleted_workspaces = Vec::new();
+    for workspace_id in owned_workspaces {
+        let heir: Option<String> = sqlx::query_scalar(
+            r#"
+            SELECT user_id
+            FROM workspace_members
+            WHERE workspace_id = ?1 AND user_id != ?2
+            ORDER BY created_at ASC
+            LIMIT 1;
+            "#,
+        )
+        .bind(&workspace_id)
+        .bind(user_id)
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(|e| AppError::Internal(format!("find workspace heir: {}", e)))?;
+        match heir {
+            Some(heir) => {
+                sqlx::query("UPDATE workspaces SET owner_id = ?1 WHERE id = ?2")
+                    .bind(&heir)
+                    .bind(&workspace_id)
+                    .execute(&mut *tx)
+                    .await
+                    .map_err(|e| AppError::Internal(format!("transfer workspace: {}", e)))?;
+                sqlx::query(
+                    "UPDATE workspace_members SET role = 'owner'
+                     WHERE workspace_id = ?1 AND user_id = ?2",
+                )
+                .bind(&workspace_id)
+                .bind(&heir)
+                .execute(&mut *tx)
+                .await
+                .map_err(|e| AppError::Internal(format!("promote workspace heir: {}", e)))?;
+            }
+            None => {
+                for (sql, what) in [
+                    (
+                        "DELETE FROM workspace_device_keys WHERE workspace_id = ?1",
+                        "delete workspace device keys",
+                    ),
+                    (
+                        "DELETE FROM workspace_members WHERE workspace_id = ?1",
+                        "delete workspace members",
+                    ),
+                    ("DELETE FROM workspaces WHERE id = ?1", "delete workspace"),
+                ] {
+                    execute_for(&mut tx, sql, &workspace_id, what).await?;
+                }
+                deleted_workspaces.push(workspace_id);
+            }
+        }
+    }
+
+    let mut collab_docs = Vec::new();
+    let owned_orgs: Vec<String> =
+        sqlx::query_scalar("SELECT id FROM organizations WHERE owner_id = ?1")
+            .bind(user_id)
+            .fetch_all(&mut *tx)
+            .await
+            .map_err(|e| AppError::Internal(format!("list owned organizations: {}", e)))?;
+    for org_id in owned_orgs {
+        let heir: Option<String> = sqlx::query_scalar(
+            r#"
+            SELECT user_id
+            FROM org_members
+            WHERE org_id = ?1 AND user_id != ?2 AND role != 'guest'
+            ORDER BY role = 'admin' DESC, joined_at ASC
+            LIMIT 1;
+            "#,
+        )
+        .bind(&org_id)
+        .bind(user_id)
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(|e| AppError::Internal(format!("find organization heir: {}", e)))?;
+        match heir {
+            Some(heir) => {
+                sqlx::query("UPDATE organizations SET owner_id = ?1 WHERE id = ?2")
+                    .bind(&heir)
+                    .bind(&org_id)
+                    .execute(&mut *tx)
+                    .await
+                    .map_err(|e| AppError::Internal(format!("transfer organization: {}", e)))?;
+                sqlx::query(
+                    "UPDATE org_members SET role = 'admin' WHERE org_id = ?1 AND user_id = ?2",
+                )
+                .bind(&org_id)
+                .bind(&heir)
+                .execute(&mut *tx)
+                .await
+                .map_err(|e| AppError::Internal(format!("promote organization heir: {}", e)))?;
+            }
+            None => collab_docs.extend(purge_org(&mut tx, &org_id).await?),
+        }
+    }
+
+    let statements = [
+        (
+            "DELETE FROM workspace_members WHERE user_id = ?1",
+            "delete workspace memberships",
+        ),
+        (
+            "DELETE FROM workspace_device_keys WHERE user_id = ?1",
+            "delete device keys",
+        ),
+        (
+            "DELETE FROM org_members WHERE user_id = ?1",
+            "delete org memberships",
+        ),
+        (
+            "DELETE FROM project_members WHERE user_id = ?1",
+            "delete project memberships",
+        ),
+        (
+            "UPDATE tasks SET assignee_id = NULL WHERE assignee_id = ?1",
+            "unassign tasks",
+        ),
+        (
+            "UPDATE task_comments SET parent_id = NULL
+             WHERE parent_id IN (SELECT id FROM task_comments WHERE user_id = ?1)",
+            "detach replies to task comments",
+        ),
+        (
+            "DELETE FROM task_comments WHERE user_id = ?1",
+            "delete task comments",
+        ),
+        (
+            "UPDATE task_activity SET actor_id = NULL WHERE actor_id = ?1",
+            "detach task activity",
+        ),
+        (
+            "DELETE FROM task_watchers WHERE user_id = ?1",
+            "delete task watches",
+        ),
+        (
+            "DELETE FROM task_reminders WHERE user_id = ?1",
+            "delete task reminders",
+        ),
+        (
+            "DELETE FROM notification_preferences WHERE user_id = ?1",
+            "delete notification preferences",
+        ),
+        (
+            "DELETE FROM annotation_replies
+             WHERE user_id = ?1
+                OR annotation_id IN (SELECT id FROM annotations WHERE user_id = ?1)",
+            "delete annotation replies",
+        ),
+        (
+            "DELETE FROM annotations WHERE user_id = ?1",
+            "delete annotations",
+        ),
+        (
+            "DELETE FROM notifications WHERE user_id = ?1",
+            "delete notifications",
+        ),
+        (
+            "DELETE FROM published_sites WHERE user_id = ?1",
+            "delete published site",
+        ),
+        (
+            "DELETE FROM refresh_tokens
+             WHERE session_id IN (SELECT id FROM sessions WHERE user_id = ?1)",
+            "delete refresh tokens",
+        ),
+        ("DELETE FROM sessions WHERE user_id = ?1", "delete sessions"),
+        (
+            "DELETE FROM app_tokens WHERE user_id = ?1",
+            "delete app tokens",
+        ),
+        (
+            "DELETE FROM recovery_codes WHERE user_id = ?1",
+            "delete recovery codes",
+        ),
+        (
+            "DELETE FROM email_tokens WHERE user_id = ?1",
+            "delete email tokens",
+        ),
+        (
+            "DELETE FROM user_identities WHERE user_id = ?1",
+            "delete identities",
+        ),
+        (
+            "DELETE FROM invite_codes WHERE created_by = ?1",
+            "delete invite codes",
+        ),
+        (
+            "UPDATE invite_codes SET used_by = NULL WHERE used_by = ?1",
+            "detach redeemed invite codes",
+        ),
+        (
+            "UPDATE org_invitations SET accepted_by = NULL WHERE accepted_by = ?1",
+            "detach accepted invitations",
+        ),
+        ("DELETE FROM users WHERE id = ?1", "delete user"),
+    ];
+    for (sql, what) in statements {
+        execute_for(&mut tx, sql, user_id, what).await?;
+    }
+
+    tx.commit()
+        .await
+        .map_err(|e| AppError::Internal(format!("commit account deletion: {}", e)))?;
+    Ok(DeletedAccount {
+        workspaces: deleted_workspaces,
+        collab_docs,
+    })
+}
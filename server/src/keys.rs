@@ -0,0 +1,368 @@
+//! Asymmetric signing keys for the JWTs this server issues.
+//!
+//! Tokens are signed with Ed25519 (`EdDSA`) and name their key in the `kid`
+//! header. Keys live in the `signing_keys` table: the newest active key signs,
+//! and retired keys keep verifying until every token they signed has expired,
+//! so rotating (`lumina-server rotate-signing-key`) never logs anyone out.
+//! Other services can verify tokens with the public keys published at
+//! `/.well-known/jwks.json`. Every token names its [`TokenKind`] in the `typ`
+//! header and `aud` claim; verifiers must check both, so that a two-factor
+//! challenge is never mistaken for an access token.
+
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+use base64::Engine as _;
+use jsonwebtoken::{
+    decode, decode_header, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation,
+};
+use ring::rand::SystemRandom;
+use ring::signature::{Ed25519KeyPair, KeyPair};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::json;
+use sqlx::SqlitePool;
+use uuid::Uuid;
+
+use crate::db::{self, SigningKeyRow};
+use crate::error::AppError;
+
+const ALGORITHM: &str = "EdDSA";
+const RELOAD_INTERVAL: Duration = Duration::from_secs(60);
+/// Minimum spacing of reloads triggered by unknown key ids, so tokens with
+/// made-up `kid`s cannot turn every request into a database query.
+const FORCED_RELOAD_MIN_INTERVAL_SECS: i64 = 5;
+
+/// What a token is for. Signing sets the `typ` header and `aud` claim;
+/// verification requires both to match.
+#[derive(Debug, Clone, Copy)]
+pub struct TokenKind {
+    pub typ: &'static str,
+    pub aud: &'static str,
+}
+
+struct VerificationKey {
+    kid: String,
+    /// Raw Ed25519 public key, base64url (the JWK `x` parameter).
+    x: String,
+    decoding: DecodingKey,
+}
+
+#[derive(Default)]
+struct KeySet {
+    signing: Option<(String, EncodingKey)>,
+    verifying: Vec<VerificationKey>,
+}
+
+/// The current signing key and every key that may still have live tokens.
+/// Cheap to clone; clones share the same keys.
+#[derive(Clone)]
+pub struct KeyRing {
+    pool: SqlitePool,
+    /// How long a retired key keeps verifying: the longest lifetime of any
+    /// token signed with these keys.
+    grace_secs: i64,
+    keys: Arc<RwLock<KeySet>>,
+    last_forced_reload: Arc<AtomicI64>,
+}
+
+impl KeyRing {
+    /// Load the keys, generating the first signing key on a fresh install.
+    pub async fn load(pool: SqlitePool, grace_secs: i64) -> Result<Self, AppError> {
+        let ring = Self {
+            pool,
+            grace_secs,
+            keys: Arc::new(RwLock::new(KeySet::default())),
+            last_forced_reload: Arc::new(AtomicI64::new(0)),
+        };
+        ring.reload().await?;
+        if ring.keys.read().expect("key ring lock").signing.is_none() {
+            rotate(&ring.pool, grace_secs).await?;
+            ring.reload().await?;
+        }
+        Ok(ring)
+    }
+
+    pub async fn reload(&self) -> Result<(), AppError> {
+        let retired_after = chrono::Utc::now().timestamp() - self.grace_secs;
+        let rows = db::list_signing_keys(&self.pool, retired_after).await?;
+        let mut set = KeySet::default();
+        for row in rows {
+            if row.algorithm != ALGORITHM {
+                tracing::warn!(kid = %row.kid, algorithm = %row.algorithm, "skipping unsupported signing key");
+                continue;
+            }
+            if row.retired_at.is_none() && set.signing.is_none() {
+                let der = STANDARD
+                    .decode(&row.private_key)
+                    .map_err(|e| AppError::Internal(format!("decode signing key: {}", e)))?;
+                set.signing = Some((row.kid.clone(), EncodingKey::from_ed_der(&der)));
+            }
+            let decoding = DecodingKey::from_ed_components(&row.public_key)
+                .map_err(|e| AppError::Internal(format!("decode verification key: {}", e)))?;
+            set.verifying.push(VerificationKey {
+                kid: row.kid,
+                x: row.public_key,
+                decoding,
+            });
+        }
+        *self.keys.write().expect("key ring lock") = set;
+        Ok(())
+    }
+
+    /// Periodically pick up keys rotated by another process.
+    pub fn spawn_reload_task(&self) {
+        let ring = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(RELOAD_INTERVAL);
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                if let Err(err) = ring.reload().await {
+                    tracing::warn!(error = %err, "failed to reload signing keys");
+                }
+            }
+        });
+    }
+
+    /// Sign `claims` as a token of `kind`. The claims must serialize to an
+    /// object without an `aud` of their own.
+    pub fn sign<T: Serialize>(&self, kind: TokenKind, claims: &T) -> Result<String, AppError> {
+        let mut claims = serde_json::to_value(claims)
+            .map_err(|e| AppError::Internal(format!("serialize token claims: {}", e)))?;
+        let object = claims
+            .as_object_mut()
+            .ok_or_else(|| AppError::Internal("token claims must be an object".to_string()))?;
+        object.insert("aud".to_string(), json!(kind.aud));
+
+        let keys = self.keys.read().expect("key ring lock");
+        let (kid, key) = keys
+            .signing
+            .as_ref()
+            .ok_or_else(|| AppError::Internal("no active signing key".to_string()))?;
+        let mut header = Header::new(Algorithm::EdDSA);
+        header.kid = Some(kid.clone());
+        header.typ = Some(kind.typ.to_string());
+        encode(&header, &claims, key).map_err(|e| AppError::Internal(format!("sign token: {}", e)))
+    }
+
+    /// Verify a token of `kind` signed by one of our keys. An unknown `kid`
+    /// triggers a reload, in case another process rotated the key since the
+    /// last one.
+    pub async fn verify<T: DeserializeOwned>(
+        &self,
+        kind: TokenKind,
+        token: &str,
+    ) -> Result<T, AppError> {
+        let header = decode_header(token).map_err(|_| AppError::Unauthorized)?;
+        let kid = header.kid.ok_or(AppError::Unauthorized)?;
+        if header.alg != Algorithm::EdDSA || header.typ.as_deref() != Some(kind.typ) {
+            return Err(AppError::Unauthorized);
+        }
+        if !self.has_key(&kid) {
+            let now = chrono::Utc::now().timestamp();
+            let last = self.last_forced_reload.load(Ordering::Relaxed);
+            if now - last >= FORCED_RELOAD_MIN_INTERVAL_SECS
+                && self
+                    .last_forced_reload
+                    .compare_exchange(last, now, Ordering::Relaxed, Ordering::Relaxed)
+                    .is_ok()
+            {
+                self.reload().await?;
+            }
+        }
+        let keys = self.keys.read().expect("key ring lock");
+        let key = keys
+            .verifying
+            .iter()
+            .find(|key| key.kid == kid)
+            .ok_or(AppError::Unauthorized)?;
+        let mut validation = Validation::new(Algorithm::EdDSA);
+        validation.set_audience(&[kind.aud]);
+        validation.set_required_spec_claims(&["exp", "aud"]);
+        decode::<T>(token, &key.decoding, &validation)
+            .map(|data| data.claims)
+            .map_err(|_| AppError::Unauthorized)
+    }
+
+    fn has_key(&self, kid: &str) -> bool {
+        self.keys
+            .read()
+            .expect("key ring lock")
+            .verifying
+            .iter()
+            .any(|key| key.kid == kid)
+    }
+
+    /// Public verification keys as a JWK set.
+    pub fn jwks(&self) -> serde_json::Value {
+        let keys = self.keys.read().expect("key ring lock");
+        let keys: Vec<_> = keys
+            .verifying
+            .iter()
+            .map(|key| {
+                json!({
+                    "kty": "OKP",
+                    "crv": "Ed25519",
+                    "alg": ALGORITHM,
+                    "use": "sig",
+                    "kid": key.kid,
+                    "x": key.x,
+                })
+            })
+            .collect();
+        json!({ "keys": keys })
+    }
+}
+
+/// Generate a new signing key and make it the active one. The previous key
+/// is retired but keeps verifying for `grace_secs`; keys retired longer ago
+/// are deleted. Returns the new key id.
+pub async fn rotate(pool: &SqlitePool, grace_secs: i64) -> Result<String, AppError> {
+    let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new())
+        .map_err(|_| AppError::Internal("generate signing key".to_string()))?;
+    let pair = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref())
+        .map_err(|_| AppError::Internal("parse generated signing key".to_string()))?;
+    let now = chrono::Utc::now().timestamp();
+    let key = SigningKeyRow {
+        kid: Uuid::new_v4().simple().to_string(),
+        algorithm: ALGORITHM.to_string(),
+        private_key: STANDARD.encode(pkcs8.as_ref()),
+        public_key: URL_SAFE_NO_PAD.encode(pair.public_key().as_ref()),
+        created_at: now,
+        retired_at: None,
+    };
+    db::rotate_signing_key(pool, &key, now - grace_secs).await?;
+    Ok(key.kid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+    use sqlx::sqlite::SqlitePoolOptions;
+
+    #[derive(Debug, Serialize, Deserialize, PartialEq)]
+    struct TestClaims {
+        sub: String,
+        exp: usize,
+    }
+
+    async fn test_pool() -> SqlitePool {
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        db::init_db(&pool).await.unwrap();
+        pool
+    }
+
+    const KIND: TokenKind = TokenKind {
+        typ: "test+jwt",
+        aud: "test",
+    };
+
+    fn claims() -> TestClaims {
+        TestClaims {
+            sub: "user".to_string(),
+            exp: (chrono::Utc::now().timestamp() + 600) as usize,
+        }
+    }
+
+    #[tokio::test]
+    async fn rotation_keeps_previous_key_verifying() {
+        let pool = test_pool().await;
+        let ring = KeyRing::load(pool.clone(), 600).await.unwrap();
+        let old_token = ring.sign(KIND, &claims()).unwrap();
+
+        rotate(&pool, 600).await.unwrap();
+        ring.reload().await.unwrap();
+        let new_token = ring.sign(KIND, &claims()).unwrap();
+
+        assert_ne!(
+            decode_header(&old_token).unwrap().kid,
+            decode_header(&new_token).unwrap().kid
+        );
+        for token in [&old_token, &new_token] {
+            assert_eq!(
+                ring.verify::<TestClaims>(KIND, token).await.unwrap().sub,
+                "user"
+            );
+        }
+        assert_eq!(ring.jwks()["keys"].as_array().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn retired_keys_stop_verifying_after_grace() {
+        let pool = test_pool().await;
+        let ring = KeyRing::load(pool.clone(), 0).await.unwrap();
+        let old_token = ring.sign(KIND, &claims()).unwrap();
+
+        rotate(&pool, 0).await.unwrap();
+        ring.reload().await.unwrap();
+        assert!(matches!(
+            ring.verify::<TestClaims>(KIND, &old_token).await,
+            Err(AppError::Unauthorized)
+        ));
+        assert_eq!(ring.jwks()["keys"].as_array().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn unknown_kid_triggers_reload() {
+        let pool = test_pool().await;
+        let ring = KeyRing::load(pool.clone(), 600).await.unwrap();
+        // Another process rotates and signs with the new key.
+        let other = KeyRing::load(pool.clone(), 600).await.unwrap();
+        rotate(&pool, 600).await.unwrap();
+        other.reload().await.unwrap();
+        let token = other.sign(KIND, &claims()).unwrap();
+
+        assert!(ring.verify::<TestClaims>(KIND, &token).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn tokens_only_verify_as_their_own_kind() {
+        let pool = test_pool().await;
+        let ring = KeyRing::load(pool, 600).await.unwrap();
+        let token = ring.sign(KIND, &claims()).unwrap();
+        let other = TokenKind {
+            typ: "other+jwt",
+            aud: "other",
+        };
+        for kind in [
+            other,
+            TokenKind {
+                aud: "other",
+                ..KIND
+            },
+            TokenKind {
+                typ: "other+jwt",
+                ..KIND
+            },
+        ] {
+            assert!(matches!(
+                ring.verify::<TestClaims>(kind, &token).await,
+                Err(AppError::Unauthorized)
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_hmac_tokens() {
+        let pool = test_pool().await;
+        let ring = KeyRing::load(pool, 600).await.unwrap();
+        let token = encode(
+            &Header::default(),
+            &claims(),
+            &EncodingKey::from_secret(b"secret"),
+        )
+        .unwrap();
+        assert!(matches!(
+            ring.verify::<TestClaims>(KIND, &token).await,
+            Err(AppError::Unauthorized)
+        ));
+    }
+}
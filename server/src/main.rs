@@ -1,16 +1,27 @@
+mod account;
+mod audit;
 mod auth;
 mod collab;
 mod config;
 mod dav;
 mod db;
 mod error;
+mod keys;
+mod mailer;
 mod models;
+mod notifications;
 mod notify_ws;
+mod oidc;
+mod orgs;
+mod projects;
 mod rate_limit;
+mod registration;
 mod relay;
 mod routes;
 mod sites;
 mod state;
+mod totp;
+mod workflows;
 
 use axum::http::{HeaderName, Request};
 use axum::routing::{any, delete, get, post, put};
@@ -30,15 +41,6 @@ const REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     dotenvy::dotenv().ok();
     let config = Config::from_env();
-    if config.jwt_secret == "dev-secret-change-me" {
-        if cfg!(debug_assertions) {
-            tracing::warn!(
-                "LUMINA_JWT_SECRET is using the default value; do not use this in production."
-            );
-        } else {
-            return Err("LUMINA_JWT_SECRET must be set for production".into());
-        }
-    }
 
     tracing_subscriber::fmt()
         .with_env_filter(EnvFilter::from_default_env())
@@ -53,6 +55,38 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .await?;
     db::init_db(&pool).await?;
 
+    let key_grace_secs = auth::signing_key_grace_secs(&config);
+    if let Some(command) = std::env::args().nth(1) {
+        return match command.as_str() {
+            "rotate-signing-key" => {
+                let kid = keys::rotate(&pool, key_grace_secs).await?;
+                println!("new signing key: {}", kid);
+                Ok(())
+            }
+            "grant-admin" | "revoke-admin" => {
+                let email = std::env::args()
+                    .nth(2)
+                    .ok_or_else(|| format!("usage: {} <email>", command))?
+                    .trim()
+                    .to_lowercase();
+                let (user_id, _) = db::find_user_by_email(&pool, &email)
+                    .await?
+                    .ok_or_else(|| format!("no account for {}", email))?;
+                let grant = command == "grant-admin";
+                db::set_user_admin(&pool, &user_id, grant).await?;
+                if grant {
+                    println!("{} is now an instance admin", email);
+                } else {
+                    println!("{} is no longer an instance admin", email);
+                }
+                Ok(())
+            }
+            other => Err(format!("unknown command: {}", other).into()),
+        };
+    }
+    let keys = keys::KeyRing::load(pool.clone(), key_grace_secs).await?;
+    keys.spawn_reload_task();
+
     let bind_addr = config.bind.parse().map_err(|_| "invalid LUMINA_BIND")?;
 
     let collab_hub = collab::CollabHub::new(&config.data_dir);
@@ -62,6 +96,20 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         config.auth_rate_limit_burst,
         config.auth_rate_limit_window_secs,
     );
+    let login_guard = rate_limit::LoginGuard::new(
+        config.login_lockout_threshold,
+        config.login_lockout_base_secs,
+        config.login_lockout_max_secs,
+    );
+
+    let mailer = mailer::from_config(&config)?;
+    let oidc = config
+        .oidc
+        .clone()
+        .map(|oidc| Arc::new(oidc::OidcClient::new(oidc)));
+    if !config.password_login_enabled && oidc.is_none() {
+        tracing::warn!("password login is disabled but no OIDC provider is configured");
+    }
 
     let state = AppState {
         pool,
@@ -71,7 +119,14 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         metrics: Arc::new(state::ServerMetrics::new()),
         notify: notify_ws::NotifyHub::new(),
         auth_limiter,
+        login_guard,
+        mailer,
+        oidc,
+        keys,
     };
+    auth::spawn_session_cleanup_task(state.clone());
+    orgs::spawn_purge_task(state.clone());
+    notifications::spawn_reminder_task(state.clone());
 
     let trace_layer = TraceLayer::new_for_http().make_span_with(|req: &Request<_>| {
         let request_id = req
@@ -92,27 +147,148 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .route("/metrics", get(routes::metrics))
         .route("/auth/register", post(routes::register))
         .route("/auth/login", post(routes::login))
+        .route("/auth/login/2fa", post(routes::login_2fa))
+        .route("/.well-known/jwks.json", get(routes::jwks))
+        .route("/auth/providers", get(routes::auth_providers))
+        .route("/auth/oidc/authorize", get(routes::oidc_authorize))
+        .route(
+            "/auth/oidc/callback",
+            get(routes::oidc_callback_redirect).post(routes::oidc_callback),
+        )
         .route("/auth/refresh", post(routes::refresh))
+        .route("/auth/logout", post(routes::logout))
+        .route("/auth/logout-all", post(routes::logout_all))
+        .route("/auth/sessions", get(routes::list_sessions))
+        .route("/auth/sessions/:session_id", delete(routes::revoke_session))
+        .route("/auth/password", post(routes::change_password))
+        .route("/auth/password/forgot", post(routes::forgot_password))
+        .route("/auth/password/reset", post(routes::reset_password))
+        .route("/auth/email/verify", post(routes::verify_email))
+        .route(
+            "/auth/email/resend",
+            post(routes::resend_verification_email),
+        )
+        .route("/auth/2fa", get(routes::two_factor_status))
+        .route("/auth/2fa/setup", post(routes::setup_two_factor))
+        .route("/auth/2fa/confirm", post(routes::confirm_two_factor))
+        .route("/auth/2fa/disable", post(routes::disable_two_factor))
+        .route(
+            "/auth/2fa/recovery-codes",
+            post(routes::regenerate_recovery_codes),
+        )
+        .route(
+            "/auth/tokens",
+            get(routes::list_app_tokens).post(routes::create_app_token),
+        )
+        .route("/auth/tokens/:token_id", delete(routes::revoke_app_token))
+        .route("/me", delete(routes::delete_account))
+        // Instance administration
+        .route("/admin/audit", get(routes::list_instance_audit))
+        .route("/admin/users", get(routes::admin_list_users))
+        .route("/admin/users/:user_id", get(routes::admin_get_user))
+        .route(
+            "/admin/users/:user_id/disable",
+            post(routes::admin_disable_user),
+        )
+        .route(
+            "/admin/users/:user_id/enable",
+            post(routes::admin_enable_user),
+        )
+        .route(
+            "/admin/users/:user_id/logout",
+            post(routes::admin_logout_user),
+        )
+        .route(
+            "/admin/users/:user_id/password",
+            post(routes::admin_reset_password),
+        )
+        .route(
+            "/admin/settings",
+            get(routes::admin_get_settings).put(routes::admin_update_settings),
+        )
+        .route(
+            "/admin/invite-codes",
+            get(routes::list_admin_invite_codes).post(routes::create_admin_invite_code),
+        )
+        .route(
+            "/orgs/:org_id/invite-codes",
+            get(routes::list_org_invite_codes).post(routes::create_org_invite_code),
+        )
+        .route(
+            "/invite-codes/:invite_id",
+            delete(routes::revoke_invite_code),
+        )
+        .route("/me/export", get(routes::export_account))
         .route(
             "/workspaces",
             get(routes::list_workspaces).post(routes::create_workspace),
         )
+        .route(
+            "/workspaces/:workspace_id/devices",
+            get(routes::list_device_keys).post(routes::register_device_key),
+        )
+        .route(
+            "/workspaces/:workspace_id/devices/:device_id",
+            delete(routes::delete_device_key),
+        )
+        .route(
+            "/workspaces/:workspace_id/devices/:device_id/key",
+            put(routes::enroll_device_key),
+        )
         // Organization routes
         .route("/orgs", get(routes::list_orgs).post(routes::create_org))
+        .route("/orgs/deleted", get(routes::list_deleted_orgs))
         .route(
             "/orgs/:org_id",
-            get(routes::get_org).put(routes::update_org),
+            get(routes::get_org)
+                .put(routes::update_org)
+                .delete(routes::delete_org),
         )
+        .route("/orgs/:org_id/restore", post(routes::restore_org))
         .route("/orgs/:org_id/members", post(routes::add_member))
+        .route(
+            "/orgs/:org_id/invitations",
+            get(routes::list_org_invitations).post(routes::create_org_invitation),
+        )
+        .route(
+            "/orgs/:org_id/invitations/:invitation_id",
+            delete(routes::revoke_org_invitation),
+        )
+        .route("/me/invitations", get(routes::list_my_invitations))
+        .route("/me/tasks", get(routes::list_my_tasks))
+        .route("/invitations/join", post(routes::join_org_with_token))
+        .route(
+            "/invitations/:invitation_id/accept",
+            post(routes::accept_org_invitation),
+        )
+        .route(
+            "/invitations/:invitation_id/decline",
+            post(routes::decline_org_invitation),
+        )
+        .route("/orgs/:org_id/audit", get(routes::list_org_audit))
         .route(
             "/orgs/:org_id/members/:user_id",
-            delete(routes::remove_member),
+            put(routes::update_member_role).delete(routes::remove_member),
         )
+        .route("/orgs/:org_id/leave", post(routes::leave_org))
+        .route("/orgs/:org_id/transfer", post(routes::transfer_org))
         // Project routes
         .route(
             "/orgs/:org_id/projects",
             get(routes::list_org_projects).post(routes::create_project),
         )
+        .route(
+            "/projects/:project_id",
+            put(routes::update_project).delete(routes::delete_project),
+        )
+        .route(
+            "/projects/:project_id/members",
+            get(routes::list_project_members),
+        )
+        .route(
+            "/projects/:project_id/members/:user_id",
+            put(routes::set_project_member).delete(routes::remove_project_member),
+        )
         // Document registry
         .route(
             "/projects/:project_id/docs/resolve",
@@ -125,7 +301,40 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         )
         .route(
             "/tasks/:task_id",
-            put(routes::update_task_handler).delete(routes::delete_task_handler),
+            get(routes::get_task_handler)
+                .put(routes::update_task_handler)
+                .delete(routes::delete_task_handler),
+        )
+        .route("/tasks/:task_id/move", post(routes::move_task))
+        .route(
+            "/tasks/:task_id/comments",
+            post(routes::create_task_comment),
+        )
+        .route(
+            "/tasks/:task_id/watch",
+            put(routes::watch_task).delete(routes::unwatch_task),
+        )
+        .route(
+            "/tasks/:task_id/blocked-by/:blocker_id",
+            put(routes::add_task_dependency).delete(routes::remove_task_dependency),
+        )
+        // Workflow routes
+        .route(
+            "/projects/:project_id/workflow",
+            get(routes::get_workflow).put(routes::update_workflow),
+        )
+        // Label routes
+        .route(
+            "/projects/:project_id/labels",
+            get(routes::list_labels).post(routes::create_label),
+        )
+        .route(
+            "/labels/:label_id",
+            put(routes::update_label).delete(routes::delete_label),
+        )
+        .route(
+            "/tasks/:task_id/labels/:label_id",
+            put(routes::attach_task_label).delete(routes::detach_task_label),
         )
         // Annotation routes
         .route(
@@ -145,6 +354,10 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .route("/notifications/read", put(routes::mark_read))
         .route("/notifications/read-all", put(routes::mark_all_read))
         .route("/notifications/unread-count", get(routes::unread_count))
+        .route(
+            "/notifications/preferences",
+            get(routes::get_notification_preferences).put(routes::update_notification_preferences),
+        )
         .route("/ws/notifications", get(notify_ws::notify_handler))
         // Existing routes
         .route("/collab/:doc_id", get(collab::collab_handler))
@@ -163,6 +376,10 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             get(routes::publish_status).post(routes::publish_confirm),
         )
         .route("/publish", delete(routes::unpublish))
+        .layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            rate_limit::tag_client_ip,
+        ))
         .with_state(state)
         .layer(PropagateRequestIdLayer::new(REQUEST_ID_HEADER))
         .layer(SetRequestIdLayer::new(REQUEST_ID_HEADER, MakeRequestUuid))
@@ -1,9 +1,14 @@
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 
 #[derive(Debug, Deserialize)]
 pub struct RegisterRequest {
     pub email: String,
     pub password: String,
+    /// Required when registration is invite-only; optional otherwise, where
+    /// it may still add the new account to an organization.
+    #[serde(default)]
+    pub invite_code: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -15,31 +20,375 @@ pub struct LoginRequest {
 #[derive(Debug, Serialize)]
 pub struct AuthResponse {
     pub token: String,
+    pub refresh_token: String,
     pub user: UserSummary,
     pub user_id: String,
     pub workspaces: Vec<WorkspaceSummary>,
 }
 
+/// Result of a login or registration: either the session itself, a
+/// challenge that must be completed at `/auth/login/2fa`, or a notice that
+/// the email address has to be confirmed first.
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum LoginResponse {
+    Authenticated(AuthResponse),
+    MfaRequired(MfaChallengeResponse),
+    VerificationRequired(VerificationRequiredResponse),
+}
+
+#[derive(Debug, Serialize)]
+pub struct VerificationRequiredResponse {
+    pub verification_required: bool,
+    pub email: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MfaChallengeResponse {
+    pub mfa_required: bool,
+    pub challenge_token: String,
+    pub methods: Vec<String>,
+}
+
+/// Second login step: exactly one of `code` or `recovery_code`.
+#[derive(Debug, Deserialize)]
+pub struct MfaLoginRequest {
+    pub challenge_token: String,
+    pub code: Option<String>,
+    pub recovery_code: Option<String>,
+}
+
 #[derive(Debug, Serialize)]
 pub struct UserSummary {
     pub id: String,
     pub email: String,
+    pub email_verified: bool,
 }
 
 #[derive(Debug, Serialize)]
 pub struct TokenResponse {
     pub token: String,
+    pub refresh_token: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RefreshRequest {
+    pub refresh_token: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SessionSummary {
+    pub id: String,
+    pub device_name: String,
+    pub ip: Option<String>,
+    pub created_at: i64,
+    pub last_used_at: i64,
+    pub expires_at: i64,
+    /// Whether this is the session the request was made with.
+    pub current: bool,
+}
+
+// ── Single sign-on ──────────────────────────────────────────────────
+
+/// Login methods offered by this instance, for rendering the login screen.
+#[derive(Debug, Serialize)]
+pub struct AuthProviders {
+    pub password: bool,
+    /// Registration mode: `open`, `closed`, `domains` or `invite`.
+    pub registration: String,
+    pub oidc: Option<OidcProviderInfo>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct OidcProviderInfo {
+    pub name: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct OidcAuthorizeResponse {
+    pub authorization_url: String,
+}
+
+/// Parameters the identity provider appends to the redirect URL.
+#[derive(Debug, Deserialize)]
+pub struct OidcCallbackRequest {
+    pub state: String,
+    pub code: Option<String>,
+    pub error: Option<String>,
+    pub error_description: Option<String>,
+}
+
+// ── Passwords & email verification ──────────────────────────────────
+
+#[derive(Debug, Deserialize)]
+pub struct ChangePasswordRequest {
+    pub current_password: String,
+    pub new_password: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct EmailRequest {
+    pub email: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ResetPasswordRequest {
+    pub token: String,
+    pub new_password: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct VerifyEmailRequest {
+    pub token: String,
+}
+
+// ── Account ─────────────────────────────────────────────────────────
+
+/// Confirmation for deleting the caller's account. `password` may only be
+/// omitted by accounts that sign in through single sign-on and have no
+/// password of their own; they must have signed in within the last ten
+/// minutes instead.
+#[derive(Debug, Deserialize)]
+pub struct DeleteAccountRequest {
+    pub confirm_email: String,
+    pub password: Option<String>,
+}
+
+// ── Instance administration ─────────────────────────────────────────
+
+#[derive(Debug, Default, Deserialize)]
+pub struct AdminUserQuery {
+    /// Substring of the email address.
+    pub q: Option<String>,
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AdminUserSummary {
+    pub id: String,
+    pub email: String,
+    pub created_at: i64,
+    pub email_verified: bool,
+    pub is_admin: bool,
+    pub disabled: bool,
+    pub disabled_at: Option<i64>,
+    pub workspace_count: i64,
+    pub last_seen_at: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AdminUserList {
+    pub users: Vec<AdminUserSummary>,
+    pub total: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AdminUserDetail {
+    #[serde(flatten)]
+    pub user: AdminUserSummary,
+    /// Bytes on disk in the workspaces the user owns and their published site.
+    pub storage_bytes: u64,
+    pub active_sessions: i64,
+}
+
+/// Set a new password, or email the user a reset link when `new_password`
+/// is omitted.
+#[derive(Debug, Default, Deserialize)]
+pub struct AdminResetPasswordRequest {
+    pub new_password: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct InstanceSettings {
+    pub registration_mode: String,
+    pub registration_domains: Vec<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct UpdateInstanceSettingsRequest {
+    pub registration_mode: Option<String>,
+    pub registration_domains: Option<Vec<String>>,
+}
+
+// ── Invite codes ────────────────────────────────────────────────────
+
+/// A single-use registration code. When `org_id` is set the new account
+/// joins that organization with `role` (default `member`). Org admins can
+/// only create codes for their own organization.
+#[derive(Debug, Default, Deserialize)]
+pub struct CreateInviteCodeRequest {
+    pub org_id: Option<String>,
+    pub role: Option<String>,
+    pub expires_in_days: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct InviteCodeInfo {
+    pub id: String,
+    pub created_by: String,
+    pub org_id: Option<String>,
+    pub role: Option<String>,
+    pub created_at: i64,
+    pub expires_at: Option<i64>,
+    pub used_at: Option<i64>,
+    pub used_by: Option<String>,
+}
+
+/// A newly created code. The code itself is only shown once.
+#[derive(Debug, Serialize)]
+pub struct CreatedInviteCode {
+    #[serde(flatten)]
+    pub invite: InviteCodeInfo,
+    pub code: String,
+}
+
+// ── Audit log ───────────────────────────────────────────────────────
+
+/// Filters for the audit log endpoints. `org_id` is only honoured by the
+/// instance-wide view.
+#[derive(Debug, Default, Deserialize)]
+pub struct AuditQuery {
+    pub org_id: Option<String>,
+    pub action: Option<String>,
+    pub actor_id: Option<String>,
+    pub target_type: Option<String>,
+    pub target_id: Option<String>,
+    pub since: Option<i64>,
+    pub until: Option<i64>,
+    /// Continue after the entry with this id (from `next_before`).
+    pub before: Option<i64>,
+    pub limit: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AuditEntry {
+    pub id: i64,
+    pub created_at: i64,
+    pub action: String,
+    pub actor_id: Option<String>,
+    pub actor_email: Option<String>,
+    pub ip: Option<String>,
+    pub org_id: Option<String>,
+    pub target_type: String,
+    pub target_id: String,
+    pub details: serde_json::Value,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AuditLogPage {
+    pub entries: Vec<AuditEntry>,
+    /// Pass as `before` to fetch the next page; absent on the last page.
+    pub next_before: Option<i64>,
+}
+
+// ── Two-factor authentication ───────────────────────────────────────
+
+#[derive(Debug, Serialize)]
+pub struct TwoFactorStatus {
+    pub enabled: bool,
+    pub recovery_codes_remaining: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TotpSetupResponse {
+    pub secret: String,
+    pub otpauth_uri: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TotpCodeRequest {
+    pub code: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DisableTwoFactorRequest {
+    pub password: String,
+    pub code: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RecoveryCodesResponse {
+    pub recovery_codes: Vec<String>,
+}
+
+// ── App passwords & personal access tokens ──────────────────────────
+
+#[derive(Debug, Deserialize)]
+pub struct CreateAppTokenRequest {
+    pub name: String,
+    /// `app_password` (HTTP Basic) or `personal_access_token` (Bearer).
+    pub kind: String,
+    pub scopes: Vec<String>,
+    pub expires_at: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AppTokenSummary {
+    pub id: String,
+    pub name: String,
+    pub kind: String,
+    pub scopes: Vec<String>,
+    pub expires_at: Option<i64>,
+    pub revoked_at: Option<i64>,
+    pub last_used_at: Option<i64>,
+    pub last_used_ip: Option<String>,
+    pub created_at: i64,
+}
+
+/// Returned once on creation; the plaintext token is never shown again.
+#[derive(Debug, Serialize)]
+pub struct CreatedAppTokenResponse {
+    pub token: String,
+    #[serde(flatten)]
+    pub info: AppTokenSummary,
 }
 
 #[derive(Debug, Serialize)]
 pub struct WorkspaceSummary {
     pub id: String,
     pub name: String,
+    /// End-to-end encrypted: the server only holds ciphertext and encrypted
+    /// filenames, so it never publishes or inspects this workspace's content.
+    pub e2ee: bool,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct CreateWorkspaceRequest {
     pub name: String,
+    #[serde(default)]
+    pub e2ee: bool,
+}
+
+// ── E2EE device keys ────────────────────────────────────────────────
+
+#[derive(Debug, Deserialize)]
+pub struct RegisterDeviceKeyRequest {
+    pub device_id: String,
+    pub device_name: String,
+    pub public_key: String,
+    /// Only accepted for the first device of a workspace, which generates the
+    /// workspace key and wraps it for itself.
+    pub wrapped_key: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct EnrollDeviceKeyRequest {
+    /// An already enrolled device owned by the caller.
+    pub approver_device_id: String,
+    pub wrapped_key: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DeviceKeyInfo {
+    pub device_id: String,
+    pub user_id: String,
+    pub device_name: String,
+    pub public_key: String,
+    pub wrapped_key: Option<String>,
+    pub enrolled_by: Option<String>,
+    pub created_at: i64,
+    pub enrolled_at: Option<i64>,
 }
 
 // ── Organization ─────────────────────────────────────────────────────
@@ -56,6 +405,15 @@ pub struct OrgSummary {
     pub role: String,
 }
 
+/// An organization pending deletion that the caller can still restore.
+#[derive(Debug, Serialize)]
+pub struct DeletedOrgSummary {
+    pub id: String,
+    pub name: String,
+    pub deleted_at: i64,
+    pub purge_at: i64,
+}
+
 #[derive(Debug, Serialize)]
 pub struct OrgDetail {
     pub id: String,
@@ -77,11 +435,60 @@ pub struct AddOrgMemberRequest {
     pub role: String,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct UpdateOrgMemberRequest {
+    pub role: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TransferOrgRequest {
+    pub user_id: String,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct UpdateOrgRequest {
     pub name: Option<String>,
 }
 
+/// Invite `email` to the organization, or create a shareable join link when
+/// `email` is omitted. Join links cannot grant `admin`.
+#[derive(Debug, Deserialize)]
+pub struct CreateOrgInvitationRequest {
+    pub email: Option<String>,
+    pub role: String,
+    pub expires_in_days: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct OrgInvitationInfo {
+    pub id: String,
+    pub org_id: String,
+    pub org_name: String,
+    pub email: Option<String>,
+    pub role: String,
+    pub invited_by: String,
+    /// `pending`, `accepted`, `declined`, `revoked` or `expired`.
+    pub status: String,
+    pub created_at: i64,
+    pub expires_at: i64,
+    pub accepted_at: Option<i64>,
+    pub accepted_by: Option<String>,
+}
+
+/// A newly created invitation. The token is only shown once.
+#[derive(Debug, Serialize)]
+pub struct CreatedOrgInvitation {
+    #[serde(flatten)]
+    pub invitation: OrgInvitationInfo,
+    pub token: String,
+    pub join_url: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct JoinOrgRequest {
+    pub token: String,
+}
+
 // ── Project ──────────────────────────────────────────────────────────
 
 #[derive(Debug, Deserialize)]
@@ -90,17 +497,47 @@ pub struct CreateProjectRequest {
     pub description: Option<String>,
 }
 
+/// Archiving hides a project from listings and makes its tasks read-only.
+#[derive(Debug, Deserialize)]
+pub struct UpdateProjectRequest {
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub archived: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SetProjectMemberRequest {
+    pub role: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ProjectMemberInfo {
+    pub user_id: String,
+    pub email: String,
+    pub role: String,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct ProjectListQuery {
+    #[serde(default)]
+    pub include_archived: bool,
+}
+
 #[derive(Debug, Serialize)]
 pub struct ProjectSummary {
     pub id: String,
     pub name: String,
     pub description: String,
+    pub archived: bool,
 }
 
 // ── Task ─────────────────────────────────────────────────────────────
 
 #[derive(Debug, Deserialize)]
 pub struct CreateTaskRequest {
+    /// Create the task as a subtask of this one.
+    #[serde(default)]
+    pub parent_id: Option<String>,
     pub title: String,
     pub description: Option<String>,
     pub status: Option<String>,
@@ -110,7 +547,7 @@ pub struct CreateTaskRequest {
     pub start_date: Option<i64>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Default, Deserialize)]
 pub struct UpdateTaskRequest {
     pub title: Option<String>,
     pub description: Option<String>,
@@ -119,14 +556,61 @@ pub struct UpdateTaskRequest {
     pub assignee_id: Option<Option<String>>,
     pub due_date: Option<Option<i64>>,
     pub start_date: Option<Option<i64>>,
+    /// Rejected: tasks are reordered through `POST /tasks/:task_id/move`,
+    /// which picks a position that cannot collide with other moves.
     pub position: Option<f64>,
+    pub parent_id: Option<Option<String>>,
+}
+
+/// Move a task into `status` (its current one when absent), directly after
+/// `after_id` and/or before `before_id`, both tasks of that column. Without
+/// either it goes to the end of the column.
+#[derive(Debug, Default, Deserialize)]
+pub struct MoveTaskRequest {
+    pub status: Option<String>,
+    pub after_id: Option<String>,
+    pub before_id: Option<String>,
+}
+
+/// Filters, order and paging for task listings.
+#[derive(Debug, Default, Deserialize)]
+pub struct TaskListQuery {
+    /// Comma-separated statuses.
+    pub status: Option<String>,
+    /// Comma-separated priorities.
+    pub priority: Option<String>,
+    /// A user id, `me`, or `none` for unassigned tasks.
+    pub assignee: Option<String>,
+    /// Only tasks carrying this label id.
+    pub label: Option<String>,
+    /// Due at or after this time.
+    pub due_after: Option<i64>,
+    /// Due before this time.
+    pub due_before: Option<i64>,
+    /// Text to look for in titles and descriptions.
+    pub q: Option<String>,
+    /// `position` (default), `due_date`, `priority`, `created_at`,
+    /// `updated_at` or `title`.
+    pub sort: Option<String>,
+    /// `asc` (default) or `desc`.
+    pub order: Option<String>,
+    /// Continue after the previous page (from `next_cursor`).
+    pub cursor: Option<String>,
+    pub limit: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TaskPage {
+    pub tasks: Vec<TaskSummary>,
+    /// Pass as `cursor` to fetch the next page; absent on the last page.
+    pub next_cursor: Option<String>,
 }
 
 #[derive(Debug, Serialize)]
-#[allow(dead_code)]
 pub struct TaskDetail {
     pub id: String,
     pub project_id: String,
+    pub parent_id: Option<String>,
     pub title: String,
     pub description: String,
     pub status: String,
@@ -138,18 +622,130 @@ pub struct TaskDetail {
     pub created_by: String,
     pub created_at: i64,
     pub updated_at: i64,
-    pub labels: Vec<String>,
+    pub labels: Vec<LabelInfo>,
+    pub progress: Option<SubtaskProgress>,
+    pub subtasks: Vec<TaskSummary>,
+    pub blocked_by: Vec<String>,
+    pub blocks: Vec<String>,
+    pub watchers: Vec<String>,
+    pub comments: Vec<TaskCommentInfo>,
+    pub activity: Vec<TaskActivityInfo>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateTaskCommentRequest {
+    pub body: String,
+    /// Reply to this comment of the same task.
+    pub parent_id: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TaskCommentInfo {
+    pub id: String,
+    pub parent_id: Option<String>,
+    pub user_id: String,
+    pub body: String,
+    pub created_at: i64,
+}
+
+/// A recorded change; `field` is `created` for the task's creation.
+#[derive(Debug, Serialize)]
+pub struct TaskActivityInfo {
+    pub id: i64,
+    pub actor_id: Option<String>,
+    pub field: String,
+    pub old_value: serde_json::Value,
+    pub new_value: serde_json::Value,
+    pub created_at: i64,
 }
 
 #[derive(Debug, Serialize)]
 pub struct TaskSummary {
     pub id: String,
+    pub project_id: String,
+    pub parent_id: Option<String>,
     pub title: String,
     pub status: String,
     pub priority: String,
     pub assignee_id: Option<String>,
     pub due_date: Option<i64>,
     pub position: f64,
+    /// Absent when the task has no subtasks.
+    pub progress: Option<SubtaskProgress>,
+    /// Tasks that must be done before this one.
+    pub blocked_by: Vec<String>,
+    /// Tasks waiting for this one.
+    pub blocks: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct SubtaskProgress {
+    pub done: i64,
+    pub total: i64,
+}
+
+// ── Workflow ─────────────────────────────────────────────────────────
+
+/// A status column of a project's task board.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WorkflowStatus {
+    pub key: String,
+    pub name: String,
+    /// `backlog`, `active` or `done`.
+    pub category: String,
+    /// Statuses a task may move to from this one; any when absent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transitions: Option<Vec<String>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WorkflowPriority {
+    pub key: String,
+    pub name: String,
+}
+
+/// A project's statuses in board order and its priorities, most urgent
+/// first. New tasks start in the first status.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Workflow {
+    pub statuses: Vec<WorkflowStatus>,
+    pub priorities: Vec<WorkflowPriority>,
+    pub default_priority: String,
+}
+
+/// Replace a project's workflow. Tasks in a status or priority that is
+/// removed must be moved with `status_map` / `priority_map` (old key to new
+/// key).
+#[derive(Debug, Deserialize)]
+pub struct UpdateWorkflowRequest {
+    #[serde(flatten)]
+    pub workflow: Workflow,
+    #[serde(default)]
+    pub status_map: HashMap<String, String>,
+    #[serde(default)]
+    pub priority_map: HashMap<String, String>,
+}
+
+// ── Label ────────────────────────────────────────────────────────────
+
+#[derive(Debug, Deserialize)]
+pub struct CreateLabelRequest {
+    pub name: String,
+    /// `#rrggbb`; a neutral gray when omitted.
+    pub color: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpdateLabelRequest {
+    pub name: Option<String>,
+    pub color: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LabelInfo {
+    pub id: String,
+    pub name: String,
+    pub color: String,
 }
 
 // ── Annotation ───────────────────────────────────────────────────────
@@ -157,6 +753,10 @@ pub struct TaskSummary {
 #[derive(Debug, Deserialize)]
 pub struct CreateAnnotationRequest {
     pub doc_path: String,
+    /// Scope the annotation to a project, so project roles apply to it.
+    /// Defaults to the project the document is registered in.
+    #[serde(default)]
+    pub project_id: Option<String>,
     pub range_start: i64,
     pub range_end: i64,
     pub content: String,
@@ -166,6 +766,7 @@ pub struct CreateAnnotationRequest {
 pub struct AnnotationDetail {
     pub id: String,
     pub doc_path: String,
+    pub project_id: Option<String>,
     pub user_id: String,
     pub range_start: i64,
     pub range_end: i64,
@@ -208,10 +809,28 @@ pub struct MarkNotificationReadRequest {
     pub notification_ids: Vec<String>,
 }
 
+/// Whether one type of notification is on. Types not listed in an update
+/// keep their setting.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct NotificationPreference {
+    #[serde(rename = "type")]
+    pub ntype: String,
+    pub enabled: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpdateNotificationPreferencesRequest {
+    pub preferences: Vec<NotificationPreference>,
+}
+
 // ── Document Registry ───────────────────────────────────────────────
 
 #[derive(Debug, Deserialize)]
 pub struct ResolveDocRequest {
+    /// Workspace the document lives in. Documents of end-to-end encrypted
+    /// workspaces cannot be registered.
+    #[serde(default)]
+    pub workspace_id: Option<String>,
     pub rel_path: String,
 }
 
@@ -222,6 +841,13 @@ pub struct ResolveDocResponse {
 
 // ── Publish ─────────────────────────────────────────────────────────
 
+#[derive(Debug, Default, Deserialize)]
+pub struct PublishConfirmRequest {
+    /// Workspace the site was exported from. End-to-end encrypted
+    /// workspaces cannot be published.
+    pub workspace_id: String,
+}
+
 #[derive(Debug, Serialize)]
 pub struct PublishStatusResponse {
     pub published: bool,
@@ -0,0 +1,362 @@
+//! Notifications about tasks and annotations.
+//!
+//! Handlers queue notices in an [`Outbox`] while they work out who should
+//! hear about a change, then send them in one go. Each user gets at most
+//! one notice per change — the first one queued for them — and never one
+//! about their own change. Users who can no longer see the project (or, for
+//! org-level annotations, the org) are skipped, as are exact repeats of a
+//! notification the user has not read yet. Users can turn off any type of
+//! notification (`PUT /notifications/preferences`).
+//!
+//! A background task reminds assignees of tasks that fall due within each
+//! of `LUMINA_DUE_REMINDER_WINDOWS` (24 and 1 hours by default), and once
+//! more when they are overdue. Sent reminders are recorded, so restarting
+//! the server never repeats one, and tasks already overdue by more than a
+//! day are left alone rather than reminded about all at once.
+
+use std::time::Duration;
+
+use crate::db::{self, ProjectRow};
+use crate::error::AppError;
+use crate::projects;
+use crate::state::AppState;
+
+pub const TASK_ASSIGNED: &str = "task_assigned";
+pub const TASK_STATUS: &str = "task_status";
+pub const TASK_UPDATED: &str = "task_updated";
+pub const TASK_COMMENT: &str = "task_comment";
+pub const TASK_UNBLOCKED: &str = "task_unblocked";
+pub const ANNOTATION_REPLY: &str = "annotation_reply";
+pub const MENTION: &str = "mention";
+pub const TASK_DUE_SOON: &str = "task_due_soon";
+pub const TASK_OVERDUE: &str = "task_overdue";
+/// Every notification type, for preferences.
+pub const TYPES: &[&str] = &[
+    TASK_ASSIGNED,
+    TASK_STATUS,
+    TASK_UPDATED,
+    TASK_COMMENT,
+    TASK_UNBLOCKED,
+    ANNOTATION_REPLY,
+    MENTION,
+    TASK_DUE_SOON,
+    TASK_OVERDUE,
+];
+
+const REMINDER_INTERVAL: Duration = Duration::from_secs(60);
+/// How long after its due date a task can still get the overdue reminder.
+const OVERDUE_REMINDER_CUTOFF_SECS: i64 = 24 * 60 * 60;
+
+pub struct Notice {
+    pub ntype: &'static str,
+    pub title: String,
+    pub body: String,
+}
+
+impl Notice {
+    pub fn new(ntype: &'static str, title: impl Into<String>, body: impl Into<String>) -> Self {
+        Self {
+            ntype,
+            title: title.into(),
+            body: body.into(),
+        }
+    }
+}
+
+/// Who may receive the notices of an [`Outbox`].
+pub enum Audience<'a> {
+    Project(&'a ProjectRow),
+    Org,
+}
+
+pub struct Outbox {
+    org_id: String,
+    ref_id: String,
+    actor_id: Option<String>,
+    queued: Vec<(String, Notice)>,
+}
+
+impl Outbox {
+    /// Notices about `ref_id` caused by `actor_id`.
+    pub fn new(org_id: &str, ref_id: &str, actor_id: &str) -> Self {
+        Self {
+            org_id: org_id.to_string(),
+            ref_id: ref_id.to_string(),
+            actor_id: Some(actor_id.to_string()),
+            queued: Vec::new(),
+        }
+    }
+
+    /// Notices about `ref_id` that nobody caused, such as reminders.
+    pub fn system(org_id: &str, ref_id: &str) -> Self {
+        Self {
+            org_id: org_id.to_string(),
+            ref_id: ref_id.to_string(),
+            actor_id: None,
+            queued: Vec::new(),
+        }
+    }
+
+    /// Queue a notice for `user_id` unless they caused the change or
+    /// already have one queued.
+    pub fn add(&mut self, user_id: &str, notice: Notice) {
+        if self.actor_id.as_deref() != Some(user_id)
+            && !self.queued.iter().any(|(queued, _)| queued == user_id)
+        {
+            self.queued.push((user_id.to_string(), notice));
+        }
+    }
+
+    /// Queue a notice for every user `@mentioned` by email in `text` but not
+    /// in `previous`, so edits only notify newly mentioned users. Failures
+    /// are logged, not returned, like those of [`Outbox::send`].
+    pub async fn add_mentions(
+        &mut self,
+        state: &AppState,
+        text: &str,
+        previous: Option<&str>,
+        notice: impl Fn() -> Notice,
+    ) {
+        let already = previous.map(mentioned_emails).unwrap_or_default();
+        for email in mentioned_emails(text) {
+            if already.contains(&email) {
+                continue;
+            }
+            match db::find_user_by_email(&state.pool, &email).await {
+                Ok(Some((user_id, _))) => self.add(&user_id, notice()),
+                Ok(None) => {}
+                Err(err) => {
+                    tracing::warn!(ref_id = %self.ref_id, error = %err, "failed to look up mentioned user")
+                }
+            }
+        }
+    }
+
+    /// Queue a notice for a task's assignee and watchers. Failures are
+    /// logged, not returned.
+    pub async fn add_task_followers(
+        &mut self,
+        state: &AppState,
+        task_id: &str,
+        notice: impl Fn() -> Notice,
+    ) {
+        let followers = async {
+            let assignee = db::get_task(&state.pool, task_id)
+                .await?
+                .and_then(|task| task.assignee_id);
+            let watchers = db::list_task_watchers(&state.pool, task_id).await?;
+            Ok::<_, AppError>(assignee.into_iter().chain(watchers))
+        };
+        match followers.await {
+            Ok(followers) => {
+                for user_id in followers {
+                    self.add(&user_id, notice());
+                }
+            }
+            Err(err) => {
+                tracing::warn!(ref_id = %self.ref_id, error = %err, "failed to look up task followers")
+            }
+        }
+    }
+
+    /// Send the queued notices. Failures are logged, not returned: the
+    /// change they describe has already been made.
+    pub async fn send(self, state: &AppState, audience: Audience<'_>) {
+        for (user_id, notice) in self.queued {
+            match deliverable(
+                state,
+                &audience,
+                &self.org_id,
+                &user_id,
+                &self.ref_id,
+                &notice,
+            )
+            .await
+            {
+                Ok(false) => continue,
+                Ok(true) => {}
+                Err(err) => {
+                    tracing::warn!(ref_id = %self.ref_id, error = %err, "failed to check notification recipient");
+                    continue;
+                }
+            }
+            let sent = crate::routes::push_notification(
+                state,
+                &user_id,
+                &self.org_id,
+                notice.ntype,
+                &notice.title,
+                &notice.body,
+                &self.ref_id,
+            )
+            .await;
+            if let Err(err) = sent {
+                tracing::warn!(ref_id = %self.ref_id, error = %err, "failed to send notification");
+            }
+        }
+    }
+}
+
+async fn deliverable(
+    state: &AppState,
+    audience: &Audience<'_>,
+    org_id: &str,
+    user_id: &str,
+    ref_id: &str,
+    notice: &Notice,
+) -> Result<bool, AppError> {
+    let allowed = match audience {
+        Audience::Project(project) => projects::role(state, project, user_id).await?.is_some(),
+        Audience::Org => db::get_org_member_role(&state.pool, org_id, user_id)
+            .await?
+            .is_some(),
+    };
+    if !allowed || !db::notification_enabled(&state.pool, user_id, notice.ntype).await? {
+        return Ok(false);
+    }
+    let repeat = db::has_unread_notification(
+        &state.pool,
+        user_id,
+        notice.ntype,
+        ref_id,
+        &notice.title,
+        &notice.body,
+    )
+    .await?;
+    Ok(!repeat)
+}
+
+/// Remind assignees of tasks due by `now` plus the largest window. Each
+/// task gets the reminder of the tightest window it is in, or the overdue
+/// one. A task whose project cannot be found is logged and skipped. Returns
+/// how many reminders were due.
+pub async fn send_due_reminders(state: &AppState, now: i64) -> Result<usize, AppError> {
+    let windows = &state.config.due_reminder_windows_secs;
+    let since = now - OVERDUE_REMINDER_CUTOFF_SECS;
+    let until = now + windows.first().copied().unwrap_or(0);
+    let mut sent = 0;
+    for task in db::list_due_tasks(&state.pool, since, until).await? {
+        let left = task.due_date - now;
+        let (kind, notice) = if left <= 0 {
+            (
+                "overdue".to_string(),
+                Notice::new(TASK_OVERDUE, "Task overdue", task.title.as_str()),
+            )
+        } else {
+            let Some(window) = windows.iter().rev().find(|window| left <= **window) else {
+                continue;
+            };
+            (
+                format!("due:{}", window),
+                Notice::new(
+                    TASK_DUE_SOON,
+                    format!("Task due within {}", describe_window(*window)),
+                    task.title.as_str(),
+                ),
+            )
+        };
+        if !db::record_task_reminder(
+            &state.pool,
+            &task.id,
+            &task.assignee_id,
+            &kind,
+            task.due_date,
+            now,
+        )
+        .await?
+        {
+            continue;
+        }
+        let Some(project) = db::get_project(&state.pool, &task.project_id).await? else {
+            tracing::warn!(task_id = %task.id, project_id = %task.project_id, "skipped due reminder for a task without a project");
+            continue;
+        };
+        let mut outbox = Outbox::system(&task.org_id, &task.id);
+        outbox.add(&task.assignee_id, notice);
+        outbox.send(state, Audience::Project(&project)).await;
+        sent += 1;
+    }
+    Ok(sent)
+}
+
+pub fn spawn_reminder_task(state: AppState) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(REMINDER_INTERVAL);
+        loop {
+            interval.tick().await;
+            match send_due_reminders(&state, chrono::Utc::now().timestamp()).await {
+                Ok(0) => {}
+                Ok(count) => tracing::info!(count, "sent task due reminders"),
+                Err(err) => tracing::warn!(error = %err, "failed to send task due reminders"),
+            }
+        }
+    });
+}
+
+/// `86400` as "1 day", `7200` as "2 hours".
+fn describe_window(secs: i64) -> String {
+    let (count, unit) = match secs {
+        s if s % 86400 == 0 => (s / 86400, "day"),
+        s if s % 3600 == 0 => (s / 3600, "hour"),
+        s if s % 60 == 0 => (s / 60, "minute"),
+        s => (s, "second"),
+    };
+    if count == 1 {
+        format!("1 {}", unit)
+    } else {
+        format!("{} {}s", count, unit)
+    }
+}
+
+/// Email addresses mentioned as `@alice@example.com`, lowercased and
+/// without duplicates.
+pub fn mentioned_emails(text: &str) -> Vec<String> {
+    let is_email_char = |c: char| c.is_ascii_alphanumeric() || "._%+-@".contains(c);
+    let mut emails: Vec<String> = Vec::new();
+    let mut start = 0;
+    while let Some(offset) = text[start..].find('@') {
+        let at = start + offset;
+        let candidate: String = text[at + 1..]
+            .chars()
+            .take_while(|c| is_email_char(*c))
+            .collect();
+        start = at + 1 + candidate.len();
+        // A mention starts a word, so `bob@example.com` alone is not one.
+        if text[..at].chars().last().is_some_and(is_email_char) {
+            continue;
+        }
+        let candidate = candidate.trim_end_matches(['.', '-']).to_lowercase();
+        let valid = candidate.split_once('@').is_some_and(|(local, domain)| {
+            !local.is_empty() && domain.contains('.') && !domain.contains('@')
+        });
+        if valid && !emails.contains(&candidate) {
+            emails.push(candidate);
+        }
+    }
+    emails
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{describe_window, mentioned_emails};
+
+    #[test]
+    fn finds_email_mentions() {
+        assert_eq!(
+            mentioned_emails("ping @Bob@Example.com, and (@carol@example.org). @bob@example.com"),
+            ["bob@example.com", "carol@example.org"]
+        );
+    }
+
+    #[test]
+    fn ignores_plain_addresses_and_handles() {
+        assert!(mentioned_emails("mail bob@example.com or @bob or @bob@localhost").is_empty());
+    }
+
+    #[test]
+    fn describes_reminder_windows() {
+        assert_eq!(describe_window(86400), "1 day");
+        assert_eq!(describe_window(7200), "2 hours");
+        assert_eq!(describe_window(90), "90 seconds");
+    }
+}
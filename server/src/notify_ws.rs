@@ -12,7 +12,7 @@ use serde_json::json;
 use tokio::sync::{mpsc, RwLock};
 use uuid::Uuid;
 
-use crate::auth::decode_token;
+use crate::auth::authenticate_access_token;
 use crate::db;
 use crate::error::AppError;
 use crate::state::AppState;
@@ -94,7 +94,7 @@ pub async fn notify_handler(
     State(state): State<AppState>,
     Query(query): Query<NotifyQuery>,
 ) -> Result<Response, AppError> {
-    let claims = decode_token(&query.token, &state.config)?;
+    let claims = authenticate_access_token(&state, &query.token).await?;
     let user_id = claims.sub;
     Ok(ws.on_upgrade(move |socket| async move {
         handle_notify_socket(state, socket, user_id).await;
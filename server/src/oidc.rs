@@ -0,0 +1,355 @@
+//! OpenID Connect single sign-on: authorization code flow with PKCE.
+//!
+//! Provider metadata is discovered from `<issuer>/.well-known/openid-configuration`
+//! on first use, and ID tokens are validated against the provider's JWKS,
+//! which is refetched whenever a token names a key we have not seen yet.
+//! Every request to the provider gives up after [`REQUEST_TIMEOUT`].
+
+use axum::http::{header, Method, Request, StatusCode};
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+use base64::Engine as _;
+use hyper::body::HttpBody as _;
+use hyper::client::HttpConnector;
+use hyper::{Body, Client};
+use hyper_rustls::HttpsConnector;
+use jsonwebtoken::jwk::{AlgorithmParameters, Jwk, JwkSet};
+use jsonwebtoken::{decode, decode_header, Algorithm, DecodingKey, Validation};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::time::Duration;
+use tokio::sync::RwLock;
+
+use crate::auth::generate_opaque_token;
+use crate::config::OidcConfig;
+use crate::error::AppError;
+
+/// How long a started login may take before its state is discarded.
+pub const STATE_TTL_SECS: i64 = 600;
+const MAX_RESPONSE_BYTES: usize = 1024 * 1024;
+/// How long a request to the provider may take, reading the response
+/// included, so a hung provider cannot hold logins up.
+pub const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Deserialize)]
+struct ProviderMetadata {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+/// Claims of a validated ID token that Lumina cares about.
+#[derive(Debug, Deserialize)]
+pub struct IdTokenClaims {
+    pub iss: String,
+    pub sub: String,
+    pub email: Option<String>,
+    #[serde(default)]
+    pub email_verified: bool,
+    nonce: Option<String>,
+}
+
+/// A started login. `state`, `code_verifier` and `nonce` must be stored until
+/// the provider redirects back.
+pub struct AuthorizationRequest {
+    pub url: String,
+    pub state: String,
+    pub code_verifier: String,
+    pub nonce: String,
+}
+
+pub struct OidcClient {
+    config: OidcConfig,
+    http: Client<HttpsConnector<HttpConnector>>,
+    metadata: RwLock<Option<ProviderMetadata>>,
+    jwks: RwLock<JwkSet>,
+}
+
+impl OidcClient {
+    pub fn new(config: OidcConfig) -> Self {
+        let connector = hyper_rustls::HttpsConnectorBuilder::new()
+            .with_webpki_roots()
+            .https_or_http()
+            .enable_http1()
+            .build();
+        Self {
+            config,
+            http: Client::builder().build(connector),
+            metadata: RwLock::new(None),
+            jwks: RwLock::new(JwkSet { keys: Vec::new() }),
+        }
+    }
+
+    pub fn config(&self) -> &OidcConfig {
+        &self.config
+    }
+
+    /// Build the provider URL the user has to visit to sign in.
+    pub async fn authorization_request(&self) -> Result<AuthorizationRequest, AppError> {
+        let metadata = self.metadata().await?;
+        let state = generate_opaque_token();
+        let nonce = generate_opaque_token();
+        let code_verifier = generate_opaque_token();
+        let challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(code_verifier.as_bytes()));
+        let separator = if metadata.authorization_endpoint.contains('?') {
+            '&'
+        } else {
+            '?'
+        };
+        let url = format!(
+            "{}{}response_type=code&client_id={}&redirect_uri={}&scope={}&state={}&nonce={}\
+             &code_challenge={}&code_challenge_method=S256",
+            metadata.authorization_endpoint,
+            separator,
+            urlencoding::encode(&self.config.client_id),
+            urlencoding::encode(&self.config.redirect_url),
+            urlencoding::encode(&self.config.scopes),
+            state,
+            nonce,
+            challenge
+        );
+        Ok(AuthorizationRequest {
+            url,
+            state,
+            code_verifier,
+            nonce,
+        })
+    }
+
+    /// Redeem an authorization code and return the validated ID token claims.
+    pub async fn exchange_code(
+        &self,
+        code: &str,
+        code_verifier: &str,
+        nonce: &str,
+    ) -> Result<IdTokenClaims, AppError> {
+        let metadata = self.metadata().await?;
+        let mut form = format!(
+            "grant_type=authorization_code&code={}&redirect_uri={}&code_verifier={}",
+            urlencoding::encode(code),
+            urlencoding::encode(&self.config.redirect_url),
+            urlencoding::encode(code_verifier)
+        );
+        let mut request = Request::builder()
+            .method(Method::POST)
+            .uri(&metadata.token_endpoint)
+            .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .header(header::ACCEPT, "application/json");
+        match &self.config.client_secret {
+            // client_secret_basic: both parts are form-encoded first (RFC 6749 §2.3.1).
+            Some(secret) => {
+                let credentials = format!(
+                    "{}:{}",
+                    urlencoding::encode(&self.config.client_id),
+                    urlencoding::encode(secret)
+                );
+                request = request.header(
+                    header::AUTHORIZATION,
+                    format!("Basic {}", STANDARD.encode(credentials)),
+                );
+            }
+            None => {
+                form.push_str(&format!(
+                    "&client_id={}",
+                    urlencoding::encode(&self.config.client_id)
+                ));
+            }
+        }
+        let request = request
+            .body(Body::from(form))
+            .map_err(|e| AppError::Internal(format!("build token request: {}", e)))?;
+        let (status, body) = self.send(request).await?;
+        if !status.is_success() {
+            tracing::warn!(status = %status, "oidc token exchange rejected");
+            return Err(AppError::Unauthorized);
+        }
+        let tokens: TokenResponse = serde_json::from_slice(&body)
+            .map_err(|e| AppError::Internal(format!("parse token response: {}", e)))?;
+        self.validate_id_token(&tokens.id_token, &metadata, nonce)
+            .await
+    }
+
+    async fn validate_id_token(
+        &self,
+        id_token: &str,
+        metadata: &ProviderMetadata,
+        nonce: &str,
+    ) -> Result<IdTokenClaims, AppError> {
+        let header = decode_header(id_token).map_err(|_| AppError::Unauthorized)?;
+        // Only asymmetric algorithms: the JWKS is public, so an HMAC token
+        // "signed" with one of its keys would prove nothing.
+        if matches!(
+            header.alg,
+            Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512
+        ) {
+            return Err(AppError::Unauthorized);
+        }
+        let key = self
+            .decoding_key(header.kid.as_deref(), header.alg, metadata)
+            .await?;
+        let mut validation = Validation::new(header.alg);
+        validation.set_issuer(&[&metadata.issuer]);
+        validation.set_audience(&[&self.config.client_id]);
+        validation.leeway = 60;
+        let claims = decode::<IdTokenClaims>(id_token, &key, &validation)
+            .map_err(|e| {
+                tracing::warn!(error = %e, "oidc id token rejected");
+                AppError::Unauthorized
+            })?
+            .claims;
+        if claims.nonce.as_deref() != Some(nonce) {
+            return Err(AppError::Unauthorized);
+        }
+        Ok(claims)
+    }
+
+    /// Find the signing key for `kid`, refetching the JWKS once if it is not
+    /// cached (providers rotate keys without notice). The key must be meant
+    /// for `alg`, the algorithm the token says it was signed with.
+    async fn decoding_key(
+        &self,
+        kid: Option<&str>,
+        alg: Algorithm,
+        metadata: &ProviderMetadata,
+    ) -> Result<DecodingKey, AppError> {
+        let cached = find_key(&*self.jwks.read().await, kid);
+        let jwk = match cached {
+            Some(jwk) => jwk,
+            None => {
+                let jwks: JwkSet = self.get_json(&metadata.jwks_uri).await?;
+                let jwk = find_key(&jwks, kid);
+                *self.jwks.write().await = jwks;
+                jwk.ok_or(AppError::Unauthorized)?
+            }
+        };
+        if !key_fits(&jwk, alg) {
+            tracing::warn!(alg = ?alg, "oidc id token algorithm does not match its key");
+            return Err(AppError::Unauthorized);
+        }
+        DecodingKey::from_jwk(&jwk).map_err(|e| AppError::Internal(format!("invalid jwk: {}", e)))
+    }
+
+    async fn metadata(&self) -> Result<ProviderMetadata, AppError> {
+        if let Some(metadata) = self.metadata.read().await.clone() {
+            return Ok(metadata);
+        }
+        let url = format!(
+            "{}/.well-known/openid-configuration",
+            self.config.issuer.trim_end_matches('/')
+        );
+        let metadata: ProviderMetadata = self.get_json(&url).await?;
+        if metadata.issuer.trim_end_matches('/') != self.config.issuer.trim_end_matches('/') {
+            return Err(AppError::Internal(format!(
+                "oidc discovery returned issuer {} (expected {})",
+                metadata.issuer, self.config.issuer
+            )));
+        }
+        *self.metadata.write().await = Some(metadata.clone());
+        Ok(metadata)
+    }
+
+    async fn get_json<T: DeserializeOwned>(&self, url: &str) -> Result<T, AppError> {
+        let request = Request::builder()
+            .method(Method::GET)
+            .uri(url)
+            .header(header::ACCEPT, "application/json")
+            .body(Body::empty())
+            .map_err(|e| AppError::Internal(format!("build oidc request: {}", e)))?;
+        let (status, body) = self.send(request).await?;
+        if !status.is_success() {
+            return Err(AppError::Internal(format!(
+                "oidc request to {} failed: {}",
+                url, status
+            )));
+        }
+        serde_json::from_slice(&body)
+            .map_err(|e| AppError::Internal(format!("parse oidc response from {}: {}", url, e)))
+    }
+
+    async fn send(&self, request: Request<Body>) -> Result<(StatusCode, Vec<u8>), AppError> {
+        let uri = request.uri().clone();
+        tokio::time::timeout(REQUEST_TIMEOUT, self.fetch(request))
+            .await
+            .map_err(|_| AppError::Internal(format!("oidc request to {} timed out", uri)))?
+    }
+
+    async fn fetch(&self, request: Request<Body>) -> Result<(StatusCode, Vec<u8>), AppError> {
+        let response = self
+            .http
+            .request(request)
+            .await
+            .map_err(|e| AppError::Internal(format!("oidc request: {}", e)))?;
+        let status = response.status();
+        // Stop reading as soon as the cap is exceeded rather than buffering
+        // whatever the provider sends.
+        let mut body = response.into_body();
+        let mut bytes = Vec::new();
+        while let Some(chunk) = body.data().await {
+            let chunk =
+                chunk.map_err(|e| AppError::Internal(format!("read oidc response: {}", e)))?;
+            if bytes.len() + chunk.len() > MAX_RESPONSE_BYTES {
+                return Err(AppError::Internal("oidc response too large".to_string()));
+            }
+            bytes.extend_from_slice(&chunk);
+        }
+        Ok((status, bytes))
+    }
+}
+
+fn find_key(jwks: &JwkSet, kid: Option<&str>) -> Option<Jwk> {
+    let jwk = match kid {
+        Some(kid) => jwks.find(kid),
+        // Without a `kid` the choice is only unambiguous for a single key.
+        None if jwks.keys.len() == 1 => jwks.keys.first(),
+        None => None,
+    };
+    jwk.cloned()
+}
+
+/// Whether `jwk` may check a signature made with `alg`: its key type has to
+/// fit the algorithm, and so does its own `alg` where it names one.
+fn key_fits(jwk: &Jwk, alg: Algorithm) -> bool {
+    use Algorithm::*;
+    let kty_fits = matches!(
+        (&jwk.algorithm, alg),
+        (
+            AlgorithmParameters::RSA(_),
+            RS256 | RS384 | RS512 | PS256 | PS384 | PS512
+        ) | (AlgorithmParameters::EllipticCurve(_), ES256 | ES384)
+            | (AlgorithmParameters::OctetKeyPair(_), EdDSA)
+    );
+    let alg_fits = jwk
+        .common
+        .key_algorithm
+        .is_none_or(|key_alg| key_alg.to_string().parse::<Algorithm>().ok() == Some(alg));
+    kty_fits && alg_fits
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_only_fit_their_own_algorithm() {
+        let jwk = |value: serde_json::Value| serde_json::from_value::<Jwk>(value).unwrap();
+        let rsa = json_rsa(Some("RS256"));
+        assert!(key_fits(&jwk(rsa.clone()), Algorithm::RS256));
+        assert!(!key_fits(&jwk(rsa), Algorithm::PS256));
+        assert!(key_fits(&jwk(json_rsa(None)), Algorithm::PS256));
+        assert!(!key_fits(&jwk(json_rsa(None)), Algorithm::ES256));
+    }
+
+    fn json_rsa(alg: Option<&str>) -> serde_json::Value {
+        let mut key = serde_json::json!({ "kty": "RSA", "n": "AQAB", "e": "AQAB" });
+        if let Some(alg) = alg {
+            key["alg"] = alg.into();
+        }
+        key
+    }
+}
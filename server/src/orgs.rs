@@ -0,0 +1,60 @@
+//! Organization deletion.
+//!
+//! Deleting an organization only marks it: it disappears for its members at
+//! once, and an org admin can restore it during the grace period
+//! (`LUMINA_ORG_DELETION_GRACE_SECS`, 30 days by default). A background task
+//! then purges its members, projects, tasks, documents, annotations and
+//! notifications in one transaction, and removes the collab state of its
+//! documents from `data/collab`.
+
+use std::time::Duration;
+
+use crate::audit::{self, AuditEvent};
+use crate::db;
+use crate::error::AppError;
+use crate::state::AppState;
+
+const PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Purge one deleted organization.
+pub async fn purge(state: &AppState, org_id: &str) -> Result<(), AppError> {
+    let doc_ids = db::purge_deleted_org(&state.pool, org_id).await?;
+    state.collab.discard_docs(&doc_ids).await;
+    audit::record(
+        state,
+        AuditEvent::new(audit::ORG_PURGED, "org", org_id).org(org_id),
+    )
+    .await;
+    Ok(())
+}
+
+/// Purge every organization whose grace period ended by `now`. Returns how
+/// many were purged. An org that fails to purge is logged and retried on the
+/// next run, without holding up the others.
+pub async fn purge_expired(state: &AppState, now: i64) -> Result<usize, AppError> {
+    let cutoff = now - state.config.org_deletion_grace_secs;
+    let mut purged = 0;
+    for org_id in db::orgs_due_for_purge(&state.pool, cutoff).await? {
+        match purge(state, &org_id).await {
+            Ok(()) => purged += 1,
+            Err(err) => {
+                tracing::warn!(org_id = %org_id, error = %err, "failed to purge deleted organization")
+            }
+        }
+    }
+    Ok(purged)
+}
+
+pub fn spawn_purge_task(state: AppState) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(PURGE_INTERVAL);
+        loop {
+            interval.tick().await;
+            match purge_expired(&state, chrono::Utc::now().timestamp()).await {
+                Ok(0) => {}
+                Ok(count) => tracing::info!(count, "purged deleted organizations"),
+                Err(err) => tracing::warn!(error = %err, "failed to purge deleted organizations"),
+            }
+        }
+    });
+}
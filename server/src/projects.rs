@@ -0,0 +1,48 @@
+//! Project roles.
+//!
+//! Org roles apply to every project in the org unless a project says
+//! otherwise: org admins manage all projects, and members manage any project
+//! they hold no explicit role in. An explicit project role (`manager`,
+//! `contributor` or `viewer`) overrides that for members and is the only way
+//! a guest gets into a project.
+//!
+//! Viewers can read tasks, annotations and collab documents but not change
+//! them; contributors can; managers can also edit the project and its
+//! members.
+
+use crate::db::{self, ProjectRow};
+use crate::error::AppError;
+use crate::state::AppState;
+
+pub const MANAGER: &str = "manager";
+pub const CONTRIBUTOR: &str = "contributor";
+pub const VIEWER: &str = "viewer";
+/// Project roles, weakest first.
+pub const ROLES: &[&str] = &[VIEWER, CONTRIBUTOR, MANAGER];
+
+/// The role `user_id` has in `project`, or `None` without access.
+pub async fn role(
+    state: &AppState,
+    project: &ProjectRow,
+    user_id: &str,
+) -> Result<Option<String>, AppError> {
+    let Some(org_role) = db::get_org_member_role(&state.pool, &project.org_id, user_id).await?
+    else {
+        return Ok(None);
+    };
+    if org_role == "admin" {
+        return Ok(Some(MANAGER.to_string()));
+    }
+    let explicit = db::get_project_member_role(&state.pool, &project.id, user_id).await?;
+    Ok(match (org_role.as_str(), explicit) {
+        (_, Some(role)) => Some(role),
+        ("guest", None) => None,
+        (_, None) => Some(MANAGER.to_string()),
+    })
+}
+
+/// Whether `role` is at least as strong as `required`.
+pub fn allows(role: &str, required: &str) -> bool {
+    let rank = |role: &str| ROLES.iter().position(|candidate| *candidate == role);
+    matches!((rank(role), rank(required)), (Some(have), Some(need)) if have >= need)
+}
@@ -1,10 +1,21 @@
-use axum::http::HeaderMap;
+use axum::extract::{ConnectInfo, State};
+use axum::http::{HeaderMap, HeaderValue, Request};
+use axum::middleware::Next;
+use axum::response::Response;
 use governor::clock::{Clock, DefaultClock};
 use governor::state::keyed::DashMapStateStore;
 use governor::{Quota, RateLimiter as GovRateLimiter};
-use std::net::IpAddr;
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
 use std::num::NonZeroU32;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::state::AppState;
+
+/// Internal request header carrying the client IP resolved by
+/// [`tag_client_ip`]. Any client-supplied value is overwritten.
+pub const CLIENT_IP_HEADER: &str = "x-lumina-client-ip";
 
 /// Keyed rate limiter — one bucket per IP string.
 pub type KeyedRateLimiter = GovRateLimiter<String, DashMapStateStore<String>, DefaultClock>;
@@ -38,6 +49,142 @@ impl AuthRateLimiter {
     }
 }
 
+/// Failures older than this are forgotten (unless the lockout itself is
+/// longer).
+const FAILURE_MEMORY: Duration = Duration::from_secs(60 * 60);
+/// Most accounts tracked at once, so a spray across random emails cannot
+/// grow the table without bound. When it is full, idle entries are pruned
+/// first; if that is not enough, the entries closest to being forgotten go,
+/// accounts that are not locked out before those that are.
+const MAX_TRACKED_ACCOUNTS: usize = 100_000;
+
+#[derive(Debug, Clone, Copy)]
+struct FailureState {
+    failures: u32,
+    last_failure: Instant,
+    locked_until: Option<Instant>,
+}
+
+/// Per-account failed-login tracking, complementing the per-IP
+/// [`AuthRateLimiter`] against attackers spread over many addresses.
+///
+/// The first `threshold - 1` failures are free. The next one locks the
+/// account for `base`, and every failure after a lockout doubles it, up to
+/// `max`. A successful login clears the record.
+///
+/// Like the per-IP limiter, the record lives in this process only: it is
+/// lost on restart, and replicas behind a load balancer each count
+/// failures separately.
+#[derive(Clone)]
+pub struct LoginGuard {
+    inner: Arc<Mutex<HashMap<String, FailureState>>>,
+    threshold: u32,
+    base: Duration,
+    max: Duration,
+    capacity: usize,
+}
+
+impl LoginGuard {
+    pub fn new(threshold: u32, base_secs: u64, max_secs: u64) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(HashMap::new())),
+            threshold: threshold.max(1),
+            base: Duration::from_secs(base_secs.max(1)),
+            max: Duration::from_secs(max_secs.max(base_secs).max(1)),
+            capacity: MAX_TRACKED_ACCOUNTS,
+        }
+    }
+
+    /// Whether `account` may attempt to log in.
+    /// Returns `Ok(())` or `Err(retry_after_secs)`.
+    pub fn check(&self, account: &str) -> Result<(), u64> {
+        self.check_at(account, Instant::now())
+    }
+
+    /// Record a failed attempt. Returns `true` if it started a lockout.
+    pub fn record_failure(&self, account: &str) -> bool {
+        self.record_failure_at(account, Instant::now())
+    }
+
+    pub fn record_success(&self, account: &str) {
+        let mut accounts = self.inner.lock().expect("login guard lock");
+        accounts.remove(account);
+    }
+
+    fn check_at(&self, account: &str, now: Instant) -> Result<(), u64> {
+        let accounts = self.inner.lock().expect("login guard lock");
+        match accounts.get(account).and_then(|state| state.locked_until) {
+            Some(until) if until > now => Err((until - now).as_secs().max(1)),
+            _ => Ok(()),
+        }
+    }
+
+    fn record_failure_at(&self, account: &str, now: Instant) -> bool {
+        let mut accounts = self.inner.lock().expect("login guard lock");
+        if accounts.len() >= self.capacity && !accounts.contains_key(account) {
+            self.make_room(&mut accounts, now);
+        }
+        let state = accounts.entry(account.to_string()).or_insert(FailureState {
+            failures: 0,
+            last_failure: now,
+            locked_until: None,
+        });
+        if self.is_stale(state, now) {
+            *state = FailureState {
+                failures: 0,
+                last_failure: now,
+                locked_until: None,
+            };
+        }
+        state.failures += 1;
+        state.last_failure = now;
+        if state.failures < self.threshold {
+            return false;
+        }
+        let doublings = (state.failures - self.threshold).min(31);
+        let lockout = self
+            .base
+            .checked_mul(1 << doublings)
+            .unwrap_or(self.max)
+            .min(self.max);
+        state.locked_until = Some(now + lockout);
+        true
+    }
+
+    /// Shrink a full table to leave a tenth of it free, so the next
+    /// failures do not each pay for a full scan.
+    fn make_room(&self, accounts: &mut HashMap<String, FailureState>, now: Instant) {
+        accounts.retain(|_, state| !self.is_stale(state, now));
+        let target = (self.capacity - self.capacity / 10).min(self.capacity - 1);
+        if accounts.len() <= target {
+            return;
+        }
+        let mut ranked: Vec<(bool, Instant, String)> = accounts
+            .iter()
+            .map(|(account, state)| {
+                let locked = state.locked_until.is_some_and(|until| until > now);
+                (locked, Self::active_until(state), account.clone())
+            })
+            .collect();
+        ranked.sort_unstable();
+        let excess = accounts.len() - target;
+        for (_, _, account) in ranked.into_iter().take(excess) {
+            accounts.remove(&account);
+        }
+    }
+
+    fn active_until(state: &FailureState) -> Instant {
+        state
+            .locked_until
+            .unwrap_or(state.last_failure)
+            .max(state.last_failure + FAILURE_MEMORY)
+    }
+
+    fn is_stale(&self, state: &FailureState, now: Instant) -> bool {
+        Self::active_until(state) <= now
+    }
+}
+
 /// Resolve client IP for rate limiting.
 ///
 /// - `trusted_proxy_hops == 0` (default): use the TCP socket address directly.
@@ -69,6 +216,35 @@ pub fn resolve_client_ip(
     socket_ip.to_string()
 }
 
+/// Middleware: resolve the client IP once per request and expose it to
+/// handlers through [`CLIENT_IP_HEADER`], so credential bookkeeping does not
+/// need `ConnectInfo` in every handler signature.
+pub async fn tag_client_ip<B>(
+    State(state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    mut req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let ip = resolve_client_ip(addr.ip(), req.headers(), state.config.trusted_proxy_hops);
+    match HeaderValue::from_str(&ip) {
+        Ok(value) => {
+            req.headers_mut().insert(CLIENT_IP_HEADER, value);
+        }
+        Err(_) => {
+            req.headers_mut().remove(CLIENT_IP_HEADER);
+        }
+    }
+    next.run(req).await
+}
+
+/// Client IP previously resolved by [`tag_client_ip`], if any.
+pub fn client_ip(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get(CLIENT_IP_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.to_string())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -129,6 +305,68 @@ mod tests {
         assert_eq!(resolve_client_ip(SOCKET_IP, &headers, 2), "198.51.100.1");
     }
 
+    // ── Per-account login guard ──
+
+    #[test]
+    fn login_guard_locks_after_threshold_with_backoff() {
+        let guard = LoginGuard::new(3, 10, 40);
+        let start = Instant::now();
+        assert!(!guard.record_failure_at("a@example.com", start));
+        assert!(!guard.record_failure_at("a@example.com", start));
+        assert!(guard.check_at("a@example.com", start).is_ok());
+
+        assert!(guard.record_failure_at("a@example.com", start));
+        assert_eq!(guard.check_at("a@example.com", start), Err(10));
+        assert!(guard.check_at("b@example.com", start).is_ok());
+
+        // Each failure after a lockout doubles it, up to the maximum.
+        let later = start + Duration::from_secs(11);
+        assert!(guard.check_at("a@example.com", later).is_ok());
+        guard.record_failure_at("a@example.com", later);
+        assert_eq!(guard.check_at("a@example.com", later), Err(20));
+        let later = later + Duration::from_secs(21);
+        guard.record_failure_at("a@example.com", later);
+        assert_eq!(guard.check_at("a@example.com", later), Err(40));
+        let later = later + Duration::from_secs(41);
+        guard.record_failure_at("a@example.com", later);
+        assert_eq!(guard.check_at("a@example.com", later), Err(40));
+    }
+
+    #[test]
+    fn login_guard_resets_on_success_and_after_idle_period() {
+        let guard = LoginGuard::new(2, 10, 40);
+        let start = Instant::now();
+        guard.record_failure_at("a@example.com", start);
+        guard.record_success("a@example.com");
+        assert!(!guard.record_failure_at("a@example.com", start));
+
+        let much_later = start + FAILURE_MEMORY + Duration::from_secs(1);
+        assert!(!guard.record_failure_at("a@example.com", much_later));
+    }
+
+    #[test]
+    fn login_guard_stays_within_capacity_and_keeps_lockouts() {
+        let guard = LoginGuard {
+            capacity: 10,
+            ..LoginGuard::new(2, 60, 60)
+        };
+        let start = Instant::now();
+        guard.record_failure_at("locked@example.com", start);
+        guard.record_failure_at("locked@example.com", start);
+        for i in 0..50 {
+            let now = start + Duration::from_secs(i + 1);
+            guard.record_failure_at(&format!("spray{}@example.com", i), now);
+            assert!(guard.inner.lock().unwrap().len() <= 10);
+        }
+        let now = start + Duration::from_secs(51);
+        assert!(guard.check_at("locked@example.com", now).is_err());
+        assert!(guard
+            .inner
+            .lock()
+            .unwrap()
+            .contains_key("spray49@example.com"));
+    }
+
     // ── Edge cases ──
 
     #[test]
@@ -0,0 +1,166 @@
+//! Registration policy and invite codes.
+//!
+//! The policy is one of four modes: `open` (anyone), `closed` (nobody),
+//! `domains` (addresses on an allow-list of email domains) and `invite`
+//! (holders of an invite code, or addresses with a pending organization
+//! invitation). Instance admins change it through
+//! `PUT /admin/settings`; until they do, `LUMINA_REGISTRATION_MODE` and
+//! `LUMINA_REGISTRATION_DOMAINS` apply.
+//!
+//! Invite codes are single-use and created by instance admins or org
+//! admins. A code also admits its holder in `domains` mode, and a code tied
+//! to an organization adds the new account to it in any mode but `closed`.
+
+use serde_json::json;
+
+use crate::audit::{self, AuditEvent};
+use crate::auth::hash_opaque_token;
+use crate::db::{self, InviteCodeRow};
+use crate::error::AppError;
+use crate::state::AppState;
+
+pub const MODE_OPEN: &str = "open";
+pub const MODE_CLOSED: &str = "closed";
+pub const MODE_DOMAINS: &str = "domains";
+pub const MODE_INVITE: &str = "invite";
+pub const MODES: &[&str] = &[MODE_OPEN, MODE_CLOSED, MODE_DOMAINS, MODE_INVITE];
+
+pub struct Policy {
+    pub mode: String,
+    pub domains: Vec<String>,
+}
+
+/// The current policy. An unknown mode is treated as `closed`.
+pub async fn policy(state: &AppState) -> Result<Policy, AppError> {
+    let mode = db::get_setting(&state.pool, db::SETTING_REGISTRATION_MODE)
+        .await?
+        .unwrap_or_else(|| state.config.registration_mode.clone());
+    let domains = match db::get_setting(&state.pool, db::SETTING_REGISTRATION_DOMAINS).await? {
+        Some(domains) => parse_domains(&domains),
+        None => state.config.registration_domains.clone(),
+    };
+    let mode = if MODES.contains(&mode.as_str()) {
+        mode
+    } else {
+        MODE_CLOSED.to_string()
+    };
+    Ok(Policy { mode, domains })
+}
+
+/// Split a comma-separated domain list, dropping blanks and leading `@`s.
+pub fn parse_domains(raw: &str) -> Vec<String> {
+    raw.split(',')
+        .map(|domain| domain.trim().trim_start_matches('@').to_lowercase())
+        .filter(|domain| !domain.is_empty())
+        .collect()
+}
+
+fn domain_allowed(domains: &[String], email: &str) -> bool {
+    email
+        .rsplit_once('@')
+        .is_some_and(|(_, domain)| domains.iter().any(|allowed| allowed == domain))
+}
+
+/// Decide whether `email` may register, claiming `invite_code` if one is
+/// given. A claimed code must be passed to [`redeem`] once the account
+/// exists, or to [`release`] if creating it fails.
+pub async fn admit(
+    state: &AppState,
+    email: &str,
+    invite_code: Option<&str>,
+) -> Result<Option<InviteCodeRow>, AppError> {
+    let policy = policy(state).await?;
+    if policy.mode == MODE_CLOSED {
+        return Err(AppError::Forbidden);
+    }
+    let invite = match invite_code.map(str::trim).filter(|code| !code.is_empty()) {
+        Some(code) => Some(
+            db::claim_invite_code(&state.pool, &hash_opaque_token(code))
+                .await?
+                .ok_or_else(|| {
+                    AppError::BadRequest("invalid or expired invite code".to_string())
+                })?,
+        ),
+        None => None,
+    };
+    let admitted = invite.is_some()
+        || match policy.mode.as_str() {
+            MODE_OPEN => true,
+            MODE_DOMAINS => domain_allowed(&policy.domains, email),
+            MODE_INVITE => !db::list_pending_invitations_for_email(&state.pool, email)
+                .await?
+                .is_empty(),
+            _ => false,
+        };
+    if !admitted {
+        return Err(AppError::Forbidden);
+    }
+    Ok(invite)
+}
+
+/// Hand a claimed code back after a failed registration.
+pub async fn release(state: &AppState, invite: Option<InviteCodeRow>) {
+    if let Some(invite) = invite {
+        if let Err(err) = db::finish_invite_code(&state.pool, &invite.id, None).await {
+            tracing::warn!(invite_id = %invite.id, error = %err, "failed to release invite code");
+        }
+    }
+}
+
+/// Record `user_id` as the holder of a claimed code and join the code's
+/// organization, if it still exists. Failures are logged, not returned: the
+/// account has already been created.
+pub async fn redeem(
+    state: &AppState,
+    invite: Option<InviteCodeRow>,
+    user_id: &str,
+    ip: Option<&str>,
+) {
+    let Some(invite) = invite else {
+        return;
+    };
+    let invite_id = invite.id.clone();
+    if let Err(err) = finish_redeem(state, invite, user_id, ip).await {
+        tracing::warn!(
+            invite_id = %invite_id,
+            user_id = %user_id,
+            error = %err,
+            "failed to redeem invite code"
+        );
+    }
+}
+
+async fn finish_redeem(
+    state: &AppState,
+    invite: InviteCodeRow,
+    user_id: &str,
+    ip: Option<&str>,
+) -> Result<(), AppError> {
+    db::finish_invite_code(&state.pool, &invite.id, Some(user_id)).await?;
+    audit::record(
+        state,
+        AuditEvent::new(audit::INVITE_CODE_REDEEMED, "invite_code", &invite.id)
+            .actor(user_id)
+            .ip(ip),
+    )
+    .await;
+
+    let Some(org_id) = invite.org_id else {
+        return Ok(());
+    };
+    if db::get_organization(&state.pool, &org_id).await?.is_none() {
+        return Ok(());
+    }
+    let role = invite.role.unwrap_or_else(|| "member".to_string());
+    db::add_org_member(&state.pool, &org_id, user_id, &role).await?;
+    audit::record(
+        state,
+        AuditEvent::new(audit::ORG_MEMBER_ADDED, "user", user_id)
+            .actor(&invite.created_by)
+            .ip(ip)
+            .org(&org_id)
+            .details(json!({ "role": role, "invite_code": invite.id })),
+    )
+    .await;
+    Ok(())
+}
@@ -2,15 +2,13 @@ use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
 use axum::extract::{Query, State};
 use axum::http::HeaderMap;
 use axum::response::Response;
-use base64::Engine;
 use futures_util::{SinkExt, StreamExt};
 use serde::Deserialize;
 use serde_json::json;
 use tokio::sync::mpsc;
 use uuid::Uuid;
 
-use crate::auth::{decode_token, verify_password};
-use crate::db;
+use crate::auth::{authorize_header, SCOPE_RELAY};
 use crate::error::AppError;
 use crate::state::{AppState, RelayPeer};
 
@@ -32,7 +30,7 @@ pub async fn relay_handler(
         ));
     }
 
-    let user_id = authorize_request(&state, &headers).await?;
+    let user_id = authorize_header(&state, &headers, SCOPE_RELAY).await?;
     let connections = state.metrics.inc_relay_connections();
     tracing::info!(
         target: "metrics",
@@ -171,36 +169,3 @@ async fn handle_socket(state: AppState, socket: WebSocket, user_id: String, clie
         client = %client
     );
 }
-
-async fn authorize_request(state: &AppState, headers: &HeaderMap) -> Result<String, AppError> {
-    let header = headers
-        .get(axum::http::header::AUTHORIZATION)
-        .ok_or(AppError::Unauthorized)?;
-    let value = header.to_str().map_err(|_| AppError::Unauthorized)?;
-
-    if let Some(token) = value.strip_prefix("Bearer ") {
-        let claims = decode_token(token.trim(), &state.config)?;
-        return Ok(claims.sub);
-    }
-
-    if let Some(encoded) = value.strip_prefix("Basic ") {
-        let decoded = base64::engine::general_purpose::STANDARD
-            .decode(encoded.trim().as_bytes())
-            .map_err(|_| AppError::Unauthorized)?;
-        let decoded = String::from_utf8(decoded).map_err(|_| AppError::Unauthorized)?;
-        let mut parts = decoded.splitn(2, ':');
-        let email = parts.next().unwrap_or("").trim().to_lowercase();
-        let password = parts.next().unwrap_or("").to_string();
-        if email.is_empty() || password.is_empty() {
-            return Err(AppError::Unauthorized);
-        }
-        let user = db::find_user_by_email(&state.pool, &email).await?;
-        let (user_id, password_hash) = user.ok_or(AppError::Unauthorized)?;
-        if !verify_password(&password, &password_hash)? {
-            return Err(AppError::Unauthorized);
-        }
-        return Ok(user_id);
-    }
-
-    Err(AppError::Unauthorized)
-}
@@ -1,23 +1,54 @@
 use axum::extract::{ConnectInfo, Json, Path, Query, State};
 use axum::http::{HeaderMap, StatusCode};
 use axum::response::IntoResponse;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine as _;
 use serde::Deserialize;
 use serde_json::json;
+use std::collections::HashMap;
 use std::net::SocketAddr;
 
-use crate::auth::{create_token, decode_token, hash_password, verify_password};
+use crate::audit::{self, AuditEvent};
+use crate::auth::{
+    authenticate_access_token, authenticate_bearer, check_login_guard, create_mfa_challenge,
+    decode_mfa_challenge, device_name_from_headers, ensure_user_enabled, generate_app_token,
+    hash_password, issue_email_token, issue_recovery_codes, login_failed, login_succeeded,
+    redeem_email_token, redeem_recovery_code, rotate_refresh_token, start_session, verify_password,
+    verify_totp_code, Claims, KIND_APP_PASSWORD, KIND_PERSONAL_ACCESS_TOKEN, SCOPE_API,
+    TOKEN_SCOPES,
+};
 use crate::db;
 use crate::error::AppError;
+use crate::mailer::Email;
 use crate::models;
 use crate::models::{
-    AddOrgMemberRequest, AnnotationDetail, AnnotationReplyDetail, AuthResponse,
-    CreateAnnotationReplyRequest, CreateAnnotationRequest, CreateOrgRequest, CreateProjectRequest,
-    CreateTaskRequest, CreateWorkspaceRequest, LoginRequest, MarkNotificationReadRequest,
-    NotificationSummary, OrgDetail, OrgMemberInfo, OrgSummary, ProjectSummary, RegisterRequest,
-    ResolveDocRequest, ResolveDocResponse, TaskSummary, TokenResponse, UpdateOrgRequest,
-    UpdateTaskRequest, UserSummary, WorkspaceSummary,
+    AddOrgMemberRequest, AdminResetPasswordRequest, AdminUserDetail, AdminUserList, AdminUserQuery,
+    AdminUserSummary, AnnotationDetail, AnnotationReplyDetail, AppTokenSummary, AuditEntry,
+    AuditLogPage, AuditQuery, AuthProviders, AuthResponse, ChangePasswordRequest,
+    CreateAnnotationReplyRequest, CreateAnnotationRequest, CreateAppTokenRequest,
+    CreateInviteCodeRequest, CreateLabelRequest, CreateOrgInvitationRequest, CreateOrgRequest,
+    CreateProjectRequest, CreateTaskCommentRequest, CreateTaskRequest, CreateWorkspaceRequest,
+    CreatedAppTokenResponse, CreatedInviteCode, CreatedOrgInvitation, DeleteAccountRequest,
+    DeletedOrgSummary, DeviceKeyInfo, DisableTwoFactorRequest, EmailRequest,
+    EnrollDeviceKeyRequest, InstanceSettings, InviteCodeInfo, JoinOrgRequest, LabelInfo,
+    LoginRequest, LoginResponse, MarkNotificationReadRequest, MfaChallengeResponse,
+    MfaLoginRequest, MoveTaskRequest, NotificationPreference, NotificationSummary,
+    OidcAuthorizeResponse, OidcCallbackRequest, OidcProviderInfo, OrgDetail, OrgInvitationInfo,
+    OrgMemberInfo, OrgSummary, ProjectListQuery, ProjectMemberInfo, ProjectSummary,
+    RecoveryCodesResponse, RefreshRequest, RegisterDeviceKeyRequest, RegisterRequest,
+    ResetPasswordRequest, ResolveDocRequest, ResolveDocResponse, SessionSummary,
+    SetProjectMemberRequest, SubtaskProgress, TaskActivityInfo, TaskCommentInfo, TaskDetail,
+    TaskListQuery, TaskPage, TaskSummary, TokenResponse, TotpCodeRequest, TotpSetupResponse,
+    TransferOrgRequest, TwoFactorStatus, UpdateInstanceSettingsRequest, UpdateLabelRequest,
+    UpdateNotificationPreferencesRequest, UpdateOrgMemberRequest, UpdateOrgRequest,
+    UpdateProjectRequest, UpdateTaskRequest, UpdateWorkflowRequest, UserSummary,
+    VerificationRequiredResponse, VerifyEmailRequest, Workflow, WorkspaceSummary,
 };
+use crate::notifications::{self, Audience, Notice, Outbox};
+use crate::projects;
+use crate::registration;
 use crate::state::AppState;
+use crate::workflows;
 
 // ── Existing routes ─────────────────────────────────────────────────
 
@@ -29,14 +60,31 @@ pub async fn metrics(State(state): State<AppState>) -> impl IntoResponse {
     (StatusCode::OK, Json(state.metrics.snapshot()))
 }
 
-pub async fn register(
-    State(state): State<AppState>,
-    ConnectInfo(addr): ConnectInfo<SocketAddr>,
-    headers: HeaderMap,
-    Json(payload): Json<RegisterRequest>,
-) -> Result<Json<AuthResponse>, AppError> {
+/// Public keys for verifying access tokens issued by this server.
+pub async fn jwks(State(state): State<AppState>) -> impl IntoResponse {
+    (
+        StatusCode::OK,
+        [(axum::http::header::CACHE_CONTROL, "public, max-age=300")],
+        Json(state.keys.jwks()),
+    )
+}
+
+fn require_password_login(state: &AppState) -> Result<(), AppError> {
+    if state.config.password_login_enabled {
+        Ok(())
+    } else {
+        Err(AppError::Forbidden)
+    }
+}
+
+/// Apply the per-IP auth rate limit and return the resolved client IP.
+fn limit_auth_attempt(
+    state: &AppState,
+    addr: SocketAddr,
+    headers: &HeaderMap,
+) -> Result<String, AppError> {
     let ip =
-        crate::rate_limit::resolve_client_ip(addr.ip(), &headers, state.config.trusted_proxy_hops);
+        crate::rate_limit::resolve_client_ip(addr.ip(), headers, state.config.trusted_proxy_hops);
     state.auth_limiter.check(&ip).map_err(|secs| {
         state
             .metrics
@@ -44,6 +92,17 @@ pub async fn register(
             .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
         AppError::RateLimited(secs)
     })?;
+    Ok(ip)
+}
+
+pub async fn register(
+    State(state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Json(payload): Json<RegisterRequest>,
+) -> Result<Json<LoginResponse>, AppError> {
+    require_password_login(&state)?;
+    let ip = limit_auth_attempt(&state, addr, &headers)?;
     let email = payload.email.trim().to_lowercase();
     let password = payload.password.trim().to_string();
     if email.is_empty() || password.len() < 8 {
@@ -52,21 +111,31 @@ pub async fn register(
         ));
     }
 
+    let invite = registration::admit(&state, &email, payload.invite_code.as_deref()).await?;
     let hash = hash_password(&password)?;
-    let user_id = db::create_user(&state.pool, &email, &hash).await?;
-    let _workspace_id = db::create_workspace(&state.pool, &user_id, "My Workspace").await?;
-    let token = create_token(&user_id, &state.config)?;
-    let workspaces = build_workspaces(&state, &user_id).await?;
+    let user_id = match db::create_user(&state.pool, &email, &hash).await {
+        Ok(user_id) => user_id,
+        Err(err) => {
+            registration::release(&state, invite).await;
+            return Err(err);
+        }
+    };
+    registration::redeem(&state, invite, &user_id, Some(ip.as_str())).await;
+    let _workspace_id = db::create_workspace(&state.pool, &user_id, "My Workspace", false).await?;
+    if let Err(err) = send_verification_email(&state, &user_id, &email).await {
+        tracing::warn!(user_id = %user_id, error = %err, "failed to send verification email");
+    }
+    if state.config.require_email_verification {
+        return Ok(Json(LoginResponse::VerificationRequired(
+            VerificationRequiredResponse {
+                verification_required: true,
+                email,
+            },
+        )));
+    }
 
-    Ok(Json(AuthResponse {
-        token,
-        user: UserSummary {
-            id: user_id.clone(),
-            email: email.clone(),
-        },
-        user_id,
-        workspaces,
-    }))
+    let response = open_session(&state, &user_id, email, &headers, &ip, "registration").await?;
+    Ok(Json(LoginResponse::Authenticated(response)))
 }
 
 pub async fn login(
@@ -74,16 +143,9 @@ pub async fn login(
     ConnectInfo(addr): ConnectInfo<SocketAddr>,
     headers: HeaderMap,
     Json(payload): Json<LoginRequest>,
-) -> Result<Json<AuthResponse>, AppError> {
-    let ip =
-        crate::rate_limit::resolve_client_ip(addr.ip(), &headers, state.config.trusted_proxy_hops);
-    state.auth_limiter.check(&ip).map_err(|secs| {
-        state
-            .metrics
-            .auth_rate_limited
-            .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
-        AppError::RateLimited(secs)
-    })?;
+) -> Result<Json<LoginResponse>, AppError> {
+    require_password_login(&state)?;
+    let ip = limit_auth_attempt(&state, addr, &headers)?;
     let email = payload.email.trim().to_lowercase();
     let password = payload.password.trim().to_string();
     if email.is_empty() || password.is_empty() {
@@ -92,891 +154,7920 @@ pub async fn login(
         ));
     }
 
+    check_login_guard(&state, &email)?;
     let user = db::find_user_by_email(&state.pool, &email).await?;
-    let (user_id, password_hash) = user.ok_or(AppError::Unauthorized)?;
+    let Some((user_id, password_hash)) = user else {
+        return Err(login_failed(&state, &email, Some(&ip), "password").await);
+    };
     if !verify_password(&password, &password_hash)? {
-        return Err(AppError::Unauthorized);
+        return Err(login_failed(&state, &email, Some(&ip), "password").await);
     }
+    ensure_user_enabled(&state, &user_id).await?;
 
-    let token = create_token(&user_id, &state.config)?;
-    let workspaces = build_workspaces(&state, &user_id).await?;
-    Ok(Json(AuthResponse {
-        token,
+    if state.config.require_email_verification
+        && !db::is_email_verified(&state.pool, &user_id).await?
+    {
+        return Ok(Json(LoginResponse::VerificationRequired(
+            VerificationRequiredResponse {
+                verification_required: true,
+                email,
+            },
+        )));
+    }
+
+    if db::get_totp_state(&state.pool, &user_id).await?.enabled {
+        return Ok(Json(LoginResponse::MfaRequired(MfaChallengeResponse {
+            mfa_required: true,
+            challenge_token: create_mfa_challenge(&state, &user_id)?,
+            methods: vec!["totp".to_string(), "recovery_code".to_string()],
+        })));
+    }
+
+    // Failures are only cleared once a session is issued, so a known
+    // password cannot be used to reset the count between second-factor
+    // guesses.
+    login_succeeded(&state, &email);
+    let response = open_session(&state, &user_id, email, &headers, &ip, "password").await?;
+    Ok(Json(LoginResponse::Authenticated(response)))
+}
+
+/// Second step of a two-factor login: trade the challenge token and a TOTP
+/// or recovery code for a session.
+pub async fn login_2fa(
+    State(state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Json(payload): Json<MfaLoginRequest>,
+) -> Result<Json<AuthResponse>, AppError> {
+    let ip = limit_auth_attempt(&state, addr, &headers)?;
+    let user_id = decode_mfa_challenge(&state, &payload.challenge_token).await?;
+    let email = db::get_user_by_id(&state.pool, &user_id)
+        .await?
+        .ok_or(AppError::Unauthorized)?;
+    check_login_guard(&state, &email)?;
+    let (method, verified) = match (payload.code.as_deref(), payload.recovery_code.as_deref()) {
+        (Some(code), None) => ("totp", verify_totp_code(&state, &user_id, code).await),
+        (None, Some(code)) => (
+            "recovery_code",
+            redeem_recovery_code(&state, &user_id, code).await,
+        ),
+        _ => {
+            return Err(AppError::BadRequest(
+                "provide either code or recovery_code".to_string(),
+            ))
+        }
+    };
+    match verified {
+        Err(AppError::Unauthorized) => {
+            return Err(login_failed(&state, &email, Some(&ip), method).await)
+        }
+        other => other?,
+    }
+
+    login_succeeded(&state, &email);
+    let response = open_session(&state, &user_id, email, &headers, &ip, method).await?;
+    Ok(Json(response))
+}
+
+/// Start a session for a fully authenticated login.
+async fn open_session(
+    state: &AppState,
+    user_id: &str,
+    email: String,
+    headers: &HeaderMap,
+    ip: &str,
+    method: &str,
+) -> Result<AuthResponse, AppError> {
+    let session =
+        start_session(state, user_id, &device_name_from_headers(headers), Some(ip)).await?;
+    audit::record(
+        state,
+        AuditEvent::new(audit::LOGIN_SUCCEEDED, "user", user_id)
+            .actor(user_id)
+            .ip(Some(ip))
+            .details(json!({ "method": method })),
+    )
+    .await;
+    accept_pending_invitations(state, user_id, &email, ip).await;
+    let workspaces = build_workspaces(state, user_id).await?;
+    Ok(AuthResponse {
+        token: session.access_token,
+        refresh_token: session.refresh_token,
         user: UserSummary {
-            id: user_id.clone(),
-            email: email.clone(),
+            id: user_id.to_string(),
+            email,
+            email_verified: db::is_email_verified(&state.pool, user_id).await?,
         },
-        user_id,
+        user_id: user_id.to_string(),
         workspaces,
-    }))
+    })
 }
 
+/// Rotate a refresh token into a new access/refresh pair.
 pub async fn refresh(
     State(state): State<AppState>,
     ConnectInfo(addr): ConnectInfo<SocketAddr>,
     headers: HeaderMap,
+    Json(payload): Json<RefreshRequest>,
 ) -> Result<Json<TokenResponse>, AppError> {
-    let ip =
-        crate::rate_limit::resolve_client_ip(addr.ip(), &headers, state.config.trusted_proxy_hops);
-    state.auth_limiter.check(&ip).map_err(|secs| {
-        state
-            .metrics
-            .auth_rate_limited
-            .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
-        AppError::RateLimited(secs)
-    })?;
-    let token = extract_bearer(&headers).ok_or(AppError::Unauthorized)?;
-    let claims = decode_token(&token, &state.config)?;
-    let new_token = create_token(&claims.sub, &state.config)?;
-    Ok(Json(TokenResponse { token: new_token }))
+    let ip = limit_auth_attempt(&state, addr, &headers)?;
+    let session = rotate_refresh_token(&state, payload.refresh_token.trim(), Some(&ip)).await?;
+    Ok(Json(TokenResponse {
+        token: session.access_token,
+        refresh_token: session.refresh_token,
+    }))
 }
 
-pub async fn list_workspaces(
+/// Revoke the session the access token belongs to.
+pub async fn logout(
     State(state): State<AppState>,
     headers: HeaderMap,
-) -> Result<Json<Vec<WorkspaceSummary>>, AppError> {
-    let user_id = require_user(&state, &headers).await?;
-    let workspaces = build_workspaces(&state, &user_id).await?;
-    Ok(Json(workspaces))
+) -> Result<Json<serde_json::Value>, AppError> {
+    let claims = require_session(&state, &headers).await?;
+    db::revoke_session(&state.pool, &claims.sid).await?;
+    Ok(Json(json!({ "ok": true })))
 }
 
-pub async fn create_workspace(
+/// Revoke every session of the caller, including the current one.
+pub async fn logout_all(
     State(state): State<AppState>,
     headers: HeaderMap,
-    Json(payload): Json<CreateWorkspaceRequest>,
-) -> Result<Json<WorkspaceSummary>, AppError> {
-    let user_id = require_user(&state, &headers).await?;
-    let name = payload.name.trim();
-    if name.is_empty() {
-        return Err(AppError::BadRequest(
-            "workspace name is required".to_string(),
-        ));
-    }
-    let workspace_id = db::create_workspace(&state.pool, &user_id, name).await?;
-    Ok(Json(WorkspaceSummary {
-        id: workspace_id,
-        name: name.to_string(),
-    }))
-}
-
-// ── Helper functions ────────────────────────────────────────────────
-
-async fn build_workspaces(
-    state: &AppState,
-    user_id: &str,
-) -> Result<Vec<WorkspaceSummary>, AppError> {
-    let workspaces = db::list_workspaces(&state.pool, user_id).await?;
-    Ok(workspaces
-        .into_iter()
-        .map(|(id, name)| WorkspaceSummary { id, name })
-        .collect())
-}
-
-async fn require_user(state: &AppState, headers: &HeaderMap) -> Result<String, AppError> {
-    let token = extract_bearer(headers).ok_or(AppError::Unauthorized)?;
-    let claims = decode_token(&token, &state.config)?;
-    Ok(claims.sub)
+) -> Result<Json<serde_json::Value>, AppError> {
+    let claims = require_session(&state, &headers).await?;
+    db::revoke_user_sessions(&state.pool, &claims.sub, None).await?;
+    Ok(Json(json!({ "ok": true })))
 }
 
-fn extract_bearer(headers: &HeaderMap) -> Option<String> {
-    let header = headers.get(axum::http::header::AUTHORIZATION)?;
-    let value = header.to_str().ok()?;
-    value
-        .strip_prefix("Bearer ")
-        .map(|token| token.trim().to_string())
+pub async fn list_sessions(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<Vec<SessionSummary>>, AppError> {
+    let claims = require_session(&state, &headers).await?;
+    let sessions = db::list_active_sessions(&state.pool, &claims.sub).await?;
+    Ok(Json(
+        sessions
+            .into_iter()
+            .map(|s| SessionSummary {
+                current: s.id == claims.sid,
+                id: s.id,
+                device_name: s.device_name,
+                ip: s.ip,
+                created_at: s.created_at,
+                last_used_at: s.last_used_at,
+                expires_at: s.expires_at,
+            })
+            .collect(),
+    ))
 }
 
-/// Verify the user is authenticated and has one of the allowed roles in the org.
-async fn require_org_role(
-    state: &AppState,
-    headers: &HeaderMap,
-    org_id: &str,
-    allowed_roles: &[&str],
-) -> Result<String, AppError> {
-    let user_id = require_user(state, headers).await?;
-    let role = db::get_org_member_role(&state.pool, org_id, &user_id)
+pub async fn revoke_session(
+    State(state): State<AppState>,
+    Path(session_id): Path<String>,
+    headers: HeaderMap,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let claims = require_session(&state, &headers).await?;
+    let session = db::get_session(&state.pool, &session_id)
         .await?
-        .ok_or(AppError::Forbidden)?;
-    if !allowed_roles.contains(&role.as_str()) {
-        return Err(AppError::Forbidden);
+        .ok_or(AppError::NotFound)?;
+    if session.user_id != claims.sub {
+        return Err(AppError::NotFound);
     }
-    Ok(user_id)
-}
-
-/// Verify the user is authenticated and is any member of the org.
-async fn require_org_member(
-    state: &AppState,
-    headers: &HeaderMap,
-    org_id: &str,
-) -> Result<String, AppError> {
-    require_org_role(state, headers, org_id, &["admin", "member", "guest"]).await
+    db::revoke_session(&state.pool, &session_id).await?;
+    Ok(Json(json!({ "ok": true })))
 }
 
-// ── Organization routes ─────────────────────────────────────────────
+// ── Single sign-on ──────────────────────────────────────────────────
 
-pub async fn create_org(
+pub async fn auth_providers(
     State(state): State<AppState>,
-    headers: HeaderMap,
-    Json(payload): Json<CreateOrgRequest>,
-) -> Result<Json<OrgSummary>, AppError> {
-    let user_id = require_user(&state, &headers).await?;
-    let name = payload.name.trim();
-    if name.is_empty() {
-        return Err(AppError::BadRequest("org name is required".to_string()));
-    }
-    let org_id = db::create_organization(&state.pool, &user_id, name).await?;
-    Ok(Json(OrgSummary {
-        id: org_id,
-        name: name.to_string(),
-        role: "admin".to_string(),
+) -> Result<Json<AuthProviders>, AppError> {
+    Ok(Json(AuthProviders {
+        password: state.config.password_login_enabled,
+        registration: registration::policy(&state).await?.mode,
+        oidc: state.oidc.as_ref().map(|oidc| OidcProviderInfo {
+            name: oidc.config().provider_name.clone(),
+        }),
     }))
 }
 
-pub async fn list_orgs(
-    State(state): State<AppState>,
-    headers: HeaderMap,
-) -> Result<Json<Vec<OrgSummary>>, AppError> {
-    let user_id = require_user(&state, &headers).await?;
-    let orgs = db::list_user_organizations(&state.pool, &user_id).await?;
-    Ok(Json(
-        orgs.into_iter()
-            .map(|(id, name, role)| OrgSummary { id, name, role })
-            .collect(),
-    ))
+fn oidc_client(state: &AppState) -> Result<&crate::oidc::OidcClient, AppError> {
+    state.oidc.as_deref().ok_or(AppError::NotFound)
 }
 
-pub async fn get_org(
+/// Start a single sign-on login. The client sends the user to the returned
+/// URL; the provider redirects back to the configured callback.
+pub async fn oidc_authorize(
     State(state): State<AppState>,
-    Path(org_id): Path<String>,
-    headers: HeaderMap,
-) -> Result<Json<OrgDetail>, AppError> {
-    let _user_id = require_org_member(&state, &headers, &org_id).await?;
-    let org = db::get_organization(&state.pool, &org_id)
-        .await?
-        .ok_or(AppError::NotFound)?;
-    let members = db::list_org_members(&state.pool, &org_id).await?;
-    Ok(Json(OrgDetail {
-        id: org.0,
-        name: org.1,
-        owner_id: org.2,
-        members: members
-            .into_iter()
-            .map(|(user_id, email, role)| OrgMemberInfo {
-                user_id,
-                email,
-                role,
-            })
-            .collect(),
+) -> Result<Json<OidcAuthorizeResponse>, AppError> {
+    let oidc = oidc_client(&state)?;
+    let request = oidc.authorization_request().await?;
+    db::insert_oidc_state(
+        &state.pool,
+        &request.state,
+        &request.code_verifier,
+        &request.nonce,
+        crate::oidc::STATE_TTL_SECS,
+    )
+    .await?;
+    Ok(Json(OidcAuthorizeResponse {
+        authorization_url: request.url,
     }))
 }
 
-pub async fn update_org(
+/// Callback when the provider redirects straight to the server.
+pub async fn oidc_callback_redirect(
     State(state): State<AppState>,
-    Path(org_id): Path<String>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
     headers: HeaderMap,
-    Json(payload): Json<UpdateOrgRequest>,
-) -> Result<Json<serde_json::Value>, AppError> {
-    let _user_id = require_org_role(&state, &headers, &org_id, &["admin"]).await?;
-    if let Some(ref name) = payload.name {
-        let name = name.trim();
-        if name.is_empty() {
-            return Err(AppError::BadRequest("org name is required".to_string()));
-        }
-        db::update_organization_name(&state.pool, &org_id, name).await?;
-    }
-    Ok(Json(json!({ "ok": true })))
+    Query(params): Query<OidcCallbackRequest>,
+) -> Result<Json<LoginResponse>, AppError> {
+    complete_oidc_login(&state, addr, &headers, params).await
 }
 
-pub async fn add_member(
+/// Callback for clients that receive the redirect themselves and forward
+/// the `code` and `state` parameters.
+pub async fn oidc_callback(
     State(state): State<AppState>,
-    Path(org_id): Path<String>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
     headers: HeaderMap,
-    Json(payload): Json<AddOrgMemberRequest>,
-) -> Result<Json<serde_json::Value>, AppError> {
-    let _user_id = require_org_role(&state, &headers, &org_id, &["admin"]).await?;
-    let email = payload.email.trim().to_lowercase();
-    let role = payload.role.trim().to_string();
-    if !["admin", "member", "guest"].contains(&role.as_str()) {
-        return Err(AppError::BadRequest("invalid role".to_string()));
+    Json(payload): Json<OidcCallbackRequest>,
+) -> Result<Json<LoginResponse>, AppError> {
+    complete_oidc_login(&state, addr, &headers, payload).await
+}
+
+/// Finish a single sign-on login. The identity is matched to a user by a
+/// previous link, else by a verified email address, else (when allowed) a
+/// new account is provisioned. Users who turned on TOTP are asked for it
+/// here too, as after a password login.
+async fn complete_oidc_login(
+    state: &AppState,
+    addr: SocketAddr,
+    headers: &HeaderMap,
+    params: OidcCallbackRequest,
+) -> Result<Json<LoginResponse>, AppError> {
+    let ip = limit_auth_attempt(state, addr, headers)?;
+    let oidc = oidc_client(state)?;
+    let (code_verifier, nonce) =
+        db::take_oidc_state(&state.pool, &params.state, crate::oidc::STATE_TTL_SECS)
+            .await?
+            .ok_or_else(|| AppError::BadRequest("unknown or expired login state".to_string()))?;
+    if let Some(error) = params.error {
+        return Err(AppError::BadRequest(
+            params.error_description.unwrap_or(error),
+        ));
+    }
+    let code = params
+        .code
+        .ok_or_else(|| AppError::BadRequest("missing authorization code".to_string()))?;
+    let claims = oidc.exchange_code(&code, &code_verifier, &nonce).await?;
+    let email = claims.email.as_deref().map(|e| e.trim().to_lowercase());
+
+    let user_id = match db::find_identity_user(&state.pool, &claims.iss, &claims.sub).await? {
+        Some(user_id) => user_id,
+        None => {
+            let email = email
+                .clone()
+                .filter(|_| claims.email_verified)
+                .ok_or_else(|| {
+                    AppError::BadRequest(
+                        "identity provider did not supply a verified email".to_string(),
+                    )
+                })?;
+            let user_id = match db::find_user_by_email(&state.pool, &email).await? {
+                Some((user_id, _)) => {
+                    if !db::is_email_verified(&state.pool, &user_id).await? {
+                        drop_unverified_credentials(state, &user_id).await?;
+                    }
+                    user_id
+                }
+                None if oidc.config().auto_provision => {
+                    registration::admit(state, &email, None).await?;
+                    // Unusable random password; the account can still get a
+                    // real one through the reset flow if password login is on.
+                    let password = hash_password(&crate::auth::generate_opaque_token())?;
+                    let user_id = db::create_user(&state.pool, &email, &password).await?;
+                    db::mark_password_unset(&state.pool, &user_id).await?;
+                    db::create_workspace(&state.pool, &user_id, "My Workspace", false).await?;
+                    user_id
+                }
+                None => return Err(AppError::Forbidden),
+            };
+            db::link_identity(
+                &state.pool,
+                &claims.iss,
+                &claims.sub,
+                &user_id,
+                Some(&email),
+            )
+            .await?;
+            db::mark_email_verified(&state.pool, &user_id).await?;
+            user_id
+        }
+    };
+
+    ensure_user_enabled(state, &user_id).await?;
+    if db::get_totp_state(&state.pool, &user_id).await?.enabled {
+        return Ok(Json(LoginResponse::MfaRequired(MfaChallengeResponse {
+            mfa_required: true,
+            challenge_token: create_mfa_challenge(state, &user_id)?,
+            methods: vec!["totp".to_string(), "recovery_code".to_string()],
+        })));
     }
-    // Look up user by email
-    let target_user = db::find_user_by_email(&state.pool, &email)
+    let email = db::get_user_by_id(&state.pool, &user_id)
         .await?
-        .ok_or(AppError::NotFound)?;
-    let target_user_id = target_user.0;
-    db::add_org_member(&state.pool, &org_id, &target_user_id, &role).await?;
-    Ok(Json(json!({ "ok": true })))
+        .ok_or(AppError::Unauthorized)?;
+    let response = open_session(state, &user_id, email, headers, &ip, "oidc").await?;
+    Ok(Json(LoginResponse::Authenticated(response)))
 }
 
-pub async fn remove_member(
-    State(state): State<AppState>,
-    Path((org_id, user_id)): Path<(String, String)>,
-    headers: HeaderMap,
-) -> Result<Json<serde_json::Value>, AppError> {
-    let _admin_id = require_org_role(&state, &headers, &org_id, &["admin"]).await?;
-    db::remove_org_member(&state.pool, &org_id, &user_id).await?;
-    Ok(Json(json!({ "ok": true })))
+/// Before an identity provider vouches for an address that a local account
+/// registered without ever confirming, drop everything whoever registered
+/// it set up: the password, TOTP, sessions and app tokens. Otherwise they
+/// could claim someone's address ahead of time and keep using the account
+/// once its owner signs in.
+async fn drop_unverified_credentials(state: &AppState, user_id: &str) -> Result<(), AppError> {
+    let password = hash_password(&crate::auth::generate_opaque_token())?;
+    db::update_user_password(&state.pool, user_id, &password).await?;
+    db::mark_password_unset(&state.pool, user_id).await?;
+    db::disable_totp(&state.pool, user_id).await?;
+    db::revoke_user_sessions(&state.pool, user_id, None).await?;
+    db::revoke_user_app_tokens(&state.pool, user_id).await?;
+    Ok(())
 }
 
-// ── Project routes ──────────────────────────────────────────────────
+// ── Passwords & email verification ──────────────────────────────────
 
-pub async fn create_project(
-    State(state): State<AppState>,
-    Path(org_id): Path<String>,
-    headers: HeaderMap,
-    Json(payload): Json<CreateProjectRequest>,
-) -> Result<Json<ProjectSummary>, AppError> {
-    let _user_id = require_org_role(&state, &headers, &org_id, &["admin", "member"]).await?;
-    let name = payload.name.trim();
-    if name.is_empty() {
-        return Err(AppError::BadRequest("project name is required".to_string()));
-    }
-    let description = payload.description.as_deref().unwrap_or("").trim();
-    let project_id = db::create_project(&state.pool, &org_id, name, description).await?;
-    Ok(Json(ProjectSummary {
-        id: project_id,
-        name: name.to_string(),
-        description: description.to_string(),
-    }))
+const EMAIL_VERIFY_TTL_SECS: i64 = 60 * 60 * 24 * 3;
+const PASSWORD_RESET_TTL_SECS: i64 = 60 * 60;
+const MIN_PASSWORD_LEN: usize = 8;
+
+async fn send_verification_email(
+    state: &AppState,
+    user_id: &str,
+    email: &str,
+) -> Result<(), AppError> {
+    let token = issue_email_token(
+        state,
+        user_id,
+        db::EMAIL_TOKEN_VERIFY,
+        EMAIL_VERIFY_TTL_SECS,
+    )
+    .await?;
+    state
+        .mailer
+        .send(Email {
+            to: email.to_string(),
+            subject: "Confirm your Lumina email address".to_string(),
+            body: format!(
+                "Open this link to confirm your email address:\n\n{}/verify-email?token={}\n\n\
+                 If you did not create a Lumina account, you can ignore this email.\n",
+                state.config.public_url, token
+            ),
+        })
+        .await
 }
 
-pub async fn list_org_projects(
+/// Change the password of the signed-in user. Every other session is signed
+/// out, since a password change often follows a suspected compromise.
+pub async fn change_password(
     State(state): State<AppState>,
-    Path(org_id): Path<String>,
     headers: HeaderMap,
-) -> Result<Json<Vec<ProjectSummary>>, AppError> {
-    let _user_id = require_org_member(&state, &headers, &org_id).await?;
-    let projects = db::list_projects(&state.pool, &org_id).await?;
-    Ok(Json(
-        projects
-            .into_iter()
-            .map(|(id, name, description)| ProjectSummary {
-                id,
-                name,
-                description,
-            })
-            .collect(),
-    ))
-}
+    Json(payload): Json<ChangePasswordRequest>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let claims = require_session(&state, &headers).await?;
+    let new_password = payload.new_password.trim();
+    if new_password.len() < MIN_PASSWORD_LEN {
+        return Err(AppError::BadRequest("new password too short".to_string()));
+    }
+    let email = db::get_user_by_id(&state.pool, &claims.sub)
+        .await?
+        .ok_or(AppError::Unauthorized)?;
+    let (_, password_hash) = db::find_user_by_email(&state.pool, &email)
+        .await?
+        .ok_or(AppError::Unauthorized)?;
+    if !verify_password(payload.current_password.trim(), &password_hash)? {
+        return Err(AppError::Unauthorized);
+    }
 
-// ── Task routes ─────────────────────────────────────────────────────
+    db::update_user_password(&state.pool, &claims.sub, &hash_password(new_password)?).await?;
+    db::revoke_user_sessions(&state.pool, &claims.sub, Some(&claims.sid)).await?;
+    Ok(Json(json!({ "ok": true })))
+}
 
-/// Helper: get project and verify user is an org member.
-async fn require_project_member(
+async fn send_password_reset_email(
     state: &AppState,
-    headers: &HeaderMap,
-    project_id: &str,
-) -> Result<(String, String), AppError> {
-    let project = db::get_project(&state.pool, project_id)
-        .await?
-        .ok_or(AppError::NotFound)?;
-    let org_id = project.1; // (id, org_id, name, description)
-    let user_id = require_org_member(state, headers, &org_id).await?;
-    Ok((user_id, org_id))
+    user_id: &str,
+    email: String,
+) -> Result<(), AppError> {
+    let token = issue_email_token(
+        state,
+        user_id,
+        db::EMAIL_TOKEN_RESET,
+        PASSWORD_RESET_TTL_SECS,
+    )
+    .await?;
+    state
+        .mailer
+        .send(Email {
+            to: email,
+            subject: "Reset your Lumina password".to_string(),
+            body: format!(
+                "Open this link to choose a new password:\n\n{}/reset-password?token={}\n\n\
+                 The link expires in one hour. If you did not ask for a reset, you can \
+                 ignore this email.\n",
+                state.config.public_url, token
+            ),
+        })
+        .await
 }
 
-// ── Document Registry ───────────────────────────────────────────────
-
-pub async fn resolve_doc(
+/// Email a password reset link. Always succeeds so the endpoint cannot be
+/// used to probe which addresses have accounts.
+pub async fn forgot_password(
     State(state): State<AppState>,
-    Path(project_id): Path<String>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
     headers: HeaderMap,
-    Json(payload): Json<ResolveDocRequest>,
-) -> Result<Json<ResolveDocResponse>, AppError> {
-    let (user_id, _org_id) = require_project_member(&state, &headers, &project_id).await?;
-    let doc_id =
-        db::resolve_or_create_doc(&state.pool, &project_id, &payload.rel_path, &user_id).await?;
-    Ok(Json(ResolveDocResponse { doc_id }))
+    Json(payload): Json<EmailRequest>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    limit_auth_attempt(&state, addr, &headers)?;
+    let email = payload.email.trim().to_lowercase();
+    if let Some((user_id, _)) = db::find_user_by_email(&state.pool, &email).await? {
+        if let Err(err) = send_password_reset_email(&state, &user_id, email).await {
+            tracing::warn!(user_id = %user_id, error = %err, "failed to send password reset email");
+        }
+    }
+    Ok(Json(json!({ "ok": true })))
 }
 
-// ── Task ────────────────────────────────────────────────────────────
-
-pub async fn create_task(
+/// Set a new password with an emailed reset token and sign out everywhere.
+pub async fn reset_password(
     State(state): State<AppState>,
-    Path(project_id): Path<String>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
     headers: HeaderMap,
-    Json(payload): Json<CreateTaskRequest>,
-) -> Result<Json<TaskSummary>, AppError> {
-    let (user_id, _org_id) = require_project_member(&state, &headers, &project_id).await?;
-    let title = payload.title.trim();
-    if title.is_empty() {
-        return Err(AppError::BadRequest("task title is required".to_string()));
+    Json(payload): Json<ResetPasswordRequest>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    require_password_login(&state)?;
+    limit_auth_attempt(&state, addr, &headers)?;
+    let new_password = payload.new_password.trim();
+    if new_password.len() < MIN_PASSWORD_LEN {
+        return Err(AppError::BadRequest("new password too short".to_string()));
     }
-    let description = payload.description.as_deref().unwrap_or("");
-    let status = payload.status.as_deref().unwrap_or("todo");
-    let priority = payload.priority.as_deref().unwrap_or("medium");
-
-    let task_id = db::create_task(
-        &state.pool,
-        &project_id,
-        title,
-        description,
-        status,
-        priority,
-        payload.assignee_id.as_deref(),
-        payload.due_date,
-        payload.start_date,
-        &user_id,
-    )
-    .await?;
-
-    Ok(Json(TaskSummary {
-        id: task_id,
-        title: title.to_string(),
-        status: status.to_string(),
-        priority: priority.to_string(),
-        assignee_id: payload.assignee_id,
-        due_date: payload.due_date,
-        position: 0.0,
-    }))
+    let user_id = redeem_email_token(&state, &payload.token, db::EMAIL_TOKEN_RESET).await?;
+    db::update_user_password(&state.pool, &user_id, &hash_password(new_password)?).await?;
+    db::revoke_user_sessions(&state.pool, &user_id, None).await?;
+    // Receiving the reset email proves control of the address.
+    db::mark_email_verified(&state.pool, &user_id).await?;
+    Ok(Json(json!({ "ok": true })))
 }
 
-pub async fn list_project_tasks(
+pub async fn verify_email(
     State(state): State<AppState>,
-    Path(project_id): Path<String>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
     headers: HeaderMap,
-) -> Result<Json<Vec<TaskSummary>>, AppError> {
-    let (_user_id, _org_id) = require_project_member(&state, &headers, &project_id).await?;
-    let tasks = db::list_tasks(&state.pool, &project_id).await?;
-    Ok(Json(
-        tasks
-            .into_iter()
-            .map(|t| TaskSummary {
-                id: t.id,
-                title: t.title,
-                status: t.status,
-                priority: t.priority,
-                assignee_id: t.assignee_id,
-                due_date: t.due_date,
-                position: t.position,
-            })
-            .collect(),
-    ))
+    Json(payload): Json<VerifyEmailRequest>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let user_id = redeem_email_token(&state, &payload.token, db::EMAIL_TOKEN_VERIFY).await?;
+    db::mark_email_verified(&state.pool, &user_id).await?;
+    // Invitations to the address were held back while it was unverified.
+    if let Some(email) = db::get_user_by_id(&state.pool, &user_id).await? {
+        let ip = crate::rate_limit::resolve_client_ip(
+            addr.ip(),
+            &headers,
+            state.config.trusted_proxy_hops,
+        );
+        accept_pending_invitations(&state, &user_id, &email, &ip).await;
+    }
+    Ok(Json(json!({ "ok": true })))
 }
 
-pub async fn update_task_handler(
+/// Send a fresh verification link. Like `forgot_password`, the response does
+/// not reveal whether the address is registered.
+pub async fn resend_verification_email(
     State(state): State<AppState>,
-    Path(task_id): Path<String>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
     headers: HeaderMap,
-    Json(payload): Json<UpdateTaskRequest>,
+    Json(payload): Json<EmailRequest>,
 ) -> Result<Json<serde_json::Value>, AppError> {
-    // Verify via task -> project -> org membership
-    let task = db::get_task(&state.pool, &task_id)
-        .await?
-        .ok_or(AppError::NotFound)?;
-    let _user_and_org = require_project_member(&state, &headers, &task.project_id).await?;
-
-    db::update_task(
-        &state.pool,
-        &task_id,
-        payload.title.as_deref(),
-        payload.description.as_deref(),
-        payload.status.as_deref(),
-        payload.priority.as_deref(),
-        payload.assignee_id.as_ref().map(|o| o.as_deref()),
-        payload.due_date,
-        payload.start_date,
-        payload.position,
-    )
-    .await?;
-
+    limit_auth_attempt(&state, addr, &headers)?;
+    let email = payload.email.trim().to_lowercase();
+    if let Some((user_id, _)) = db::find_user_by_email(&state.pool, &email).await? {
+        if !db::is_email_verified(&state.pool, &user_id).await? {
+            if let Err(err) = send_verification_email(&state, &user_id, &email).await {
+                tracing::warn!(user_id = %user_id, error = %err, "failed to send verification email");
+            }
+        }
+    }
     Ok(Json(json!({ "ok": true })))
 }
 
-pub async fn delete_task_handler(
+// ── Two-factor authentication ───────────────────────────────────────
+
+pub async fn two_factor_status(
     State(state): State<AppState>,
-    Path(task_id): Path<String>,
     headers: HeaderMap,
-) -> Result<Json<serde_json::Value>, AppError> {
-    let task = db::get_task(&state.pool, &task_id)
+) -> Result<Json<TwoFactorStatus>, AppError> {
+    let claims = require_session(&state, &headers).await?;
+    let totp = db::get_totp_state(&state.pool, &claims.sub).await?;
+    let remaining = db::list_unused_recovery_codes(&state.pool, &claims.sub)
         .await?
-        .ok_or(AppError::NotFound)?;
-    let _user_and_org = require_project_member(&state, &headers, &task.project_id).await?;
-    db::delete_task(&state.pool, &task_id).await?;
-    Ok(Json(json!({ "ok": true })))
+        .len();
+    Ok(Json(TwoFactorStatus {
+        enabled: totp.enabled,
+        recovery_codes_remaining: remaining,
+    }))
 }
 
-// ── Annotation routes ───────────────────────────────────────────────
-
-pub async fn create_annotation_handler(
+/// Generate a new TOTP secret. It is only enforced once confirmed with a
+/// valid code, so an abandoned setup never locks the user out.
+pub async fn setup_two_factor(
     State(state): State<AppState>,
-    Path(org_id): Path<String>,
     headers: HeaderMap,
-    Json(payload): Json<CreateAnnotationRequest>,
-) -> Result<Json<serde_json::Value>, AppError> {
-    let user_id = require_org_member(&state, &headers, &org_id).await?;
-    if payload.content.trim().is_empty() {
-        return Err(AppError::BadRequest(
-            "annotation content is required".to_string(),
+) -> Result<Json<TotpSetupResponse>, AppError> {
+    let claims = require_session(&state, &headers).await?;
+    if db::get_totp_state(&state.pool, &claims.sub).await?.enabled {
+        return Err(AppError::Conflict(
+            "two-factor authentication already enabled".to_string(),
         ));
     }
-    let annotation_id = db::create_annotation(
-        &state.pool,
-        &payload.doc_path,
-        &org_id,
-        &user_id,
-        payload.range_start,
-        payload.range_end,
-        &payload.content,
-    )
-    .await?;
-    Ok(Json(json!({ "id": annotation_id })))
-}
-
-#[derive(Deserialize)]
-pub struct AnnotationQuery {
-    pub doc_path: String,
+    let email = db::get_user_by_id(&state.pool, &claims.sub)
+        .await?
+        .ok_or(AppError::Unauthorized)?;
+    let secret = crate::totp::generate_secret();
+    db::set_pending_totp_secret(&state.pool, &claims.sub, &secret).await?;
+    Ok(Json(TotpSetupResponse {
+        otpauth_uri: crate::totp::otpauth_uri("Lumina", &email, &secret),
+        secret,
+    }))
 }
 
-pub async fn list_annotations_handler(
+/// Confirm enrollment with a code from the authenticator and hand out the
+/// initial recovery codes.
+pub async fn confirm_two_factor(
     State(state): State<AppState>,
-    Path(org_id): Path<String>,
     headers: HeaderMap,
-    Query(query): Query<AnnotationQuery>,
-) -> Result<Json<Vec<AnnotationDetail>>, AppError> {
-    let _user_id = require_org_member(&state, &headers, &org_id).await?;
-    let annotations = db::list_annotations(&state.pool, &query.doc_path, &org_id).await?;
-
-    let mut result = Vec::with_capacity(annotations.len());
-    for ann in annotations {
-        let replies = db::list_annotation_replies(&state.pool, &ann.id).await?;
-        result.push(AnnotationDetail {
-            id: ann.id,
-            doc_path: ann.doc_path,
-            user_id: ann.user_id,
-            range_start: ann.range_start,
-            range_end: ann.range_end,
-            content: ann.content,
-            resolved: ann.resolved,
-            created_at: ann.created_at,
-            replies: replies
-                .into_iter()
-                .map(|(id, user_id, content, created_at)| AnnotationReplyDetail {
-                    id,
-                    user_id,
-                    content,
-                    created_at,
-                })
-                .collect(),
-        });
+    Json(payload): Json<TotpCodeRequest>,
+) -> Result<Json<RecoveryCodesResponse>, AppError> {
+    let claims = require_session(&state, &headers).await?;
+    let totp = db::get_totp_state(&state.pool, &claims.sub).await?;
+    if totp.enabled {
+        return Err(AppError::Conflict(
+            "two-factor authentication already enabled".to_string(),
+        ));
     }
-    Ok(Json(result))
+    let secret = totp
+        .secret
+        .ok_or_else(|| AppError::BadRequest("two-factor setup not started".to_string()))?;
+    let step = crate::totp::verify(&secret, &payload.code, chrono::Utc::now().timestamp())
+        .ok_or_else(|| AppError::BadRequest("invalid code".to_string()))?;
+    db::enable_totp(&state.pool, &claims.sub, step).await?;
+    let recovery_codes = issue_recovery_codes(&state, &claims.sub).await?;
+    Ok(Json(RecoveryCodesResponse { recovery_codes }))
 }
 
-pub async fn create_reply(
+/// Turn two-factor authentication off. Requires the account password and a
+/// current code so a stolen session alone cannot downgrade the account.
+pub async fn disable_two_factor(
     State(state): State<AppState>,
-    Path(annotation_id): Path<String>,
     headers: HeaderMap,
-    Json(payload): Json<CreateAnnotationReplyRequest>,
+    Json(payload): Json<DisableTwoFactorRequest>,
 ) -> Result<Json<serde_json::Value>, AppError> {
-    // Verify via annotation -> org membership
-    let annotation = db::get_annotation(&state.pool, &annotation_id)
+    let claims = require_session(&state, &headers).await?;
+    let email = db::get_user_by_id(&state.pool, &claims.sub)
         .await?
-        .ok_or(AppError::NotFound)?;
-    let user_id = require_org_member(&state, &headers, &annotation.org_id).await?;
-    if payload.content.trim().is_empty() {
+        .ok_or(AppError::Unauthorized)?;
+    let (_, password_hash) = db::find_user_by_email(&state.pool, &email)
+        .await?
+        .ok_or(AppError::Unauthorized)?;
+    if !verify_password(payload.password.trim(), &password_hash)? {
+        return Err(AppError::Unauthorized);
+    }
+    if !db::get_totp_state(&state.pool, &claims.sub).await?.enabled {
         return Err(AppError::BadRequest(
-            "reply content is required".to_string(),
+            "two-factor authentication not enabled".to_string(),
         ));
     }
-    let reply_id =
-        db::create_annotation_reply(&state.pool, &annotation_id, &user_id, &payload.content)
-            .await?;
-    Ok(Json(json!({ "id": reply_id })))
+    verify_totp_code(&state, &claims.sub, &payload.code).await?;
+    db::disable_totp(&state.pool, &claims.sub).await?;
+    Ok(Json(json!({ "ok": true })))
 }
 
-pub async fn resolve_annotation_handler(
+/// Replace the recovery codes; the previous set stops working.
+pub async fn regenerate_recovery_codes(
     State(state): State<AppState>,
-    Path(annotation_id): Path<String>,
     headers: HeaderMap,
-) -> Result<Json<serde_json::Value>, AppError> {
-    let annotation = db::get_annotation(&state.pool, &annotation_id)
-        .await?
-        .ok_or(AppError::NotFound)?;
-    let _user_id = require_org_member(&state, &headers, &annotation.org_id).await?;
-    db::resolve_annotation(&state.pool, &annotation_id).await?;
-    Ok(Json(json!({ "ok": true })))
+    Json(payload): Json<TotpCodeRequest>,
+) -> Result<Json<RecoveryCodesResponse>, AppError> {
+    let claims = require_session(&state, &headers).await?;
+    if !db::get_totp_state(&state.pool, &claims.sub).await?.enabled {
+        return Err(AppError::BadRequest(
+            "two-factor authentication not enabled".to_string(),
+        ));
+    }
+    verify_totp_code(&state, &claims.sub, &payload.code).await?;
+    let recovery_codes = issue_recovery_codes(&state, &claims.sub).await?;
+    Ok(Json(RecoveryCodesResponse { recovery_codes }))
 }
 
-// ── Notification routes ─────────────────────────────────────────────
+// ── App passwords & personal access tokens ──────────────────────────
 
-#[derive(Deserialize)]
-pub struct NotificationListQuery {
-    pub limit: Option<i64>,
+fn app_token_summary(row: db::AppTokenRow) -> AppTokenSummary {
+    AppTokenSummary {
+        id: row.id,
+        name: row.name,
+        kind: row.kind,
+        scopes: row.scopes,
+        expires_at: row.expires_at,
+        revoked_at: row.revoked_at,
+        last_used_at: row.last_used_at,
+        last_used_ip: row.last_used_ip,
+        created_at: row.created_at,
+    }
 }
 
-pub async fn list_notifications_handler(
+pub async fn list_app_tokens(
     State(state): State<AppState>,
     headers: HeaderMap,
-    Query(query): Query<NotificationListQuery>,
-) -> Result<Json<Vec<NotificationSummary>>, AppError> {
-    let user_id = require_user(&state, &headers).await?;
-    let limit = query.limit.unwrap_or(50);
-    let notifications = db::list_notifications(&state.pool, &user_id, limit).await?;
-    Ok(Json(
-        notifications
-            .into_iter()
-            .map(|n| NotificationSummary {
-                id: n.id,
-                org_id: n.org_id,
-                ntype: n.ntype,
-                title: n.title,
-                body: n.body,
-                ref_id: n.ref_id,
-                read: n.read,
-                created_at: n.created_at,
-            })
-            .collect(),
-    ))
+) -> Result<Json<Vec<AppTokenSummary>>, AppError> {
+    let user_id = require_session_user(&state, &headers).await?;
+    let tokens = db::list_app_tokens(&state.pool, &user_id).await?;
+    Ok(Json(tokens.into_iter().map(app_token_summary).collect()))
 }
 
-pub async fn mark_read(
+pub async fn create_app_token(
     State(state): State<AppState>,
     headers: HeaderMap,
-    Json(payload): Json<MarkNotificationReadRequest>,
+    Json(payload): Json<CreateAppTokenRequest>,
+) -> Result<Json<CreatedAppTokenResponse>, AppError> {
+    let user_id = require_session_user(&state, &headers).await?;
+    let name = payload.name.trim();
+    if name.is_empty() {
+        return Err(AppError::BadRequest("token name is required".to_string()));
+    }
+    let kind = payload.kind.trim();
+    if kind != KIND_APP_PASSWORD && kind != KIND_PERSONAL_ACCESS_TOKEN {
+        return Err(AppError::BadRequest("invalid token kind".to_string()));
+    }
+    let mut scopes: Vec<String> = Vec::new();
+    for scope in &payload.scopes {
+        let scope = scope.trim();
+        if !TOKEN_SCOPES.contains(&scope) {
+            return Err(AppError::BadRequest(format!("invalid scope: {}", scope)));
+        }
+        if !scopes.iter().any(|s| s == scope) {
+            scopes.push(scope.to_string());
+        }
+    }
+    if scopes.is_empty() {
+        return Err(AppError::BadRequest(
+            "at least one scope is required".to_string(),
+        ));
+    }
+    if payload
+        .expires_at
+        .is_some_and(|exp| exp <= chrono::Utc::now().timestamp())
+    {
+        return Err(AppError::BadRequest(
+            "expires_at must be in the future".to_string(),
+        ));
+    }
+
+    let token_id = uuid::Uuid::new_v4().simple().to_string();
+    let token = generate_app_token(&token_id);
+    let token_hash = hash_password(&token)?;
+    db::create_app_token(
+        &state.pool,
+        &token_id,
+        &user_id,
+        name,
+        kind,
+        &token_hash,
+        &scopes,
+        payload.expires_at,
+    )
+    .await?;
+
+    let row = db::get_app_token(&state.pool, &token_id)
+        .await?
+        .ok_or(AppError::NotFound)?;
+    Ok(Json(CreatedAppTokenResponse {
+        token,
+        info: app_token_summary(row),
+    }))
+}
+
+pub async fn revoke_app_token(
+    State(state): State<AppState>,
+    Path(token_id): Path<String>,
+    headers: HeaderMap,
 ) -> Result<Json<serde_json::Value>, AppError> {
-    let user_id = require_user(&state, &headers).await?;
-    for id in &payload.notification_ids {
-        db::mark_notification_read(&state.pool, id, &user_id).await?;
+    let user_id = require_session_user(&state, &headers).await?;
+    if !db::revoke_app_token(&state.pool, &token_id, &user_id).await? {
+        return Err(AppError::NotFound);
     }
     Ok(Json(json!({ "ok": true })))
 }
 
-pub async fn mark_all_read(
+pub async fn list_workspaces(
     State(state): State<AppState>,
     headers: HeaderMap,
-) -> Result<Json<serde_json::Value>, AppError> {
+) -> Result<Json<Vec<WorkspaceSummary>>, AppError> {
     let user_id = require_user(&state, &headers).await?;
-    db::mark_all_notifications_read(&state.pool, &user_id).await?;
-    Ok(Json(json!({ "ok": true })))
+    let workspaces = build_workspaces(&state, &user_id).await?;
+    Ok(Json(workspaces))
 }
 
-pub async fn unread_count(
+pub async fn create_workspace(
     State(state): State<AppState>,
     headers: HeaderMap,
-) -> Result<Json<serde_json::Value>, AppError> {
+    Json(payload): Json<CreateWorkspaceRequest>,
+) -> Result<Json<WorkspaceSummary>, AppError> {
     let user_id = require_user(&state, &headers).await?;
-    let count = db::count_unread_notifications(&state.pool, &user_id).await?;
-    Ok(Json(json!({ "count": count })))
+    let name = payload.name.trim();
+    if name.is_empty() {
+        return Err(AppError::BadRequest(
+            "workspace name is required".to_string(),
+        ));
+    }
+    let workspace_id = db::create_workspace(&state.pool, &user_id, name, payload.e2ee).await?;
+    Ok(Json(WorkspaceSummary {
+        id: workspace_id,
+        name: name.to_string(),
+        e2ee: payload.e2ee,
+    }))
 }
 
-/// Create a notification in the DB and push it via WebSocket.
-#[allow(dead_code)]
-pub async fn push_notification(
+// ── E2EE device keys ────────────────────────────────────────────────
+
+/// Verify the caller belongs to the workspace and that it is end-to-end encrypted.
+async fn require_e2ee_workspace(
     state: &AppState,
-    user_id: &str,
-    org_id: &str,
-    ntype: &str,
-    title: &str,
-    body: &str,
-    ref_id: &str,
+    headers: &HeaderMap,
+    workspace_id: &str,
 ) -> Result<String, AppError> {
-    let notification_id =
-        db::create_notification(&state.pool, user_id, org_id, ntype, title, body, ref_id).await?;
-
-    let unread_count = db::count_unread_notifications(&state.pool, user_id).await?;
-
-    let payload = serde_json::to_string(&json!({
-        "type": "notification",
-        "data": {
-            "id": &notification_id,
-            "org_id": org_id,
-            "type": ntype,
-            "title": title,
-            "body": body,
-            "ref_id": ref_id,
-            "read": false,
-            "created_at": chrono::Utc::now().timestamp(),
-        },
-        "unread_count": unread_count,
-    }))
-    .map_err(|e| AppError::Internal(format!("serialize notification: {}", e)))?;
-
-    state.notify.send(user_id, &payload).await;
+    let user_id = require_user(state, headers).await?;
+    if !db::user_has_workspace(&state.pool, &user_id, workspace_id).await? {
+        return Err(AppError::Forbidden);
+    }
+    if !db::workspace_is_e2ee(&state.pool, workspace_id).await? {
+        return Err(AppError::BadRequest(
+            "workspace is not end-to-end encrypted".to_string(),
+        ));
+    }
+    Ok(user_id)
+}
 
-    Ok(notification_id)
+fn device_key_info(row: db::DeviceKeyRow) -> DeviceKeyInfo {
+    DeviceKeyInfo {
+        device_id: row.device_id,
+        user_id: row.user_id,
+        device_name: row.device_name,
+        public_key: row.public_key,
+        wrapped_key: row.wrapped_key,
+        enrolled_by: row.enrolled_by,
+        created_at: row.created_at,
+        enrolled_at: row.enrolled_at,
+    }
 }
 
-// ── Publish ─────────────────────────────────────────────────────────
+pub async fn list_device_keys(
+    State(state): State<AppState>,
+    Path(workspace_id): Path<String>,
+    headers: HeaderMap,
+) -> Result<Json<Vec<DeviceKeyInfo>>, AppError> {
+    let _user_id = require_e2ee_workspace(&state, &headers, &workspace_id).await?;
+    let devices = db::list_device_keys(&state.pool, &workspace_id).await?;
+    Ok(Json(devices.into_iter().map(device_key_info).collect()))
+}
 
-pub async fn publish_status(
+/// Register a device's public key. The first device of a workspace enrolls
+/// itself; later devices stay pending and an enrollment request is pushed to
+/// the caller's other devices over the relay.
+pub async fn register_device_key(
     State(state): State<AppState>,
+    Path(workspace_id): Path<String>,
     headers: HeaderMap,
-) -> Result<Json<models::PublishStatusResponse>, AppError> {
-    let user_id = require_user(&state, &headers).await?;
-    match db::get_published_site(&state.pool, &user_id).await? {
-        Some((url, published_at, updated_at)) => Ok(Json(models::PublishStatusResponse {
-            published: true,
-            url: Some(url),
-            published_at: Some(published_at),
-            updated_at: Some(updated_at),
-        })),
-        None => Ok(Json(models::PublishStatusResponse {
-            published: false,
-            url: None,
-            published_at: None,
-            updated_at: None,
-        })),
+    Json(payload): Json<RegisterDeviceKeyRequest>,
+) -> Result<Json<DeviceKeyInfo>, AppError> {
+    let user_id = require_e2ee_workspace(&state, &headers, &workspace_id).await?;
+    let device_id = payload.device_id.trim();
+    let device_name = payload.device_name.trim();
+    let public_key = payload.public_key.trim();
+    if device_id.is_empty() || device_id.len() > 128 || public_key.is_empty() {
+        return Err(AppError::BadRequest(
+            "device_id and public_key are required".to_string(),
+        ));
+    }
+
+    let wrapped_key = payload.wrapped_key.as_deref().map(str::trim);
+    db::register_device_key(
+        &state.pool,
+        &workspace_id,
+        device_id,
+        &user_id,
+        device_name,
+        public_key,
+        wrapped_key,
+    )
+    .await?;
+
+    let device = db::get_device_key(&state.pool, &workspace_id, device_id)
+        .await?
+        .ok_or(AppError::NotFound)?;
+
+    if device.wrapped_key.is_none() {
+        let message = json!({
+            "type": "e2ee_enroll_request",
+            "data": {
+                "workspace_id": &workspace_id,
+                "device_id": &device.device_id,
+                "device_name": &device.device_name,
+                "public_key": &device.public_key,
+            }
+        });
+        state
+            .relay
+            .send_to_user(&user_id, &message.to_string())
+            .await;
     }
+
+    Ok(Json(device_key_info(device)))
 }
 
-pub async fn publish_confirm(
+/// Store the workspace key wrapped for a pending device. Only an enrolled
+/// device owned by the caller may approve it.
+pub async fn enroll_device_key(
     State(state): State<AppState>,
+    Path((workspace_id, device_id)): Path<(String, String)>,
     headers: HeaderMap,
-) -> Result<Json<serde_json::Value>, AppError> {
-    let user_id = require_user(&state, &headers).await?;
-    let site_url = format!("/sites/{}/", user_id);
-    db::upsert_published_site(&state.pool, &user_id, &site_url).await?;
-    Ok(Json(json!({ "ok": true, "url": site_url })))
+    Json(payload): Json<EnrollDeviceKeyRequest>,
+) -> Result<Json<DeviceKeyInfo>, AppError> {
+    let user_id = require_e2ee_workspace(&state, &headers, &workspace_id).await?;
+    let approver = db::get_device_key(&state.pool, &workspace_id, &payload.approver_device_id)
+        .await?
+        .ok_or(AppError::Forbidden)?;
+    if approver.user_id != user_id || approver.wrapped_key.is_none() {
+        return Err(AppError::Forbidden);
+    }
+    let target = db::get_device_key(&state.pool, &workspace_id, &device_id)
+        .await?
+        .ok_or(AppError::NotFound)?;
+    if target.wrapped_key.is_some() {
+        return Err(AppError::Conflict("device already enrolled".to_string()));
+    }
+    let wrapped_key = payload.wrapped_key.trim();
+    if wrapped_key.is_empty() {
+        return Err(AppError::BadRequest("wrapped_key is required".to_string()));
+    }
+
+    db::enroll_device_key(
+        &state.pool,
+        &workspace_id,
+        &device_id,
+        wrapped_key,
+        &approver.device_id,
+    )
+    .await?;
+
+    let device = db::get_device_key(&state.pool, &workspace_id, &device_id)
+        .await?
+        .ok_or(AppError::NotFound)?;
+    let message = json!({
+        "type": "e2ee_enrolled",
+        "data": {
+            "workspace_id": &workspace_id,
+            "device_id": &device.device_id,
+        }
+    });
+    state
+        .relay
+        .send_to_user(&device.user_id, &message.to_string())
+        .await;
+
+    Ok(Json(device_key_info(device)))
 }
 
-pub async fn unpublish(
+pub async fn delete_device_key(
     State(state): State<AppState>,
+    Path((workspace_id, device_id)): Path<(String, String)>,
     headers: HeaderMap,
 ) -> Result<Json<serde_json::Value>, AppError> {
-    let user_id = require_user(&state, &headers).await?;
-    db::delete_published_site(&state.pool, &user_id).await?;
-
-    let site_dir = crate::dav::site_root(&state, &user_id);
-    if site_dir.exists() {
-        tokio::fs::remove_dir_all(&site_dir)
-            .await
-            .map_err(|e| AppError::Internal(format!("remove site dir: {}", e)))?;
+    let user_id = require_e2ee_workspace(&state, &headers, &workspace_id).await?;
+    let device = db::get_device_key(&state.pool, &workspace_id, &device_id)
+        .await?
+        .ok_or(AppError::NotFound)?;
+    if device.user_id != user_id {
+        return Err(AppError::Forbidden);
     }
-
+    db::delete_device_key(&state.pool, &workspace_id, &device_id).await?;
     Ok(Json(json!({ "ok": true })))
 }
 
-// ── Tests ───────────────────────────────────────────────────────────
+// ── Helper functions ────────────────────────────────────────────────
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::collab::CollabHub;
-    use crate::config::Config;
-    use crate::db;
-    use crate::state::{RelayHub, ServerMetrics};
-    use axum::extract::ConnectInfo;
-    use axum::http::{header::AUTHORIZATION, HeaderValue};
-    use sqlx::sqlite::SqlitePoolOptions;
-    use std::net::SocketAddr;
-    use std::sync::Arc;
+async fn build_workspaces(
+    state: &AppState,
+    user_id: &str,
+) -> Result<Vec<WorkspaceSummary>, AppError> {
+    let workspaces = db::list_workspaces(&state.pool, user_id).await?;
+    Ok(workspaces
+        .into_iter()
+        .map(|(id, name, e2ee)| WorkspaceSummary { id, name, e2ee })
+        .collect())
+}
 
-    const TEST_ADDR: SocketAddr = SocketAddr::new(
-        std::net::IpAddr::V4(std::net::Ipv4Addr::new(127, 0, 0, 1)),
-        9999,
-    );
-    async fn test_state() -> AppState {
-        let data_dir = std::env::temp_dir().join(format!("lumina-test-{}", uuid::Uuid::new_v4()));
-        std::fs::create_dir_all(&data_dir).unwrap();
+/// Authenticate a Bearer session token or a personal access token with the
+/// `api` scope.
+async fn require_user(state: &AppState, headers: &HeaderMap) -> Result<String, AppError> {
+    let token = extract_bearer(headers).ok_or(AppError::Unauthorized)?;
+    let ip = crate::rate_limit::client_ip(headers);
+    authenticate_bearer(state, &token, Some(SCOPE_API), ip.as_deref()).await
+}
 
-        let pool = SqlitePoolOptions::new()
-            .max_connections(1)
-            .connect("sqlite::memory:")
-            .await
-            .unwrap();
-        db::init_db(&pool).await.unwrap();
+/// Like [`require_user`], but only accepts session tokens. Used for
+/// credential management so a leaked access token cannot mint new ones.
+async fn require_session_user(state: &AppState, headers: &HeaderMap) -> Result<String, AppError> {
+    Ok(require_session(state, headers).await?.sub)
+}
 
-        AppState {
-            pool,
-            config: Config {
-                bind: "127.0.0.1:0".to_string(),
-                db_url: "sqlite::memory:".to_string(),
-                data_dir: data_dir.display().to_string(),
-                jwt_secret: "test-secret".to_string(),
-                auth_rate_limit_burst: 100,
-                auth_rate_limit_window_secs: 1,
-                trusted_proxy_hops: 0,
-            },
-            relay: RelayHub::new(),
-            collab: CollabHub::new(&data_dir.display().to_string()),
-            metrics: Arc::new(ServerMetrics::new()),
-            notify: crate::notify_ws::NotifyHub::new(),
-            auth_limiter: crate::rate_limit::AuthRateLimiter::new(100, 1),
-        }
+/// Authenticate a session access token and return its claims.
+async fn require_session(state: &AppState, headers: &HeaderMap) -> Result<Claims, AppError> {
+    let token = extract_bearer(headers).ok_or(AppError::Unauthorized)?;
+    authenticate_access_token(state, &token).await
+}
+
+fn extract_bearer(headers: &HeaderMap) -> Option<String> {
+    let header = headers.get(axum::http::header::AUTHORIZATION)?;
+    let value = header.to_str().ok()?;
+    value
+        .strip_prefix("Bearer ")
+        .map(|token| token.trim().to_string())
+}
+
+/// Authenticate a session of an instance admin: an account granted admin
+/// with `lumina-server grant-admin`, or whose verified email is listed in
+/// `LUMINA_ADMIN_EMAILS`.
+async fn require_instance_admin(state: &AppState, headers: &HeaderMap) -> Result<String, AppError> {
+    let user_id = require_session_user(state, headers).await?;
+    if db::is_user_admin(&state.pool, &user_id).await? {
+        return Ok(user_id);
     }
+    let email = db::get_user_by_id(&state.pool, &user_id)
+        .await?
+        .ok_or(AppError::Unauthorized)?;
+    if !state.config.admin_emails.contains(&email)
+        || !db::is_email_verified(&state.pool, &user_id).await?
+    {
+        return Err(AppError::Forbidden);
+    }
+    Ok(user_id)
+}
 
-    fn auth_headers(token: &str) -> HeaderMap {
-        let mut headers = HeaderMap::new();
-        headers.insert(
-            AUTHORIZATION,
-            HeaderValue::from_str(&format!("Bearer {}", token)).unwrap(),
+/// Verify the user is authenticated and has one of the allowed roles in the org.
+async fn require_org_role(
+    state: &AppState,
+    headers: &HeaderMap,
+    org_id: &str,
+    allowed_roles: &[&str],
+) -> Result<String, AppError> {
+    let user_id = require_user(state, headers).await?;
+    let role = db::get_org_member_role(&state.pool, org_id, &user_id)
+        .await?
+        .ok_or(AppError::Forbidden)?;
+    if !allowed_roles.contains(&role.as_str()) {
+        return Err(AppError::Forbidden);
+    }
+    Ok(user_id)
+}
+
+/// Verify the user is authenticated and is any member of the org.
+async fn require_org_member(
+    state: &AppState,
+    headers: &HeaderMap,
+    org_id: &str,
+) -> Result<String, AppError> {
+    require_org_role(state, headers, org_id, &["admin", "member", "guest"]).await
+}
+
+// ── Organization routes ─────────────────────────────────────────────
+
+pub async fn create_org(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(payload): Json<CreateOrgRequest>,
+) -> Result<Json<OrgSummary>, AppError> {
+    let user_id = require_user(&state, &headers).await?;
+    let name = payload.name.trim();
+    if name.is_empty() {
+        return Err(AppError::BadRequest("org name is required".to_string()));
+    }
+    let org_id = db::create_organization(&state.pool, &user_id, name).await?;
+    Ok(Json(OrgSummary {
+        id: org_id,
+        name: name.to_string(),
+        role: "admin".to_string(),
+    }))
+}
+
+pub async fn list_orgs(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<Vec<OrgSummary>>, AppError> {
+    let user_id = require_user(&state, &headers).await?;
+    let orgs = db::list_user_organizations(&state.pool, &user_id).await?;
+    Ok(Json(
+        orgs.into_iter()
+            .map(|(id, name, role)| OrgSummary { id, name, role })
+            .collect(),
+    ))
+}
+
+pub async fn get_org(
+    State(state): State<AppState>,
+    Path(org_id): Path<String>,
+    headers: HeaderMap,
+) -> Result<Json<OrgDetail>, AppError> {
+    let _user_id = require_org_member(&state, &headers, &org_id).await?;
+    let org = db::get_organization(&state.pool, &org_id)
+        .await?
+        .ok_or(AppError::NotFound)?;
+    let members = db::list_org_members(&state.pool, &org_id).await?;
+    Ok(Json(OrgDetail {
+        id: org.0,
+        name: org.1,
+        owner_id: org.2,
+        members: members
+            .into_iter()
+            .map(|(user_id, email, role)| OrgMemberInfo {
+                user_id,
+                email,
+                role,
+            })
+            .collect(),
+    }))
+}
+
+pub async fn update_org(
+    State(state): State<AppState>,
+    Path(org_id): Path<String>,
+    headers: HeaderMap,
+    Json(payload): Json<UpdateOrgRequest>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let _user_id = require_org_role(&state, &headers, &org_id, &["admin"]).await?;
+    if let Some(ref name) = payload.name {
+        let name = name.trim();
+        if name.is_empty() {
+            return Err(AppError::BadRequest("org name is required".to_string()));
+        }
+        db::update_organization_name(&state.pool, &org_id, name).await?;
+    }
+    Ok(Json(json!({ "ok": true })))
+}
+
+/// Add a registered user to the organization. Addresses without an account
+/// get an emailed invitation instead, which they accept by signing up.
+pub async fn add_member(
+    State(state): State<AppState>,
+    Path(org_id): Path<String>,
+    headers: HeaderMap,
+    Json(payload): Json<AddOrgMemberRequest>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let admin_id = require_org_role(&state, &headers, &org_id, &["admin"]).await?;
+    let email = payload.email.trim().to_lowercase();
+    let role = payload.role.trim().to_string();
+    if !ORG_ROLES.contains(&role.as_str()) {
+        return Err(AppError::BadRequest("invalid role".to_string()));
+    }
+    let Some((target_user_id, _)) = db::find_user_by_email(&state.pool, &email).await? else {
+        let created = invite_to_org(
+            &state,
+            &headers,
+            &admin_id,
+            &org_id,
+            Some(email),
+            role,
+            None,
+        )
+        .await?;
+        return Ok(Json(
+            json!({ "ok": true, "invitation_id": created.invitation.id }),
+        ));
+    };
+    db::add_org_member(&state.pool, &org_id, &target_user_id, &role).await?;
+    audit::record(
+        &state,
+        AuditEvent::new(audit::ORG_MEMBER_ADDED, "user", &target_user_id)
+            .actor(&admin_id)
+            .ip(crate::rate_limit::client_ip(&headers).as_deref())
+            .org(&org_id)
+            .details(json!({ "email": email, "role": role })),
+    )
+    .await;
+    Ok(Json(json!({ "ok": true })))
+}
+
+pub async fn remove_member(
+    State(state): State<AppState>,
+    Path((org_id, user_id)): Path<(String, String)>,
+    headers: HeaderMap,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let admin_id = require_org_role(&state, &headers, &org_id, &["admin"]).await?;
+    db::remove_org_member(&state.pool, &org_id, &user_id).await?;
+    audit::record(
+        &state,
+        AuditEvent::new(audit::ORG_MEMBER_REMOVED, "user", &user_id)
+            .actor(&admin_id)
+            .ip(crate::rate_limit::client_ip(&headers).as_deref())
+            .org(&org_id),
+    )
+    .await;
+    Ok(Json(json!({ "ok": true })))
+}
+
+/// Delete an organization. Only the owner can do this; it can be restored
+/// by an admin until it is purged, see [`crate::orgs`].
+pub async fn delete_org(
+    State(state): State<AppState>,
+    Path(org_id): Path<String>,
+    headers: HeaderMap,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let user_id = require_org_member(&state, &headers, &org_id).await?;
+    let (_, _, owner_id) = db::get_organization(&state.pool, &org_id)
+        .await?
+        .ok_or(AppError::NotFound)?;
+    if owner_id != user_id {
+        return Err(AppError::Forbidden);
+    }
+    if !db::soft_delete_org(&state.pool, &org_id, &user_id).await? {
+        return Err(AppError::NotFound);
+    }
+    let purge_at = chrono::Utc::now().timestamp() + state.config.org_deletion_grace_secs;
+    audit::record(
+        &state,
+        AuditEvent::new(audit::ORG_DELETED, "org", &org_id)
+            .actor(&user_id)
+            .ip(crate::rate_limit::client_ip(&headers).as_deref())
+            .org(&org_id)
+            .details(json!({ "purge_at": purge_at })),
+    )
+    .await;
+    Ok(Json(json!({ "ok": true, "purge_at": purge_at })))
+}
+
+pub async fn restore_org(
+    State(state): State<AppState>,
+    Path(org_id): Path<String>,
+    headers: HeaderMap,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let user_id = require_user(&state, &headers).await?;
+    match db::deleted_org_role(&state.pool, &org_id, &user_id).await? {
+        Some(role) if role == "admin" => {}
+        Some(_) => return Err(AppError::Forbidden),
+        None => return Err(AppError::NotFound),
+    }
+    if !db::restore_org(&state.pool, &org_id).await? {
+        return Err(AppError::NotFound);
+    }
+    audit::record(
+        &state,
+        AuditEvent::new(audit::ORG_RESTORED, "org", &org_id)
+            .actor(&user_id)
+            .ip(crate::rate_limit::client_ip(&headers).as_deref())
+            .org(&org_id),
+    )
+    .await;
+    Ok(Json(json!({ "ok": true })))
+}
+
+/// Organizations pending deletion that the caller can restore.
+pub async fn list_deleted_orgs(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<Vec<DeletedOrgSummary>>, AppError> {
+    let user_id = require_user(&state, &headers).await?;
+    let orgs = db::list_deleted_orgs_for_admin(&state.pool, &user_id).await?;
+    Ok(Json(
+        orgs.into_iter()
+            .map(|(id, name, deleted_at)| DeletedOrgSummary {
+                id,
+                name,
+                deleted_at,
+                purge_at: deleted_at + state.config.org_deletion_grace_secs,
+            })
+            .collect(),
+    ))
+}
+
+pub async fn update_member_role(
+    State(state): State<AppState>,
+    Path((org_id, user_id)): Path<(String, String)>,
+    headers: HeaderMap,
+    Json(payload): Json<UpdateOrgMemberRequest>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let admin_id = require_org_role(&state, &headers, &org_id, &["admin"]).await?;
+    let role = payload.role.trim().to_string();
+    if !ORG_ROLES.contains(&role.as_str()) {
+        return Err(AppError::BadRequest("invalid role".to_string()));
+    }
+    let previous = db::update_org_member_role(&state.pool, &org_id, &user_id, &role).await?;
+    if previous != role {
+        audit::record(
+            &state,
+            AuditEvent::new(audit::ORG_MEMBER_ROLE_CHANGED, "user", &user_id)
+                .actor(&admin_id)
+                .ip(crate::rate_limit::client_ip(&headers).as_deref())
+                .org(&org_id)
+                .details(json!({ "from": previous, "to": role })),
+        )
+        .await;
+    }
+    Ok(Json(json!({ "ok": true })))
+}
+
+/// Leave an organization. The owner has to transfer ownership first.
+pub async fn leave_org(
+    State(state): State<AppState>,
+    Path(org_id): Path<String>,
+    headers: HeaderMap,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let user_id = require_org_member(&state, &headers, &org_id).await?;
+    db::remove_org_member(&state.pool, &org_id, &user_id).await?;
+    audit::record(
+        &state,
+        AuditEvent::new(audit::ORG_MEMBER_LEFT, "user", &user_id)
+            .actor(&user_id)
+            .ip(crate::rate_limit::client_ip(&headers).as_deref())
+            .org(&org_id),
+    )
+    .await;
+    Ok(Json(json!({ "ok": true })))
+}
+
+/// Hand the organization to another member. Only the owner can do this.
+pub async fn transfer_org(
+    State(state): State<AppState>,
+    Path(org_id): Path<String>,
+    headers: HeaderMap,
+    Json(payload): Json<TransferOrgRequest>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let user_id = require_org_member(&state, &headers, &org_id).await?;
+    let (_, _, owner_id) = db::get_organization(&state.pool, &org_id)
+        .await?
+        .ok_or(AppError::NotFound)?;
+    if owner_id != user_id {
+        return Err(AppError::Forbidden);
+    }
+    if payload.user_id != user_id {
+        db::transfer_org_ownership(&state.pool, &org_id, &payload.user_id).await?;
+        audit::record(
+            &state,
+            AuditEvent::new(audit::ORG_OWNER_TRANSFERRED, "user", &payload.user_id)
+                .actor(&user_id)
+                .ip(crate::rate_limit::client_ip(&headers).as_deref())
+                .org(&org_id)
+                .details(json!({ "from": user_id })),
+        )
+        .await;
+    }
+    Ok(Json(json!({ "ok": true })))
+}
+
+// ── Org invitations ─────────────────────────────────────────────────
+
+const ORG_ROLES: &[&str] = &["admin", "member", "guest"];
+const ORG_INVITATION_TTL_DAYS: i64 = 7;
+const ORG_INVITATION_MAX_DAYS: i64 = 30;
+
+fn org_invitation_info(row: db::OrgInvitationRow) -> OrgInvitationInfo {
+    let status = row.status(chrono::Utc::now().timestamp()).to_string();
+    OrgInvitationInfo {
+        id: row.id,
+        org_id: row.org_id,
+        org_name: row.org_name,
+        email: row.email,
+        role: row.role,
+        invited_by: row.invited_by,
+        status,
+        created_at: row.created_at,
+        expires_at: row.expires_at,
+        accepted_at: row.accepted_at,
+        accepted_by: row.accepted_by,
+    }
+}
+
+/// Create an invitation to `org_id`: addressed to `email`, which is emailed
+/// a join link and, if it has an account, notified in the app; or, without
+/// `email`, a shareable join link.
+async fn invite_to_org(
+    state: &AppState,
+    headers: &HeaderMap,
+    admin_id: &str,
+    org_id: &str,
+    email: Option<String>,
+    role: String,
+    expires_in_days: Option<i64>,
+) -> Result<CreatedOrgInvitation, AppError> {
+    if !ORG_ROLES.contains(&role.as_str()) {
+        return Err(AppError::BadRequest("invalid role".to_string()));
+    }
+    let email = email.map(|email| email.trim().to_lowercase());
+    let invitee = match &email {
+        Some(email) => {
+            if !email.contains('@') {
+                return Err(AppError::BadRequest("invalid email".to_string()));
+            }
+            db::find_user_by_email(&state.pool, email)
+                .await?
+                .map(|(user_id, _)| user_id)
+        }
+        None if role == "admin" => {
+            return Err(AppError::BadRequest(
+                "join links cannot grant admin".to_string(),
+            ));
+        }
+        None => None,
+    };
+    if let Some(invitee) = &invitee {
+        if db::get_org_member_role(&state.pool, org_id, invitee)
+            .await?
+            .is_some()
+        {
+            return Err(AppError::Conflict("member already exists".to_string()));
+        }
+    }
+    let days = expires_in_days.unwrap_or(ORG_INVITATION_TTL_DAYS);
+    if !(1..=ORG_INVITATION_MAX_DAYS).contains(&days) {
+        return Err(AppError::BadRequest(format!(
+            "expires_in_days must be between 1 and {}",
+            ORG_INVITATION_MAX_DAYS
+        )));
+    }
+
+    let token = crate::auth::generate_opaque_token();
+    let invitation_id = db::create_org_invitation(
+        &state.pool,
+        org_id,
+        email.as_deref(),
+        &role,
+        &crate::auth::hash_opaque_token(&token),
+        admin_id,
+        chrono::Utc::now().timestamp() + days * 24 * 60 * 60,
+    )
+    .await?;
+    let invitation = db::get_org_invitation(&state.pool, &invitation_id)
+        .await?
+        .ok_or(AppError::NotFound)?;
+    audit::record(
+        state,
+        AuditEvent::new(
+            audit::ORG_INVITATION_CREATED,
+            "org_invitation",
+            &invitation_id,
+        )
+        .actor(admin_id)
+        .ip(crate::rate_limit::client_ip(headers).as_deref())
+        .org(org_id)
+        .details(json!({ "email": email, "role": role })),
+    )
+    .await;
+
+    let join_url = format!("{}/join?token={}", state.config.public_url, token);
+    if let Some(email) = email {
+        let inviter = db::get_user_by_id(&state.pool, admin_id)
+            .await?
+            .unwrap_or_default();
+        let org_name = &invitation.org_name;
+        let sent = state
+            .mailer
+            .send(Email {
+                to: email,
+                subject: format!("Join {} on Lumina", org_name),
+                body: format!(
+                    "{} invited you to join {} as {}.\n\nOpen this link to accept:\n\n{}\n\n\
+                     If you do not have an account yet, sign up with this email address \
+                     and the invitation is accepted automatically.\n",
+                    inviter, org_name, role, join_url
+                ),
+            })
+            .await;
+        if let Err(err) = sent {
+            tracing::warn!(invitation_id = %invitation_id, error = %err, "failed to send invitation email");
+        }
+        if let Some(invitee) = invitee {
+            let notified = push_notification(
+                state,
+                &invitee,
+                org_id,
+                "org_invitation",
+                &format!("Invitation to {}", org_name),
+                &format!("{} invited you to join {} as {}", inviter, org_name, role),
+                &invitation_id,
+            )
+            .await;
+            if let Err(err) = notified {
+                tracing::warn!(invitation_id = %invitation_id, error = %err, "failed to notify invitee");
+            }
+        }
+    }
+
+    Ok(CreatedOrgInvitation {
+        invitation: org_invitation_info(invitation),
+        token,
+        join_url,
+    })
+}
+
+/// Add `user_id` to the invitation's organization. Joining an organization
+/// one already belongs to keeps the current role.
+async fn join_org(
+    state: &AppState,
+    invitation: &db::OrgInvitationRow,
+    user_id: &str,
+    ip: Option<&str>,
+) -> Result<(), AppError> {
+    match db::add_org_member(&state.pool, &invitation.org_id, user_id, &invitation.role).await {
+        Ok(()) => {}
+        Err(AppError::Conflict(_)) => return Ok(()),
+        Err(err) => return Err(err),
+    }
+    audit::record(
+        state,
+        AuditEvent::new(audit::ORG_MEMBER_ADDED, "user", user_id)
+            .actor(user_id)
+            .ip(ip)
+            .org(&invitation.org_id)
+            .details(json!({ "role": invitation.role, "invitation_id": invitation.id })),
+    )
+    .await;
+    Ok(())
+}
+
+/// Accept every pending invitation addressed to `email`. Runs whenever a
+/// session is opened; failures are logged so they never block signing in.
+/// Unverified addresses are skipped: anyone can register one, so only the
+/// emailed join link proves it belongs to the caller.
+async fn accept_pending_invitations(state: &AppState, user_id: &str, email: &str, ip: &str) {
+    match db::is_email_verified(&state.pool, user_id).await {
+        Ok(true) => {}
+        Ok(false) => return,
+        Err(err) => {
+            tracing::warn!(user_id = %user_id, error = %err, "failed to look up invitations");
+            return;
+        }
+    }
+    let invitations = match db::list_pending_invitations_for_email(&state.pool, email).await {
+        Ok(invitations) => invitations,
+        Err(err) => {
+            tracing::warn!(user_id = %user_id, error = %err, "failed to look up invitations");
+            return;
+        }
+    };
+    for invitation in invitations {
+        let accepted =
+            match db::settle_org_invitation(&state.pool, &invitation.id, Some(user_id)).await {
+                Ok(true) => join_org(state, &invitation, user_id, Some(ip)).await,
+                Ok(false) => Ok(()),
+                Err(err) => Err(err),
+            };
+        if let Err(err) = accepted {
+            tracing::warn!(invitation_id = %invitation.id, error = %err, "failed to accept invitation");
+        }
+    }
+}
+
+pub async fn create_org_invitation(
+    State(state): State<AppState>,
+    Path(org_id): Path<String>,
+    headers: HeaderMap,
+    Json(payload): Json<CreateOrgInvitationRequest>,
+) -> Result<Json<CreatedOrgInvitation>, AppError> {
+    let admin_id = require_org_role(&state, &headers, &org_id, &["admin"]).await?;
+    Ok(Json(
+        invite_to_org(
+            &state,
+            &headers,
+            &admin_id,
+            &org_id,
+            payload.email,
+            payload.role.trim().to_string(),
+            payload.expires_in_days,
+        )
+        .await?,
+    ))
+}
+
+pub async fn list_org_invitations(
+    State(state): State<AppState>,
+    Path(org_id): Path<String>,
+    headers: HeaderMap,
+) -> Result<Json<Vec<OrgInvitationInfo>>, AppError> {
+    require_org_role(&state, &headers, &org_id, &["admin"]).await?;
+    let rows = db::list_org_invitations(&state.pool, &org_id).await?;
+    Ok(Json(rows.into_iter().map(org_invitation_info).collect()))
+}
+
+pub async fn revoke_org_invitation(
+    State(state): State<AppState>,
+    Path((org_id, invitation_id)): Path<(String, String)>,
+    headers: HeaderMap,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let admin_id = require_org_role(&state, &headers, &org_id, &["admin"]).await?;
+    if !db::revoke_org_invitation(&state.pool, &org_id, &invitation_id).await? {
+        return Err(AppError::NotFound);
+    }
+    audit::record(
+        &state,
+        AuditEvent::new(
+            audit::ORG_INVITATION_REVOKED,
+            "org_invitation",
+            &invitation_id,
+        )
+        .actor(&admin_id)
+        .ip(crate::rate_limit::client_ip(&headers).as_deref())
+        .org(&org_id),
+    )
+    .await;
+    Ok(Json(json!({ "ok": true })))
+}
+
+/// Pending invitations addressed to the caller's email, once it is verified.
+pub async fn list_my_invitations(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<Vec<OrgInvitationInfo>>, AppError> {
+    let user_id = require_user(&state, &headers).await?;
+    let email = db::get_user_by_id(&state.pool, &user_id)
+        .await?
+        .ok_or(AppError::Unauthorized)?;
+    if !db::is_email_verified(&state.pool, &user_id).await? {
+        return Ok(Json(Vec::new()));
+    }
+    let rows = db::list_pending_invitations_for_email(&state.pool, &email).await?;
+    Ok(Json(rows.into_iter().map(org_invitation_info).collect()))
+}
+
+/// Load an invitation addressed to the caller, whose email must be
+/// verified.
+async fn caller_invitation(
+    state: &AppState,
+    user_id: &str,
+    invitation_id: &str,
+) -> Result<db::OrgInvitationRow, AppError> {
+    let email = db::get_user_by_id(&state.pool, user_id)
+        .await?
+        .ok_or(AppError::Unauthorized)?;
+    if !db::is_email_verified(&state.pool, user_id).await? {
+        return Err(AppError::Forbidden);
+    }
+    let invitation = db::get_org_invitation(&state.pool, invitation_id)
+        .await?
+        .filter(|invitation| invitation.email.as_deref() == Some(email.as_str()))
+        .ok_or(AppError::NotFound)?;
+    db::get_organization(&state.pool, &invitation.org_id)
+        .await?
+        .ok_or(AppError::NotFound)?;
+    Ok(invitation)
+}
+
+fn invitation_not_pending() -> AppError {
+    AppError::Conflict("invitation is no longer pending".to_string())
+}
+
+pub async fn accept_org_invitation(
+    State(state): State<AppState>,
+    Path(invitation_id): Path<String>,
+    headers: HeaderMap,
+) -> Result<Json<OrgSummary>, AppError> {
+    let user_id = require_user(&state, &headers).await?;
+    let invitation = caller_invitation(&state, &user_id, &invitation_id).await?;
+    if !db::settle_org_invitation(&state.pool, &invitation_id, Some(&user_id)).await? {
+        return Err(invitation_not_pending());
+    }
+    let ip = crate::rate_limit::client_ip(&headers);
+    join_org(&state, &invitation, &user_id, ip.as_deref()).await?;
+    joined_org_summary(&state, &invitation, &user_id).await
+}
+
+pub async fn decline_org_invitation(
+    State(state): State<AppState>,
+    Path(invitation_id): Path<String>,
+    headers: HeaderMap,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let user_id = require_user(&state, &headers).await?;
+    let invitation = caller_invitation(&state, &user_id, &invitation_id).await?;
+    if !db::settle_org_invitation(&state.pool, &invitation_id, None).await? {
+        return Err(invitation_not_pending());
+    }
+    audit::record(
+        &state,
+        AuditEvent::new(
+            audit::ORG_INVITATION_DECLINED,
+            "org_invitation",
+            &invitation_id,
+        )
+        .actor(&user_id)
+        .ip(crate::rate_limit::client_ip(&headers).as_deref())
+        .org(&invitation.org_id),
+    )
+    .await;
+    Ok(Json(json!({ "ok": true })))
+}
+
+/// Join through the token of an invitation link. An emailed invitation can
+/// only be used by the account it was addressed to; a shareable link by
+/// anyone until it expires or is revoked.
+pub async fn join_org_with_token(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(payload): Json<JoinOrgRequest>,
+) -> Result<Json<OrgSummary>, AppError> {
+    let user_id = require_user(&state, &headers).await?;
+    let invitation = db::find_pending_invitation_by_token(
+        &state.pool,
+        &crate::auth::hash_opaque_token(payload.token.trim()),
+    )
+    .await?
+    .ok_or_else(|| AppError::BadRequest("invalid or expired invitation".to_string()))?;
+    if let Some(email) = &invitation.email {
+        let caller = db::get_user_by_id(&state.pool, &user_id)
+            .await?
+            .ok_or(AppError::Unauthorized)?;
+        if &caller != email {
+            return Err(AppError::Forbidden);
+        }
+        if !db::settle_org_invitation(&state.pool, &invitation.id, Some(&user_id)).await? {
+            return Err(invitation_not_pending());
+        }
+    }
+    let ip = crate::rate_limit::client_ip(&headers);
+    join_org(&state, &invitation, &user_id, ip.as_deref()).await?;
+    joined_org_summary(&state, &invitation, &user_id).await
+}
+
+async fn joined_org_summary(
+    state: &AppState,
+    invitation: &db::OrgInvitationRow,
+    user_id: &str,
+) -> Result<Json<OrgSummary>, AppError> {
+    let role = db::get_org_member_role(&state.pool, &invitation.org_id, user_id)
+        .await?
+        .ok_or(AppError::NotFound)?;
+    Ok(Json(OrgSummary {
+        id: invitation.org_id.clone(),
+        name: invitation.org_name.clone(),
+        role,
+    }))
+}
+
+// ── Project routes ──────────────────────────────────────────────────
+
+pub async fn create_project(
+    State(state): State<AppState>,
+    Path(org_id): Path<String>,
+    headers: HeaderMap,
+    Json(payload): Json<CreateProjectRequest>,
+) -> Result<Json<ProjectSummary>, AppError> {
+    let _user_id = require_org_role(&state, &headers, &org_id, &["admin", "member"]).await?;
+    let name = payload.name.trim();
+    if name.is_empty() {
+        return Err(AppError::BadRequest("project name is required".to_string()));
+    }
+    let description = payload.description.as_deref().unwrap_or("").trim();
+    let project_id = db::create_project(&state.pool, &org_id, name, description).await?;
+    Ok(Json(ProjectSummary {
+        id: project_id,
+        name: name.to_string(),
+        description: description.to_string(),
+        archived: false,
+    }))
+}
+
+fn project_summary(project: db::ProjectRow) -> ProjectSummary {
+    ProjectSummary {
+        id: project.id,
+        name: project.name,
+        description: project.description,
+        archived: project.archived_at.is_some(),
+    }
+}
+
+pub async fn list_org_projects(
+    State(state): State<AppState>,
+    Path(org_id): Path<String>,
+    headers: HeaderMap,
+    Query(query): Query<ProjectListQuery>,
+) -> Result<Json<Vec<ProjectSummary>>, AppError> {
+    let user_id = require_org_member(&state, &headers, &org_id).await?;
+    // Guests only see the projects they were added to.
+    let guest =
+        db::get_org_member_role(&state.pool, &org_id, &user_id).await? == Some("guest".to_string());
+    let projects = db::list_projects(
+        &state.pool,
+        &org_id,
+        query.include_archived,
+        guest.then_some(user_id.as_str()),
+    )
+    .await?;
+    Ok(Json(projects.into_iter().map(project_summary).collect()))
+}
+
+pub async fn update_project(
+    State(state): State<AppState>,
+    Path(project_id): Path<String>,
+    headers: HeaderMap,
+    Json(payload): Json<UpdateProjectRequest>,
+) -> Result<Json<ProjectSummary>, AppError> {
+    let (user_id, project) =
+        require_project_role(&state, &headers, &project_id, projects::MANAGER).await?;
+    let name = payload.name.as_deref().map(str::trim);
+    if name == Some("") {
+        return Err(AppError::BadRequest("project name is required".to_string()));
+    }
+    let description = payload.description.as_deref().map(str::trim);
+    db::update_project(
+        &state.pool,
+        &project_id,
+        name,
+        description,
+        payload.archived,
+    )
+    .await?;
+
+    let was_archived = project.archived_at.is_some();
+    if let Some(archived) = payload
+        .archived
+        .filter(|archived| *archived != was_archived)
+    {
+        let action = if archived {
+            audit::PROJECT_ARCHIVED
+        } else {
+            audit::PROJECT_UNARCHIVED
+        };
+        audit::record(
+            &state,
+            AuditEvent::new(action, "project", &project_id)
+                .actor(&user_id)
+                .ip(crate::rate_limit::client_ip(&headers).as_deref())
+                .org(&project.org_id),
+        )
+        .await;
+    }
+    let project = db::get_project(&state.pool, &project_id)
+        .await?
+        .ok_or(AppError::NotFound)?;
+    Ok(Json(project_summary(project)))
+}
+
+/// Delete a project with its tasks, labels, documents and their collab
+/// state.
+pub async fn delete_project(
+    State(state): State<AppState>,
+    Path(project_id): Path<String>,
+    headers: HeaderMap,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let (user_id, project) =
+        require_project_role(&state, &headers, &project_id, projects::MANAGER).await?;
+    let doc_ids = db::delete_project(&state.pool, &project_id).await?;
+    state.collab.discard_docs(&doc_ids).await;
+    audit::record(
+        &state,
+        AuditEvent::new(audit::PROJECT_DELETED, "project", &project_id)
+            .actor(&user_id)
+            .ip(crate::rate_limit::client_ip(&headers).as_deref())
+            .org(&project.org_id)
+            .details(json!({ "name": project.name, "documents": doc_ids.len() })),
+    )
+    .await;
+    Ok(Json(json!({ "ok": true })))
+}
+
+// ── Task routes ─────────────────────────────────────────────────────
+
+/// Helper: get a project and verify the caller holds at least `required`
+/// in it. Returns the caller's id and the project.
+async fn require_project_role(
+    state: &AppState,
+    headers: &HeaderMap,
+    project_id: &str,
+    required: &str,
+) -> Result<(String, db::ProjectRow), AppError> {
+    let project = db::get_project(&state.pool, project_id)
+        .await?
+        .ok_or(AppError::NotFound)?;
+    let user_id = require_user(state, headers).await?;
+    let role = projects::role(state, &project, &user_id)
+        .await?
+        .ok_or(AppError::Forbidden)?;
+    if !projects::allows(&role, required) {
+        return Err(AppError::Forbidden);
+    }
+    Ok((user_id, project))
+}
+
+/// Helper: get project and verify the caller can read it.
+async fn require_project_member(
+    state: &AppState,
+    headers: &HeaderMap,
+    project_id: &str,
+) -> Result<(String, String), AppError> {
+    let (user_id, project) =
+        require_project_role(state, headers, project_id, projects::VIEWER).await?;
+    Ok((user_id, project.org_id))
+}
+
+/// Helper: reject assigning a task in `project_id` to someone who cannot
+/// see the project.
+async fn require_assignable(
+    state: &AppState,
+    project_id: &str,
+    assignee_id: Option<&str>,
+) -> Result<(), AppError> {
+    let Some(assignee_id) = assignee_id else {
+        return Ok(());
+    };
+    let project = db::get_project(&state.pool, project_id)
+        .await?
+        .ok_or(AppError::NotFound)?;
+    if projects::role(state, &project, assignee_id)
+        .await?
+        .is_none()
+    {
+        return Err(AppError::BadRequest(
+            "assignee has no access to this project".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Helper: verify the caller may change the project's tasks, which
+/// archived projects do not accept.
+async fn require_active_project(
+    state: &AppState,
+    headers: &HeaderMap,
+    project_id: &str,
+) -> Result<(String, String), AppError> {
+    let (user_id, project) =
+        require_project_role(state, headers, project_id, projects::CONTRIBUTOR).await?;
+    if project.archived_at.is_some() {
+        return Err(AppError::Conflict("project is archived".to_string()));
+    }
+    Ok((user_id, project.org_id))
+}
+
+pub async fn get_workflow(
+    State(state): State<AppState>,
+    Path(project_id): Path<String>,
+    headers: HeaderMap,
+) -> Result<Json<Workflow>, AppError> {
+    let _user_and_org = require_project_member(&state, &headers, &project_id).await?;
+    Ok(Json(db::get_workflow(&state.pool, &project_id).await?))
+}
+
+/// Replace a project's statuses and priorities (managers only).
+pub async fn update_workflow(
+    State(state): State<AppState>,
+    Path(project_id): Path<String>,
+    headers: HeaderMap,
+    Json(payload): Json<UpdateWorkflowRequest>,
+) -> Result<Json<Workflow>, AppError> {
+    let (user_id, project) =
+        require_project_role(&state, &headers, &project_id, projects::MANAGER).await?;
+    if project.archived_at.is_some() {
+        return Err(AppError::Conflict("project is archived".to_string()));
+    }
+    workflows::validate(&payload.workflow)?;
+    db::replace_workflow(
+        &state.pool,
+        &project_id,
+        &payload.workflow,
+        &payload.status_map,
+        &payload.priority_map,
+    )
+    .await?;
+    audit::record(
+        &state,
+        AuditEvent::new(audit::PROJECT_WORKFLOW_UPDATED, "project", &project_id)
+            .actor(&user_id)
+            .ip(crate::rate_limit::client_ip(&headers).as_deref())
+            .org(&project.org_id)
+            .details(json!({
+                "statuses": payload.workflow.statuses.iter().map(|s| &s.key).collect::<Vec<_>>(),
+                "priorities": payload.workflow.priorities.iter().map(|p| &p.key).collect::<Vec<_>>(),
+            })),
+    )
+    .await;
+    Ok(Json(payload.workflow))
+}
+
+pub async fn list_project_members(
+    State(state): State<AppState>,
+    Path(project_id): Path<String>,
+    headers: HeaderMap,
+) -> Result<Json<Vec<ProjectMemberInfo>>, AppError> {
+    let _user_and_org = require_project_member(&state, &headers, &project_id).await?;
+    let members = db::list_project_members(&state.pool, &project_id).await?;
+    Ok(Json(
+        members
+            .into_iter()
+            .map(|(user_id, email, role)| ProjectMemberInfo {
+                user_id,
+                email,
+                role,
+            })
+            .collect(),
+    ))
+}
+
+/// Give an org member an explicit role in a project.
+pub async fn set_project_member(
+    State(state): State<AppState>,
+    Path((project_id, member_id)): Path<(String, String)>,
+    headers: HeaderMap,
+    Json(payload): Json<SetProjectMemberRequest>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let (user_id, project) =
+        require_project_role(&state, &headers, &project_id, projects::MANAGER).await?;
+    if !projects::ROLES.contains(&payload.role.as_str()) {
+        return Err(AppError::BadRequest(
+            "role must be manager, contributor or viewer".to_string(),
+        ));
+    }
+    if db::get_org_member_role(&state.pool, &project.org_id, &member_id)
+        .await?
+        .is_none()
+    {
+        return Err(AppError::BadRequest(
+            "user is not a member of this organization".to_string(),
+        ));
+    }
+    db::set_project_member(
+        &state.pool,
+        &project_id,
+        &member_id,
+        &payload.role,
+        &user_id,
+    )
+    .await?;
+    audit::record(
+        &state,
+        AuditEvent::new(audit::PROJECT_MEMBER_SET, "user", &member_id)
+            .actor(&user_id)
+            .ip(crate::rate_limit::client_ip(&headers).as_deref())
+            .org(&project.org_id)
+            .details(json!({ "project_id": project_id, "role": payload.role })),
+    )
+    .await;
+    Ok(Json(json!({ "ok": true })))
+}
+
+pub async fn remove_project_member(
+    State(state): State<AppState>,
+    Path((project_id, member_id)): Path<(String, String)>,
+    headers: HeaderMap,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let (user_id, project) =
+        require_project_role(&state, &headers, &project_id, projects::MANAGER).await?;
+    if !db::remove_project_member(&state.pool, &project_id, &member_id).await? {
+        return Err(AppError::NotFound);
+    }
+    audit::record(
+        &state,
+        AuditEvent::new(audit::PROJECT_MEMBER_REMOVED, "user", &member_id)
+            .actor(&user_id)
+            .ip(crate::rate_limit::client_ip(&headers).as_deref())
+            .org(&project.org_id)
+            .details(json!({ "project_id": project_id })),
+    )
+    .await;
+    Ok(Json(json!({ "ok": true })))
+}
+
+// ── Document Registry ───────────────────────────────────────────────
+
+pub async fn resolve_doc(
+    State(state): State<AppState>,
+    Path(project_id): Path<String>,
+    headers: HeaderMap,
+    Json(payload): Json<ResolveDocRequest>,
+) -> Result<Json<ResolveDocResponse>, AppError> {
+    let (user_id, _org_id) = require_project_member(&state, &headers, &project_id).await?;
+    if let Some(workspace_id) = payload.workspace_id.as_deref() {
+        if !db::user_has_workspace(&state.pool, &user_id, workspace_id).await? {
+            return Err(AppError::Forbidden);
+        }
+        if db::workspace_is_e2ee(&state.pool, workspace_id).await? {
+            return Err(AppError::BadRequest(
+                "documents of end-to-end encrypted workspaces cannot be shared".to_string(),
+            ));
+        }
+    }
+    let doc_id =
+        db::resolve_or_create_doc(&state.pool, &project_id, &payload.rel_path, &user_id).await?;
+    Ok(Json(ResolveDocResponse { doc_id }))
+}
+
+// ── Task ────────────────────────────────────────────────────────────
+
+pub async fn create_task(
+    State(state): State<AppState>,
+    Path(project_id): Path<String>,
+    headers: HeaderMap,
+    Json(payload): Json<CreateTaskRequest>,
+) -> Result<Json<TaskSummary>, AppError> {
+    let (user_id, org_id) = require_active_project(&state, &headers, &project_id).await?;
+    let title = payload.title.trim();
+    if title.is_empty() {
+        return Err(AppError::BadRequest("task title is required".to_string()));
+    }
+    let description = payload.description.as_deref().unwrap_or("");
+    let workflow = db::get_workflow(&state.pool, &project_id).await?;
+    let status = payload
+        .status
+        .as_deref()
+        .unwrap_or(&workflow.statuses[0].key);
+    let priority = payload
+        .priority
+        .as_deref()
+        .unwrap_or(&workflow.default_priority);
+    workflows::check_task(&workflow, None, Some(status), Some(priority))?;
+    if let Some(parent_id) = payload.parent_id.as_deref() {
+        let parent = db::get_task(&state.pool, parent_id).await?;
+        if parent.is_none_or(|parent| parent.project_id != project_id) {
+            return Err(AppError::BadRequest(
+                "parent task is not in this project".to_string(),
+            ));
+        }
+    }
+    require_assignable(&state, &project_id, payload.assignee_id.as_deref()).await?;
+
+    let task_id = db::create_task(
+        &state.pool,
+        &project_id,
+        payload.parent_id.as_deref(),
+        title,
+        description,
+        status,
+        priority,
+        payload.assignee_id.as_deref(),
+        payload.due_date,
+        payload.start_date,
+        &user_id,
+    )
+    .await?;
+
+    let mut outbox = Outbox::new(&org_id, &task_id, &user_id);
+    if let Some(assignee_id) = payload.assignee_id.as_deref() {
+        outbox.add(
+            assignee_id,
+            Notice::new(notifications::TASK_ASSIGNED, "Task assigned to you", title),
+        );
+    }
+    outbox
+        .add_mentions(&state, description, None, || {
+            Notice::new(
+                notifications::MENTION,
+                "You were mentioned in a task",
+                title,
+            )
+        })
+        .await;
+    send_project_notices(&state, outbox, &project_id).await;
+
+    Ok(Json(TaskSummary {
+        id: task_id,
+        project_id,
+        parent_id: payload.parent_id,
+        title: title.to_string(),
+        status: status.to_string(),
+        priority: priority.to_string(),
+        assignee_id: payload.assignee_id,
+        due_date: payload.due_date,
+        position: 0.0,
+        progress: None,
+        blocked_by: Vec::new(),
+        blocks: Vec::new(),
+    }))
+}
+
+const TASK_PAGE_DEFAULT: i64 = 50;
+const TASK_PAGE_MAX: i64 = 200;
+
+/// Subtask progress and dependencies of a set of tasks.
+struct TaskLinks {
+    progress: HashMap<String, (i64, i64)>,
+    dependencies: Vec<(String, String)>,
+}
+
+impl TaskLinks {
+    async fn load(state: &AppState, task_ids: &[String]) -> Result<Self, AppError> {
+        Ok(Self {
+            progress: db::subtask_progress(&state.pool, task_ids).await?,
+            dependencies: db::list_task_dependencies(&state.pool, task_ids).await?,
+        })
+    }
+
+    fn progress(&self, task_id: &str) -> Option<SubtaskProgress> {
+        self.progress
+            .get(task_id)
+            .map(|&(done, total)| SubtaskProgress { done, total })
+    }
+
+    fn blocked_by(&self, task_id: &str) -> Vec<String> {
+        self.dependencies
+            .iter()
+            .filter(|(_, blocked)| blocked == task_id)
+            .map(|(blocker, _)| blocker.clone())
+            .collect()
+    }
+
+    fn blocks(&self, task_id: &str) -> Vec<String> {
+        self.dependencies
+            .iter()
+            .filter(|(blocker, _)| blocker == task_id)
+            .map(|(_, blocked)| blocked.clone())
+            .collect()
+    }
+}
+
+fn task_summary(task: db::TaskRow, links: &TaskLinks) -> TaskSummary {
+    TaskSummary {
+        progress: links.progress(&task.id),
+        blocked_by: links.blocked_by(&task.id),
+        blocks: links.blocks(&task.id),
+        id: task.id,
+        project_id: task.project_id,
+        parent_id: task.parent_id,
+        title: task.title,
+        status: task.status,
+        priority: task.priority,
+        assignee_id: task.assignee_id,
+        due_date: task.due_date,
+        position: task.position,
+    }
+}
+
+/// Summaries of `tasks` with their subtask progress and dependencies.
+async fn task_summaries(
+    state: &AppState,
+    tasks: Vec<db::TaskRow>,
+) -> Result<Vec<TaskSummary>, AppError> {
+    let task_ids: Vec<String> = tasks.iter().map(|task| task.id.clone()).collect();
+    let links = TaskLinks::load(state, &task_ids).await?;
+    Ok(tasks
+        .into_iter()
+        .map(|task| task_summary(task, &links))
+        .collect())
+}
+
+fn invalid_cursor() -> AppError {
+    AppError::BadRequest("invalid cursor".to_string())
+}
+
+fn encode_task_cursor(cursor: &db::TaskCursor) -> String {
+    URL_SAFE_NO_PAD.encode(json!(cursor).to_string())
+}
+
+fn decode_task_cursor(cursor: &str, sort: db::TaskSort) -> Result<db::TaskCursor, AppError> {
+    let bytes = URL_SAFE_NO_PAD
+        .decode(cursor)
+        .map_err(|_| invalid_cursor())?;
+    let cursor: db::TaskCursor = serde_json::from_slice(&bytes).map_err(|_| invalid_cursor())?;
+    // A cursor only makes sense for the order it was issued for.
+    let text_key = matches!(cursor.key, db::TaskSortKey::Text(_));
+    if text_key != (sort == db::TaskSort::Title) {
+        return Err(invalid_cursor());
+    }
+    Ok(cursor)
+}
+
+/// Turn listing parameters into a filter. `assignee=me` means `user_id`.
+fn task_filter(query: TaskListQuery, user_id: &str) -> Result<db::TaskFilter, AppError> {
+    let sort = match query.sort.as_deref() {
+        None => db::TaskSort::default(),
+        Some(sort) => db::TaskSort::parse(sort)
+            .ok_or_else(|| AppError::BadRequest(format!("unknown sort: {}", sort)))?,
+    };
+    let descending = match query.order.as_deref() {
+        None | Some("asc") => false,
+        Some("desc") => true,
+        Some(_) => {
+            return Err(AppError::BadRequest(
+                "order must be asc or desc".to_string(),
+            ))
+        }
+    };
+    let after = query
+        .cursor
+        .as_deref()
+        .map(|cursor| decode_task_cursor(cursor, sort))
+        .transpose()?;
+    let (assignee_id, unassigned) = match query.assignee.as_deref() {
+        None => (None, false),
+        Some("none") => (None, true),
+        Some("me") => (Some(user_id.to_string()), false),
+        Some(assignee) => (Some(assignee.to_string()), false),
+    };
+    let not_blank = |value: Option<String>| {
+        value
+            .map(|value| value.trim().to_string())
+            .filter(|value| !value.is_empty())
+    };
+    Ok(db::TaskFilter {
+        project_id: None,
+        visible_to: None,
+        statuses: not_blank(query.status),
+        priorities: not_blank(query.priority),
+        assignee_id,
+        unassigned,
+        label_id: not_blank(query.label),
+        due_after: query.due_after,
+        due_before: query.due_before,
+        search: not_blank(query.q),
+        sort,
+        descending,
+        after,
+        limit: query
+            .limit
+            .unwrap_or(TASK_PAGE_DEFAULT)
+            .clamp(1, TASK_PAGE_MAX),
+    })
+}
+
+async fn task_page(state: &AppState, filter: &db::TaskFilter) -> Result<TaskPage, AppError> {
+    let rows = db::list_tasks(&state.pool, filter).await?;
+    let next_cursor = if rows.len() as i64 == filter.limit {
+        rows.last().map(|(_, cursor)| encode_task_cursor(cursor))
+    } else {
+        None
+    };
+    let tasks = rows.into_iter().map(|(task, _)| task).collect();
+    Ok(TaskPage {
+        tasks: task_summaries(state, tasks).await?,
+        next_cursor,
+    })
+}
+
+pub async fn list_project_tasks(
+    State(state): State<AppState>,
+    Path(project_id): Path<String>,
+    headers: HeaderMap,
+    Query(query): Query<TaskListQuery>,
+) -> Result<Json<TaskPage>, AppError> {
+    let (user_id, _org_id) = require_project_member(&state, &headers, &project_id).await?;
+    let filter = db::TaskFilter {
+        project_id: Some(project_id),
+        ..task_filter(query, &user_id)?
+    };
+    Ok(Json(task_page(&state, &filter).await?))
+}
+
+/// Tasks assigned to the caller in every project they can see.
+pub async fn list_my_tasks(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(query): Query<TaskListQuery>,
+) -> Result<Json<TaskPage>, AppError> {
+    let user_id = require_user(&state, &headers).await?;
+    let filter = db::TaskFilter {
+        visible_to: Some(user_id.clone()),
+        assignee_id: Some(user_id.clone()),
+        unassigned: false,
+        ..task_filter(query, &user_id)?
+    };
+    Ok(Json(task_page(&state, &filter).await?))
+}
+
+pub async fn get_task_handler(
+    State(state): State<AppState>,
+    Path(task_id): Path<String>,
+    headers: HeaderMap,
+) -> Result<Json<TaskDetail>, AppError> {
+    let task = db::get_task(&state.pool, &task_id)
+        .await?
+        .ok_or(AppError::NotFound)?;
+    let _user_and_org = require_project_member(&state, &headers, &task.project_id).await?;
+    let labels = db::list_task_labels(&state.pool, &task_id).await?;
+    let watchers = db::list_task_watchers(&state.pool, &task_id).await?;
+    let comments = db::list_task_comments(&state.pool, &task_id).await?;
+    let activity = db::list_task_activity(&state.pool, &task_id).await?;
+    let links = TaskLinks::load(&state, std::slice::from_ref(&task_id)).await?;
+    let subtasks = db::list_subtasks(&state.pool, &task_id).await?;
+    Ok(Json(TaskDetail {
+        progress: links.progress(&task_id),
+        subtasks: task_summaries(&state, subtasks).await?,
+        blocked_by: links.blocked_by(&task_id),
+        blocks: links.blocks(&task_id),
+        id: task.id,
+        project_id: task.project_id,
+        parent_id: task.parent_id,
+        title: task.title,
+        description: task.description,
+        status: task.status,
+        priority: task.priority,
+        assignee_id: task.assignee_id,
+        due_date: task.due_date,
+        start_date: task.start_date,
+        position: task.position,
+        created_by: task.created_by,
+        created_at: task.created_at,
+        updated_at: task.updated_at,
+        labels: labels.into_iter().map(label_info).collect(),
+        watchers,
+        comments: comments
+            .into_iter()
+            .map(|comment| TaskCommentInfo {
+                id: comment.id,
+                parent_id: comment.parent_id,
+                user_id: comment.user_id,
+                body: comment.body,
+                created_at: comment.created_at,
+            })
+            .collect(),
+        activity: activity
+            .into_iter()
+            .map(|entry| TaskActivityInfo {
+                id: entry.id,
+                actor_id: entry.actor_id,
+                field: entry.field,
+                old_value: entry.old_value,
+                new_value: entry.new_value,
+                created_at: entry.created_at,
+            })
+            .collect(),
+    }))
+}
+
+pub async fn update_task_handler(
+    State(state): State<AppState>,
+    Path(task_id): Path<String>,
+    headers: HeaderMap,
+    Json(payload): Json<UpdateTaskRequest>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    // Verify via task -> project -> org membership
+    let task = db::get_task(&state.pool, &task_id)
+        .await?
+        .ok_or(AppError::NotFound)?;
+    let (user_id, org_id) = require_active_project(&state, &headers, &task.project_id).await?;
+    if payload.position.is_some() {
+        return Err(AppError::BadRequest(
+            "position cannot be set directly; use POST /tasks/:task_id/move".to_string(),
+        ));
+    }
+    if let Some(assignee_id) = &payload.assignee_id {
+        require_assignable(&state, &task.project_id, assignee_id.as_deref()).await?;
+    }
+    let workflow = db::get_workflow(&state.pool, &task.project_id).await?;
+    let changes = db::update_task(
+        &state.pool,
+        &task_id,
+        &user_id,
+        &workflow,
+        payload.parent_id.as_ref().map(|o| o.as_deref()),
+        payload.title.as_deref(),
+        payload.description.as_deref(),
+        payload.status.as_deref(),
+        payload.priority.as_deref(),
+        payload.assignee_id.as_ref().map(|o| o.as_deref()),
+        payload.due_date,
+        payload.start_date,
+    )
+    .await?;
+
+    let title = payload.title.as_deref().unwrap_or(&task.title);
+    let change = TaskChangeContext {
+        task: &task,
+        org_id: &org_id,
+        actor_id: &user_id,
+        title,
+        workflow: &workflow,
+    };
+    notify_task_changes(&state, change, &changes, payload.description.as_deref()).await;
+
+    Ok(Json(json!({ "ok": true })))
+}
+
+/// Move a task to a status column, between two of its tasks. The server
+/// picks the position, so concurrent moves cannot collide.
+pub async fn move_task(
+    State(state): State<AppState>,
+    Path(task_id): Path<String>,
+    headers: HeaderMap,
+    Json(payload): Json<MoveTaskRequest>,
+) -> Result<Json<TaskSummary>, AppError> {
+    let task = db::get_task(&state.pool, &task_id)
+        .await?
+        .ok_or(AppError::NotFound)?;
+    let (user_id, org_id) = require_active_project(&state, &headers, &task.project_id).await?;
+    let workflow = db::get_workflow(&state.pool, &task.project_id).await?;
+    let changes = db::move_task(
+        &state.pool,
+        &task_id,
+        &user_id,
+        &workflow,
+        payload.status.as_deref(),
+        payload.after_id.as_deref(),
+        payload.before_id.as_deref(),
+    )
+    .await?;
+    let change = TaskChangeContext {
+        task: &task,
+        org_id: &org_id,
+        actor_id: &user_id,
+        title: &task.title,
+        workflow: &workflow,
+    };
+    notify_task_changes(&state, change, &changes, None).await;
+
+    let moved = db::get_task(&state.pool, &task_id)
+        .await?
+        .ok_or(AppError::NotFound)?;
+    let mut summaries = task_summaries(&state, vec![moved]).await?;
+    Ok(Json(summaries.remove(0)))
+}
+
+/// The task a set of changes was made to, as it was before them.
+struct TaskChangeContext<'a> {
+    task: &'a db::TaskRow,
+    org_id: &'a str,
+    actor_id: &'a str,
+    /// The task's title after the change.
+    title: &'a str,
+    workflow: &'a Workflow,
+}
+
+/// Notify about changes to a task: a new assignee, the creator about a new
+/// status, anyone newly mentioned in `description`, the followers about
+/// any change, and the assignees of blocked tasks when it is done. The
+/// changes are already saved, so failures are logged, not returned.
+async fn notify_task_changes(
+    state: &AppState,
+    context: TaskChangeContext<'_>,
+    changes: &[db::TaskChange],
+    description: Option<&str>,
+) {
+    let TaskChangeContext {
+        task,
+        org_id,
+        actor_id,
+        title,
+        workflow,
+    } = context;
+    let mut outbox = Outbox::new(org_id, &task.id, actor_id);
+    for change in changes {
+        match (change.field, change.new.as_str()) {
+            ("assignee_id", Some(assignee_id)) => outbox.add(
+                assignee_id,
+                Notice::new(notifications::TASK_ASSIGNED, "Task assigned to you", title),
+            ),
+            ("status", Some(status)) => outbox.add(
+                &task.created_by,
+                Notice::new(
+                    notifications::TASK_STATUS,
+                    "Task status changed",
+                    format!("{}: {}", title, status),
+                ),
+            ),
+            _ => {}
+        }
+    }
+    if let Some(description) = description {
+        outbox
+            .add_mentions(state, description, Some(&task.description), || {
+                Notice::new(
+                    notifications::MENTION,
+                    "You were mentioned in a task",
+                    title,
+                )
+            })
+            .await;
+    }
+    if !changes.is_empty() {
+        let fields: Vec<_> = changes.iter().map(|change| change.field).collect();
+        let body = format!("{}: {} changed", title, fields.join(", "));
+        outbox
+            .add_task_followers(state, &task.id, || {
+                Notice::new(notifications::TASK_UPDATED, "Task updated", body.as_str())
+            })
+            .await;
+    }
+    send_project_notices(state, outbox, &task.project_id).await;
+    let done = changes.iter().any(|change| {
+        change.field == "status"
+            && change
+                .new
+                .as_str()
+                .is_some_and(|status| workflows::is_done(workflow, status))
+    });
+    if done {
+        if let Err(err) = notify_unblocked(state, org_id, actor_id, &task.id, title).await {
+            tracing::warn!(task_id = %task.id, error = %err, "failed to notify unblocked tasks");
+        }
+    }
+}
+
+/// Tell the assignees of the unfinished tasks `task_id` was blocking that
+/// it is done, once none of their other blockers is left unfinished.
+async fn notify_unblocked(
+    state: &AppState,
+    org_id: &str,
+    actor_id: &str,
+    task_id: &str,
+    title: &str,
+) -> Result<(), AppError> {
+    let blocked_ids = TaskLinks::load(state, &[task_id.to_string()])
+        .await?
+        .blocks(task_id);
+    let links = TaskLinks::load(state, &blocked_ids).await?;
+    for blocked_id in blocked_ids {
+        let Some(blocked) = db::get_task(&state.pool, &blocked_id).await? else {
+            continue;
+        };
+        let Some(assignee_id) = blocked.assignee_id.as_deref() else {
+            continue;
+        };
+        let workflow = db::get_workflow(&state.pool, &blocked.project_id).await?;
+        if workflows::is_done(&workflow, &blocked.status) {
+            continue;
+        }
+        let mut still_blocked = false;
+        for blocker_id in links.blocked_by(&blocked_id) {
+            let blocker = db::get_task(&state.pool, &blocker_id).await?;
+            if blocker.is_some_and(|blocker| !workflows::is_done(&workflow, &blocker.status)) {
+                still_blocked = true;
+                break;
+            }
+        }
+        if still_blocked {
+            continue;
+        }
+        let mut outbox = Outbox::new(org_id, &blocked.id, actor_id);
+        outbox.add(
+            assignee_id,
+            Notice::new(
+                notifications::TASK_UNBLOCKED,
+                format!("{} is no longer blocked", blocked.title),
+                format!("{} is done", title),
+            ),
+        );
+        send_project_notices(state, outbox, &blocked.project_id).await;
+    }
+    Ok(())
+}
+
+/// Helper: changing a dependency needs contributor access to the task, and
+/// the blocking task must be in the same project.
+async fn require_dependency_access(
+    state: &AppState,
+    headers: &HeaderMap,
+    task_id: &str,
+    blocker_id: &str,
+) -> Result<(), AppError> {
+    let task = db::get_task(&state.pool, task_id)
+        .await?
+        .ok_or(AppError::NotFound)?;
+    let _user_and_org = require_active_project(state, headers, &task.project_id).await?;
+    let blocker = db::get_task(&state.pool, blocker_id).await?;
+    if blocker.is_none_or(|blocker| blocker.project_id != task.project_id) {
+        return Err(AppError::BadRequest(
+            "blocking task is not in this project".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Mark `task_id` as blocked by `blocker_id`.
+pub async fn add_task_dependency(
+    State(state): State<AppState>,
+    Path((task_id, blocker_id)): Path<(String, String)>,
+    headers: HeaderMap,
+) -> Result<Json<serde_json::Value>, AppError> {
+    require_dependency_access(&state, &headers, &task_id, &blocker_id).await?;
+    db::add_task_dependency(&state.pool, &blocker_id, &task_id).await?;
+    Ok(Json(json!({ "ok": true })))
+}
+
+pub async fn remove_task_dependency(
+    State(state): State<AppState>,
+    Path((task_id, blocker_id)): Path<(String, String)>,
+    headers: HeaderMap,
+) -> Result<Json<serde_json::Value>, AppError> {
+    require_dependency_access(&state, &headers, &task_id, &blocker_id).await?;
+    if !db::remove_task_dependency(&state.pool, &blocker_id, &task_id).await? {
+        return Err(AppError::NotFound);
+    }
+    Ok(Json(json!({ "ok": true })))
+}
+
+/// Send notices to those who can still see the project.
+async fn send_project_notices(state: &AppState, outbox: Outbox, project_id: &str) {
+    match db::get_project(&state.pool, project_id).await {
+        Ok(Some(project)) => outbox.send(state, Audience::Project(&project)).await,
+        Ok(None) => {}
+        Err(err) => {
+            tracing::warn!(project_id = %project_id, error = %err, "failed to load project for notifications")
+        }
+    }
+}
+
+pub async fn create_task_comment(
+    State(state): State<AppState>,
+    Path(task_id): Path<String>,
+    headers: HeaderMap,
+    Json(payload): Json<CreateTaskCommentRequest>,
+) -> Result<Json<TaskCommentInfo>, AppError> {
+    let task = db::get_task(&state.pool, &task_id)
+        .await?
+        .ok_or(AppError::NotFound)?;
+    let (user_id, org_id) = require_active_project(&state, &headers, &task.project_id).await?;
+    let body = payload.body.trim();
+    if body.is_empty() {
+        return Err(AppError::BadRequest("comment body is required".to_string()));
+    }
+    if let Some(parent_id) = payload.parent_id.as_deref() {
+        if db::get_task_comment_task(&state.pool, parent_id)
+            .await?
+            .as_deref()
+            != Some(task_id.as_str())
+        {
+            return Err(AppError::BadRequest(
+                "parent comment is not on this task".to_string(),
+            ));
+        }
+    }
+    let comment_id = db::create_task_comment(
+        &state.pool,
+        &task_id,
+        payload.parent_id.as_deref(),
+        &user_id,
+        body,
+    )
+    .await?;
+    let mut outbox = Outbox::new(&org_id, &task_id, &user_id);
+    outbox
+        .add_mentions(&state, body, None, || {
+            Notice::new(
+                notifications::MENTION,
+                format!("You were mentioned on {}", task.title),
+                body,
+            )
+        })
+        .await;
+    outbox
+        .add_task_followers(&state, &task_id, || {
+            Notice::new(
+                notifications::TASK_COMMENT,
+                format!("New comment on {}", task.title),
+                body,
+            )
+        })
+        .await;
+    send_project_notices(&state, outbox, &task.project_id).await;
+    Ok(Json(TaskCommentInfo {
+        id: comment_id,
+        parent_id: payload.parent_id,
+        user_id,
+        body: body.to_string(),
+        created_at: chrono::Utc::now().timestamp(),
+    }))
+}
+
+async fn set_task_watch(
+    state: &AppState,
+    headers: &HeaderMap,
+    task_id: &str,
+    watching: bool,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let task = db::get_task(&state.pool, task_id)
+        .await?
+        .ok_or(AppError::NotFound)?;
+    let (user_id, _org_id) = require_project_member(state, headers, &task.project_id).await?;
+    db::set_task_watch(&state.pool, task_id, &user_id, watching).await?;
+    Ok(Json(json!({ "ok": true })))
+}
+
+pub async fn watch_task(
+    State(state): State<AppState>,
+    Path(task_id): Path<String>,
+    headers: HeaderMap,
+) -> Result<Json<serde_json::Value>, AppError> {
+    set_task_watch(&state, &headers, &task_id, true).await
+}
+
+pub async fn unwatch_task(
+    State(state): State<AppState>,
+    Path(task_id): Path<String>,
+    headers: HeaderMap,
+) -> Result<Json<serde_json::Value>, AppError> {
+    set_task_watch(&state, &headers, &task_id, false).await
+}
+
+pub async fn delete_task_handler(
+    State(state): State<AppState>,
+    Path(task_id): Path<String>,
+    headers: HeaderMap,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let task = db::get_task(&state.pool, &task_id)
+        .await?
+        .ok_or(AppError::NotFound)?;
+    let (user_id, org_id) = require_active_project(&state, &headers, &task.project_id).await?;
+    db::delete_task(&state.pool, &task_id).await?;
+    audit::record(
+        &state,
+        AuditEvent::new(audit::TASK_DELETED, "task", &task_id)
+            .actor(&user_id)
+            .ip(crate::rate_limit::client_ip(&headers).as_deref())
+            .org(&org_id)
+            .details(json!({ "project_id": task.project_id, "title": task.title })),
+    )
+    .await;
+    Ok(Json(json!({ "ok": true })))
+}
+
+// ── Label routes ────────────────────────────────────────────────────
+
+const DEFAULT_LABEL_COLOR: &str = "#6b7280";
+
+fn label_info(label: db::LabelRow) -> LabelInfo {
+    LabelInfo {
+        id: label.id,
+        name: label.name,
+        color: label.color,
+    }
+}
+
+/// Accept `#rrggbb` colors, lowercased.
+fn parse_label_color(color: &str) -> Result<String, AppError> {
+    let color = color.trim();
+    let valid = color.len() == 7
+        && color.starts_with('#')
+        && color[1..].chars().all(|c| c.is_ascii_hexdigit());
+    if !valid {
+        return Err(AppError::BadRequest(
+            "label color must look like #rrggbb".to_string(),
+        ));
+    }
+    Ok(color.to_lowercase())
+}
+
+fn parse_label_name(name: &str) -> Result<&str, AppError> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err(AppError::BadRequest("label name is required".to_string()));
+    }
+    Ok(name)
+}
+
+pub async fn list_labels(
+    State(state): State<AppState>,
+    Path(project_id): Path<String>,
+    headers: HeaderMap,
+) -> Result<Json<Vec<LabelInfo>>, AppError> {
+    let _user_and_org = require_project_member(&state, &headers, &project_id).await?;
+    let labels = db::list_labels(&state.pool, &project_id).await?;
+    Ok(Json(labels.into_iter().map(label_info).collect()))
+}
+
+pub async fn create_label(
+    State(state): State<AppState>,
+    Path(project_id): Path<String>,
+    headers: HeaderMap,
+    Json(payload): Json<CreateLabelRequest>,
+) -> Result<Json<LabelInfo>, AppError> {
+    let _user_and_project =
+        require_project_role(&state, &headers, &project_id, projects::CONTRIBUTOR).await?;
+    let name = parse_label_name(&payload.name)?;
+    let color = parse_label_color(payload.color.as_deref().unwrap_or(DEFAULT_LABEL_COLOR))?;
+    let label_id = db::create_label(&state.pool, &project_id, name, &color).await?;
+    Ok(Json(LabelInfo {
+        id: label_id,
+        name: name.to_string(),
+        color,
+    }))
+}
+
+/// Helper: get a label and verify the caller can change its project's
+/// labels.
+async fn require_label_contributor(
+    state: &AppState,
+    headers: &HeaderMap,
+    label_id: &str,
+) -> Result<db::LabelRow, AppError> {
+    let label = db::get_label(&state.pool, label_id)
+        .await?
+        .ok_or(AppError::NotFound)?;
+    let _user_and_project =
+        require_project_role(state, headers, &label.project_id, projects::CONTRIBUTOR).await?;
+    Ok(label)
+}
+
+pub async fn update_label(
+    State(state): State<AppState>,
+    Path(label_id): Path<String>,
+    headers: HeaderMap,
+    Json(payload): Json<UpdateLabelRequest>,
+) -> Result<Json<LabelInfo>, AppError> {
+    let _label = require_label_contributor(&state, &headers, &label_id).await?;
+    let name = payload.name.as_deref().map(parse_label_name).transpose()?;
+    let color = payload
+        .color
+        .as_deref()
+        .map(parse_label_color)
+        .transpose()?;
+    db::update_label(&state.pool, &label_id, name, color.as_deref()).await?;
+    let label = db::get_label(&state.pool, &label_id)
+        .await?
+        .ok_or(AppError::NotFound)?;
+    Ok(Json(label_info(label)))
+}
+
+pub async fn delete_label(
+    State(state): State<AppState>,
+    Path(label_id): Path<String>,
+    headers: HeaderMap,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let _label = require_label_contributor(&state, &headers, &label_id).await?;
+    db::delete_label(&state.pool, &label_id).await?;
+    Ok(Json(json!({ "ok": true })))
+}
+
+/// Helper: resolve a task and a label of the same project, checking the
+/// caller may change the task.
+async fn require_task_label(
+    state: &AppState,
+    headers: &HeaderMap,
+    task_id: &str,
+    label_id: &str,
+) -> Result<(), AppError> {
+    let task = db::get_task(&state.pool, task_id)
+        .await?
+        .ok_or(AppError::NotFound)?;
+    let _user_and_org = require_active_project(state, headers, &task.project_id).await?;
+    match db::get_label(&state.pool, label_id).await? {
+        Some(label) if label.project_id == task.project_id => Ok(()),
+        _ => Err(AppError::NotFound),
+    }
+}
+
+pub async fn attach_task_label(
+    State(state): State<AppState>,
+    Path((task_id, label_id)): Path<(String, String)>,
+    headers: HeaderMap,
+) -> Result<Json<serde_json::Value>, AppError> {
+    require_task_label(&state, &headers, &task_id, &label_id).await?;
+    db::attach_task_label(&state.pool, &task_id, &label_id).await?;
+    Ok(Json(json!({ "ok": true })))
+}
+
+pub async fn detach_task_label(
+    State(state): State<AppState>,
+    Path((task_id, label_id)): Path<(String, String)>,
+    headers: HeaderMap,
+) -> Result<Json<serde_json::Value>, AppError> {
+    require_task_label(&state, &headers, &task_id, &label_id).await?;
+    if !db::detach_task_label(&state.pool, &task_id, &label_id).await? {
+        return Err(AppError::NotFound);
+    }
+    Ok(Json(json!({ "ok": true })))
+}
+
+// ── Annotation routes ───────────────────────────────────────────────
+
+pub async fn create_annotation_handler(
+    State(state): State<AppState>,
+    Path(org_id): Path<String>,
+    headers: HeaderMap,
+    Json(payload): Json<CreateAnnotationRequest>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let (user_id, project_id) = require_annotation_access(
+        &state,
+        &headers,
+        &org_id,
+        &payload.doc_path,
+        payload.project_id.as_deref(),
+        projects::CONTRIBUTOR,
+    )
+    .await?;
+    if payload.content.trim().is_empty() {
+        return Err(AppError::BadRequest(
+            "annotation content is required".to_string(),
+        ));
+    }
+    let annotation_id = db::create_annotation(
+        &state.pool,
+        &payload.doc_path,
+        &org_id,
+        project_id.as_deref(),
+        &user_id,
+        payload.range_start,
+        payload.range_end,
+        &payload.content,
+    )
+    .await?;
+    let mut outbox = Outbox::new(&org_id, &annotation_id, &user_id);
+    outbox
+        .add_mentions(&state, &payload.content, None, || {
+            Notice::new(
+                notifications::MENTION,
+                format!("You were mentioned on {}", payload.doc_path),
+                payload.content.as_str(),
+            )
+        })
+        .await;
+    send_annotation_notices(&state, outbox, project_id.as_deref()).await;
+    Ok(Json(json!({ "id": annotation_id })))
+}
+
+#[derive(Deserialize)]
+pub struct AnnotationQuery {
+    pub doc_path: String,
+    pub project_id: Option<String>,
+}
+
+/// Helper: verify access to the annotations on `doc_path` in an org and
+/// return the caller's id with the project they belong to. Without a
+/// `project_id` they go to the project the document is registered in, so
+/// leaving it out never gets around a project role. Annotations in a
+/// project need `required` there; org-level ones are kept from guests.
+async fn require_annotation_access(
+    state: &AppState,
+    headers: &HeaderMap,
+    org_id: &str,
+    doc_path: &str,
+    project_id: Option<&str>,
+    required: &str,
+) -> Result<(String, Option<String>), AppError> {
+    let project_id = match project_id {
+        Some(project_id) => project_id.to_string(),
+        None => {
+            let mut registered = db::doc_path_projects(&state.pool, org_id, doc_path).await?;
+            match registered.len() {
+                0 => {
+                    let user_id =
+                        require_org_role(state, headers, org_id, &["admin", "member"]).await?;
+                    return Ok((user_id, None));
+                }
+                1 => registered.remove(0),
+                _ => {
+                    return Err(AppError::BadRequest(
+                        "project_id is required for a document in several projects".to_string(),
+                    ))
+                }
+            }
+        }
+    };
+    let (user_id, project) = require_project_role(state, headers, &project_id, required).await?;
+    if project.org_id != org_id {
+        return Err(AppError::NotFound);
+    }
+    Ok((user_id, Some(project_id)))
+}
+
+pub async fn list_annotations_handler(
+    State(state): State<AppState>,
+    Path(org_id): Path<String>,
+    headers: HeaderMap,
+    Query(query): Query<AnnotationQuery>,
+) -> Result<Json<Vec<AnnotationDetail>>, AppError> {
+    let (_user_id, project_id) = require_annotation_access(
+        &state,
+        &headers,
+        &org_id,
+        &query.doc_path,
+        query.project_id.as_deref(),
+        projects::VIEWER,
+    )
+    .await?;
+    let annotations =
+        db::list_annotations(&state.pool, &query.doc_path, &org_id, project_id.as_deref()).await?;
+
+    let mut result = Vec::with_capacity(annotations.len());
+    for ann in annotations {
+        let replies = db::list_annotation_replies(&state.pool, &ann.id).await?;
+        result.push(AnnotationDetail {
+            id: ann.id,
+            doc_path: ann.doc_path,
+            project_id: ann.project_id,
+            user_id: ann.user_id,
+            range_start: ann.range_start,
+            range_end: ann.range_end,
+            content: ann.content,
+            resolved: ann.resolved,
+            created_at: ann.created_at,
+            replies: replies
+                .into_iter()
+                .map(|(id, user_id, content, created_at)| AnnotationReplyDetail {
+                    id,
+                    user_id,
+                    content,
+                    created_at,
+                })
+                .collect(),
+        });
+    }
+    Ok(Json(result))
+}
+
+pub async fn create_reply(
+    State(state): State<AppState>,
+    Path(annotation_id): Path<String>,
+    headers: HeaderMap,
+    Json(payload): Json<CreateAnnotationReplyRequest>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    // Verify via annotation -> org membership
+    let annotation = db::get_annotation(&state.pool, &annotation_id)
+        .await?
+        .ok_or(AppError::NotFound)?;
+    let (user_id, _) = require_annotation_access(
+        &state,
+        &headers,
+        &annotation.org_id,
+        &annotation.doc_path,
+        annotation.project_id.as_deref(),
+        projects::CONTRIBUTOR,
+    )
+    .await?;
+    if payload.content.trim().is_empty() {
+        return Err(AppError::BadRequest(
+            "reply content is required".to_string(),
+        ));
+    }
+    let reply_id =
+        db::create_annotation_reply(&state.pool, &annotation_id, &user_id, &payload.content)
+            .await?;
+    let mut outbox = Outbox::new(&annotation.org_id, &annotation_id, &user_id);
+    outbox
+        .add_mentions(&state, &payload.content, None, || {
+            Notice::new(
+                notifications::MENTION,
+                format!("You were mentioned on {}", annotation.doc_path),
+                payload.content.as_str(),
+            )
+        })
+        .await;
+    outbox.add(
+        &annotation.user_id,
+        Notice::new(
+            notifications::ANNOTATION_REPLY,
+            format!("New reply on {}", annotation.doc_path),
+            payload.content.as_str(),
+        ),
+    );
+    send_annotation_notices(&state, outbox, annotation.project_id.as_deref()).await;
+    Ok(Json(json!({ "id": reply_id })))
+}
+
+/// Send notices about an annotation to those who can still see it: the
+/// project's members for project annotations, the org's otherwise.
+async fn send_annotation_notices(state: &AppState, outbox: Outbox, project_id: Option<&str>) {
+    let Some(project_id) = project_id else {
+        return outbox.send(state, Audience::Org).await;
+    };
+    send_project_notices(state, outbox, project_id).await;
+}
+
+pub async fn resolve_annotation_handler(
+    State(state): State<AppState>,
+    Path(annotation_id): Path<String>,
+    headers: HeaderMap,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let annotation = db::get_annotation(&state.pool, &annotation_id)
+        .await?
+        .ok_or(AppError::NotFound)?;
+    let _user_id = require_annotation_access(
+        &state,
+        &headers,
+        &annotation.org_id,
+        &annotation.doc_path,
+        annotation.project_id.as_deref(),
+        projects::CONTRIBUTOR,
+    )
+    .await?;
+    db::resolve_annotation(&state.pool, &annotation_id).await?;
+    Ok(Json(json!({ "ok": true })))
+}
+
+// ── Notification routes ─────────────────────────────────────────────
+
+#[derive(Deserialize)]
+pub struct NotificationListQuery {
+    pub limit: Option<i64>,
+}
+
+pub async fn list_notifications_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(query): Query<NotificationListQuery>,
+) -> Result<Json<Vec<NotificationSummary>>, AppError> {
+    let user_id = require_user(&state, &headers).await?;
+    let limit = query.limit.unwrap_or(50);
+    let notifications = db::list_notifications(&state.pool, &user_id, limit).await?;
+    Ok(Json(
+        notifications
+            .into_iter()
+            .map(|n| NotificationSummary {
+                id: n.id,
+                org_id: n.org_id,
+                ntype: n.ntype,
+                title: n.title,
+                body: n.body,
+                ref_id: n.ref_id,
+                read: n.read,
+                created_at: n.created_at,
+            })
+            .collect(),
+    ))
+}
+
+pub async fn mark_read(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(payload): Json<MarkNotificationReadRequest>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let user_id = require_user(&state, &headers).await?;
+    for id in &payload.notification_ids {
+        db::mark_notification_read(&state.pool, id, &user_id).await?;
+    }
+    Ok(Json(json!({ "ok": true })))
+}
+
+pub async fn mark_all_read(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let user_id = require_user(&state, &headers).await?;
+    db::mark_all_notifications_read(&state.pool, &user_id).await?;
+    Ok(Json(json!({ "ok": true })))
+}
+
+pub async fn unread_count(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let user_id = require_user(&state, &headers).await?;
+    let count = db::count_unread_notifications(&state.pool, &user_id).await?;
+    Ok(Json(json!({ "count": count })))
+}
+
+pub async fn get_notification_preferences(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<Vec<NotificationPreference>>, AppError> {
+    let user_id = require_user(&state, &headers).await?;
+    notification_preferences(&state, &user_id).await.map(Json)
+}
+
+pub async fn update_notification_preferences(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(payload): Json<UpdateNotificationPreferencesRequest>,
+) -> Result<Json<Vec<NotificationPreference>>, AppError> {
+    let user_id = require_user(&state, &headers).await?;
+    if let Some(unknown) = payload
+        .preferences
+        .iter()
+        .find(|pref| !notifications::TYPES.contains(&pref.ntype.as_str()))
+    {
+        return Err(AppError::BadRequest(format!(
+            "unknown notification type: {}",
+            unknown.ntype
+        )));
+    }
+    for pref in &payload.preferences {
+        db::set_notification_preference(&state.pool, &user_id, &pref.ntype, pref.enabled).await?;
+    }
+    notification_preferences(&state, &user_id).await.map(Json)
+}
+
+/// Every notification type and whether `user_id` has it on.
+async fn notification_preferences(
+    state: &AppState,
+    user_id: &str,
+) -> Result<Vec<NotificationPreference>, AppError> {
+    let stored = db::list_notification_preferences(&state.pool, user_id).await?;
+    Ok(notifications::TYPES
+        .iter()
+        .map(|ntype| NotificationPreference {
+            ntype: ntype.to_string(),
+            enabled: stored
+                .iter()
+                .find(|(stored, _)| stored == ntype)
+                .is_none_or(|(_, enabled)| *enabled),
+        })
+        .collect())
+}
+
+/// Create a notification in the DB and push it via WebSocket.
+pub async fn push_notification(
+    state: &AppState,
+    user_id: &str,
+    org_id: &str,
+    ntype: &str,
+    title: &str,
+    body: &str,
+    ref_id: &str,
+) -> Result<String, AppError> {
+    let notification_id =
+        db::create_notification(&state.pool, user_id, org_id, ntype, title, body, ref_id).await?;
+
+    let unread_count = db::count_unread_notifications(&state.pool, user_id).await?;
+
+    let payload = serde_json::to_string(&json!({
+        "type": "notification",
+        "data": {
+            "id": &notification_id,
+            "org_id": org_id,
+            "type": ntype,
+            "title": title,
+            "body": body,
+            "ref_id": ref_id,
+            "read": false,
+            "created_at": chrono::Utc::now().timestamp(),
+        },
+        "unread_count": unread_count,
+    }))
+    .map_err(|e| AppError::Internal(format!("serialize notification: {}", e)))?;
+
+    state.notify.send(user_id, &payload).await;
+
+    Ok(notification_id)
+}
+
+// ── Publish ─────────────────────────────────────────────────────────
+
+pub async fn publish_status(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<models::PublishStatusResponse>, AppError> {
+    let user_id = require_user(&state, &headers).await?;
+    match db::get_published_site(&state.pool, &user_id).await? {
+        Some((url, published_at, updated_at)) => Ok(Json(models::PublishStatusResponse {
+            published: true,
+            url: Some(url),
+            published_at: Some(published_at),
+            updated_at: Some(updated_at),
+        })),
+        None => Ok(Json(models::PublishStatusResponse {
+            published: false,
+            url: None,
+            published_at: None,
+            updated_at: None,
+        })),
+    }
+}
+
+pub async fn publish_confirm(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(payload): Json<models::PublishConfirmRequest>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let user_id = require_user(&state, &headers).await?;
+    crate::sites::ensure_publishable(&state, &user_id, &payload.workspace_id).await?;
+    let site_url = format!("/sites/{}/", user_id);
+    db::upsert_published_site(&state.pool, &user_id, &site_url, &payload.workspace_id).await?;
+    audit::record(
+        &state,
+        AuditEvent::new(audit::SITE_PUBLISHED, "site", &user_id)
+            .actor(&user_id)
+            .ip(crate::rate_limit::client_ip(&headers).as_deref())
+            .details(json!({ "workspace_id": payload.workspace_id })),
+    )
+    .await;
+    Ok(Json(json!({ "ok": true, "url": site_url })))
+}
+
+pub async fn unpublish(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let user_id = require_user(&state, &headers).await?;
+    db::delete_published_site(&state.pool, &user_id).await?;
+
+    let site_dir = crate::dav::site_root(&state, &user_id);
+    if site_dir.exists() {
+        tokio::fs::remove_dir_all(&site_dir)
+            .await
+            .map_err(|e| AppError::Internal(format!("remove site dir: {}", e)))?;
+    }
+    audit::record(
+        &state,
+        AuditEvent::new(audit::SITE_UNPUBLISHED, "site", &user_id)
+            .actor(&user_id)
+            .ip(crate::rate_limit::client_ip(&headers).as_deref()),
+    )
+    .await;
+
+    Ok(Json(json!({ "ok": true })))
+}
+
+// ── Instance administration ─────────────────────────────────────────
+
+const ADMIN_USERS_PAGE_DEFAULT: i64 = 50;
+const ADMIN_USERS_PAGE_MAX: i64 = 500;
+
+fn admin_user_summary(row: db::AdminUserRow) -> AdminUserSummary {
+    AdminUserSummary {
+        id: row.id,
+        email: row.email,
+        created_at: row.created_at,
+        email_verified: row.email_verified,
+        is_admin: row.is_admin,
+        disabled: row.disabled_at.is_some(),
+        disabled_at: row.disabled_at,
+        workspace_count: row.workspace_count,
+        last_seen_at: row.last_seen_at,
+    }
+}
+
+pub async fn admin_list_users(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(query): Query<AdminUserQuery>,
+) -> Result<Json<AdminUserList>, AppError> {
+    require_instance_admin(&state, &headers).await?;
+    let search = query
+        .q
+        .map(|q| q.trim().to_lowercase())
+        .filter(|q| !q.is_empty());
+    let limit = query
+        .limit
+        .unwrap_or(ADMIN_USERS_PAGE_DEFAULT)
+        .clamp(1, ADMIN_USERS_PAGE_MAX);
+    let offset = query.offset.unwrap_or(0).max(0);
+    let (rows, total) = db::list_users(&state.pool, search.as_deref(), limit, offset).await?;
+    Ok(Json(AdminUserList {
+        users: rows.into_iter().map(admin_user_summary).collect(),
+        total,
+    }))
+}
+
+pub async fn admin_get_user(
+    State(state): State<AppState>,
+    Path(user_id): Path<String>,
+    headers: HeaderMap,
+) -> Result<Json<AdminUserDetail>, AppError> {
+    require_instance_admin(&state, &headers).await?;
+    let row = db::get_admin_user(&state.pool, &user_id)
+        .await?
+        .ok_or(AppError::NotFound)?;
+    let storage_bytes = crate::account::storage_usage(&state, &user_id).await?;
+    let active_sessions = db::list_active_sessions(&state.pool, &user_id).await?.len() as i64;
+    Ok(Json(AdminUserDetail {
+        user: admin_user_summary(row),
+        storage_bytes,
+        active_sessions,
+    }))
+}
+
+/// Disable an account and end all of its sessions. Its app tokens stop
+/// working until the account is enabled again.
+pub async fn admin_disable_user(
+    State(state): State<AppState>,
+    Path(user_id): Path<String>,
+    headers: HeaderMap,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let admin_id = require_instance_admin(&state, &headers).await?;
+    if admin_id == user_id {
+        return Err(AppError::BadRequest(
+            "cannot disable your own account".to_string(),
+        ));
+    }
+    if !db::set_user_disabled(&state.pool, &user_id, true).await? {
+        return Err(AppError::NotFound);
+    }
+    db::revoke_user_sessions(&state.pool, &user_id, None).await?;
+    record_admin_action(
+        &state,
+        &headers,
+        &admin_id,
+        audit::ADMIN_USER_DISABLED,
+        &user_id,
+    )
+    .await;
+    Ok(Json(json!({ "ok": true })))
+}
+
+pub async fn admin_enable_user(
+    State(state): State<AppState>,
+    Path(user_id): Path<String>,
+    headers: HeaderMap,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let admin_id = require_instance_admin(&state, &headers).await?;
+    if !db::set_user_disabled(&state.pool, &user_id, false).await? {
+        return Err(AppError::NotFound);
+    }
+    record_admin_action(
+        &state,
+        &headers,
+        &admin_id,
+        audit::ADMIN_USER_ENABLED,
+        &user_id,
+    )
+    .await;
+    Ok(Json(json!({ "ok": true })))
+}
+
+/// End every session of a user.
+pub async fn admin_logout_user(
+    State(state): State<AppState>,
+    Path(user_id): Path<String>,
+    headers: HeaderMap,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let admin_id = require_instance_admin(&state, &headers).await?;
+    db::get_user_by_id(&state.pool, &user_id)
+        .await?
+        .ok_or(AppError::NotFound)?;
+    db::revoke_user_sessions(&state.pool, &user_id, None).await?;
+    record_admin_action(
+        &state,
+        &headers,
+        &admin_id,
+        audit::ADMIN_USER_LOGGED_OUT,
+        &user_id,
+    )
+    .await;
+    Ok(Json(json!({ "ok": true })))
+}
+
+pub async fn admin_reset_password(
+    State(state): State<AppState>,
+    Path(user_id): Path<String>,
+    headers: HeaderMap,
+    payload: Option<Json<AdminResetPasswordRequest>>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let admin_id = require_instance_admin(&state, &headers).await?;
+    let payload = payload.map(|Json(p)| p).unwrap_or_default();
+    let email = db::get_user_by_id(&state.pool, &user_id)
+        .await?
+        .ok_or(AppError::NotFound)?;
+    match payload.new_password.as_deref().map(str::trim) {
+        Some(password) => {
+            if password.len() < MIN_PASSWORD_LEN {
+                return Err(AppError::BadRequest("new password too short".to_string()));
+            }
+            db::update_user_password(&state.pool, &user_id, &hash_password(password)?).await?;
+            db::revoke_user_sessions(&state.pool, &user_id, None).await?;
+        }
+        None => send_password_reset_email(&state, &user_id, email).await?,
+    }
+    record_admin_action(
+        &state,
+        &headers,
+        &admin_id,
+        audit::ADMIN_PASSWORD_RESET,
+        &user_id,
+    )
+    .await;
+    Ok(Json(json!({ "ok": true })))
+}
+
+async fn record_admin_action(
+    state: &AppState,
+    headers: &HeaderMap,
+    admin_id: &str,
+    action: &'static str,
+    user_id: &str,
+) {
+    audit::record(
+        state,
+        AuditEvent::new(action, "user", user_id)
+            .actor(admin_id)
+            .ip(crate::rate_limit::client_ip(headers).as_deref()),
+    )
+    .await;
+}
+
+async fn instance_settings(state: &AppState) -> Result<InstanceSettings, AppError> {
+    let policy = registration::policy(state).await?;
+    Ok(InstanceSettings {
+        registration_mode: policy.mode,
+        registration_domains: policy.domains,
+    })
+}
+
+pub async fn admin_get_settings(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<InstanceSettings>, AppError> {
+    require_instance_admin(&state, &headers).await?;
+    Ok(Json(instance_settings(&state).await?))
+}
+
+pub async fn admin_update_settings(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(payload): Json<UpdateInstanceSettingsRequest>,
+) -> Result<Json<InstanceSettings>, AppError> {
+    let admin_id = require_instance_admin(&state, &headers).await?;
+    let mode = payload
+        .registration_mode
+        .map(|mode| mode.trim().to_lowercase());
+    if let Some(mode) = &mode {
+        if !registration::MODES.contains(&mode.as_str()) {
+            return Err(AppError::BadRequest(
+                "invalid registration mode".to_string(),
+            ));
+        }
+    }
+    let domains = payload
+        .registration_domains
+        .map(|domains| registration::parse_domains(&domains.join(",")));
+
+    let mut changes = Vec::new();
+    if let Some(mode) = mode {
+        changes.push((db::SETTING_REGISTRATION_MODE, json!(mode), mode));
+    }
+    if let Some(domains) = domains {
+        let stored = domains.join(",");
+        changes.push((db::SETTING_REGISTRATION_DOMAINS, json!(domains), stored));
+    }
+    for (key, value, stored) in changes {
+        db::set_setting(&state.pool, key, &stored).await?;
+        audit::record(
+            &state,
+            AuditEvent::new(audit::ADMIN_SETTINGS_UPDATED, "setting", key)
+                .actor(&admin_id)
+                .ip(crate::rate_limit::client_ip(&headers).as_deref())
+                .details(json!({ "value": value })),
+        )
+        .await;
+    }
+    Ok(Json(instance_settings(&state).await?))
+}
+
+// ── Invite codes ────────────────────────────────────────────────────
+
+const INVITE_CODE_MAX_DAYS: i64 = 365;
+
+fn invite_code_info(row: db::InviteCodeRow) -> InviteCodeInfo {
+    InviteCodeInfo {
+        id: row.id,
+        created_by: row.created_by,
+        org_id: row.org_id,
+        role: row.role,
+        created_at: row.created_at,
+        expires_at: row.expires_at,
+        used_at: row.used_at,
+        used_by: row.used_by,
+    }
+}
+
+async fn issue_invite_code(
+    state: &AppState,
+    headers: &HeaderMap,
+    creator_id: &str,
+    org_id: Option<String>,
+    payload: CreateInviteCodeRequest,
+) -> Result<CreatedInviteCode, AppError> {
+    let role = payload.role.map(|role| role.trim().to_string());
+    if let Some(role) = &role {
+        if org_id.is_none() {
+            return Err(AppError::BadRequest(
+                "role requires an organization".to_string(),
+            ));
+        }
+        if !["admin", "member", "guest"].contains(&role.as_str()) {
+            return Err(AppError::BadRequest("invalid role".to_string()));
+        }
+    }
+    if let Some(org_id) = &org_id {
+        db::get_organization(&state.pool, org_id)
+            .await?
+            .ok_or(AppError::NotFound)?;
+    }
+    let expires_at = match payload.expires_in_days {
+        Some(days) if !(1..=INVITE_CODE_MAX_DAYS).contains(&days) => {
+            return Err(AppError::BadRequest(format!(
+                "expires_in_days must be between 1 and {}",
+                INVITE_CODE_MAX_DAYS
+            )));
+        }
+        Some(days) => Some(chrono::Utc::now().timestamp() + days * 24 * 60 * 60),
+        None => None,
+    };
+
+    let code = crate::auth::generate_opaque_token();
+    let row = db::create_invite_code(
+        &state.pool,
+        &crate::auth::hash_opaque_token(&code),
+        creator_id,
+        org_id.as_deref(),
+        role.as_deref(),
+        expires_at,
+    )
+    .await?;
+    let mut event = AuditEvent::new(audit::INVITE_CODE_CREATED, "invite_code", &row.id)
+        .actor(creator_id)
+        .ip(crate::rate_limit::client_ip(headers).as_deref())
+        .details(json!({ "role": role, "expires_at": expires_at }));
+    if let Some(org_id) = &org_id {
+        event = event.org(org_id);
+    }
+    audit::record(state, event).await;
+    Ok(CreatedInviteCode {
+        invite: invite_code_info(row),
+        code,
+    })
+}
+
+pub async fn create_admin_invite_code(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(payload): Json<CreateInviteCodeRequest>,
+) -> Result<Json<CreatedInviteCode>, AppError> {
+    let admin_id = require_instance_admin(&state, &headers).await?;
+    let org_id = payload.org_id.clone();
+    Ok(Json(
+        issue_invite_code(&state, &headers, &admin_id, org_id, payload).await?,
+    ))
+}
+
+pub async fn list_admin_invite_codes(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<Vec<InviteCodeInfo>>, AppError> {
+    require_instance_admin(&state, &headers).await?;
+    let rows = db::list_invite_codes(&state.pool, None).await?;
+    Ok(Json(rows.into_iter().map(invite_code_info).collect()))
+}
+
+/// Create a code that adds its holder to this organization. `org_id` in
+/// the body is ignored.
+pub async fn create_org_invite_code(
+    State(state): State<AppState>,
+    Path(org_id): Path<String>,
+    headers: HeaderMap,
+    Json(payload): Json<CreateInviteCodeRequest>,
+) -> Result<Json<CreatedInviteCode>, AppError> {
+    let admin_id = require_org_role(&state, &headers, &org_id, &["admin"]).await?;
+    Ok(Json(
+        issue_invite_code(&state, &headers, &admin_id, Some(org_id), payload).await?,
+    ))
+}
+
+pub async fn list_org_invite_codes(
+    State(state): State<AppState>,
+    Path(org_id): Path<String>,
+    headers: HeaderMap,
+) -> Result<Json<Vec<InviteCodeInfo>>, AppError> {
+    require_org_role(&state, &headers, &org_id, &["admin"]).await?;
+    let rows = db::list_invite_codes(&state.pool, Some(&org_id)).await?;
+    Ok(Json(rows.into_iter().map(invite_code_info).collect()))
+}
+
+/// Revoke an unused code. Instance admins can revoke any code, org admins
+/// the codes of their organization.
+pub async fn revoke_invite_code(
+    State(state): State<AppState>,
+    Path(invite_id): Path<String>,
+    headers: HeaderMap,
+) -> Result<Json<serde_json::Value>, AppError> {
+    require_session_user(&state, &headers).await?;
+    let invite = db::get_invite_code(&state.pool, &invite_id)
+        .await?
+        .ok_or(AppError::NotFound)?;
+    let actor_id = match require_instance_admin(&state, &headers).await {
+        Ok(user_id) => user_id,
+        Err(AppError::Forbidden) => match &invite.org_id {
+            Some(org_id) => require_org_role(&state, &headers, org_id, &["admin"]).await?,
+            None => return Err(AppError::Forbidden),
+        },
+        Err(err) => return Err(err),
+    };
+    if invite.used_at.is_some() {
+        return Err(AppError::Conflict("invite code already used".to_string()));
+    }
+    db::delete_invite_code(&state.pool, &invite_id).await?;
+    let mut event = AuditEvent::new(audit::INVITE_CODE_REVOKED, "invite_code", &invite_id)
+        .actor(&actor_id)
+        .ip(crate::rate_limit::client_ip(&headers).as_deref());
+    if let Some(org_id) = &invite.org_id {
+        event = event.org(org_id);
+    }
+    audit::record(&state, event).await;
+    Ok(Json(json!({ "ok": true })))
+}
+
+// ── Audit log ───────────────────────────────────────────────────────
+
+const AUDIT_PAGE_DEFAULT: i64 = 50;
+const AUDIT_PAGE_MAX: i64 = 200;
+
+async fn audit_page(
+    state: &AppState,
+    org_id: Option<String>,
+    query: AuditQuery,
+) -> Result<AuditLogPage, AppError> {
+    let limit = query
+        .limit
+        .unwrap_or(AUDIT_PAGE_DEFAULT)
+        .clamp(1, AUDIT_PAGE_MAX);
+    let filter = db::AuditFilter {
+        org_id,
+        action: query.action,
+        actor_id: query.actor_id,
+        target_type: query.target_type,
+        target_id: query.target_id,
+        since: query.since,
+        until: query.until,
+        before: query.before,
+        limit,
+    };
+    let rows = db::list_audit_events(&state.pool, &filter).await?;
+    let next_before = if rows.len() as i64 == limit {
+        rows.last().map(|row| row.id)
+    } else {
+        None
+    };
+    let entries = rows
+        .into_iter()
+        .map(|row| AuditEntry {
+            id: row.id,
+            created_at: row.created_at,
+            action: row.action,
+            actor_id: row.actor_id,
+            actor_email: row.actor_email,
+            ip: row.ip,
+            org_id: row.org_id,
+            target_type: row.target_type,
+            target_id: row.target_id,
+            details: row.details,
+        })
+        .collect();
+    Ok(AuditLogPage {
+        entries,
+        next_before,
+    })
+}
+
+/// Audit log of one organization, for its admins.
+pub async fn list_org_audit(
+    State(state): State<AppState>,
+    Path(org_id): Path<String>,
+    headers: HeaderMap,
+    Query(query): Query<AuditQuery>,
+) -> Result<Json<AuditLogPage>, AppError> {
+    require_org_role(&state, &headers, &org_id, &["admin"]).await?;
+    Ok(Json(audit_page(&state, Some(org_id), query).await?))
+}
+
+/// Instance-wide audit log, for server admins.
+pub async fn list_instance_audit(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(mut query): Query<AuditQuery>,
+) -> Result<Json<AuditLogPage>, AppError> {
+    require_instance_admin(&state, &headers).await?;
+    let org_id = query.org_id.take();
+    Ok(Json(audit_page(&state, org_id, query).await?))
+}
+
+// ── Account ─────────────────────────────────────────────────────────
+
+/// Download everything stored about the caller as a tar archive.
+pub async fn export_account(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<axum::response::Response, AppError> {
+    let user_id = require_session_user(&state, &headers).await?;
+    let file = crate::account::export_archive(&state, &user_id).await?;
+    let filename = format!("lumina-export-{}.tar", chrono::Utc::now().format("%Y%m%d"));
+    let body = axum::body::StreamBody::new(tokio_util::io::ReaderStream::new(
+        tokio::fs::File::from_std(file),
+    ));
+    Ok((
+        StatusCode::OK,
+        [
+            (
+                axum::http::header::CONTENT_TYPE,
+                "application/x-tar".to_string(),
+            ),
+            (
+                axum::http::header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{}\"", filename),
+            ),
+        ],
+        body,
+    )
+        .into_response())
+}
+
+/// How recently an account without a password must have signed in to
+/// delete itself.
+const ACCOUNT_DELETION_REAUTH_SECS: i64 = 10 * 60;
+
+/// Permanently delete the caller's account. See
+/// [`db::delete_user_account`] for what happens to shared data.
+pub async fn delete_account(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(payload): Json<DeleteAccountRequest>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let claims = require_session(&state, &headers).await?;
+    let user_id = claims.sub;
+    let email = db::get_user_by_id(&state.pool, &user_id)
+        .await?
+        .ok_or(AppError::Unauthorized)?;
+    if payload.confirm_email.trim().to_lowercase() != email {
+        return Err(AppError::BadRequest(
+            "confirm_email does not match the account".to_string(),
+        ));
+    }
+    if db::user_has_password(&state.pool, &user_id).await?
+        || !db::user_has_identity(&state.pool, &user_id).await?
+    {
+        let password = payload
+            .password
+            .as_deref()
+            .ok_or_else(|| AppError::BadRequest("password required".to_string()))?;
+        check_login_guard(&state, &email)?;
+        let (_, password_hash) = db::find_user_by_email(&state.pool, &email)
+            .await?
+            .ok_or(AppError::Unauthorized)?;
+        if !verify_password(password.trim(), &password_hash)? {
+            let ip = crate::rate_limit::client_ip(&headers);
+            return Err(login_failed(&state, &email, ip.as_deref(), "password").await);
+        }
+    } else {
+        // Without a password, only a fresh sign-in at the identity provider
+        // shows the caller is more than a stolen access token.
+        let session = db::get_session(&state.pool, &claims.sid)
+            .await?
+            .ok_or(AppError::Unauthorized)?;
+        if session.created_at < chrono::Utc::now().timestamp() - ACCOUNT_DELETION_REAUTH_SECS {
+            return Err(AppError::BadRequest(
+                "sign in again to delete this account".to_string(),
+            ));
+        }
+    }
+
+    let ip = crate::rate_limit::client_ip(&headers);
+    crate::account::delete_account(&state, &user_id, ip.as_deref()).await?;
+    tracing::info!(user_id = %user_id, "account deleted");
+    Ok(Json(json!({ "ok": true })))
+}
+
+// ── Tests ───────────────────────────────────────────────────────────
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::collab::CollabHub;
+    use crate::config::Config;
+    use crate::db;
+    use crate::state::{RelayHub, ServerMetrics};
+    use axum::extract::ConnectInfo;
+    use axum::http::{header::AUTHORIZATION, HeaderValue};
+    use sqlx::sqlite::SqlitePoolOptions;
+    use std::net::SocketAddr;
+    use std::sync::Arc;
+
+    const TEST_ADDR: SocketAddr = SocketAddr::new(
+        std::net::IpAddr::V4(std::net::Ipv4Addr::new(127, 0, 0, 1)),
+        9999,
+    );
+    fn test_config(data_dir: &std::path::Path) -> Config {
+        Config {
+            bind: "127.0.0.1:0".to_string(),
+            db_url: "sqlite::memory:".to_string(),
+            data_dir: data_dir.display().to_string(),
+            auth_rate_limit_burst: 100,
+            auth_rate_limit_window_secs: 1,
+            login_lockout_threshold: 5,
+            login_lockout_base_secs: 30,
+            login_lockout_max_secs: 900,
+            trusted_proxy_hops: 0,
+            dav_require_app_password: false,
+            access_token_ttl_secs: 900,
+            refresh_token_ttl_secs: 3600,
+            public_url: "http://lumina.test".to_string(),
+            smtp_url: None,
+            mail_from: "Lumina <no-reply@lumina.test>".to_string(),
+            require_email_verification: false,
+            oidc: None,
+            password_login_enabled: true,
+            admin_emails: Vec::new(),
+            registration_mode: "open".to_string(),
+            registration_domains: Vec::new(),
+            org_deletion_grace_secs: 60 * 60 * 24 * 30,
+            due_reminder_windows_secs: vec![60 * 60 * 24, 60 * 60],
+        }
+    }
+
+    async fn state_with_config(config: Config) -> AppState {
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        db::init_db(&pool).await.unwrap();
+        let keys =
+            crate::keys::KeyRing::load(pool.clone(), crate::auth::signing_key_grace_secs(&config))
+                .await
+                .unwrap();
+
+        AppState {
+            pool,
+            collab: CollabHub::new(&config.data_dir),
+            mailer: crate::mailer::from_config(&config).unwrap(),
+            keys,
+            oidc: config
+                .oidc
+                .clone()
+                .map(|oidc| Arc::new(crate::oidc::OidcClient::new(oidc))),
+            auth_limiter: crate::rate_limit::AuthRateLimiter::new(
+                config.auth_rate_limit_burst,
+                config.auth_rate_limit_window_secs,
+            ),
+            login_guard: crate::rate_limit::LoginGuard::new(
+                config.login_lockout_threshold,
+                config.login_lockout_base_secs,
+                config.login_lockout_max_secs,
+            ),
+            config,
+            relay: RelayHub::new(),
+            metrics: Arc::new(ServerMetrics::new()),
+            notify: crate::notify_ws::NotifyHub::new(),
+        }
+    }
+
+    async fn test_state() -> AppState {
+        let data_dir = std::env::temp_dir().join(format!("lumina-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&data_dir).unwrap();
+        state_with_config(test_config(&data_dir)).await
+    }
+
+    fn auth_headers(token: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            AUTHORIZATION,
+            HeaderValue::from_str(&format!("Bearer {}", token)).unwrap(),
+        );
+        headers
+    }
+
+    #[tokio::test]
+    async fn register_returns_user_and_default_workspace() {
+        let state = test_state().await;
+
+        let response = expect_authenticated(
+            register(
+                State(state),
+                ConnectInfo(TEST_ADDR),
+                HeaderMap::new(),
+                Json(RegisterRequest {
+                    email: "dev@example.com".to_string(),
+                    password: "change-me".to_string(),
+                    invite_code: None,
+                }),
+            )
+            .await
+            .unwrap()
+            .0,
+        );
+
+        assert_eq!(response.user.email, "dev@example.com");
+        assert!(!response.token.is_empty());
+        assert_eq!(response.workspaces.len(), 1);
+        assert_eq!(response.workspaces[0].name, "My Workspace");
+    }
+
+    #[tokio::test]
+    async fn register_rejects_passwords_shorter_than_eight_characters() {
+        let state = test_state().await;
+
+        let result = register(
+            State(state),
+            ConnectInfo(TEST_ADDR),
+            HeaderMap::new(),
+            Json(RegisterRequest {
+                email: "dev@example.com".to_string(),
+                password: "1234567".to_string(),
+                invite_code: None,
+            }),
+        )
+        .await;
+
+        match result {
+            Err(AppError::BadRequest(message)) => {
+                assert_eq!(message, "invalid email or password");
+            }
+            other => panic!("expected bad request, got {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn login_and_create_workspace_share_same_contract() {
+        let state = test_state().await;
+
+        let registered = expect_authenticated(
+            register(
+                State(state.clone()),
+                ConnectInfo(TEST_ADDR),
+                HeaderMap::new(),
+                Json(RegisterRequest {
+                    email: "dev@example.com".to_string(),
+                    password: "change-me".to_string(),
+                    invite_code: None,
+                }),
+            )
+            .await
+            .unwrap()
+            .0,
+        );
+
+        let login_response = expect_authenticated(
+            login(
+                State(state.clone()),
+                ConnectInfo(TEST_ADDR),
+                HeaderMap::new(),
+                Json(LoginRequest {
+                    email: "dev@example.com".to_string(),
+                    password: "change-me".to_string(),
+                }),
+            )
+            .await
+            .unwrap()
+            .0,
+        );
+
+        assert_eq!(login_response.user.id, registered.user.id);
+        assert_eq!(login_response.user.email, "dev@example.com");
+
+        let created = create_workspace(
+            State(state.clone()),
+            auth_headers(&login_response.token),
+            Json(CreateWorkspaceRequest {
+                name: "Research".to_string(),
+                e2ee: false,
+            }),
+        )
+        .await
+        .unwrap()
+        .0;
+
+        assert_eq!(created.name, "Research");
+
+        let listed = list_workspaces(State(state), auth_headers(&login_response.token))
+            .await
+            .unwrap()
+            .0;
+        assert_eq!(listed.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn login_rejects_after_rate_limit_exceeded() {
+        let data_dir = std::env::temp_dir().join(format!("lumina-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&data_dir).unwrap();
+        let state = state_with_config(Config {
+            auth_rate_limit_burst: 2,
+            auth_rate_limit_window_secs: 60,
+            ..test_config(&data_dir)
+        })
+        .await;
+
+        // Request 1: register (uses 1 of 2 tokens)
+        let _ = register(
+            State(state.clone()),
+            ConnectInfo(TEST_ADDR),
+            HeaderMap::new(),
+            Json(RegisterRequest {
+                email: "ratelimit@example.com".to_string(),
+                password: "strongpass123".to_string(),
+                invite_code: None,
+            }),
+        )
+        .await
+        .unwrap();
+
+        // Request 2: login (uses 2 of 2 tokens)
+        let _ = login(
+            State(state.clone()),
+            ConnectInfo(TEST_ADDR),
+            HeaderMap::new(),
+            Json(LoginRequest {
+                email: "ratelimit@example.com".to_string(),
+                password: "strongpass123".to_string(),
+            }),
+        )
+        .await
+        .unwrap();
+
+        // Request 3: should be rate limited
+        let result = login(
+            State(state),
+            ConnectInfo(TEST_ADDR),
+            HeaderMap::new(),
+            Json(LoginRequest {
+                email: "ratelimit@example.com".to_string(),
+                password: "strongpass123".to_string(),
+            }),
+        )
+        .await;
+
+        match result {
+            Err(AppError::RateLimited(secs)) => {
+                assert!(secs > 0);
+            }
+            other => panic!("expected RateLimited, got {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn repeated_failures_lock_the_account_across_login_paths() {
+        let data_dir = std::env::temp_dir().join(format!("lumina-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&data_dir).unwrap();
+        let state = state_with_config(Config {
+            login_lockout_threshold: 3,
+            ..test_config(&data_dir)
+        })
+        .await;
+        register_user(&state, "target@example.com").await;
+
+        let attempt = |password: &str| {
+            login(
+                State(state.clone()),
+                ConnectInfo(TEST_ADDR),
+                HeaderMap::new(),
+                Json(LoginRequest {
+                    email: "target@example.com".to_string(),
+                    password: password.to_string(),
+                }),
+            )
+        };
+        assert!(matches!(
+            attempt("wrong-1").await,
+            Err(AppError::Unauthorized)
+        ));
+        // Basic auth failures (WebDAV, relay) count towards the same account.
+        assert!(matches!(
+            crate::auth::authenticate_basic(
+                &state,
+                "target@example.com",
+                "wrong-2",
+                "dav:read",
+                None
+            )
+            .await,
+            Err(AppError::Unauthorized)
+        ));
+        assert!(matches!(
+            attempt("wrong-3").await,
+            Err(AppError::Unauthorized)
+        ));
+        assert_eq!(state.metrics.snapshot().auth_lockouts, 1);
+
+        // Locked: even the right password is refused, on every path.
+        assert!(matches!(
+            attempt("change-me").await,
+            Err(AppError::RateLimited(secs)) if secs > 0
+        ));
+        assert!(matches!(
+            crate::auth::authenticate_basic(
+                &state,
+                "target@example.com",
+                "change-me",
+                "relay",
+                None
+            )
+            .await,
+            Err(AppError::RateLimited(_))
+        ));
+        // Other accounts are unaffected.
+        register_user(&state, "bystander@example.com").await;
+        assert!(login(
+            State(state.clone()),
+            ConnectInfo(TEST_ADDR),
+            HeaderMap::new(),
+            Json(LoginRequest {
+                email: "bystander@example.com".to_string(),
+                password: "change-me".to_string(),
+            }),
+        )
+        .await
+        .is_ok());
+    }
+
+    fn expect_authenticated(response: LoginResponse) -> AuthResponse {
+        match response {
+            LoginResponse::Authenticated(auth) => auth,
+            other => panic!("expected a session, got {other:?}"),
+        }
+    }
+
+    async fn register_user(state: &AppState, email: &str) -> AuthResponse {
+        expect_authenticated(
+            register(
+                State(state.clone()),
+                ConnectInfo(TEST_ADDR),
+                HeaderMap::new(),
+                Json(RegisterRequest {
+                    email: email.to_string(),
+                    password: "change-me".to_string(),
+                    invite_code: None,
+                }),
+            )
+            .await
+            .unwrap()
+            .0,
+        )
+    }
+
+    #[tokio::test]
+    async fn e2ee_workspace_enrolls_new_device_through_existing_one() {
+        let state = test_state().await;
+        let user = register_user(&state, "vault@example.com").await;
+        let headers = auth_headers(&user.token);
+
+        let workspace = create_workspace(
+            State(state.clone()),
+            headers.clone(),
+            Json(CreateWorkspaceRequest {
+                name: "Private".to_string(),
+                e2ee: true,
+            }),
+        )
+        .await
+        .unwrap()
+        .0;
+        assert!(workspace.e2ee);
+
+        let first = register_device_key(
+            State(state.clone()),
+            Path(workspace.id.clone()),
+            headers.clone(),
+            Json(RegisterDeviceKeyRequest {
+                device_id: "laptop".to_string(),
+                device_name: "Laptop".to_string(),
+                public_key: "pk-laptop".to_string(),
+                wrapped_key: Some("wrapped-for-laptop".to_string()),
+            }),
+        )
+        .await
+        .unwrap()
+        .0;
+        assert!(first.enrolled_at.is_some());
+
+        // A second device cannot bootstrap its own key once one exists.
+        let rejected = register_device_key(
+            State(state.clone()),
+            Path(workspace.id.clone()),
+            headers.clone(),
+            Json(RegisterDeviceKeyRequest {
+                device_id: "phone".to_string(),
+                device_name: "Phone".to_string(),
+                public_key: "pk-phone".to_string(),
+                wrapped_key: Some("self-wrapped".to_string()),
+            }),
+        )
+        .await;
+        assert!(matches!(rejected, Err(AppError::Conflict(_))));
+
+        let pending = register_device_key(
+            State(state.clone()),
+            Path(workspace.id.clone()),
+            headers.clone(),
+            Json(RegisterDeviceKeyRequest {
+                device_id: "phone".to_string(),
+                device_name: "Phone".to_string(),
+                public_key: "pk-phone".to_string(),
+                wrapped_key: None,
+            }),
+        )
+        .await
+        .unwrap()
+        .0;
+        assert!(pending.wrapped_key.is_none());
+
+        let enrolled = enroll_device_key(
+            State(state.clone()),
+            Path((workspace.id.clone(), "phone".to_string())),
+            headers.clone(),
+            Json(EnrollDeviceKeyRequest {
+                approver_device_id: "laptop".to_string(),
+                wrapped_key: "wrapped-for-phone".to_string(),
+            }),
+        )
+        .await
+        .unwrap()
+        .0;
+        assert_eq!(enrolled.wrapped_key.as_deref(), Some("wrapped-for-phone"));
+        assert_eq!(enrolled.enrolled_by.as_deref(), Some("laptop"));
+    }
+
+    #[tokio::test]
+    async fn plaintext_workspaces_reject_device_keys() {
+        let state = test_state().await;
+        let user = register_user(&state, "plain@example.com").await;
+
+        let result = list_device_keys(
+            State(state),
+            Path(user.workspaces[0].id.clone()),
+            auth_headers(&user.token),
+        )
+        .await;
+        assert!(matches!(result, Err(AppError::BadRequest(_))));
+    }
+
+    #[tokio::test]
+    async fn sites_go_online_only_when_published_from_plaintext_workspaces() {
+        let state = test_state().await;
+        let user = register_user(&state, "vault@example.com").await;
+        let other = register_user(&state, "other@example.com").await;
+        let headers = auth_headers(&user.token);
+        let private = create_workspace(
+            State(state.clone()),
+            headers.clone(),
+            Json(CreateWorkspaceRequest {
+                name: "Private".to_string(),
+                e2ee: true,
+            }),
+        )
+        .await
+        .unwrap()
+        .0;
+        let publish = |workspace_id: &str| {
+            publish_confirm(
+                State(state.clone()),
+                headers.clone(),
+                Json(models::PublishConfirmRequest {
+                    workspace_id: workspace_id.to_string(),
+                }),
+            )
+        };
+        let serve =
+            || crate::sites::serve_site_root(State(state.clone()), Path(user.user_id.clone()));
+        let site_dir = crate::dav::site_root(&state, &user.user_id);
+        std::fs::create_dir_all(&site_dir).unwrap();
+        std::fs::write(site_dir.join("index.html"), "hello").unwrap();
+
+        // Uploaded files stay offline until a publish confirms them.
+        assert!(matches!(serve().await, Err(AppError::NotFound)));
+        assert!(matches!(
+            publish(&private.id).await,
+            Err(AppError::BadRequest(_))
+        ));
+        assert!(matches!(
+            publish(&other.workspaces[0].id).await,
+            Err(AppError::Forbidden)
+        ));
+        assert!(db::get_published_site(&state.pool, &user.user_id)
+            .await
+            .unwrap()
+            .is_none());
+
+        // Holding an encrypted workspace does not stop publishing another.
+        let _ = publish(&user.workspaces[0].id).await.unwrap();
+        assert!(serve().await.is_ok());
+        db::mark_site_pending(&state.pool, &user.user_id)
+            .await
+            .unwrap();
+        assert!(matches!(serve().await, Err(AppError::NotFound)));
+        let _ = publish(&user.workspaces[0].id).await.unwrap();
+        assert!(serve().await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn app_passwords_are_scoped_and_revocable() {
+        let data_dir = std::env::temp_dir().join(format!("lumina-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&data_dir).unwrap();
+        let state = state_with_config(Config {
+            dav_require_app_password: true,
+            ..test_config(&data_dir)
+        })
+        .await;
+        let user = register_user(&state, "dav@example.com").await;
+
+        let created = create_app_token(
+            State(state.clone()),
+            auth_headers(&user.token),
+            Json(CreateAppTokenRequest {
+                name: "Phone sync".to_string(),
+                kind: KIND_APP_PASSWORD.to_string(),
+                scopes: vec!["dav:read".to_string()],
+                expires_at: None,
+            }),
+        )
+        .await
+        .unwrap()
+        .0;
+
+        let basic = crate::auth::authenticate_basic;
+        let email = "dav@example.com";
+        assert_eq!(
+            basic(&state, email, &created.token, "dav:read", Some("10.0.0.7"))
+                .await
+                .unwrap(),
+            user.user_id
+        );
+        assert!(matches!(
+            basic(&state, email, &created.token, "dav:write", None).await,
+            Err(AppError::Forbidden)
+        ));
+        // The account password is refused for DAV once app passwords are required.
+        assert!(matches!(
+            basic(&state, email, "change-me", "dav:read", None).await,
+            Err(AppError::Unauthorized)
+        ));
+        // An app password is not a bearer token.
+        assert!(matches!(
+            require_user(&state, &auth_headers(&created.token)).await,
+            Err(AppError::Unauthorized)
+        ));
+
+        let listed = list_app_tokens(State(state.clone()), auth_headers(&user.token))
+            .await
+            .unwrap()
+            .0;
+        assert_eq!(listed[0].last_used_ip.as_deref(), Some("10.0.0.7"));
+        assert!(listed[0].last_used_at.is_some());
+
+        let _ = revoke_app_token(
+            State(state.clone()),
+            Path(created.info.id.clone()),
+            auth_headers(&user.token),
+        )
+        .await
+        .unwrap();
+        assert!(matches!(
+            basic(&state, email, &created.token, "dav:read", None).await,
+            Err(AppError::Unauthorized)
+        ));
+    }
+
+    #[tokio::test]
+    async fn account_export_and_deletion_follow_ownership_rules() {
+        let state = test_state().await;
+        let alice = register_user(&state, "alice@example.com").await;
+        let bob = register_user(&state, "bob@example.com").await;
+        let pool = &state.pool;
+
+        let shared = db::create_organization(pool, &alice.user_id, "Shared")
+            .await
+            .unwrap();
+        db::add_org_member(pool, &shared, &bob.user_id, "member")
+            .await
+            .unwrap();
+        let solo = db::create_organization(pool, &alice.user_id, "Solo")
+            .await
+            .unwrap();
+        let project = db::create_project(pool, &shared, "Roadmap", "")
+            .await
+            .unwrap();
+        let task = create_task(
+            State(state.clone()),
+            Path(project),
+            auth_headers(&alice.token),
+            Json(CreateTaskRequest {
+                parent_id: None,
+                title: "Ship it".to_string(),
+                description: None,
+                status: None,
+                priority: None,
+                assignee_id: Some(alice.user_id.clone()),
+                due_date: None,
+                start_date: None,
+            }),
+        )
+        .await
+        .unwrap()
+        .0;
+        let workspace_id = alice.workspaces[0].id.clone();
+        let workspace_dir = crate::dav::workspace_root(&state, &workspace_id);
+        std::fs::create_dir_all(&workspace_dir).unwrap();
+        std::fs::write(workspace_dir.join("note.md"), "# hello").unwrap();
+
+        let response = export_account(State(state.clone()), auth_headers(&alice.token))
+            .await
+            .unwrap();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let mut archive = tar::Archive::new(body.as_ref());
+        let mut files = std::collections::HashMap::new();
+        for entry in archive.entries().unwrap() {
+            let mut entry = entry.unwrap();
+            let path = entry.path().unwrap().display().to_string();
+            let mut content = String::new();
+            std::io::Read::read_to_string(&mut entry, &mut content).unwrap();
+            files.insert(path, content);
+        }
+        let profile: serde_json::Value =
+            serde_json::from_str(&files["lumina-export/profile.json"]).unwrap();
+        assert_eq!(profile["email"], "alice@example.com");
+        assert!(profile.get("password_hash").is_none());
+        let tasks: serde_json::Value =
+            serde_json::from_str(&files["lumina-export/tasks.json"]).unwrap();
+        assert_eq!(tasks[0]["id"], task.id.as_str());
+        assert_eq!(
+            files[&format!("lumina-export/workspaces/{}/note.md", workspace_id)],
+            "# hello"
+        );
+
+        let delete = |confirm_email: &str, password: Option<&str>| {
+            delete_account(
+                State(state.clone()),
+                auth_headers(&alice.token),
+                Json(DeleteAccountRequest {
+                    confirm_email: confirm_email.to_string(),
+                    password: password.map(str::to_string),
+                }),
+            )
+        };
+        assert!(matches!(
+            delete("bob@example.com", Some("change-me")).await,
+            Err(AppError::BadRequest(_))
+        ));
+        assert!(matches!(
+            delete("alice@example.com", None).await,
+            Err(AppError::BadRequest(_))
+        ));
+        assert!(matches!(
+            delete("alice@example.com", Some("wrong-password")).await,
+            Err(AppError::Unauthorized)
+        ));
+        let _ = delete("alice@example.com", Some("change-me"))
+            .await
+            .unwrap();
+
+        // The shared org goes to the remaining member, the solo org is gone.
+        let (_, _, owner) = db::get_organization(pool, &shared).await.unwrap().unwrap();
+        assert_eq!(owner, bob.user_id);
+        assert_eq!(
+            db::get_org_member_role(pool, &shared, &bob.user_id)
+                .await
+                .unwrap()
+                .as_deref(),
+            Some("admin")
+        );
+        assert!(db::get_organization(pool, &solo).await.unwrap().is_none());
+        let kept = db::get_task(pool, &task.id).await.unwrap().unwrap();
+        assert!(kept.assignee_id.is_none());
+
+        assert!(!workspace_dir.exists());
+        assert!(matches!(
+            require_user(&state, &auth_headers(&alice.token)).await,
+            Err(AppError::Unauthorized)
+        ));
+        assert!(db::find_user_by_email(pool, "alice@example.com")
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn audit_log_records_events_for_org_and_instance_admins() {
+        let data_dir = std::env::temp_dir().join(format!("lumina-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&data_dir).unwrap();
+        let state = state_with_config(Config {
+            admin_emails: vec!["ops@example.com".to_string()],
+            ..test_config(&data_dir)
+        })
+        .await;
+        let alice = register_user(&state, "alice@example.com").await;
+        let bob = register_user(&state, "bob@example.com").await;
+        let org_id = db::create_organization(&state.pool, &alice.user_id, "Acme")
+            .await
+            .unwrap();
+
+        let _ = add_member(
+            State(state.clone()),
+            Path(org_id.clone()),
+            auth_headers(&alice.token),
+            Json(AddOrgMemberRequest {
+                email: "bob@example.com".to_string(),
+                role: "member".to_string(),
+            }),
+        )
+        .await
+        .unwrap();
+        let _ = remove_member(
+            State(state.clone()),
+            Path((org_id.clone(), bob.user_id.clone())),
+            auth_headers(&alice.token),
+        )
+        .await
+        .unwrap();
+        let failed = login(
+            State(state.clone()),
+            ConnectInfo(TEST_ADDR),
+            HeaderMap::new(),
+            Json(LoginRequest {
+                email: "alice@example.com".to_string(),
+                password: "wrong-password".to_string(),
+            }),
+        )
+        .await;
+        assert!(matches!(failed, Err(AppError::Unauthorized)));
+
+        let org_audit = |query: AuditQuery, token: &str| {
+            list_org_audit(
+                State(state.clone()),
+                Path(org_id.clone()),
+                auth_headers(token),
+                Query(query),
+            )
+        };
+        let first = org_audit(
+            AuditQuery {
+                limit: Some(1),
+                ..Default::default()
+            },
+            &alice.token,
+        )
+        .await
+        .unwrap()
+        .0;
+        assert_eq!(first.entries.len(), 1);
+        assert_eq!(first.entries[0].action, audit::ORG_MEMBER_REMOVED);
+        assert_eq!(first.entries[0].target_id, bob.user_id);
+        assert_eq!(
+            first.entries[0].actor_email.as_deref(),
+            Some("alice@example.com")
+        );
+        let second = org_audit(
+            AuditQuery {
+                limit: Some(1),
+                before: first.next_before,
+                ..Default::default()
+            },
+            &alice.token,
+        )
+        .await
+        .unwrap()
+        .0;
+        assert_eq!(second.entries[0].action, audit::ORG_MEMBER_ADDED);
+        assert_eq!(second.entries[0].details["role"], "member");
+        let filtered = org_audit(
+            AuditQuery {
+                action: Some(audit::ORG_MEMBER_ADDED.to_string()),
+                ..Default::default()
+            },
+            &alice.token,
+        )
+        .await
+        .unwrap()
+        .0;
+        assert_eq!(filtered.entries.len(), 1);
+        assert!(filtered.next_before.is_none());
+        // Login events are not tied to an org.
+        assert!(!filtered
+            .entries
+            .iter()
+            .any(|entry| entry.action == audit::LOGIN_FAILED));
+        assert!(matches!(
+            org_audit(AuditQuery::default(), &bob.token).await,
+            Err(AppError::Forbidden)
+        ));
+
+        let instance_audit = |token: &str| {
+            list_instance_audit(
+                State(state.clone()),
+                auth_headers(token),
+                Query(AuditQuery {
+                    action: Some(audit::LOGIN_FAILED.to_string()),
+                    ..Default::default()
+                }),
+            )
+        };
+        assert!(matches!(
+            instance_audit(&alice.token).await,
+            Err(AppError::Forbidden)
+        ));
+        // Listed admins still need a verified address.
+        let ops = register_user(&state, "ops@example.com").await;
+        assert!(matches!(
+            instance_audit(&ops.token).await,
+            Err(AppError::Forbidden)
+        ));
+        db::mark_email_verified(&state.pool, &ops.user_id)
+            .await
+            .unwrap();
+        let failures = instance_audit(&ops.token).await.unwrap().0;
+        assert_eq!(failures.entries.len(), 1);
+        assert_eq!(failures.entries[0].target_id, "alice@example.com");
+        assert_eq!(failures.entries[0].ip.as_deref(), Some("127.0.0.1"));
+    }
+
+    #[tokio::test]
+    async fn instance_admin_manages_users_and_registration() {
+        let state = test_state().await;
+        let ops = register_user(&state, "ops@example.com").await;
+        let alice = register_user(&state, "alice@example.com").await;
+        register_user(&state, "bob@example.com").await;
+
+        let list = |token: &str, q: Option<&str>| {
+            admin_list_users(
+                State(state.clone()),
+                auth_headers(token),
+                Query(AdminUserQuery {
+                    q: q.map(str::to_string),
+                    ..Default::default()
+                }),
+            )
+        };
+        assert!(matches!(
+            list(&ops.token, None).await,
+            Err(AppError::Forbidden)
+        ));
+        assert!(db::set_user_admin(&state.pool, &ops.user_id, true)
+            .await
+            .unwrap());
+        assert_eq!(list(&ops.token, None).await.unwrap().0.total, 3);
+        let found = list(&ops.token, Some("ALICE")).await.unwrap().0;
+        assert_eq!(found.total, 1);
+        assert_eq!(found.users[0].id, alice.user_id);
+        let detail = admin_get_user(
+            State(state.clone()),
+            Path(alice.user_id.clone()),
+            auth_headers(&ops.token),
+        )
+        .await
+        .unwrap()
+        .0;
+        assert_eq!(detail.active_sessions, 1);
+        assert!(!detail.user.disabled);
+
+        // Disabling ends the session and is checked on every auth path.
+        let _ = admin_disable_user(
+            State(state.clone()),
+            Path(alice.user_id.clone()),
+            auth_headers(&ops.token),
+        )
+        .await
+        .unwrap();
+        let alice_login = || {
+            login(
+                State(state.clone()),
+                ConnectInfo(TEST_ADDR),
+                HeaderMap::new(),
+                Json(LoginRequest {
+                    email: "alice@example.com".to_string(),
+                    password: "change-me".to_string(),
+                }),
+            )
+        };
+        assert!(matches!(
+            alice_login().await,
+            Err(AppError::AccountDisabled)
+        ));
+        assert!(
+            crate::auth::authenticate_bearer(&state, &alice.token, None, None)
+                .await
+                .is_err()
+        );
+        assert!(matches!(
+            crate::auth::authenticate_basic(
+                &state,
+                "alice@example.com",
+                "change-me",
+                "relay",
+                None
+            )
+            .await,
+            Err(AppError::AccountDisabled)
+        ));
+        assert!(matches!(
+            admin_disable_user(
+                State(state.clone()),
+                Path(ops.user_id.clone()),
+                auth_headers(&ops.token),
+            )
+            .await,
+            Err(AppError::BadRequest(_))
+        ));
+        let _ = admin_enable_user(
+            State(state.clone()),
+            Path(alice.user_id.clone()),
+            auth_headers(&ops.token),
+        )
+        .await
+        .unwrap();
+        expect_authenticated(alice_login().await.unwrap().0);
+
+        // Closing registration blocks sign-ups and is advertised.
+        let settings = admin_update_settings(
+            State(state.clone()),
+            auth_headers(&ops.token),
+            Json(UpdateInstanceSettingsRequest {
+                registration_mode: Some("closed".to_string()),
+                ..Default::default()
+            }),
+        )
+        .await
+        .unwrap()
+        .0;
+        assert_eq!(settings.registration_mode, "closed");
+        assert_eq!(
+            auth_providers(State(state.clone()))
+                .await
+                .unwrap()
+                .0
+                .registration,
+            "closed"
+        );
+        assert!(matches!(
+            register(
+                State(state.clone()),
+                ConnectInfo(TEST_ADDR),
+                HeaderMap::new(),
+                Json(RegisterRequest {
+                    email: "carol@example.com".to_string(),
+                    password: "change-me".to_string(),
+                    invite_code: None,
+                }),
+            )
+            .await,
+            Err(AppError::Forbidden)
+        ));
+    }
+
+    #[tokio::test]
+    async fn registration_modes_and_invite_codes() {
+        let data_dir = std::env::temp_dir().join(format!("lumina-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&data_dir).unwrap();
+        let state = state_with_config(Config {
+            registration_mode: "domains".to_string(),
+            registration_domains: vec!["acme.test".to_string()],
+            ..test_config(&data_dir)
+        })
+        .await;
+        let sign_up = |email: &str, invite_code: Option<&str>| {
+            register(
+                State(state.clone()),
+                ConnectInfo(TEST_ADDR),
+                HeaderMap::new(),
+                Json(RegisterRequest {
+                    email: email.to_string(),
+                    password: "change-me".to_string(),
+                    invite_code: invite_code.map(str::to_string),
+                }),
+            )
+        };
+
+        // Domain allow-list from config.
+        let owner = expect_authenticated(sign_up("owner@ACME.test", None).await.unwrap().0);
+        assert!(matches!(
+            sign_up("mallory@example.com", None).await,
+            Err(AppError::Forbidden)
+        ));
+
+        // Org admins mint codes that join the invitee to their org.
+        let org_id = db::create_organization(&state.pool, &owner.user_id, "Acme")
+            .await
+            .unwrap();
+        let created = create_org_invite_code(
+            State(state.clone()),
+            Path(org_id.clone()),
+            auth_headers(&owner.token),
+            Json(CreateInviteCodeRequest {
+                role: Some("guest".to_string()),
+                expires_in_days: Some(7),
+                ..Default::default()
+            }),
+        )
+        .await
+        .unwrap()
+        .0;
+        assert_eq!(created.invite.org_id.as_deref(), Some(org_id.as_str()));
+
+        // Invite-only: a code is required, and only works once.
+        db::set_setting(
+            &state.pool,
+            db::SETTING_REGISTRATION_MODE,
+            registration::MODE_INVITE,
+        )
+        .await
+        .unwrap();
+        assert!(matches!(
+            sign_up("partner@example.com", None).await,
+            Err(AppError::Forbidden)
+        ));
+        assert!(matches!(
+            sign_up("partner@example.com", Some("not-a-code")).await,
+            Err(AppError::BadRequest(_))
+        ));
+        // A failed registration hands the code back.
+        assert!(sign_up("owner@acme.test", Some(&created.code))
+            .await
+            .is_err());
+        let partner = expect_authenticated(
+            sign_up("partner@example.com", Some(&created.code))
+                .await
+                .unwrap()
+                .0,
+        );
+        assert_eq!(
+            db::get_org_member_role(&state.pool, &org_id, &partner.user_id)
+                .await
+                .unwrap()
+                .as_deref(),
+            Some("guest")
+        );
+        assert!(matches!(
+            sign_up("second@example.com", Some(&created.code)).await,
+            Err(AppError::BadRequest(_))
+        ));
+        // So does an emailed organization invitation, while it is pending.
+        let invitation = create_org_invitation(
+            State(state.clone()),
+            Path(org_id.clone()),
+            auth_headers(&owner.token),
+            Json(CreateOrgInvitationRequest {
+                email: Some("invited@example.com".to_string()),
+                role: "member".to_string(),
+                expires_in_days: None,
+            }),
+        )
+        .await
+        .unwrap()
+        .0;
+        let _ = revoke_org_invitation(
+            State(state.clone()),
+            Path((org_id.clone(), invitation.invitation.id.clone())),
+            auth_headers(&owner.token),
+        )
+        .await
+        .unwrap();
+        assert!(matches!(
+            sign_up("invited@example.com", None).await,
+            Err(AppError::Forbidden)
+        ));
+        let _ = create_org_invitation(
+            State(state.clone()),
+            Path(org_id.clone()),
+            auth_headers(&owner.token),
+            Json(CreateOrgInvitationRequest {
+                email: Some("invited@example.com".to_string()),
+                role: "member".to_string(),
+                expires_in_days: None,
+            }),
+        )
+        .await
+        .unwrap();
+        let _ = expect_authenticated(sign_up("Invited@example.com", None).await.unwrap().0);
+        let codes = list_org_invite_codes(
+            State(state.clone()),
+            Path(org_id.clone()),
+            auth_headers(&owner.token),
+        )
+        .await
+        .unwrap()
+        .0;
+        assert_eq!(codes[0].used_by.as_deref(), Some(partner.user_id.as_str()));
+        assert!(matches!(
+            revoke_invite_code(
+                State(state.clone()),
+                Path(created.invite.id.clone()),
+                auth_headers(&owner.token),
+            )
+            .await,
+            Err(AppError::Conflict(_))
+        ));
+
+        // Members who are not org admins cannot mint or revoke codes.
+        let spare = create_org_invite_code(
+            State(state.clone()),
+            Path(org_id.clone()),
+            auth_headers(&owner.token),
+            Json(CreateInviteCodeRequest::default()),
+        )
+        .await
+        .unwrap()
+        .0;
+        assert!(matches!(
+            revoke_invite_code(
+                State(state.clone()),
+                Path(spare.invite.id.clone()),
+                auth_headers(&partner.token),
+            )
+            .await,
+            Err(AppError::Forbidden)
+        ));
+        let _ = revoke_invite_code(
+            State(state.clone()),
+            Path(spare.invite.id.clone()),
+            auth_headers(&owner.token),
+        )
+        .await
+        .unwrap();
+        assert!(matches!(
+            sign_up("third@example.com", Some(&spare.code)).await,
+            Err(AppError::BadRequest(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn refresh_rotates_tokens_and_revokes_session_on_reuse() {
+        let state = test_state().await;
+        let user = register_user(&state, "rotate@example.com").await;
+
+        let rotated = refresh(
+            State(state.clone()),
+            ConnectInfo(TEST_ADDR),
+            HeaderMap::new(),
+            Json(RefreshRequest {
+                refresh_token: user.refresh_token.clone(),
+            }),
+        )
+        .await
+        .unwrap()
+        .0;
+        assert_ne!(rotated.refresh_token, user.refresh_token);
+        assert!(require_user(&state, &auth_headers(&rotated.token))
+            .await
+            .is_ok());
+
+        // Replaying the consumed refresh token revokes the whole session.
+        let replay = refresh(
+            State(state.clone()),
+            ConnectInfo(TEST_ADDR),
+            HeaderMap::new(),
+            Json(RefreshRequest {
+                refresh_token: user.refresh_token.clone(),
+            }),
+        )
+        .await;
+        assert!(matches!(replay, Err(AppError::Unauthorized)));
+        assert!(matches!(
+            require_user(&state, &auth_headers(&rotated.token)).await,
+            Err(AppError::Unauthorized)
+        ));
+        let after_reuse = refresh(
+            State(state.clone()),
+            ConnectInfo(TEST_ADDR),
+            HeaderMap::new(),
+            Json(RefreshRequest {
+                refresh_token: rotated.refresh_token,
+            }),
+        )
+        .await;
+        assert!(matches!(after_reuse, Err(AppError::Unauthorized)));
+    }
+
+    #[tokio::test]
+    async fn sessions_record_use_and_ended_ones_are_purged() {
+        let state = test_state().await;
+        let user = register_user(&state, "idle@example.com").await;
+        let session_id = crate::auth::authenticate_access_token(&state, &user.token)
+            .await
+            .unwrap()
+            .sid;
+        let session = || {
+            let pool = state.pool.clone();
+            let session_id = session_id.clone();
+            async move { db::get_session(&pool, &session_id).await.unwrap() }
+        };
+        let refresh_tokens = || {
+            let pool = state.pool.clone();
+            let session_id = session_id.clone();
+            async move {
+                sqlx::query_scalar::<_, i64>(
+                    "SELECT COUNT(*) FROM refresh_tokens WHERE session_id = ?1",
+                )
+                .bind(&session_id)
+                .fetch_one(&pool)
+                .await
+                .unwrap()
+            }
+        };
+
+        sqlx::query("UPDATE sessions SET last_used_at = 0 WHERE id = ?1")
+            .bind(&session_id)
+            .execute(&state.pool)
+            .await
+            .unwrap();
+        require_user(&state, &auth_headers(&user.token))
+            .await
+            .unwrap();
+        assert!(session().await.unwrap().last_used_at > 0);
+
+        // Live sessions keep their refresh tokens.
+        let now = chrono::Utc::now().timestamp();
+        assert_eq!(
+            db::purge_ended_sessions(&state.pool, now, now)
+                .await
+                .unwrap(),
+            0
+        );
+        assert_eq!(refresh_tokens().await, 1);
+
+        // An ended session loses its refresh tokens at once and is deleted
+        // once the retention period is over.
+        db::revoke_session(&state.pool, &session_id).await.unwrap();
+        assert_eq!(
+            db::purge_ended_sessions(&state.pool, now, now - 60)
+                .await
+                .unwrap(),
+            0
+        );
+        assert_eq!(refresh_tokens().await, 0);
+        assert!(session().await.is_some());
+        assert_eq!(
+            db::purge_ended_sessions(&state.pool, now + 60, now + 60)
+                .await
+                .unwrap(),
+            1
+        );
+        assert!(session().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn logout_all_revokes_every_session() {
+        let state = test_state().await;
+        let first = register_user(&state, "devices@example.com").await;
+        let second = expect_authenticated(
+            login(
+                State(state.clone()),
+                ConnectInfo(TEST_ADDR),
+                HeaderMap::new(),
+                Json(LoginRequest {
+                    email: "devices@example.com".to_string(),
+                    password: "change-me".to_string(),
+                }),
+            )
+            .await
+            .unwrap()
+            .0,
+        );
+
+        let sessions = list_sessions(State(state.clone()), auth_headers(&second.token))
+            .await
+            .unwrap()
+            .0;
+        assert_eq!(sessions.len(), 2);
+        assert_eq!(sessions.iter().filter(|s| s.current).count(), 1);
+
+        let _ = logout_all(State(state.clone()), auth_headers(&second.token))
+            .await
+            .unwrap();
+        for token in [&first.token, &second.token] {
+            assert!(matches!(
+                require_user(&state, &auth_headers(token)).await,
+                Err(AppError::Unauthorized)
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn two_factor_login_requires_code_and_blocks_basic_passwords() {
+        let state = test_state().await;
+        let user = register_user(&state, "mfa@example.com").await;
+        let headers = auth_headers(&user.token);
+
+        let setup = setup_two_factor(State(state.clone()), headers.clone())
+            .await
+            .unwrap()
+            .0;
+        assert!(setup.otpauth_uri.starts_with("otpauth://totp/Lumina:"));
+        let now = chrono::Utc::now().timestamp();
+        let confirmed = confirm_two_factor(
+            State(state.clone()),
+            headers.clone(),
+            Json(TotpCodeRequest {
+                code: crate::totp::code_at(&setup.secret, now),
+            }),
+        )
+        .await
+        .unwrap()
+        .0;
+        assert_eq!(confirmed.recovery_codes.len(), 10);
+
+        let login_as = |state: AppState| {
+            login(
+                State(state),
+                ConnectInfo(TEST_ADDR),
+                HeaderMap::new(),
+                Json(LoginRequest {
+                    email: "mfa@example.com".to_string(),
+                    password: "change-me".to_string(),
+                }),
+            )
+        };
+        let challenge = match login_as(state.clone()).await.unwrap().0 {
+            LoginResponse::MfaRequired(challenge) => challenge.challenge_token,
+            other => panic!("expected a challenge, got {other:?}"),
+        };
+        // The challenge token is not an access token, and neither is
+        // accepted as the other.
+        assert!(matches!(
+            require_user(&state, &auth_headers(&challenge)).await,
+            Err(AppError::Unauthorized)
+        ));
+        assert!(matches!(
+            decode_mfa_challenge(&state, &user.token).await,
+            Err(AppError::Unauthorized)
+        ));
+        let header = jsonwebtoken::decode_header(&challenge).unwrap();
+        assert_ne!(header.typ.as_deref(), Some(crate::auth::ACCESS_TOKEN.typ));
+
+        let second_step = |code: Option<String>, recovery_code: Option<String>| {
+            login_2fa(
+                State(state.clone()),
+                ConnectInfo(TEST_ADDR),
+                HeaderMap::new(),
+                Json(MfaLoginRequest {
+                    challenge_token: challenge.clone(),
+                    code,
+                    recovery_code,
+                }),
+            )
+        };
+        // The code used to confirm enrollment cannot be replayed.
+        let replayed = second_step(Some(crate::totp::code_at(&setup.secret, now)), None).await;
+        assert!(matches!(replayed, Err(AppError::Unauthorized)));
+        let session = second_step(Some(crate::totp::code_at(&setup.secret, now + 30)), None)
+            .await
+            .unwrap()
+            .0;
+        assert_eq!(session.user_id, user.user_id);
+
+        let recovery = confirmed.recovery_codes[0].clone();
+        assert!(second_step(None, Some(recovery.clone())).await.is_ok());
+        assert!(matches!(
+            second_step(None, Some(recovery)).await,
+            Err(AppError::Unauthorized)
+        ));
+
+        // Basic auth cannot prompt for a code, so the account password stops
+        // working there; app passwords are required instead.
+        assert!(matches!(
+            crate::auth::authenticate_basic(
+                &state,
+                "mfa@example.com",
+                "change-me",
+                crate::auth::SCOPE_DAV_READ,
+                None,
+            )
+            .await,
+            Err(AppError::Unauthorized)
+        ));
+    }
+
+    /// Pull the token out of the newest outbox email linking to `path`.
+    fn outbox_token(data_dir: &std::path::Path, path: &str) -> String {
+        let mut files: Vec<_> = std::fs::read_dir(data_dir.join("outbox"))
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        files.sort();
+        let needle = format!("{}?token=", path);
+        files
+            .iter()
+            .rev()
+            .map(|file| {
+                // Undo quoted-printable soft line breaks and escaped `=`.
+                std::fs::read_to_string(file)
+                    .unwrap()
+                    .replace("=\r\n", "")
+                    .replace("=3D", "=")
+            })
+            .find_map(|body| {
+                let start = body.find(&needle)? + needle.len();
+                Some(body[start..].split_whitespace().next().unwrap().to_string())
+            })
+            .expect("email with token in outbox")
+    }
+
+    #[tokio::test]
+    async fn org_roles_leaving_and_ownership_transfer_keep_invariants() {
+        let state = test_state().await;
+        let alice = register_user(&state, "alice@example.com").await;
+        let bob = register_user(&state, "bob@example.com").await;
+        let carol = register_user(&state, "carol@example.com").await;
+        let org_id = db::create_organization(&state.pool, &alice.user_id, "Acme")
+            .await
+            .unwrap();
+        db::add_org_member(&state.pool, &org_id, &bob.user_id, "member")
+            .await
+            .unwrap();
+        db::add_org_member(&state.pool, &org_id, &carol.user_id, "guest")
+            .await
+            .unwrap();
+        let set_role = |token: &str, user_id: &str, role: &str| {
+            update_member_role(
+                State(state.clone()),
+                Path((org_id.clone(), user_id.to_string())),
+                auth_headers(token),
+                Json(UpdateOrgMemberRequest {
+                    role: role.to_string(),
+                }),
+            )
+        };
+        let leave = |token: &str| {
+            leave_org(
+                State(state.clone()),
+                Path(org_id.clone()),
+                auth_headers(token),
+            )
+        };
+        let transfer = |token: &str, user_id: &str| {
+            transfer_org(
+                State(state.clone()),
+                Path(org_id.clone()),
+                auth_headers(token),
+                Json(TransferOrgRequest {
+                    user_id: user_id.to_string(),
+                }),
+            )
+        };
+
+        // The owner can be neither demoted, removed nor leave.
+        assert!(matches!(
+            set_role(&alice.token, &alice.user_id, "member").await,
+            Err(AppError::Conflict(_))
+        ));
+        assert!(matches!(
+            remove_member(
+                State(state.clone()),
+                Path((org_id.clone(), alice.user_id.clone())),
+                auth_headers(&alice.token),
+            )
+            .await,
+            Err(AppError::Conflict(_))
+        ));
+        assert!(matches!(
+            leave(&alice.token).await,
+            Err(AppError::Conflict(_))
+        ));
+
+        assert!(matches!(
+            set_role(&bob.token, &carol.user_id, "member").await,
+            Err(AppError::Forbidden)
+        ));
+        let _ = set_role(&alice.token, &bob.user_id, "admin").await.unwrap();
+        let _ = leave(&carol.token).await.unwrap();
+        assert!(matches!(
+            transfer(&bob.token, &bob.user_id).await,
+            Err(AppError::Forbidden)
+        ));
+        assert!(matches!(
+            transfer(&alice.token, &carol.user_id).await,
+            Err(AppError::BadRequest(_))
+        ));
+
+        let _ = transfer(&alice.token, &bob.user_id).await.unwrap();
+        let (_, _, owner_id) = db::get_organization(&state.pool, &org_id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(owner_id, bob.user_id);
+        let _ = set_role(&bob.token, &alice.user_id, "member")
+            .await
+            .unwrap();
+        let _ = leave(&alice.token).await.unwrap();
+        // Bob is now the owner and the only admin.
+        assert!(matches!(
+            set_role(&bob.token, &bob.user_id, "member").await,
+            Err(AppError::Conflict(_))
+        ));
+        assert!(matches!(
+            leave(&bob.token).await,
+            Err(AppError::Conflict(_))
+        ));
+
+        let changes = db::list_audit_events(
+            &state.pool,
+            &db::AuditFilter {
+                org_id: Some(org_id.clone()),
+                action: Some(audit::ORG_MEMBER_ROLE_CHANGED.to_string()),
+                limit: 10,
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(changes.len(), 2);
+        assert_eq!(changes[0].details["to"], "member");
+    }
+
+    #[tokio::test]
+    async fn deleted_org_can_be_restored_until_purged() {
+        let data_dir = std::env::temp_dir().join(format!("lumina-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(data_dir.join("collab")).unwrap();
+        let state = state_with_config(test_config(&data_dir)).await;
+        let alice = register_user(&state, "alice@example.com").await;
+        let bob = register_user(&state, "bob@example.com").await;
+        let org_id = db::create_organization(&state.pool, &alice.user_id, "Acme")
+            .await
+            .unwrap();
+        db::add_org_member(&state.pool, &org_id, &bob.user_id, "member")
+            .await
+            .unwrap();
+        let project_id = db::create_project(&state.pool, &org_id, "Docs", "")
+            .await
+            .unwrap();
+        db::create_task(
+            &state.pool,
+            &project_id,
+            None,
+            "Write",
+            "",
+            "todo",
+            "medium",
+            Some(&bob.user_id),
+            None,
+            None,
+            &alice.user_id,
+        )
+        .await
+        .unwrap();
+        let doc_id = db::resolve_or_create_doc(&state.pool, &project_id, "a.md", &alice.user_id)
+            .await
+            .unwrap();
+        let collab_file = data_dir.join("collab").join(format!("{}.bin", doc_id));
+        std::fs::write(&collab_file, b"state").unwrap();
+        db::create_notification(&state.pool, &bob.user_id, &org_id, "task", "t", "", "")
+            .await
+            .unwrap();
+
+        let delete = |token: &str| {
+            delete_org(
+                State(state.clone()),
+                Path(org_id.clone()),
+                auth_headers(token),
+            )
+        };
+        let restore = |token: &str| {
+            restore_org(
+                State(state.clone()),
+                Path(org_id.clone()),
+                auth_headers(token),
+            )
+        };
+        assert!(matches!(delete(&bob.token).await, Err(AppError::Forbidden)));
+        let _ = delete(&alice.token).await.unwrap();
+        assert!(matches!(
+            get_org(
+                State(state.clone()),
+                Path(org_id.clone()),
+                auth_headers(&alice.token),
+            )
+            .await,
+            Err(AppError::Forbidden)
+        ));
+        assert!(list_orgs(State(state.clone()), auth_headers(&bob.token))
+            .await
+            .unwrap()
+            .0
+            .is_empty());
+        let trash = list_deleted_orgs(State(state.clone()), auth_headers(&alice.token))
+            .await
+            .unwrap()
+            .0;
+        assert_eq!(trash.len(), 1);
+        assert_eq!(
+            trash[0].purge_at - trash[0].deleted_at,
+            state.config.org_deletion_grace_secs
+        );
+
+        // Any admin can undo the deletion during the grace period.
+        assert!(matches!(
+            restore(&bob.token).await,
+            Err(AppError::Forbidden)
+        ));
+        let _ = restore(&alice.token).await.unwrap();
+        assert_eq!(
+            list_orgs(State(state.clone()), auth_headers(&bob.token))
+                .await
+                .unwrap()
+                .0
+                .len(),
+            1
+        );
+
+        let _ = delete(&alice.token).await.unwrap();
+        let now = chrono::Utc::now().timestamp();
+        assert_eq!(crate::orgs::purge_expired(&state, now).await.unwrap(), 0);
+        let after_grace = now + state.config.org_deletion_grace_secs + 1;
+        assert_eq!(
+            crate::orgs::purge_expired(&state, after_grace)
+                .await
+                .unwrap(),
+            1
+        );
+        assert!(!collab_file.exists());
+        for table in [
+            "organizations",
+            "org_members",
+            "projects",
+            "tasks",
+            "document_registry",
+            "notifications",
+        ] {
+            let count: i64 = sqlx::query_scalar(&format!("SELECT COUNT(*) FROM {}", table))
+                .fetch_one(&state.pool)
+                .await
+                .unwrap();
+            assert_eq!(count, 0, "{} not purged", table);
+        }
+        assert!(matches!(
+            restore(&alice.token).await,
+            Err(AppError::NotFound)
+        ));
+    }
+
+    #[tokio::test]
+    async fn org_invitations_by_email_and_join_link() {
+        let data_dir = std::env::temp_dir().join(format!("lumina-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&data_dir).unwrap();
+        let state = state_with_config(test_config(&data_dir)).await;
+        let alice = register_user(&state, "alice@example.com").await;
+        let bob = register_user(&state, "bob@example.com").await;
+        let org_id = db::create_organization(&state.pool, &alice.user_id, "Acme")
+            .await
+            .unwrap();
+        let invite = |email: Option<&str>, role: &str| {
+            create_org_invitation(
+                State(state.clone()),
+                Path(org_id.clone()),
+                auth_headers(&alice.token),
+                Json(CreateOrgInvitationRequest {
+                    email: email.map(str::to_string),
+                    role: role.to_string(),
+                    expires_in_days: None,
+                }),
+            )
+        };
+        let role_of = |user_id: String| {
+            let state = state.clone();
+            let org_id = org_id.clone();
+            async move {
+                db::get_org_member_role(&state.pool, &org_id, &user_id)
+                    .await
+                    .unwrap()
+            }
+        };
+
+        // Unknown addresses get an invitation that signing up accepts.
+        let added = add_member(
+            State(state.clone()),
+            Path(org_id.clone()),
+            auth_headers(&alice.token),
+            Json(AddOrgMemberRequest {
+                email: "newbie@example.com".to_string(),
+                role: "member".to_string(),
+            }),
+        )
+        .await
+        .unwrap()
+        .0;
+        assert!(added["invitation_id"].is_string());
+        let join_token = outbox_token(&data_dir, "/join");
+        assert!(!join_token.is_empty());
+        // Signing up with the address is not enough while it is unverified;
+        // the emailed link is.
+        let newbie = register_user(&state, "newbie@example.com").await;
+        assert_eq!(role_of(newbie.user_id.clone()).await, None);
+        let _ = join_org_with_token(
+            State(state.clone()),
+            auth_headers(&newbie.token),
+            Json(JoinOrgRequest { token: join_token }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            role_of(newbie.user_id.clone()).await.as_deref(),
+            Some("member")
+        );
+
+        // A verified address accepts its invitations on sign-in.
+        let _ = invite(Some("carol@example.com"), "member").await.unwrap();
+        let carol = register_user(&state, "carol@example.com").await;
+        assert_eq!(role_of(carol.user_id.clone()).await, None);
+        db::mark_email_verified(&state.pool, &carol.user_id)
+            .await
+            .unwrap();
+        let _ = login(
+            State(state.clone()),
+            ConnectInfo(TEST_ADDR),
+            HeaderMap::new(),
+            Json(LoginRequest {
+                email: "carol@example.com".to_string(),
+                password: "change-me".to_string(),
+            }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            role_of(carol.user_id.clone()).await.as_deref(),
+            Some("member")
+        );
+
+        // Verifying the address accepts them too.
+        let _ = invite(Some("dan@example.com"), "guest").await.unwrap();
+        let dan = register_user(&state, "dan@example.com").await;
+        assert_eq!(role_of(dan.user_id.clone()).await, None);
+        let _ = verify_email(
+            State(state.clone()),
+            ConnectInfo(TEST_ADDR),
+            HeaderMap::new(),
+            Json(VerifyEmailRequest {
+                token: outbox_token(&data_dir, "/verify-email"),
+            }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(role_of(dan.user_id.clone()).await.as_deref(), Some("guest"));
+
+        // Existing users are notified, and can decline or accept once their
+        // address is verified.
+        let declined = invite(Some("bob@example.com"), "guest").await.unwrap().0;
+        let notifications = db::list_notifications(&state.pool, &bob.user_id, 10)
+            .await
+            .unwrap();
+        assert_eq!(notifications.len(), 1);
+        let my_invitations = || list_my_invitations(State(state.clone()), auth_headers(&bob.token));
+        assert!(my_invitations().await.unwrap().0.is_empty());
+        assert!(matches!(
+            accept_org_invitation(
+                State(state.clone()),
+                Path(declined.invitation.id.clone()),
+                auth_headers(&bob.token),
+            )
+            .await,
+            Err(AppError::Forbidden)
+        ));
+        db::mark_email_verified(&state.pool, &bob.user_id)
+            .await
+            .unwrap();
+        let pending = my_invitations().await.unwrap().0;
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].org_name, "Acme");
+        let respond = |id: &str, accept: bool| {
+            let state = state.clone();
+            let id = id.to_string();
+            let headers = auth_headers(&bob.token);
+            async move {
+                if accept {
+                    accept_org_invitation(State(state), Path(id), headers)
+                        .await
+                        .map(|_| ())
+                } else {
+                    decline_org_invitation(State(state), Path(id), headers)
+                        .await
+                        .map(|_| ())
+                }
+            }
+        };
+        respond(&declined.invitation.id, false).await.unwrap();
+        assert!(matches!(
+            respond(&declined.invitation.id, true).await,
+            Err(AppError::Conflict(_))
+        ));
+        assert_eq!(role_of(bob.user_id.clone()).await, None);
+        let accepted = invite(Some("bob@example.com"), "member").await.unwrap().0;
+        // An emailed token only works for its addressee.
+        assert!(matches!(
+            join_org_with_token(
+                State(state.clone()),
+                auth_headers(&newbie.token),
+                Json(JoinOrgRequest {
+                    token: accepted.token.clone(),
+                }),
+            )
+            .await,
+            Err(AppError::Forbidden)
+        ));
+        respond(&accepted.invitation.id, true).await.unwrap();
+        assert_eq!(
+            role_of(bob.user_id.clone()).await.as_deref(),
+            Some("member")
+        );
+        assert!(matches!(
+            invite(Some("bob@example.com"), "guest").await,
+            Err(AppError::Conflict(_))
+        ));
+
+        // Shareable links: never admin, reusable until revoked.
+        assert!(matches!(
+            invite(None, "admin").await,
+            Err(AppError::BadRequest(_))
+        ));
+        let link = invite(None, "guest").await.unwrap().0;
+        assert!(link
+            .join_url
+            .ends_with(&format!("/join?token={}", link.token)));
+        let join = |token: String| {
+            let state = state.clone();
+            async move {
+                join_org_with_token(
+                    State(state.clone()),
+                    auth_headers(
+                        &register_user(&state, &format!("{}@example.com", uuid::Uuid::new_v4()))
+                            .await
+                            .token,
+                    ),
+                    Json(JoinOrgRequest { token }),
+                )
+                .await
+            }
+        };
+        assert_eq!(join(link.token.clone()).await.unwrap().0.role, "guest");
+        assert_eq!(join(link.token.clone()).await.unwrap().0.role, "guest");
+        let _ = revoke_org_invitation(
+            State(state.clone()),
+            Path((org_id.clone(), link.invitation.id.clone())),
+            auth_headers(&alice.token),
+        )
+        .await
+        .unwrap();
+        assert!(matches!(
+            join(link.token.clone()).await,
+            Err(AppError::BadRequest(_))
+        ));
+
+        let mut statuses: Vec<String> = list_org_invitations(
+            State(state.clone()),
+            Path(org_id.clone()),
+            auth_headers(&alice.token),
+        )
+        .await
+        .unwrap()
+        .0
+        .into_iter()
+        .map(|invitation| invitation.status)
+        .collect();
+        statuses.sort();
+        assert_eq!(
+            statuses,
+            ["accepted", "accepted", "accepted", "accepted", "declined", "revoked"]
+        );
+    }
+
+    #[tokio::test]
+    async fn email_verification_and_password_reset_flow() {
+        let data_dir = std::env::temp_dir().join(format!("lumina-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&data_dir).unwrap();
+        let state = state_with_config(Config {
+            require_email_verification: true,
+            ..test_config(&data_dir)
+        })
+        .await;
+        let login_with = |password: &str| {
+            login(
+                State(state.clone()),
+                ConnectInfo(TEST_ADDR),
+                HeaderMap::new(),
+                Json(LoginRequest {
+                    email: "reset@example.com".to_string(),
+                    password: password.to_string(),
+                }),
+            )
+        };
+
+        let registered = register(
+            State(state.clone()),
+            ConnectInfo(TEST_ADDR),
+            HeaderMap::new(),
+            Json(RegisterRequest {
+                email: "reset@example.com".to_string(),
+                password: "change-me".to_string(),
+                invite_code: None,
+            }),
+        )
+        .await
+        .unwrap()
+        .0;
+        assert!(matches!(registered, LoginResponse::VerificationRequired(_)));
+        assert!(matches!(
+            login_with("change-me").await.unwrap().0,
+            LoginResponse::VerificationRequired(_)
+        ));
+
+        let token = outbox_token(&data_dir, "/verify-email");
+        let _ = verify_email(
+            State(state.clone()),
+            ConnectInfo(TEST_ADDR),
+            HeaderMap::new(),
+            Json(VerifyEmailRequest {
+                token: token.clone(),
+            }),
+        )
+        .await
+        .unwrap();
+        assert!(matches!(
+            verify_email(
+                State(state.clone()),
+                ConnectInfo(TEST_ADDR),
+                HeaderMap::new(),
+                Json(VerifyEmailRequest { token }),
+            )
+            .await,
+            Err(AppError::BadRequest(_))
+        ));
+        let first = expect_authenticated(login_with("change-me").await.unwrap().0);
+        let second = expect_authenticated(login_with("change-me").await.unwrap().0);
+        assert!(first.user.email_verified);
+
+        // Changing the password keeps the current session and ends the others.
+        let _ = change_password(
+            State(state.clone()),
+            auth_headers(&first.token),
+            Json(ChangePasswordRequest {
+                current_password: "change-me".to_string(),
+                new_password: "changed-once".to_string(),
+            }),
+        )
+        .await
+        .unwrap();
+        assert!(require_user(&state, &auth_headers(&first.token))
+            .await
+            .is_ok());
+        assert!(matches!(
+            require_user(&state, &auth_headers(&second.token)).await,
+            Err(AppError::Unauthorized)
+        ));
+
+        // Unknown addresses get the same answer as registered ones.
+        for email in ["reset@example.com", "nobody@example.com"] {
+            let _ = forgot_password(
+                State(state.clone()),
+                ConnectInfo(TEST_ADDR),
+                HeaderMap::new(),
+                Json(EmailRequest {
+                    email: email.to_string(),
+                }),
+            )
+            .await
+            .unwrap();
+        }
+        let reset = |token: String| {
+            reset_password(
+                State(state.clone()),
+                ConnectInfo(TEST_ADDR),
+                HeaderMap::new(),
+                Json(ResetPasswordRequest {
+                    token,
+                    new_password: "changed-twice".to_string(),
+                }),
+            )
+        };
+        let token = outbox_token(&data_dir, "/reset-password");
+        assert!(reset(token.clone()).await.is_ok());
+        assert!(matches!(reset(token).await, Err(AppError::BadRequest(_))));
+        assert!(matches!(
+            require_user(&state, &auth_headers(&first.token)).await,
+            Err(AppError::Unauthorized)
+        ));
+        assert!(matches!(
+            login_with("changed-once").await,
+            Err(AppError::Unauthorized)
+        ));
+        assert!(login_with("changed-twice").await.is_ok());
+    }
+
+    /// RSA key of the mock identity provider; the matching JWK modulus is
+    /// `MOCK_IDP_KEY_N`.
+    const MOCK_IDP_KEY: &str = include_str!("testdata/oidc_rsa_key.pem");
+    const MOCK_IDP_KEY_N: &str = "kSbbUWp2DUrZu8KvCKxr5SYfMwwpaWiVMUdAeIFqJJVCPiLLa0S4rNqQHpDqxTFf-RpFIAfyozeizzbzNCkPT1FC_rhG09gusoy7b_SxkB5r13UWuSBe9ZTQ1oXWtocB6z8VTiuU6zB5CfyvdzEAA3JREDqOtWkIcbj--UA5QbZBc7T_mK_jdd0yIM0epphK_k9qv0cPuP-a9csZ93qmenGRDQj7iaVBJw3RBAgYr929m5yT3jJGKJrAWkzycCywYuOKuSkLtJlKwMgubpMtPd90QbtZRQvQZ-r5phAP_PTWKYf5qmx0tNRNNv-bU2fwdjJhFIkYD7EZumdS4rk1Ew";
+    const MOCK_IDP_CLIENT_ID: &str = "lumina-test";
+
+    /// Authorization code the mock provider will accept, with the ID token
+    /// claims it returns and the PKCE challenge it expects.
+    struct MockGrant {
+        claims: serde_json::Value,
+        code_challenge: String,
+    }
+
+    #[derive(Clone)]
+    struct MockIdp {
+        issuer: String,
+        grants: Arc<std::sync::Mutex<std::collections::HashMap<String, MockGrant>>>,
+    }
+
+    impl MockIdp {
+        async fn spawn() -> Self {
+            use axum::extract::Form;
+            use axum::routing::{get, post};
+            use base64::Engine as _;
+
+            let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            let idp = MockIdp {
+                issuer: format!("http://{}", listener.local_addr().unwrap()),
+                grants: Default::default(),
+            };
+            let discovery = {
+                let issuer = idp.issuer.clone();
+                move || async move {
+                    Json(json!({
+                        "issuer": issuer,
+                        "authorization_endpoint": format!("{}/authorize", issuer),
+                        "token_endpoint": format!("{}/token", issuer),
+                        "jwks_uri": format!("{}/jwks", issuer),
+                    }))
+                }
+            };
+            let jwks = || async {
+                Json(json!({ "keys": [{
+                    "kty": "RSA", "kid": "mock-key", "alg": "RS256", "use": "sig",
+                    "n": MOCK_IDP_KEY_N, "e": "AQAB",
+                }]}))
+            };
+            let token = {
+                let idp = idp.clone();
+                move |Form(form): Form<std::collections::HashMap<String, String>>| async move {
+                    let grant = idp.grants.lock().unwrap().remove(&form["code"]);
+                    let Some(grant) = grant else {
+                        return Err(StatusCode::BAD_REQUEST);
+                    };
+                    let challenge = base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(
+                        <sha2::Sha256 as sha2::Digest>::digest(form["code_verifier"].as_bytes()),
+                    );
+                    if challenge != grant.code_challenge || form["client_id"] != MOCK_IDP_CLIENT_ID
+                    {
+                        return Err(StatusCode::BAD_REQUEST);
+                    }
+                    let mut header = jsonwebtoken::Header::new(jsonwebtoken::Algorithm::RS256);
+                    header.kid = Some("mock-key".to_string());
+                    let key =
+                        jsonwebtoken::EncodingKey::from_rsa_pem(MOCK_IDP_KEY.as_bytes()).unwrap();
+                    let id_token = jsonwebtoken::encode(&header, &grant.claims, &key).unwrap();
+                    Ok(Json(
+                        json!({ "access_token": "unused", "id_token": id_token }),
+                    ))
+                }
+            };
+            let app = axum::Router::new()
+                .route("/.well-known/openid-configuration", get(discovery))
+                .route("/jwks", get(jwks))
+                .route("/token", post(token));
+            let server = axum::Server::from_tcp(listener)
+                .unwrap()
+                .serve(app.into_make_service());
+            tokio::spawn(server);
+            idp
+        }
+
+        /// Play the user signing in at the provider: issue a code for the
+        /// login started at `authorization_url`.
+        fn sign_in(
+            &self,
+            authorization_url: &str,
+            sub: &str,
+            email: &str,
+            verified: bool,
+        ) -> (String, String) {
+            let params: std::collections::HashMap<&str, &str> = authorization_url
+                .split_once('?')
+                .unwrap()
+                .1
+                .split('&')
+                .filter_map(|pair| pair.split_once('='))
+                .collect();
+            let code = uuid::Uuid::new_v4().to_string();
+            let claims = json!({
+                "iss": self.issuer,
+                "sub": sub,
+                "aud": MOCK_IDP_CLIENT_ID,
+                "exp": chrono::Utc::now().timestamp() + 300,
+                "iat": chrono::Utc::now().timestamp(),
+                "email": email,
+                "email_verified": verified,
+                "nonce": params["nonce"],
+            });
+            self.grants.lock().unwrap().insert(
+                code.clone(),
+                MockGrant {
+                    claims,
+                    code_challenge: params["code_challenge"].to_string(),
+                },
+            );
+            (params["state"].to_string(), code)
+        }
+    }
+
+    #[tokio::test]
+    async fn oidc_login_provisions_links_and_replaces_password_login() {
+        let idp = MockIdp::spawn().await;
+        let data_dir = std::env::temp_dir().join(format!("lumina-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&data_dir).unwrap();
+        let state = state_with_config(Config {
+            oidc: Some(crate::config::OidcConfig {
+                issuer: idp.issuer.clone(),
+                client_id: MOCK_IDP_CLIENT_ID.to_string(),
+                client_secret: None,
+                redirect_url: "http://lumina.test/auth/oidc/callback".to_string(),
+                scopes: "openid email".to_string(),
+                provider_name: "Mock IdP".to_string(),
+                auto_provision: true,
+            }),
+            password_login_enabled: false,
+            ..test_config(&data_dir)
+        })
+        .await;
+        let existing = db::create_user(
+            &state.pool,
+            "bob@example.com",
+            &hash_password("bob-password").unwrap(),
+        )
+        .await
+        .unwrap();
+        db::mark_email_verified(&state.pool, &existing)
+            .await
+            .unwrap();
+
+        let providers = auth_providers(State(state.clone())).await.unwrap().0;
+        assert!(!providers.password);
+        assert_eq!(providers.oidc.unwrap().name, "Mock IdP");
+
+        let respond = |sub: &'static str, email: &'static str, verified: bool| {
+            let state = state.clone();
+            let idp = idp.clone();
+            async move {
+                let url = oidc_authorize(State(state.clone()))
+                    .await
+                    .unwrap()
+                    .0
+                    .authorization_url;
+                let (login_state, code) = idp.sign_in(&url, sub, email, verified);
+                let callback = OidcCallbackRequest {
+                    state: login_state,
+                    code: Some(code),
+                    error: None,
+                    error_description: None,
+                };
+                oidc_callback(
+                    State(state),
+                    ConnectInfo(TEST_ADDR),
+                    HeaderMap::new(),
+                    Json(callback),
+                )
+                .await
+                .map(|response| response.0)
+            }
+        };
+        let sign_in = |sub: &'static str, email: &'static str, verified: bool| {
+            let response = respond(sub, email, verified);
+            async move { response.await.map(expect_authenticated) }
+        };
+
+        // First sign-in provisions an account; later ones match on `sub`
+        // even if the email at the provider changed.
+        let alice = sign_in("alice-sub", "alice@example.com", true)
+            .await
+            .unwrap();
+        assert_eq!(alice.user.email, "alice@example.com");
+        assert!(alice.user.email_verified);
+        assert_eq!(alice.workspaces.len(), 1);
+        let again = sign_in("alice-sub", "alice@new.example.com", false)
+            .await
+            .unwrap();
+        assert_eq!(again.user_id, alice.user_id);
+
+        // A verified email links to the existing account.
+        let bob = sign_in("bob-sub", "bob@example.com", true).await.unwrap();
+        assert_eq!(bob.user_id, existing);
+        assert!(db::user_has_password(&state.pool, &existing).await.unwrap());
+
+        // An account registered under someone's address without confirming
+        // it loses what its registrant set up once the owner signs in.
+        let squatter = db::create_user(
+            &state.pool,
+            "dave@example.com",
+            &hash_password("squatter-password").unwrap(),
+        )
+        .await
+        .unwrap();
+        let squatter_session = crate::auth::start_session(&state, &squatter, "laptop", None)
+            .await
+            .unwrap();
+        db::create_app_token(
+            &state.pool,
+            "squatter-token",
+            &squatter,
+            "sync",
+            "personal_access_token",
+            "hash",
+            &[],
+            None,
+        )
+        .await
+        .unwrap();
+        db::set_pending_totp_secret(&state.pool, &squatter, "SECRET")
+            .await
+            .unwrap();
+        db::enable_totp(&state.pool, &squatter, 0).await.unwrap();
+        let dave = sign_in("dave-sub", "dave@example.com", true).await.unwrap();
+        assert_eq!(dave.user_id, squatter);
+        assert!(!db::user_has_password(&state.pool, &squatter).await.unwrap());
+        let (_, password_hash) = db::find_user_by_email(&state.pool, "dave@example.com")
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(!verify_password("squatter-password", &password_hash).unwrap());
+        assert!(matches!(
+            require_user(&state, &auth_headers(&squatter_session.access_token)).await,
+            Err(AppError::Unauthorized)
+        ));
+        assert!(db::list_app_tokens(&state.pool, &squatter)
+            .await
+            .unwrap()
+            .iter()
+            .all(|token| token.revoked_at.is_some()));
+
+        // TOTP still applies to single sign-on.
+        db::set_pending_totp_secret(&state.pool, &existing, "SECRET")
+            .await
+            .unwrap();
+        db::enable_totp(&state.pool, &existing, 0).await.unwrap();
+        assert!(matches!(
+            respond("bob-sub", "bob@example.com", true).await,
+            Ok(LoginResponse::MfaRequired(_))
+        ));
+
+        // Unverified emails are never trusted for linking or provisioning.
+        assert!(matches!(
+            sign_in("carol-sub", "bob@example.com", false).await,
+            Err(AppError::BadRequest(_))
+        ));
+
+        // Login states are single-use.
+        let url = oidc_authorize(State(state.clone()))
+            .await
+            .unwrap()
+            .0
+            .authorization_url;
+        let (login_state, code) = idp.sign_in(&url, "alice-sub", "alice@example.com", true);
+        let callback = |code: String| OidcCallbackRequest {
+            state: login_state.clone(),
+            code: Some(code),
+            error: None,
+            error_description: None,
+        };
+        assert!(oidc_callback(
+            State(state.clone()),
+            ConnectInfo(TEST_ADDR),
+            HeaderMap::new(),
+            Json(callback(code.clone())),
+        )
+        .await
+        .is_ok());
+        assert!(matches!(
+            oidc_callback(
+                State(state.clone()),
+                ConnectInfo(TEST_ADDR),
+                HeaderMap::new(),
+                Json(callback(code)),
+            )
+            .await,
+            Err(AppError::BadRequest(_))
+        ));
+
+        assert!(matches!(
+            login(
+                State(state.clone()),
+                ConnectInfo(TEST_ADDR),
+                HeaderMap::new(),
+                Json(LoginRequest {
+                    email: "alice@example.com".to_string(),
+                    password: "whatever".to_string(),
+                }),
+            )
+            .await,
+            Err(AppError::Forbidden)
+        ));
+
+        // Deleting an account takes its password if it has one, and
+        // otherwise a recent sign-in at the provider.
+        let delete_as = |token: &str, email: &str| {
+            delete_account(
+                State(state.clone()),
+                auth_headers(token),
+                Json(DeleteAccountRequest {
+                    confirm_email: email.to_string(),
+                    password: None,
+                }),
+            )
+        };
+        assert!(matches!(
+            delete_as(&bob.token, "bob@example.com").await,
+            Err(AppError::BadRequest(_))
+        ));
+        sqlx::query("UPDATE sessions SET created_at = created_at - 3600")
+            .execute(&state.pool)
+            .await
+            .unwrap();
+        assert!(matches!(
+            delete_as(&again.token, "alice@example.com").await,
+            Err(AppError::BadRequest(_))
+        ));
+        let fresh = sign_in("alice-sub", "alice@example.com", true)
+            .await
+            .unwrap();
+        assert!(delete_as(&fresh.token, "alice@example.com").await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn archived_projects_are_read_only_and_deletion_cascades() {
+        let data_dir = std::env::temp_dir().join(format!("lumina-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(data_dir.join("collab")).unwrap();
+        let state = state_with_config(test_config(&data_dir)).await;
+        let alice = register_user(&state, "alice@example.com").await;
+        let guest = register_user(&state, "guest@example.com").await;
+        let org_id = db::create_organization(&state.pool, &alice.user_id, "Acme")
+            .await
+            .unwrap();
+        db::add_org_member(&state.pool, &org_id, &guest.user_id, "guest")
+            .await
+            .unwrap();
+        let project_id = db::create_project(&state.pool, &org_id, "Docs", "")
+            .await
+            .unwrap();
+        let new_task = || CreateTaskRequest {
+            parent_id: None,
+            title: "Write".to_string(),
+            description: None,
+            status: None,
+            priority: None,
+            assignee_id: None,
+            due_date: None,
+            start_date: None,
+        };
+        let task = create_task(
+            State(state.clone()),
+            Path(project_id.clone()),
+            auth_headers(&alice.token),
+            Json(new_task()),
+        )
+        .await
+        .unwrap()
+        .0;
+        let doc_id = db::resolve_or_create_doc(&state.pool, &project_id, "a.md", &alice.user_id)
+            .await
+            .unwrap();
+        let collab_file = data_dir.join("collab").join(format!("{}.bin", doc_id));
+        std::fs::write(&collab_file, b"state").unwrap();
+
+        let update = |token: &str, payload: UpdateProjectRequest| {
+            update_project(
+                State(state.clone()),
+                Path(project_id.clone()),
+                auth_headers(token),
+                Json(payload),
+            )
+        };
+        let list = |include_archived: bool| {
+            list_org_projects(
+                State(state.clone()),
+                Path(org_id.clone()),
+                auth_headers(&alice.token),
+                Query(ProjectListQuery { include_archived }),
+            )
+        };
+        let archive = |archived: bool| UpdateProjectRequest {
+            name: None,
+            description: None,
+            archived: Some(archived),
+        };
+
+        assert!(matches!(
+            update(&guest.token, archive(true)).await,
+            Err(AppError::Forbidden)
+        ));
+        let renamed = update(
+            &alice.token,
+            UpdateProjectRequest {
+                name: Some(" Handbook ".to_string()),
+                description: Some("Team docs".to_string()),
+                archived: None,
+            },
+        )
+        .await
+        .unwrap()
+        .0;
+        assert_eq!(renamed.name, "Handbook");
+        assert_eq!(renamed.description, "Team docs");
+
+        assert!(
+            update(&alice.token, archive(true))
+                .await
+                .unwrap()
+                .0
+                .archived
+        );
+        assert!(list(false).await.unwrap().0.is_empty());
+        assert_eq!(list(true).await.unwrap().0.len(), 1);
+        assert!(matches!(
+            create_task(
+                State(state.clone()),
+                Path(project_id.clone()),
+                auth_headers(&alice.token),
+                Json(new_task()),
+            )
+            .await,
+            Err(AppError::Conflict(_))
+        ));
+        assert!(matches!(
+            delete_task_handler(
+                State(state.clone()),
+                Path(task.id.clone()),
+                auth_headers(&alice.token),
+            )
+            .await,
+            Err(AppError::Conflict(_))
+        ));
+        assert!(
+            !update(&alice.token, archive(false))
+                .await
+                .unwrap()
+                .0
+                .archived
+        );
+        assert_eq!(list(false).await.unwrap().0.len(), 1);
+
+        assert!(matches!(
+            delete_project(
+                State(state.clone()),
+                Path(project_id.clone()),
+                auth_headers(&guest.token),
+            )
+            .await,
+            Err(AppError::Forbidden)
+        ));
+        let _ = delete_project(
+            State(state.clone()),
+            Path(project_id.clone()),
+            auth_headers(&alice.token),
+        )
+        .await
+        .unwrap();
+        assert!(!collab_file.exists());
+        for table in ["projects", "tasks", "document_registry"] {
+            let count: i64 = sqlx::query_scalar(&format!("SELECT COUNT(*) FROM {}", table))
+                .fetch_one(&state.pool)
+                .await
+                .unwrap();
+            assert_eq!(count, 0, "{} should be empty", table);
+        }
+        assert!(matches!(
+            update(&alice.token, archive(true)).await,
+            Err(AppError::NotFound)
+        ));
+    }
+
+    #[tokio::test]
+    async fn project_roles_scope_guests_and_make_viewers_read_only() {
+        let state = test_state().await;
+        let alice = register_user(&state, "alice@example.com").await;
+        let bob = register_user(&state, "bob@example.com").await;
+        let guest = register_user(&state, "guest@example.com").await;
+        let outsider = register_user(&state, "outsider@example.com").await;
+        let org_id = db::create_organization(&state.pool, &alice.user_id, "Acme")
+            .await
+            .unwrap();
+        db::add_org_member(&state.pool, &org_id, &bob.user_id, "member")
+            .await
+            .unwrap();
+        db::add_org_member(&state.pool, &org_id, &guest.user_id, "guest")
+            .await
+            .unwrap();
+        let shared = db::create_project(&state.pool, &org_id, "Shared", "")
+            .await
+            .unwrap();
+        let internal = db::create_project(&state.pool, &org_id, "Internal", "")
+            .await
+            .unwrap();
+
+        let list = |token: &str| {
+            list_org_projects(
+                State(state.clone()),
+                Path(org_id.clone()),
+                auth_headers(token),
+                Query(ProjectListQuery::default()),
+            )
+        };
+        let new_task = |token: &str, project_id: &str| {
+            create_task(
+                State(state.clone()),
+                Path(project_id.to_string()),
+                auth_headers(token),
+                Json(CreateTaskRequest {
+                    parent_id: None,
+                    title: "Write".to_string(),
+                    description: None,
+                    status: None,
+                    priority: None,
+                    assignee_id: None,
+                    due_date: None,
+                    start_date: None,
+                }),
+            )
+        };
+        let set_role = |token: &str, project_id: &str, user_id: &str, role: &str| {
+            set_project_member(
+                State(state.clone()),
+                Path((project_id.to_string(), user_id.to_string())),
+                auth_headers(token),
+                Json(SetProjectMemberRequest {
+                    role: role.to_string(),
+                }),
+            )
+        };
+        let annotate = |token: &str, doc_path: &str, project_id: Option<&str>| {
+            create_annotation_handler(
+                State(state.clone()),
+                Path(org_id.clone()),
+                auth_headers(token),
+                Json(CreateAnnotationRequest {
+                    doc_path: doc_path.to_string(),
+                    project_id: project_id.map(str::to_string),
+                    range_start: 0,
+                    range_end: 1,
+                    content: "note".to_string(),
+                }),
+            )
+        };
+
+        // Guests see nothing until they are added to a project.
+        assert_eq!(list(&bob.token).await.unwrap().0.len(), 2);
+        assert!(list(&guest.token).await.unwrap().0.is_empty());
+        assert!(matches!(
+            new_task(&guest.token, &shared).await,
+            Err(AppError::Forbidden)
+        ));
+
+        assert!(matches!(
+            set_role(&alice.token, &shared, &guest.user_id, "owner").await,
+            Err(AppError::BadRequest(_))
+        ));
+        assert!(matches!(
+            set_role(&alice.token, &shared, &outsider.user_id, "viewer").await,
+            Err(AppError::BadRequest(_))
+        ));
+        let _ = set_role(&alice.token, &shared, &guest.user_id, "viewer")
+            .await
+            .unwrap();
+        let visible = list(&guest.token).await.unwrap().0;
+        assert_eq!(visible.len(), 1);
+        assert_eq!(visible[0].id, shared);
+        assert!(list_project_tasks(
+            State(state.clone()),
+            Path(shared.clone()),
+            auth_headers(&guest.token),
+            Query(TaskListQuery::default()),
+        )
+        .await
+        .is_ok());
+        assert!(matches!(
+            list_project_tasks(
+                State(state.clone()),
+                Path(internal.clone()),
+                auth_headers(&guest.token),
+                Query(TaskListQuery::default()),
+            )
+            .await,
+            Err(AppError::Forbidden)
+        ));
+
+        // Viewers read but do not write.
+        assert!(matches!(
+            new_task(&guest.token, &shared).await,
+            Err(AppError::Forbidden)
+        ));
+        assert!(matches!(
+            annotate(&guest.token, "a.md", Some(&shared)).await,
+            Err(AppError::Forbidden)
+        ));
+        let _ = annotate(&bob.token, "a.md", Some(&shared)).await.unwrap();
+        let list_notes = |doc_path: &str, project_id: Option<&str>| {
+            list_annotations_handler(
+                State(state.clone()),
+                Path(org_id.clone()),
+                auth_headers(&guest.token),
+                Query(AnnotationQuery {
+                    doc_path: doc_path.to_string(),
+                    project_id: project_id.map(str::to_string),
+                }),
+            )
+        };
+        assert_eq!(list_notes("a.md", Some(&shared)).await.unwrap().0.len(), 1);
+
+        // Leaving out the project does not get around it: a registered
+        // document's annotations stay in its project, and org-level ones
+        // are closed to guests.
+        db::resolve_or_create_doc(&state.pool, &shared, "a.md", &alice.user_id)
+            .await
+            .unwrap();
+        assert!(matches!(
+            annotate(&guest.token, "a.md", None).await,
+            Err(AppError::Forbidden)
+        ));
+        assert_eq!(list_notes("a.md", None).await.unwrap().0.len(), 1);
+        assert!(matches!(
+            annotate(&guest.token, "loose.md", None).await,
+            Err(AppError::Forbidden)
+        ));
+        assert!(matches!(
+            list_notes("loose.md", None).await,
+            Err(AppError::Forbidden)
+        ));
+        let _ = annotate(&bob.token, "loose.md", None).await.unwrap();
+
+        // An explicit role also narrows a member's access.
+        let _ = set_role(&alice.token, &internal, &bob.user_id, "viewer")
+            .await
+            .unwrap();
+        assert!(matches!(
+            new_task(&bob.token, &internal).await,
+            Err(AppError::Forbidden)
+        ));
+        let _ = new_task(&bob.token, &shared).await.unwrap();
+
+        let _ = set_role(&alice.token, &shared, &guest.user_id, "contributor")
+            .await
+            .unwrap();
+        let _ = new_task(&guest.token, &shared).await.unwrap();
+        assert!(matches!(
+            set_role(&guest.token, &shared, &bob.user_id, "viewer").await,
+            Err(AppError::Forbidden)
+        ));
+        let members = list_project_members(
+            State(state.clone()),
+            Path(shared.clone()),
+            auth_headers(&guest.token),
+        )
+        .await
+        .unwrap()
+        .0;
+        assert_eq!(members.len(), 1);
+        assert_eq!(members[0].role, "contributor");
+
+        // Leaving the org drops project roles too.
+        db::remove_org_member(&state.pool, &org_id, &guest.user_id)
+            .await
+            .unwrap();
+        assert!(
+            db::get_project_member_role(&state.pool, &shared, &guest.user_id)
+                .await
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[tokio::test]
+    async fn task_labels_attach_filter_and_show_on_task_detail() {
+        let state = test_state().await;
+        let alice = register_user(&state, "alice@example.com").await;
+        let org_id = db::create_organization(&state.pool, &alice.user_id, "Acme")
+            .await
+            .unwrap();
+        let project_id = db::create_project(&state.pool, &org_id, "Docs", "")
+            .await
+            .unwrap();
+        let other_project = db::create_project(&state.pool, &org_id, "Other", "")
+            .await
+            .unwrap();
+        let mut task_ids = Vec::new();
+        for title in ["Write", "Review"] {
+            task_ids.push(
+                db::create_task(
+                    &state.pool,
+                    &project_id,
+                    None,
+                    title,
+                    "",
+                    "todo",
+                    "medium",
+                    None,
+                    None,
+                    None,
+                    &alice.user_id,
+                )
+                .await
+                .unwrap(),
+            );
+        }
+
+        let new_label = |project_id: &str, name: &str, color: Option<&str>| {
+            create_label(
+                State(state.clone()),
+                Path(project_id.to_string()),
+                auth_headers(&alice.token),
+                Json(CreateLabelRequest {
+                    name: name.to_string(),
+                    color: color.map(str::to_string),
+                }),
+            )
+        };
+        let labeled = |label: Option<&str>| {
+            list_project_tasks(
+                State(state.clone()),
+                Path(project_id.clone()),
+                auth_headers(&alice.token),
+                Query(TaskListQuery {
+                    label: label.map(str::to_string),
+                    ..Default::default()
+                }),
+            )
+        };
+        let attach = |task_id: &str, label_id: &str| {
+            attach_task_label(
+                State(state.clone()),
+                Path((task_id.to_string(), label_id.to_string())),
+                auth_headers(&alice.token),
+            )
+        };
+        let detail = |task_id: &str| {
+            get_task_handler(
+                State(state.clone()),
+                Path(task_id.to_string()),
+                auth_headers(&alice.token),
+            )
+        };
+
+        assert!(matches!(
+            new_label(&project_id, "Bug", Some("red")).await,
+            Err(AppError::BadRequest(_))
+        ));
+        let bug = new_label(&project_id, " Bug ", Some("#FF0000"))
+            .await
+            .unwrap()
+            .0;
+        assert_eq!(bug.name, "Bug");
+        assert_eq!(bug.color, "#ff0000");
+        assert!(matches!(
+            new_label(&project_id, "Bug", None).await,
+            Err(AppError::Conflict(_))
+        ));
+        let docs = new_label(&project_id, "Docs", None).await.unwrap().0;
+        assert_eq!(docs.color, DEFAULT_LABEL_COLOR);
+        let foreign = new_label(&other_project, "Bug", None).await.unwrap().0;
+
+        let _ = attach(&task_ids[0], &bug.id).await.unwrap();
+        let _ = attach(&task_ids[0], &bug.id).await.unwrap();
+        let _ = attach(&task_ids[0], &docs.id).await.unwrap();
+        let _ = attach(&task_ids[1], &docs.id).await.unwrap();
+        assert!(matches!(
+            attach(&task_ids[1], &foreign.id).await,
+            Err(AppError::NotFound)
+        ));
+
+        assert_eq!(labeled(None).await.unwrap().0.tasks.len(), 2);
+        let bugs = labeled(Some(&bug.id)).await.unwrap().0.tasks;
+        assert_eq!(bugs.len(), 1);
+        assert_eq!(bugs[0].id, task_ids[0]);
+        let task = detail(&task_ids[0]).await.unwrap().0;
+        let names: Vec<_> = task.labels.iter().map(|l| l.name.as_str()).collect();
+        assert_eq!(names, ["Bug", "Docs"]);
+
+        let _ = update_label(
+            State(state.clone()),
+            Path(bug.id.clone()),
+            auth_headers(&alice.token),
+            Json(UpdateLabelRequest {
+                name: Some("Defect".to_string()),
+                color: None,
+            }),
+        )
+        .await
+        .unwrap();
+        let _ = detach_task_label(
+            State(state.clone()),
+            Path((task_ids[0].clone(), docs.id.clone())),
+            auth_headers(&alice.token),
+        )
+        .await
+        .unwrap();
+        let task = detail(&task_ids[0]).await.unwrap().0;
+        assert_eq!(task.labels.len(), 1);
+        assert_eq!(task.labels[0].name, "Defect");
+        assert_eq!(task.labels[0].color, "#ff0000");
+
+        // Deleting a label or a task leaves no dangling task_labels rows.
+        let _ = delete_label(
+            State(state.clone()),
+            Path(docs.id.clone()),
+            auth_headers(&alice.token),
+        )
+        .await
+        .unwrap();
+        assert!(detail(&task_ids[1]).await.unwrap().0.labels.is_empty());
+        db::delete_task(&state.pool, &task_ids[0]).await.unwrap();
+        let remaining: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM task_labels")
+            .fetch_one(&state.pool)
+            .await
+            .unwrap();
+        assert_eq!(remaining, 0);
+    }
+
+    #[tokio::test]
+    async fn task_listing_filters_sorts_and_pages() {
+        let state = test_state().await;
+        let alice = register_user(&state, "alice@example.com").await;
+        let bob = register_user(&state, "bob@example.com").await;
+        let org_id = db::create_organization(&state.pool, &alice.user_id, "Acme")
+            .await
+            .unwrap();
+        let project_id = db::create_project(&state.pool, &org_id, "Docs", "")
+            .await
+            .unwrap();
+        let add = |project_id: &str, title: &str, status: &str, priority: &str| {
+            let pool = state.pool.clone();
+            let (project_id, title, status, priority) = (
+                project_id.to_string(),
+                title.to_string(),
+                status.to_string(),
+                priority.to_string(),
+            );
+            let creator = alice.user_id.clone();
+            async move {
+                db::create_task(
+                    &pool,
+                    &project_id,
+                    None,
+                    &title,
+                    "",
+                    &status,
+                    &priority,
+                    None,
+                    None,
+                    None,
+                    &creator,
+                )
+                .await
+                .unwrap()
+            }
+        };
+        let write = add(&project_id, "Write 100% of it", "todo", "high").await;
+        let review = add(&project_id, "review", "doing", "urgent").await;
+        let publish = add(&project_id, "Publish", "done", "low").await;
+        let _plan = add(&project_id, "Plan", "todo", "medium").await;
+        for (task_id, assignee, due) in [
+            (&write, Some(&alice.user_id), Some(300)),
+            (&review, Some(&bob.user_id), Some(100)),
+            (&publish, Some(&alice.user_id), None),
+        ] {
+            db::update_task(
+                &state.pool,
+                task_id,
+                &alice.user_id,
+                &workflows::default_workflow(),
+                None,
+                None,
+                None,
+                None,
+                None,
+                Some(assignee.map(String::as_str)),
+                Some(due),
+                None,
+            )
+            .await
+            .unwrap();
+        }
+
+        let list = |query: TaskListQuery| {
+            list_project_tasks(
+                State(state.clone()),
+                Path(project_id.clone()),
+                auth_headers(&alice.token),
+                Query(query),
+            )
+        };
+        let titles = |page: &TaskPage| {
+            page.tasks
+                .iter()
+                .map(|task| task.title.clone())
+                .collect::<Vec<_>>()
+        };
+
+        let page = list(TaskListQuery {
+            status: Some("todo,doing".to_string()),
+            ..Default::default()
+        })
+        .await
+        .unwrap()
+        .0;
+        assert_eq!(page.tasks.len(), 3);
+        let page = list(TaskListQuery {
+            assignee: Some("me".to_string()),
+            sort: Some("due_date".to_string()),
+            ..Default::default()
+        })
+        .await
+        .unwrap()
+        .0;
+        assert_eq!(titles(&page), ["Write 100% of it", "Publish"]);
+        let page = list(TaskListQuery {
+            assignee: Some("none".to_string()),
+            ..Default::default()
+        })
+        .await
+        .unwrap()
+        .0;
+        assert_eq!(titles(&page), ["Plan"]);
+        let page = list(TaskListQuery {
+            due_after: Some(100),
+            due_before: Some(300),
+            ..Default::default()
+        })
+        .await
+        .unwrap()
+        .0;
+        assert_eq!(titles(&page), ["review"]);
+        let page = list(TaskListQuery {
+            q: Some("100%".to_string()),
+            ..Default::default()
+        })
+        .await
+        .unwrap()
+        .0;
+        assert_eq!(titles(&page), ["Write 100% of it"]);
+        let page = list(TaskListQuery {
+            sort: Some("priority".to_string()),
+            ..Default::default()
+        })
+        .await
+        .unwrap()
+        .0;
+        assert_eq!(
+            titles(&page),
+            ["review", "Write 100% of it", "Plan", "Publish"]
+        );
+        assert!(matches!(
+            list(TaskListQuery {
+                sort: Some("color".to_string()),
+                ..Default::default()
+            })
+            .await,
+            Err(AppError::BadRequest(_))
+        ));
+
+        // Walk every page of a title sort, newest order last.
+        let mut seen = Vec::new();
+        let mut cursor = None;
+        loop {
+            let page = list(TaskListQuery {
+                sort: Some("title".to_string()),
+                order: Some("desc".to_string()),
+                cursor: cursor.take(),
+                limit: Some(3),
+                ..Default::default()
+            })
+            .await
+            .unwrap()
+            .0;
+            seen.extend(titles(&page));
+            match page.next_cursor {
+                Some(next) => cursor = Some(next),
+                None => break,
+            }
+        }
+        assert_eq!(seen, ["Write 100% of it", "review", "Publish", "Plan"]);
+        let title_cursor = list(TaskListQuery {
+            sort: Some("title".to_string()),
+            limit: Some(1),
+            ..Default::default()
+        })
+        .await
+        .unwrap()
+        .0
+        .next_cursor
+        .unwrap();
+        assert!(matches!(
+            list(TaskListQuery {
+                sort: Some("due_date".to_string()),
+                cursor: Some(title_cursor),
+                ..Default::default()
+            })
+            .await,
+            Err(AppError::BadRequest(_))
+        ));
+
+        // My tasks spans orgs but skips projects the caller cannot see.
+        let other_org = db::create_organization(&state.pool, &bob.user_id, "Other")
+            .await
+            .unwrap();
+        db::add_org_member(&state.pool, &other_org, &alice.user_id, "guest")
+            .await
+            .unwrap();
+        let shared = db::create_project(&state.pool, &other_org, "Shared", "")
+            .await
+            .unwrap();
+        let hidden = db::create_project(&state.pool, &other_org, "Hidden", "")
+            .await
+            .unwrap();
+        db::set_project_member(&state.pool, &shared, &alice.user_id, "viewer", &bob.user_id)
+            .await
+            .unwrap();
+        for project in [&shared, &hidden] {
+            let task_id = add(project, "Elsewhere", "todo", "medium").await;
+            db::update_task(
+                &state.pool,
+                &task_id,
+                &alice.user_id,
+                &workflows::default_workflow(),
+                None,
+                None,
+                None,
+                None,
+                None,
+                Some(Some(&alice.user_id)),
+                None,
+                None,
+            )
+            .await
+            .unwrap();
+        }
+        let mine = list_my_tasks(
+            State(state.clone()),
+            auth_headers(&alice.token),
+            Query(TaskListQuery::default()),
+        )
+        .await
+        .unwrap()
+        .0;
+        let in_project = |id: &str| mine.tasks.iter().filter(|t| t.project_id == id).count();
+        assert_eq!(mine.tasks.len(), 3);
+        assert_eq!(in_project(&project_id), 2);
+        assert_eq!(in_project(&shared), 1);
+    }
+
+    #[tokio::test]
+    async fn task_comments_and_activity_notify_followers() {
+        let state = test_state().await;
+        let alice = register_user(&state, "alice@example.com").await;
+        let bob = register_user(&state, "bob@example.com").await;
+        let carol = register_user(&state, "carol@example.com").await;
+        let org_id = db::create_organization(&state.pool, &alice.user_id, "Acme")
+            .await
+            .unwrap();
+        for user_id in [&bob.user_id, &carol.user_id] {
+            db::add_org_member(&state.pool, &org_id, user_id, "member")
+                .await
+                .unwrap();
+        }
+        let project_id = db::create_project(&state.pool, &org_id, "Docs", "")
+            .await
+            .unwrap();
+        let task = create_task(
+            State(state.clone()),
+            Path(project_id.clone()),
+            auth_headers(&alice.token),
+            Json(CreateTaskRequest {
+                parent_id: None,
+                title: "Write".to_string(),
+                description: None,
+                status: None,
+                priority: None,
+                assignee_id: None,
+                due_date: None,
+                start_date: None,
+            }),
+        )
+        .await
+        .unwrap()
+        .0;
+        let update = |token: &str, payload: UpdateTaskRequest| {
+            update_task_handler(
+                State(state.clone()),
+                Path(task.id.clone()),
+                auth_headers(token),
+                Json(payload),
+            )
+        };
+        let comment = |token: &str, body: &str, parent_id: Option<String>| {
+            create_task_comment(
+                State(state.clone()),
+                Path(task.id.clone()),
+                auth_headers(token),
+                Json(CreateTaskCommentRequest {
+                    body: body.to_string(),
+                    parent_id,
+                }),
+            )
+        };
+        let inbox = |user_id: &str| {
+            let pool = state.pool.clone();
+            let user_id = user_id.to_string();
+            async move {
+                db::list_notifications(&pool, &user_id, 50)
+                    .await
+                    .unwrap()
+                    .into_iter()
+                    .map(|n| n.ntype)
+                    .collect::<Vec<_>>()
+            }
+        };
+
+        // Tasks can only be assigned to people who can see the project.
+        let outsider = register_user(&state, "dave@example.com").await;
+        assert!(matches!(
+            create_task(
+                State(state.clone()),
+                Path(project_id.clone()),
+                auth_headers(&alice.token),
+                Json(CreateTaskRequest {
+                    parent_id: None,
+                    title: "Leak".to_string(),
+                    description: None,
+                    status: None,
+                    priority: None,
+                    assignee_id: Some(outsider.user_id.clone()),
+                    due_date: None,
+                    start_date: None,
+                }),
+            )
+            .await,
+            Err(AppError::BadRequest(_))
+        ));
+        assert!(matches!(
+            update(
+                &alice.token,
+                UpdateTaskRequest {
+                    assignee_id: Some(Some(outsider.user_id.clone())),
+                    ..Default::default()
+                },
+            )
+            .await,
+            Err(AppError::BadRequest(_))
+        ));
+
+        let _ = update(
+            &alice.token,
+            UpdateTaskRequest {
+                assignee_id: Some(Some(bob.user_id.clone())),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        let _ = watch_task(
+            State(state.clone()),
+            Path(task.id.clone()),
+            auth_headers(&carol.token),
+        )
+        .await
+        .unwrap();
+        let _ = update(
+            &alice.token,
+            UpdateTaskRequest {
+                status: Some("doing".to_string()),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        // Positions only change through the move endpoint.
+        assert!(matches!(
+            update(
+                &alice.token,
+                UpdateTaskRequest {
+                    position: Some(3.0),
+                    ..Default::default()
+                },
+            )
+            .await,
+            Err(AppError::BadRequest(_))
+        ));
+        // An update without a tracked field records nothing.
+        let _ = update(&alice.token, UpdateTaskRequest::default())
+            .await
+            .unwrap();
+        assert!(inbox(&alice.user_id).await.is_empty());
+        assert_eq!(inbox(&carol.user_id).await, ["task_updated"]);
+        assert_eq!(inbox(&bob.user_id).await.len(), 2);
+
+        let first = comment(&bob.token, " Started ", None).await.unwrap().0;
+        assert_eq!(first.body, "Started");
+        let reply = comment(&carol.token, "Thanks", Some(first.id.clone()))
+            .await
+            .unwrap()
+            .0;
+        assert!(matches!(
+            comment(&alice.token, "", None).await,
+            Err(AppError::BadRequest(_))
+        ));
+        assert!(matches!(
+            comment(&alice.token, "Hi", Some("missing".to_string())).await,
+            Err(AppError::BadRequest(_))
+        ));
+        assert_eq!(
+            inbox(&alice.user_id).await,
+            ["task_comment", "task_comment"]
         );
-        headers
+        assert_eq!(inbox(&carol.user_id).await.len(), 2);
+
+        let detail = get_task_handler(
+            State(state.clone()),
+            Path(task.id.clone()),
+            auth_headers(&carol.token),
+        )
+        .await
+        .unwrap()
+        .0;
+        assert_eq!(detail.comments.len(), 2);
+        assert_eq!(detail.comments[1].id, reply.id);
+        assert_eq!(
+            detail.comments[1].parent_id.as_deref(),
+            Some(first.id.as_str())
+        );
+        let fields: Vec<_> = detail.activity.iter().map(|a| a.field.as_str()).collect();
+        assert_eq!(fields, ["created", "assignee_id", "status"]);
+        assert_eq!(detail.activity[2].old_value, "todo");
+        assert_eq!(detail.activity[2].new_value, "doing");
+        assert_eq!(
+            detail.activity[1].actor_id.as_deref(),
+            Some(alice.user_id.as_str())
+        );
+        let mut watchers = detail.watchers.clone();
+        watchers.sort();
+        let mut expected = vec![
+            alice.user_id.clone(),
+            bob.user_id.clone(),
+            carol.user_id.clone(),
+        ];
+        expected.sort();
+        assert_eq!(watchers, expected);
+
+        // Followers who leave the org stop hearing about the task.
+        db::remove_org_member(&state.pool, &org_id, &carol.user_id)
+            .await
+            .unwrap();
+        let _ = comment(&bob.token, "Done soon", None).await.unwrap();
+        assert_eq!(inbox(&carol.user_id).await.len(), 2);
     }
 
     #[tokio::test]
-    async fn register_returns_user_and_default_workspace() {
+    async fn assignments_status_replies_and_mentions_notify_once() {
         let state = test_state().await;
+        let alice = register_user(&state, "alice@example.com").await;
+        let bob = register_user(&state, "bob@example.com").await;
+        let carol = register_user(&state, "carol@example.com").await;
+        let dave = register_user(&state, "dave@example.com").await;
+        let org_id = db::create_organization(&state.pool, &alice.user_id, "Acme")
+            .await
+            .unwrap();
+        for (user_id, role) in [
+            (&bob.user_id, "member"),
+            (&carol.user_id, "member"),
+            (&dave.user_id, "guest"),
+        ] {
+            db::add_org_member(&state.pool, &org_id, user_id, role)
+                .await
+                .unwrap();
+        }
+        let project_id = db::create_project(&state.pool, &org_id, "Docs", "")
+            .await
+            .unwrap();
+        let inbox = |user_id: &str| {
+            let pool = state.pool.clone();
+            let user_id = user_id.to_string();
+            async move {
+                let mut types: Vec<_> = db::list_notifications(&pool, &user_id, 50)
+                    .await
+                    .unwrap()
+                    .into_iter()
+                    .map(|n| n.ntype)
+                    .collect();
+                types.sort();
+                types
+            }
+        };
 
-        let response = register(
-            State(state),
-            ConnectInfo(TEST_ADDR),
-            HeaderMap::new(),
-            Json(RegisterRequest {
-                email: "dev@example.com".to_string(),
-                password: "change-me".to_string(),
+        // The guest cannot see the project, and nobody hears about their own
+        // mention.
+        let task = create_task(
+            State(state.clone()),
+            Path(project_id.clone()),
+            auth_headers(&alice.token),
+            Json(CreateTaskRequest {
+                parent_id: None,
+                title: "Write".to_string(),
+                description: Some(
+                    "For @carol@example.com, @alice@example.com and @dave@example.com".to_string(),
+                ),
+                status: None,
+                priority: None,
+                assignee_id: Some(bob.user_id.clone()),
+                due_date: None,
+                start_date: None,
             }),
         )
         .await
         .unwrap()
         .0;
+        assert_eq!(inbox(&bob.user_id).await, ["task_assigned"]);
+        assert_eq!(inbox(&carol.user_id).await, ["mention"]);
+        assert!(inbox(&alice.user_id).await.is_empty());
+        assert!(inbox(&dave.user_id).await.is_empty());
 
-        assert_eq!(response.user.email, "dev@example.com");
-        assert!(!response.token.is_empty());
-        assert_eq!(response.workspaces.len(), 1);
-        assert_eq!(response.workspaces[0].name, "My Workspace");
-    }
-
-    #[tokio::test]
-    async fn register_rejects_passwords_shorter_than_eight_characters() {
-        let state = test_state().await;
+        // The creator hears about the status change once, although they also
+        // watch the task; carol was already mentioned.
+        let _ = update_task_handler(
+            State(state.clone()),
+            Path(task.id.clone()),
+            auth_headers(&bob.token),
+            Json(UpdateTaskRequest {
+                status: Some("done".to_string()),
+                description: Some("Done, thanks @carol@example.com".to_string()),
+                ..Default::default()
+            }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(inbox(&alice.user_id).await, ["task_status"]);
+        assert_eq!(inbox(&carol.user_id).await, ["mention"]);
+        assert_eq!(inbox(&bob.user_id).await, ["task_assigned"]);
 
-        let result = register(
-            State(state),
-            ConnectInfo(TEST_ADDR),
-            HeaderMap::new(),
-            Json(RegisterRequest {
-                email: "dev@example.com".to_string(),
-                password: "1234567".to_string(),
+        let _ = update_task_handler(
+            State(state.clone()),
+            Path(task.id.clone()),
+            auth_headers(&alice.token),
+            Json(UpdateTaskRequest {
+                assignee_id: Some(Some(carol.user_id.clone())),
+                ..Default::default()
             }),
         )
-        .await;
+        .await
+        .unwrap();
+        assert_eq!(inbox(&carol.user_id).await, ["mention", "task_assigned"]);
 
-        match result {
-            Err(AppError::BadRequest(message)) => {
-                assert_eq!(message, "invalid email or password");
-            }
-            other => panic!("expected bad request, got {other:?}"),
-        }
+        let annotation = create_annotation_handler(
+            State(state.clone()),
+            Path(org_id.clone()),
+            auth_headers(&bob.token),
+            Json(CreateAnnotationRequest {
+                doc_path: "notes.md".to_string(),
+                project_id: None,
+                range_start: 0,
+                range_end: 4,
+                content: "@carol@example.com see this".to_string(),
+            }),
+        )
+        .await
+        .unwrap()
+        .0;
+        let annotation_id = annotation["id"].as_str().unwrap().to_string();
+        assert_eq!(
+            inbox(&carol.user_id).await,
+            ["mention", "mention", "task_assigned"]
+        );
+        let reply = |token: &str, content: &str| {
+            create_reply(
+                State(state.clone()),
+                Path(annotation_id.clone()),
+                auth_headers(token),
+                Json(CreateAnnotationReplyRequest {
+                    content: content.to_string(),
+                }),
+            )
+        };
+        // A reply mentioning the author notifies them once.
+        let _ = reply(&alice.token, "Agreed @bob@example.com")
+            .await
+            .unwrap();
+        let _ = reply(&bob.token, "Thanks").await.unwrap();
+        // An identical unread notification is not repeated.
+        let _ = reply(&carol.token, "Looks good").await.unwrap();
+        let _ = reply(&carol.token, "Looks good").await.unwrap();
+        assert_eq!(
+            inbox(&bob.user_id).await,
+            ["annotation_reply", "mention", "task_assigned"]
+        );
     }
 
     #[tokio::test]
-    async fn login_and_create_workspace_share_same_contract() {
+    async fn due_reminders_are_sent_once_per_window() {
         let state = test_state().await;
+        let alice = register_user(&state, "alice@example.com").await;
+        let bob = register_user(&state, "bob@example.com").await;
+        let org_id = db::create_organization(&state.pool, &alice.user_id, "Acme")
+            .await
+            .unwrap();
+        db::add_org_member(&state.pool, &org_id, &bob.user_id, "member")
+            .await
+            .unwrap();
+        let project_id = db::create_project(&state.pool, &org_id, "Docs", "")
+            .await
+            .unwrap();
+        let now = chrono::Utc::now().timestamp();
+        let add_task = |title: &str, status: &str, due_date: i64| {
+            create_task(
+                State(state.clone()),
+                Path(project_id.clone()),
+                auth_headers(&alice.token),
+                Json(CreateTaskRequest {
+                    parent_id: None,
+                    title: title.to_string(),
+                    description: None,
+                    status: Some(status.to_string()),
+                    priority: None,
+                    assignee_id: Some(bob.user_id.clone()),
+                    due_date: Some(due_date),
+                    start_date: None,
+                }),
+            )
+        };
+        let _ = add_task("Soon", "todo", now + 30 * 60).await.unwrap();
+        let _ = add_task("Tomorrow", "todo", now + 5 * 60 * 60)
+            .await
+            .unwrap();
+        let late = add_task("Late", "todo", now - 10).await.unwrap().0;
+        let _ = add_task("Finished", "done", now - 10).await.unwrap();
+        let _ = add_task("Forgotten", "todo", now - 2 * 24 * 60 * 60)
+            .await
+            .unwrap();
+        let _ = add_task("Later", "todo", now + 3 * 24 * 60 * 60)
+            .await
+            .unwrap();
+        let reminders = |user_id: &str| {
+            let pool = state.pool.clone();
+            let user_id = user_id.to_string();
+            async move {
+                let mut reminders: Vec<_> = db::list_notifications(&pool, &user_id, 50)
+                    .await
+                    .unwrap()
+                    .into_iter()
+                    .filter(|n| n.ntype != notifications::TASK_ASSIGNED)
+                    .map(|n| format!("{}: {}", n.title, n.body))
+                    .collect();
+                reminders.sort();
+                reminders
+            }
+        };
 
-        let registered = register(
+        assert_eq!(
+            notifications::send_due_reminders(&state, now)
+                .await
+                .unwrap(),
+            3
+        );
+        assert_eq!(
+            reminders(&bob.user_id).await,
+            [
+                "Task due within 1 day: Tomorrow",
+                "Task due within 1 hour: Soon",
+                "Task overdue: Late",
+            ]
+        );
+        // Already sent, as after a restart.
+        assert_eq!(
+            notifications::send_due_reminders(&state, now)
+                .await
+                .unwrap(),
+            0
+        );
+
+        // Moving the due date re-arms the reminder, but bob turned it off.
+        let prefs = update_notification_preferences(
             State(state.clone()),
-            ConnectInfo(TEST_ADDR),
-            HeaderMap::new(),
-            Json(RegisterRequest {
-                email: "dev@example.com".to_string(),
-                password: "change-me".to_string(),
+            auth_headers(&bob.token),
+            Json(UpdateNotificationPreferencesRequest {
+                preferences: vec![NotificationPreference {
+                    ntype: notifications::TASK_OVERDUE.to_string(),
+                    enabled: false,
+                }],
             }),
         )
         .await
         .unwrap()
         .0;
+        assert!(prefs
+            .iter()
+            .all(|pref| pref.enabled == (pref.ntype != notifications::TASK_OVERDUE)));
+        assert!(matches!(
+            update_notification_preferences(
+                State(state.clone()),
+                auth_headers(&bob.token),
+                Json(UpdateNotificationPreferencesRequest {
+                    preferences: vec![NotificationPreference {
+                        ntype: "nope".to_string(),
+                        enabled: false,
+                    }],
+                }),
+            )
+            .await,
+            Err(AppError::BadRequest(_))
+        ));
+        db::update_task(
+            &state.pool,
+            &late.id,
+            &alice.user_id,
+            &workflows::default_workflow(),
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+            Some(Some(now - 5)),
+            None,
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            notifications::send_due_reminders(&state, now)
+                .await
+                .unwrap(),
+            1
+        );
+        assert_eq!(reminders(&bob.user_id).await.len(), 3);
 
-        let login_response = login(
+        // An hour later the first task is overdue; the second stays in the
+        // window it was already reminded about.
+        let _ = update_notification_preferences(
             State(state.clone()),
-            ConnectInfo(TEST_ADDR),
-            HeaderMap::new(),
-            Json(LoginRequest {
-                email: "dev@example.com".to_string(),
-                password: "change-me".to_string(),
+            auth_headers(&bob.token),
+            Json(UpdateNotificationPreferencesRequest {
+                preferences: vec![NotificationPreference {
+                    ntype: notifications::TASK_OVERDUE.to_string(),
+                    enabled: true,
+                }],
             }),
         )
         .await
-        .unwrap()
-        .0;
+        .unwrap();
+        assert_eq!(
+            notifications::send_due_reminders(&state, now + 60 * 60)
+                .await
+                .unwrap(),
+            1
+        );
+        assert!(reminders(&bob.user_id)
+            .await
+            .contains(&"Task overdue: Soon".to_string()));
+    }
 
-        assert_eq!(login_response.user.id, registered.user.id);
-        assert_eq!(login_response.user.email, "dev@example.com");
+    #[tokio::test]
+    async fn subtasks_roll_up_and_dependencies_reject_cycles() {
+        let state = test_state().await;
+        let alice = register_user(&state, "alice@example.com").await;
+        let bob = register_user(&state, "bob@example.com").await;
+        let org_id = db::create_organization(&state.pool, &alice.user_id, "Acme")
+            .await
+            .unwrap();
+        db::add_org_member(&state.pool, &org_id, &bob.user_id, "member")
+            .await
+            .unwrap();
+        let project_id = db::create_project(&state.pool, &org_id, "Docs", "")
+            .await
+            .unwrap();
+        let add_task =
+            |title: &str, parent_id: Option<&str>, status: &str, assignee: Option<&str>| {
+                create_task(
+                    State(state.clone()),
+                    Path(project_id.clone()),
+                    auth_headers(&alice.token),
+                    Json(CreateTaskRequest {
+                        parent_id: parent_id.map(str::to_string),
+                        title: title.to_string(),
+                        description: None,
+                        status: Some(status.to_string()),
+                        priority: None,
+                        assignee_id: assignee.map(str::to_string),
+                        due_date: None,
+                        start_date: None,
+                    }),
+                )
+            };
+        let detail = |task_id: &str| {
+            get_task_handler(
+                State(state.clone()),
+                Path(task_id.to_string()),
+                auth_headers(&alice.token),
+            )
+        };
+        let set_parent = |task_id: &str, parent_id: Option<&str>| {
+            update_task_handler(
+                State(state.clone()),
+                Path(task_id.to_string()),
+                auth_headers(&alice.token),
+                Json(UpdateTaskRequest {
+                    parent_id: Some(parent_id.map(str::to_string)),
+                    ..Default::default()
+                }),
+            )
+        };
+        let block = |task_id: &str, blocker_id: &str| {
+            add_task_dependency(
+                State(state.clone()),
+                Path((task_id.to_string(), blocker_id.to_string())),
+                auth_headers(&alice.token),
+            )
+        };
 
-        let created = create_workspace(
+        let parent = add_task("Release", None, "todo", None).await.unwrap().0;
+        let first = add_task("Docs", Some(&parent.id), "todo", None)
+            .await
+            .unwrap()
+            .0;
+        let _ = add_task("Tests", Some(&parent.id), "done", None)
+            .await
+            .unwrap();
+        let release = detail(&parent.id).await.unwrap().0;
+        assert_eq!(
+            release.progress,
+            Some(SubtaskProgress { done: 1, total: 2 })
+        );
+        assert_eq!(release.subtasks.len(), 2);
+        let page = list_project_tasks(
             State(state.clone()),
-            auth_headers(&login_response.token),
-            Json(CreateWorkspaceRequest {
-                name: "Research".to_string(),
-            }),
+            Path(project_id.clone()),
+            auth_headers(&alice.token),
+            Query(TaskListQuery::default()),
         )
         .await
         .unwrap()
         .0;
+        let summary = |id: &str| page.tasks.iter().find(|task| task.id == id).unwrap();
+        assert_eq!(summary(&parent.id).progress.map(|p| p.total), Some(2));
+        assert_eq!(
+            summary(&first.id).parent_id.as_deref(),
+            Some(parent.id.as_str())
+        );
 
-        assert_eq!(created.name, "Research");
+        // A task cannot end up below itself.
+        for (task_id, parent_id) in [(&parent.id, &first.id), (&parent.id, &parent.id)] {
+            assert!(matches!(
+                set_parent(task_id, Some(parent_id)).await,
+                Err(AppError::BadRequest(_))
+            ));
+        }
+        let _ = set_parent(&first.id, None).await.unwrap();
+        let release = detail(&parent.id).await.unwrap().0;
+        assert_eq!(
+            release.progress,
+            Some(SubtaskProgress { done: 1, total: 1 })
+        );
+        assert_eq!(
+            detail(&first.id)
+                .await
+                .unwrap()
+                .0
+                .activity
+                .last()
+                .unwrap()
+                .field,
+            "parent_id"
+        );
 
-        let listed = list_workspaces(State(state), auth_headers(&login_response.token))
+        let design = add_task("Design", None, "todo", None).await.unwrap().0;
+        let build = add_task("Build", None, "todo", Some(&bob.user_id))
             .await
             .unwrap()
             .0;
-        assert_eq!(listed.len(), 2);
-    }
+        let ship = add_task("Ship", None, "todo", None).await.unwrap().0;
+        let review = add_task("Review", None, "todo", None).await.unwrap().0;
+        let _ = block(&build.id, &design.id).await.unwrap();
+        let _ = block(&build.id, &review.id).await.unwrap();
+        let _ = block(&ship.id, &build.id).await.unwrap();
+        for (task_id, blocker_id) in [(&design.id, &ship.id), (&design.id, &design.id)] {
+            assert!(matches!(
+                block(task_id, blocker_id).await,
+                Err(AppError::BadRequest(_))
+            ));
+        }
+        let built = detail(&build.id).await.unwrap().0;
+        assert_eq!(built.blocked_by.len(), 2);
+        assert!(built.blocked_by.contains(&design.id));
+        assert_eq!(built.blocks, [ship.id.as_str()]);
 
-    #[tokio::test]
-    async fn login_rejects_after_rate_limit_exceeded() {
-        let data_dir = std::env::temp_dir().join(format!("lumina-test-{}", uuid::Uuid::new_v4()));
-        std::fs::create_dir_all(&data_dir).unwrap();
+        // Build is only unblocked once both of its blockers are done.
+        let finish = |task_id: &str| {
+            update_task_handler(
+                State(state.clone()),
+                Path(task_id.to_string()),
+                auth_headers(&alice.token),
+                Json(UpdateTaskRequest {
+                    status: Some("done".to_string()),
+                    ..Default::default()
+                }),
+            )
+        };
+        let unblocked = || async {
+            db::list_notifications(&state.pool, &bob.user_id, 50)
+                .await
+                .unwrap()
+                .into_iter()
+                .filter(|n| n.ntype == notifications::TASK_UNBLOCKED)
+                .collect::<Vec<_>>()
+        };
+        let _ = finish(&design.id).await.unwrap();
+        assert!(unblocked().await.is_empty());
+        let _ = finish(&review.id).await.unwrap();
+        let unblocked = unblocked().await;
+        assert_eq!(unblocked.len(), 1);
+        assert_eq!(unblocked[0].ref_id, build.id);
 
-        let pool = SqlitePoolOptions::new()
-            .max_connections(1)
-            .connect("sqlite::memory:")
+        for (task_id, blocker_id) in [(&ship.id, &build.id), (&build.id, &review.id)] {
+            let _ = remove_task_dependency(
+                State(state.clone()),
+                Path((task_id.clone(), blocker_id.clone())),
+                auth_headers(&alice.token),
+            )
             .await
             .unwrap();
-        db::init_db(&pool).await.unwrap();
+        }
+        let _ = delete_task_handler(
+            State(state.clone()),
+            Path(design.id.clone()),
+            auth_headers(&alice.token),
+        )
+        .await
+        .unwrap();
+        let built = detail(&build.id).await.unwrap().0;
+        assert!(built.blocked_by.is_empty() && built.blocks.is_empty());
+    }
 
-        let state = AppState {
-            pool,
-            config: Config {
-                bind: "127.0.0.1:0".to_string(),
-                db_url: "sqlite::memory:".to_string(),
-                data_dir: data_dir.display().to_string(),
-                jwt_secret: "test-secret".to_string(),
-                auth_rate_limit_burst: 2,
-                auth_rate_limit_window_secs: 60,
-                trusted_proxy_hops: 0,
-            },
-            relay: RelayHub::new(),
-            collab: CollabHub::new(&data_dir.display().to_string()),
-            metrics: Arc::new(ServerMetrics::new()),
-            notify: crate::notify_ws::NotifyHub::new(),
-            auth_limiter: crate::rate_limit::AuthRateLimiter::new(2, 60),
+    #[tokio::test]
+    async fn project_workflows_validate_tasks_and_migrate_legacy_values() {
+        let state = test_state().await;
+        let alice = register_user(&state, "alice@example.com").await;
+        let bob = register_user(&state, "bob@example.com").await;
+        let org_id = db::create_organization(&state.pool, &alice.user_id, "Acme")
+            .await
+            .unwrap();
+        db::add_org_member(&state.pool, &org_id, &bob.user_id, "member")
+            .await
+            .unwrap();
+        let project_id = db::create_project(&state.pool, &org_id, "Docs", "")
+            .await
+            .unwrap();
+        db::set_project_member(
+            &state.pool,
+            &project_id,
+            &bob.user_id,
+            projects::CONTRIBUTOR,
+            &alice.user_id,
+        )
+        .await
+        .unwrap();
+        let add_task = |parent_id: Option<&str>, status: Option<&str>| {
+            create_task(
+                State(state.clone()),
+                Path(project_id.clone()),
+                auth_headers(&bob.token),
+                Json(CreateTaskRequest {
+                    parent_id: parent_id.map(str::to_string),
+                    title: "Write".to_string(),
+                    description: None,
+                    status: status.map(str::to_string),
+                    priority: None,
+                    assignee_id: None,
+                    due_date: None,
+                    start_date: None,
+                }),
+            )
+        };
+        let set_status = |task_id: &str, status: &str| {
+            update_task_handler(
+                State(state.clone()),
+                Path(task_id.to_string()),
+                auth_headers(&bob.token),
+                Json(UpdateTaskRequest {
+                    status: Some(status.to_string()),
+                    ..Default::default()
+                }),
+            )
+        };
+        let update = |token: &str, request: serde_json::Value| {
+            update_workflow(
+                State(state.clone()),
+                Path(project_id.clone()),
+                auth_headers(token),
+                Json(serde_json::from_value(request).unwrap()),
+            )
         };
 
-        // Request 1: register (uses 1 of 2 tokens)
-        let _ = register(
+        let task = add_task(None, None).await.unwrap().0;
+        assert_eq!(
+            (task.status.as_str(), task.priority.as_str()),
+            ("todo", "medium")
+        );
+        assert!(matches!(
+            add_task(None, Some("nope")).await,
+            Err(AppError::BadRequest(_))
+        ));
+
+        let workflow = json!({
+            "statuses": [
+                { "key": "backlog", "name": "Backlog", "category": "backlog",
+                  "transitions": ["active"] },
+                { "key": "active", "name": "Active", "category": "active" },
+                { "key": "shipped", "name": "Shipped", "category": "done" },
+            ],
+            "priorities": [
+                { "key": "p1", "name": "P1" },
+                { "key": "p2", "name": "P2" },
+            ],
+            "default_priority": "p2",
+        });
+        assert!(matches!(
+            update(&bob.token, workflow.clone()).await,
+            Err(AppError::Forbidden)
+        ));
+        // The task would be left in a status that no longer exists.
+        assert!(matches!(
+            update(&alice.token, workflow.clone()).await,
+            Err(AppError::Conflict(_))
+        ));
+        let mut duplicate = workflow.clone();
+        duplicate["priorities"][1]["key"] = json!("p1");
+        assert!(matches!(
+            update(&alice.token, duplicate).await,
+            Err(AppError::BadRequest(_))
+        ));
+        let mut mapped = workflow.clone();
+        mapped["status_map"] = json!({ "todo": "backlog" });
+        mapped["priority_map"] = json!({ "medium": "p2" });
+        let _ = update(&alice.token, mapped).await.unwrap();
+        let stored = get_workflow(
             State(state.clone()),
-            ConnectInfo(TEST_ADDR),
-            HeaderMap::new(),
-            Json(RegisterRequest {
-                email: "ratelimit@example.com".to_string(),
-                password: "strongpass123".to_string(),
-            }),
+            Path(project_id.clone()),
+            auth_headers(&bob.token),
         )
         .await
-        .unwrap();
+        .unwrap()
+        .0;
+        assert_eq!(
+            stored.statuses[0].transitions,
+            Some(vec!["active".to_string()])
+        );
+        assert_eq!(stored.default_priority, "p2");
+        let moved = db::get_task(&state.pool, &task.id).await.unwrap().unwrap();
+        assert_eq!(
+            (moved.status.as_str(), moved.priority.as_str()),
+            ("backlog", "p2")
+        );
 
-        // Request 2: login (uses 2 of 2 tokens)
-        let _ = login(
+        assert!(matches!(
+            set_status(&task.id, "shipped").await,
+            Err(AppError::BadRequest(_))
+        ));
+        let _ = set_status(&task.id, "active").await.unwrap();
+        let subtask = add_task(Some(&task.id), None).await.unwrap().0;
+        assert_eq!(subtask.status, "backlog");
+        let _ = set_status(&subtask.id, "active").await.unwrap();
+        let _ = set_status(&subtask.id, "shipped").await.unwrap();
+        let progress = db::subtask_progress(&state.pool, std::slice::from_ref(&task.id))
+            .await
+            .unwrap();
+        assert_eq!(progress[&task.id], (1, 1));
+
+        // Swapped keys move every task once.
+        let _ = update_task_handler(
             State(state.clone()),
-            ConnectInfo(TEST_ADDR),
-            HeaderMap::new(),
-            Json(LoginRequest {
-                email: "ratelimit@example.com".to_string(),
-                password: "strongpass123".to_string(),
+            Path(task.id.clone()),
+            auth_headers(&bob.token),
+            Json(UpdateTaskRequest {
+                priority: Some("p1".to_string()),
+                ..Default::default()
             }),
         )
         .await
         .unwrap();
+        let mut swapped = workflow.clone();
+        swapped["priority_map"] = json!({ "p1": "p2", "p2": "p1" });
+        let _ = update(&alice.token, swapped).await.unwrap();
+        let priority = |task_id: String| {
+            let pool = state.pool.clone();
+            async move {
+                db::get_task(&pool, &task_id)
+                    .await
+                    .unwrap()
+                    .unwrap()
+                    .priority
+            }
+        };
+        assert_eq!(priority(task.id.clone()).await, "p2");
+        assert_eq!(priority(subtask.id.clone()).await, "p1");
 
-        // Request 3: should be rate limited
-        let result = login(
-            State(state),
-            ConnectInfo(TEST_ADDR),
-            HeaderMap::new(),
-            Json(LoginRequest {
-                email: "ratelimit@example.com".to_string(),
-                password: "strongpass123".to_string(),
-            }),
+        // A project from before workflows gets one fitting its tasks.
+        sqlx::query(
+            "INSERT INTO projects (id, org_id, name, description, created_at)
+             VALUES ('legacy', ?1, 'Legacy', '', 0)",
         )
-        .await;
+        .bind(&org_id)
+        .execute(&state.pool)
+        .await
+        .unwrap();
+        let legacy = db::create_task(
+            &state.pool,
+            "legacy",
+            None,
+            "Old",
+            "",
+            "In Progress",
+            "P0",
+            None,
+            None,
+            None,
+            &alice.user_id,
+        )
+        .await
+        .unwrap();
+        db::init_db(&state.pool).await.unwrap();
+        let legacy = db::get_task(&state.pool, &legacy).await.unwrap().unwrap();
+        assert_eq!(
+            (legacy.status.as_str(), legacy.priority.as_str()),
+            ("doing", "urgent")
+        );
+        let migrated = db::get_workflow(&state.pool, "legacy").await.unwrap();
+        assert_eq!(migrated, crate::workflows::default_workflow());
+    }
 
-        match result {
-            Err(AppError::RateLimited(secs)) => {
-                assert!(secs > 0);
+    #[tokio::test]
+    async fn moving_tasks_bisects_positions_and_rebalances_columns() {
+        let state = test_state().await;
+        let alice = register_user(&state, "alice@example.com").await;
+        let org_id = db::create_organization(&state.pool, &alice.user_id, "Acme")
+            .await
+            .unwrap();
+        let project_id = db::create_project(&state.pool, &org_id, "Docs", "")
+            .await
+            .unwrap();
+        let mut ids = Vec::new();
+        for title in ["A", "B", "C"] {
+            let task = create_task(
+                State(state.clone()),
+                Path(project_id.clone()),
+                auth_headers(&alice.token),
+                Json(CreateTaskRequest {
+                    parent_id: None,
+                    title: title.to_string(),
+                    description: None,
+                    status: None,
+                    priority: None,
+                    assignee_id: None,
+                    due_date: None,
+                    start_date: None,
+                }),
+            )
+            .await
+            .unwrap()
+            .0;
+            ids.push(task.id);
+        }
+        let move_to =
+            |task_id: &str, status: Option<&str>, after: Option<&str>, before: Option<&str>| {
+                move_task(
+                    State(state.clone()),
+                    Path(task_id.to_string()),
+                    auth_headers(&alice.token),
+                    Json(MoveTaskRequest {
+                        status: status.map(str::to_string),
+                        after_id: after.map(str::to_string),
+                        before_id: before.map(str::to_string),
+                    }),
+                )
+            };
+        let column = |status: &str| {
+            let pool = state.pool.clone();
+            let status = status.to_string();
+            async move {
+                let tasks: Vec<(String, f64)> = sqlx::query_as(
+                    "SELECT title, position FROM tasks WHERE status = ?1
+                     ORDER BY position, created_at, id",
+                )
+                .bind(&status)
+                .fetch_all(&pool)
+                .await
+                .unwrap();
+                tasks
             }
-            other => panic!("expected RateLimited, got {other:?}"),
+        };
+        let (a, b, c) = (ids[0].as_str(), ids[1].as_str(), ids[2].as_str());
+
+        // New tasks share position 0; moving each to the end orders them.
+        for id in [a, b, c] {
+            let _ = move_to(id, None, None, None).await.unwrap();
+        }
+        let titles =
+            |tasks: &[(String, f64)]| tasks.iter().map(|t| t.0.clone()).collect::<Vec<_>>();
+        assert_eq!(titles(&column("todo").await), ["A", "B", "C"]);
+
+        let moved = move_to(c, None, Some(a), Some(b)).await.unwrap().0;
+        let todo = column("todo").await;
+        assert_eq!(titles(&todo), ["A", "C", "B"]);
+        assert_eq!(moved.position, (todo[0].1 + todo[2].1) / 2.0);
+        assert!(matches!(
+            move_to(a, None, Some(b), Some(c)).await,
+            Err(AppError::BadRequest(_))
+        ));
+
+        // Dragging between the same two tasks again and again would exhaust
+        // float precision; the column is renumbered first, keeping its order.
+        let (mut first, mut second) = (b, c);
+        for _ in 0..60 {
+            let _ = move_to(first, None, Some(a), Some(second)).await.unwrap();
+            std::mem::swap(&mut first, &mut second);
         }
+        let todo = column("todo").await;
+        assert_eq!(titles(&todo)[0], "A");
+        assert!(todo.windows(2).all(|pair| pair[1].1 > pair[0].1));
+
+        assert!(matches!(
+            move_to(a, Some("doing"), Some(b), None).await,
+            Err(AppError::BadRequest(_))
+        ));
+        let moved = move_to(a, Some("doing"), None, None).await.unwrap().0;
+        assert_eq!(moved.status, "doing");
+        let detail = get_task_handler(
+            State(state.clone()),
+            Path(a.to_string()),
+            auth_headers(&alice.token),
+        )
+        .await
+        .unwrap()
+        .0;
+        assert_eq!(detail.activity.last().unwrap().field, "status");
+        assert_eq!(titles(&column("todo").await).len(), 2);
     }
 }
@@ -5,17 +5,37 @@ use mime_guess::MimeGuess;
 use tokio_util::io::ReaderStream;
 
 use crate::dav;
+use crate::db;
 use crate::error::AppError;
 use crate::state::AppState;
 
+/// Refuse to publish a site from an end-to-end encrypted workspace, or from
+/// one the user does not belong to. Uploads only go online once a publish
+/// from a workspace that passes this check confirms them.
+pub async fn ensure_publishable(
+    state: &AppState,
+    user_id: &str,
+    workspace_id: &str,
+) -> Result<(), AppError> {
+    if !db::user_has_workspace(&state.pool, user_id, workspace_id).await? {
+        return Err(AppError::Forbidden);
+    }
+    if db::workspace_is_e2ee(&state.pool, workspace_id).await? {
+        return Err(AppError::BadRequest(
+            "end-to-end encrypted workspaces cannot be published".to_string(),
+        ));
+    }
+    Ok(())
+}
+
 /// Serve published site files: GET /sites/{user_id}/*path
-/// No authentication required — public access.
+/// No authentication required — public access, once the site is confirmed.
 pub async fn serve_site_file(
     State(state): State<AppState>,
     AxumPath((user_id, path)): AxumPath<(String, String)>,
 ) -> Result<Response<Body>, AppError> {
     let site_dir = dav::site_root(&state, &user_id);
-    if !site_dir.exists() {
+    if !site_dir.exists() || !db::site_is_live(&state.pool, &user_id).await? {
         return Err(AppError::NotFound);
     }
 
@@ -31,6 +31,16 @@ impl RelayHub {
             mobiles: Arc::new(RwLock::new(HashMap::new())),
         }
     }
+
+    /// Push a server-originated text frame to every relay peer of `user_id`.
+    pub async fn send_to_user(&self, user_id: &str, payload: &str) {
+        if let Some(peer) = self.desktops.read().await.get(user_id) {
+            let _ = peer.sender.send(Message::Text(payload.to_string()));
+        }
+        if let Some(peer) = self.mobiles.read().await.get(user_id) {
+            let _ = peer.sender.send(Message::Text(payload.to_string()));
+        }
+    }
 }
 
 #[derive(Clone)]
@@ -42,6 +52,10 @@ pub struct AppState {
     pub metrics: Arc<ServerMetrics>,
     pub notify: crate::notify_ws::NotifyHub,
     pub auth_limiter: crate::rate_limit::AuthRateLimiter,
+    pub login_guard: crate::rate_limit::LoginGuard,
+    pub mailer: Arc<dyn crate::mailer::Mailer>,
+    pub oidc: Option<Arc<crate::oidc::OidcClient>>,
+    pub keys: crate::keys::KeyRing,
 }
 
 #[derive(Debug, Default)]
@@ -54,6 +68,7 @@ pub struct ServerMetrics {
     pub relay_active: AtomicU64,
     pub relay_failures: AtomicU64,
     pub auth_rate_limited: AtomicU64,
+    pub auth_lockouts: AtomicU64,
 }
 
 #[derive(Debug, Clone, Serialize)]
@@ -66,6 +81,7 @@ pub struct ServerMetricsSnapshot {
     pub relay_active: u64,
     pub relay_failures: u64,
     pub auth_rate_limited: u64,
+    pub auth_lockouts: u64,
 }
 
 impl ServerMetrics {
@@ -83,6 +99,7 @@ impl ServerMetrics {
             relay_active: self.relay_active.load(Ordering::Relaxed),
             relay_failures: self.relay_failures.load(Ordering::Relaxed),
             auth_rate_limited: self.auth_rate_limited.load(Ordering::Relaxed),
+            auth_lockouts: self.auth_lockouts.load(Ordering::Relaxed),
         }
     }
 
@@ -0,0 +1,260 @@
+//! Per-project task workflows.
+//!
+//! Every project has its own status columns, in board order, and its own
+//! priorities, most urgent first. Each status belongs to a category:
+//! `backlog`, `active` or `done`. The rest of the server goes by the
+//! category, so tasks in any `done` status get no due-date reminders, count
+//! as finished subtasks and unblock the tasks waiting for them. A status can
+//! list the statuses tasks may move to from it; without a list any move is
+//! allowed.
+//!
+//! New projects get [`default_workflow`]. Projects from before workflows
+//! existed are migrated once at startup with [`legacy_workflow`]: common
+//! spellings of the old free-form values are mapped onto the defaults, and
+//! anything else is kept as an extra status or priority.
+
+use std::collections::HashMap;
+
+use crate::error::AppError;
+use crate::models::{Workflow, WorkflowPriority, WorkflowStatus};
+
+pub const BACKLOG: &str = "backlog";
+pub const ACTIVE: &str = "active";
+pub const DONE: &str = "done";
+pub const CATEGORIES: &[&str] = &[BACKLOG, ACTIVE, DONE];
+
+/// `todo`, `doing` and `done`; `urgent`, `high`, `medium` and `low`.
+pub fn default_workflow() -> Workflow {
+    let status = |key: &str, name: &str, category: &str| WorkflowStatus {
+        key: key.to_string(),
+        name: name.to_string(),
+        category: category.to_string(),
+        transitions: None,
+    };
+    let priority = |key: &str, name: &str| WorkflowPriority {
+        key: key.to_string(),
+        name: name.to_string(),
+    };
+    Workflow {
+        statuses: vec![
+            status("todo", "To do", BACKLOG),
+            status("doing", "In progress", ACTIVE),
+            status("done", "Done", DONE),
+        ],
+        priorities: vec![
+            priority("urgent", "Urgent"),
+            priority("high", "High"),
+            priority("medium", "Medium"),
+            priority("low", "Low"),
+        ],
+        default_priority: "medium".to_string(),
+    }
+}
+
+/// Check that a workflow is usable: at least one status and priority,
+/// unique non-empty keys, known categories, and transitions and the default
+/// priority that refer to existing keys.
+pub fn validate(workflow: &Workflow) -> Result<(), AppError> {
+    let invalid = |message: String| Err(AppError::BadRequest(message));
+    if workflow.statuses.is_empty() || workflow.priorities.is_empty() {
+        return invalid("a workflow needs at least one status and one priority".to_string());
+    }
+    let status_keys: Vec<&str> = workflow.statuses.iter().map(|s| s.key.as_str()).collect();
+    let priority_keys: Vec<&str> = workflow.priorities.iter().map(|p| p.key.as_str()).collect();
+    for keys in [&status_keys, &priority_keys] {
+        for (index, key) in keys.iter().enumerate() {
+            if key.trim().is_empty() || key.trim() != *key {
+                return invalid(format!("invalid key: {:?}", key));
+            }
+            if keys[..index].contains(key) {
+                return invalid(format!("duplicate key: {}", key));
+            }
+        }
+    }
+    for status in &workflow.statuses {
+        if !CATEGORIES.contains(&status.category.as_str()) {
+            return invalid(format!(
+                "category of {} must be backlog, active or done",
+                status.key
+            ));
+        }
+        for target in status.transitions.iter().flatten() {
+            if !status_keys.contains(&target.as_str()) {
+                return invalid(format!("{} moves to unknown status {}", status.key, target));
+            }
+        }
+    }
+    if !priority_keys.contains(&workflow.default_priority.as_str()) {
+        return invalid("default priority is not one of the priorities".to_string());
+    }
+    Ok(())
+}
+
+pub fn status<'a>(workflow: &'a Workflow, key: &str) -> Option<&'a WorkflowStatus> {
+    workflow.statuses.iter().find(|status| status.key == key)
+}
+
+/// Whether `key` is a status in the `done` category.
+pub fn is_done(workflow: &Workflow, key: &str) -> bool {
+    status(workflow, key).is_some_and(|status| status.category == DONE)
+}
+
+/// Check a task's new status, coming `from` its current one, and priority.
+pub fn check_task(
+    workflow: &Workflow,
+    from: Option<&str>,
+    to: Option<&str>,
+    priority: Option<&str>,
+) -> Result<(), AppError> {
+    if let Some(to) = to {
+        if status(workflow, to).is_none() {
+            return Err(AppError::BadRequest(format!("unknown status: {}", to)));
+        }
+        let allowed = from
+            .and_then(|from| status(workflow, from))
+            .and_then(|from| from.transitions.as_ref())
+            .is_none_or(|targets| from == Some(to) || targets.iter().any(|t| t == to));
+        if !allowed {
+            return Err(AppError::BadRequest(format!(
+                "cannot move a task from {} to {}",
+                from.unwrap_or_default(),
+                to
+            )));
+        }
+    }
+    if let Some(priority) = priority {
+        if !workflow.priorities.iter().any(|p| p.key == priority) {
+            return Err(AppError::BadRequest(format!(
+                "unknown priority: {}",
+                priority
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// The default status a free-form value from before workflows stands for.
+fn legacy_status(value: &str) -> Option<&'static str> {
+    let value = value.trim().to_lowercase().replace(['-', ' '], "_");
+    Some(match value.as_str() {
+        "todo" | "to_do" | "open" | "new" | "backlog" | "planned" => "todo",
+        "doing" | "in_progress" | "inprogress" | "wip" | "active" | "started" | "review"
+        | "in_review" => "doing",
+        "done" | "complete" | "completed" | "closed" | "finished" | "resolved" => "done",
+        _ => return None,
+    })
+}
+
+/// The default priority a free-form value from before workflows stands for.
+fn legacy_priority(value: &str) -> Option<&'static str> {
+    Some(match value.trim().to_lowercase().as_str() {
+        "urgent" | "critical" | "highest" | "blocker" | "p0" => "urgent",
+        "high" | "major" | "important" | "p1" => "high",
+        "medium" | "normal" | "med" | "default" | "p2" => "medium",
+        "low" | "minor" | "lowest" | "trivial" | "p3" => "low",
+        _ => return None,
+    })
+}
+
+/// The workflow for a project whose tasks use the given statuses and
+/// priorities, and how to map those values onto it. Values the defaults
+/// cover are mapped; others become extra `active` statuses before `done`
+/// and extra priorities after `low`, keyed by the trimmed value. Blank
+/// values go to the first status and the default priority.
+pub fn legacy_workflow(
+    statuses: &[String],
+    priorities: &[String],
+) -> (Workflow, HashMap<String, String>, HashMap<String, String>) {
+    let mut workflow = default_workflow();
+    let mut status_map = HashMap::new();
+    for value in statuses {
+        let key = match legacy_status(value) {
+            Some(key) => key.to_string(),
+            None if value.trim().is_empty() => workflow.statuses[0].key.clone(),
+            None => value.trim().to_string(),
+        };
+        if status(&workflow, &key).is_none() {
+            let done = workflow.statuses.len() - 1;
+            workflow.statuses.insert(
+                done,
+                WorkflowStatus {
+                    key: key.clone(),
+                    name: key.clone(),
+                    category: ACTIVE.to_string(),
+                    transitions: None,
+                },
+            );
+        }
+        status_map.insert(value.clone(), key);
+    }
+    let mut priority_map = HashMap::new();
+    for value in priorities {
+        let key = match legacy_priority(value) {
+            Some(key) => key.to_string(),
+            None if value.trim().is_empty() => workflow.default_priority.clone(),
+            None => value.trim().to_string(),
+        };
+        if !workflow.priorities.iter().any(|p| p.key == key) {
+            workflow.priorities.push(WorkflowPriority {
+                key: key.clone(),
+                name: key.clone(),
+            });
+        }
+        priority_map.insert(value.clone(), key);
+    }
+    status_map.retain(|from, to| from != to);
+    priority_map.retain(|from, to| from != to);
+    (workflow, status_map, priority_map)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn legacy_values_map_onto_defaults_or_are_kept() {
+        let strings = |values: &[&str]| values.iter().map(|v| v.to_string()).collect::<Vec<_>>();
+        let (workflow, statuses, priorities) = legacy_workflow(
+            &strings(&["todo", "In Progress", "blocked", "Closed"]),
+            &strings(&["medium", "P0", "someday"]),
+        );
+        let keys: Vec<_> = workflow.statuses.iter().map(|s| s.key.as_str()).collect();
+        assert_eq!(keys, ["todo", "doing", "blocked", "done"]);
+        assert_eq!(statuses.len(), 2);
+        assert_eq!(statuses["In Progress"], "doing");
+        assert_eq!(statuses["Closed"], "done");
+        assert_eq!(priorities.len(), 1);
+        assert_eq!(priorities["P0"], "urgent");
+        assert_eq!(workflow.priorities.last().unwrap().key, "someday");
+        assert!(validate(&workflow).is_ok());
+    }
+
+    #[test]
+    fn legacy_keys_are_trimmed() {
+        let strings = |values: &[&str]| values.iter().map(|v| v.to_string()).collect::<Vec<_>>();
+        let (workflow, statuses, priorities) = legacy_workflow(
+            &strings(&[" blocked", "blocked ", ""]),
+            &strings(&["someday ", " "]),
+        );
+        let keys: Vec<_> = workflow.statuses.iter().map(|s| s.key.as_str()).collect();
+        assert_eq!(keys, ["todo", "doing", "blocked", "done"]);
+        assert_eq!(statuses[" blocked"], "blocked");
+        assert_eq!(statuses["blocked "], "blocked");
+        assert_eq!(statuses[""], "todo");
+        assert_eq!(priorities["someday "], "someday");
+        assert_eq!(priorities[" "], "medium");
+        assert!(validate(&workflow).is_ok());
+    }
+
+    #[test]
+    fn transitions_limit_status_changes() {
+        let mut workflow = default_workflow();
+        workflow.statuses[0].transitions = Some(vec!["doing".to_string()]);
+        assert!(check_task(&workflow, Some("todo"), Some("doing"), None).is_ok());
+        assert!(check_task(&workflow, Some("todo"), Some("todo"), None).is_ok());
+        assert!(check_task(&workflow, Some("todo"), Some("done"), None).is_err());
+        assert!(check_task(&workflow, Some("doing"), Some("todo"), None).is_ok());
+        assert!(check_task(&workflow, None, Some("nope"), None).is_err());
+        assert!(check_task(&workflow, None, None, Some("someday")).is_err());
+    }
+}
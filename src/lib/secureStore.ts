@@ -1,6 +1,7 @@
 import { invoke } from '@/lib/host';
 
 const CLOUD_TOKEN_KEY = 'cloud-auth-token';
+const CLOUD_REFRESH_TOKEN_KEY = 'cloud-refresh-token';
 
 export async function getSecureToken(): Promise<string | null> {
   return invoke<string | null>('secure_store_get', { key: CLOUD_TOKEN_KEY });
@@ -13,3 +14,15 @@ export async function setSecureToken(token: string): Promise<void> {
 export async function deleteSecureToken(): Promise<void> {
   await invoke('secure_store_delete', { key: CLOUD_TOKEN_KEY });
 }
+
+export async function getSecureRefreshToken(): Promise<string | null> {
+  return invoke<string | null>('secure_store_get', { key: CLOUD_REFRESH_TOKEN_KEY });
+}
+
+export async function setSecureRefreshToken(token: string): Promise<void> {
+  await invoke('secure_store_set', { key: CLOUD_REFRESH_TOKEN_KEY, value: token });
+}
+
+export async function deleteSecureRefreshToken(): Promise<void> {
+  await invoke('secure_store_delete', { key: CLOUD_REFRESH_TOKEN_KEY });
+}
@@ -11,6 +11,7 @@ import {
   createCloudWorkspace,
   listCloudWorkspaces,
   loginCloudAccount,
+  logoutCloudSession,
   normalizeCloudBaseUrl,
   parseCloudErrorMessage,
   refreshCloudToken,
@@ -83,28 +84,53 @@ describe('cloudSync client helpers', () => {
     );
   });
 
-  it('sends bearer token headers for refresh, listing, and workspace creation', async () => {
+  it('refreshes with the refresh token in the body instead of a bearer header', async () => {
+    tauriFetchJsonMock.mockResolvedValueOnce({
+      ok: true,
+      data: { token: 'next-token', refresh_token: 'next-refresh' },
+    });
+
+    await expect(refreshCloudToken('https://sync.example.com/', 'refresh-abc')).resolves.toEqual({
+      token: 'next-token',
+      refresh_token: 'next-refresh',
+    });
+
+    expect(tauriFetchJsonMock).toHaveBeenCalledWith(
+      'https://sync.example.com/auth/refresh',
+      expect.objectContaining({
+        method: 'POST',
+        headers: { 'Content-Type': 'application/json' },
+        body: JSON.stringify({ refresh_token: 'refresh-abc' }),
+      }),
+    );
+  });
+
+  it('revokes the session with its access token on logout', async () => {
+    tauriFetchJsonMock.mockResolvedValueOnce({ ok: true, data: { ok: true } });
+
+    await logoutCloudSession('https://sync.example.com/', 'abc');
+
+    expect(tauriFetchJsonMock).toHaveBeenCalledWith(
+      'https://sync.example.com/auth/logout',
+      expect.objectContaining({ method: 'POST', headers: expect.objectContaining({ Authorization: 'Bearer abc' }) }),
+    );
+  });
+
+  it('sends bearer token headers for listing and workspace creation', async () => {
     tauriFetchJsonMock
-      .mockResolvedValueOnce({ ok: true, data: { token: 'next-token' } })
       .mockResolvedValueOnce({ ok: true, data: [{ id: 'w1', name: 'Workspace' }] })
       .mockResolvedValueOnce({ ok: true, data: { id: 'w2', name: 'New Workspace' } });
 
-    await refreshCloudToken('https://sync.example.com/', 'abc');
     await listCloudWorkspaces('https://sync.example.com', 'abc');
     await createCloudWorkspace('https://sync.example.com', 'abc', { name: 'New Workspace' } as never);
 
     expect(tauriFetchJsonMock).toHaveBeenNthCalledWith(
       1,
-      'https://sync.example.com/auth/refresh',
-      expect.objectContaining({ headers: expect.objectContaining({ Authorization: 'Bearer abc' }) }),
-    );
-    expect(tauriFetchJsonMock).toHaveBeenNthCalledWith(
-      2,
       'https://sync.example.com/workspaces',
       expect.objectContaining({ method: 'GET', headers: { Authorization: 'Bearer abc' } }),
     );
     expect(tauriFetchJsonMock).toHaveBeenNthCalledWith(
-      3,
+      2,
       'https://sync.example.com/workspaces',
       expect.objectContaining({ method: 'POST', headers: expect.objectContaining({ Authorization: 'Bearer abc' }) }),
     );
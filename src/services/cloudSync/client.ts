@@ -3,6 +3,7 @@ import type { WebDAVConfig } from '@/services/webdav';
 import type {
   CloudAuthResponse,
   CloudErrorResponse,
+  CloudTokenResponse,
   WorkspaceSummary,
   CreateWorkspaceRequest,
 } from '@lumina/shared';
@@ -91,8 +92,14 @@ export async function loginCloudAccount(credentials: CloudCredentials): Promise<
   });
 }
 
-export async function refreshCloudToken(baseUrl: string, token: string): Promise<{ token: string }> {
-  return postJson<{ token: string }>(`${normalizeCloudBaseUrl(baseUrl)}/auth/refresh`, {}, token);
+export async function refreshCloudToken(baseUrl: string, refreshToken: string): Promise<CloudTokenResponse> {
+  return postJson<CloudTokenResponse>(`${normalizeCloudBaseUrl(baseUrl)}/auth/refresh`, {
+    refresh_token: refreshToken,
+  });
+}
+
+export async function logoutCloudSession(baseUrl: string, token: string): Promise<void> {
+  await postJson<{ ok: boolean }>(`${normalizeCloudBaseUrl(baseUrl)}/auth/logout`, {}, token);
 }
 
 export async function listCloudWorkspaces(baseUrl: string, token: string): Promise<WorkspaceSummary[]> {
@@ -11,11 +11,17 @@ vi.mock('@/lib/tauriFetch', () => ({
 const setSecureTokenMock = vi.fn().mockResolvedValue(undefined);
 const getSecureTokenMock = vi.fn().mockResolvedValue('mock-token');
 const deleteSecureTokenMock = vi.fn().mockResolvedValue(undefined);
+const setSecureRefreshTokenMock = vi.fn().mockResolvedValue(undefined);
+const getSecureRefreshTokenMock = vi.fn().mockResolvedValue('mock-refresh-token');
+const deleteSecureRefreshTokenMock = vi.fn().mockResolvedValue(undefined);
 
 vi.mock('@/lib/secureStore', () => ({
   getSecureToken: (...args: unknown[]) => getSecureTokenMock(...args),
   setSecureToken: (...args: unknown[]) => setSecureTokenMock(...args),
   deleteSecureToken: (...args: unknown[]) => deleteSecureTokenMock(...args),
+  getSecureRefreshToken: (...args: unknown[]) => getSecureRefreshTokenMock(...args),
+  setSecureRefreshToken: (...args: unknown[]) => setSecureRefreshTokenMock(...args),
+  deleteSecureRefreshToken: (...args: unknown[]) => deleteSecureRefreshTokenMock(...args),
 }));
 
 const resetStores = () => {
@@ -63,6 +69,9 @@ describe('useCloudSyncStore', () => {
     setSecureTokenMock.mockReset().mockResolvedValue(undefined);
     getSecureTokenMock.mockReset().mockResolvedValue('mock-token');
     deleteSecureTokenMock.mockReset().mockResolvedValue(undefined);
+    setSecureRefreshTokenMock.mockReset().mockResolvedValue(undefined);
+    getSecureRefreshTokenMock.mockReset().mockResolvedValue('mock-refresh-token');
+    deleteSecureRefreshTokenMock.mockReset().mockResolvedValue(undefined);
     resetStores();
   });
 
@@ -130,6 +139,7 @@ describe('useCloudSyncStore', () => {
       status: 200,
       data: {
         token: 'secure-token',
+        refresh_token: 'secure-refresh-token',
         user: { id: 'user-1', email: 'dev@example.com' },
         workspaces: [{ id: 'ws-1', name: 'Personal' }],
       },
@@ -144,6 +154,37 @@ describe('useCloudSyncStore', () => {
     await useCloudSyncStore.getState().login();
 
     expect(setSecureTokenMock).toHaveBeenCalledWith('secure-token');
+    expect(setSecureRefreshTokenMock).toHaveBeenCalledWith('secure-refresh-token');
+    expect(useCloudSyncStore.getState().session).not.toHaveProperty('refresh_token');
+  });
+
+  it('refreshSession rotates both tokens with the stored refresh token', async () => {
+    tauriFetchJsonMock.mockResolvedValueOnce({
+      ok: true,
+      status: 200,
+      data: { token: 'token-2', refresh_token: 'refresh-2' },
+    });
+
+    useCloudSyncStore.setState({
+      serverBaseUrl: 'https://sync.example.com',
+      session: {
+        token: 'token-1',
+        user: { id: 'u1', email: 'test@example.com' },
+        workspaces: [],
+        currentWorkspaceId: null,
+      },
+      authStatus: 'authenticated',
+    });
+
+    await expect(useCloudSyncStore.getState().refreshSession()).resolves.toBe('token-2');
+
+    expect(tauriFetchJsonMock).toHaveBeenCalledWith(
+      'https://sync.example.com/auth/refresh',
+      expect.objectContaining({ body: JSON.stringify({ refresh_token: 'mock-refresh-token' }) }),
+    );
+    expect(setSecureTokenMock).toHaveBeenCalledWith('token-2');
+    expect(setSecureRefreshTokenMock).toHaveBeenCalledWith('refresh-2');
+    expect(useCloudSyncStore.getState().session?.token).toBe('token-2');
   });
 
   it('logout deletes token from keychain', () => {
@@ -160,6 +201,7 @@ describe('useCloudSyncStore', () => {
     useCloudSyncStore.getState().logout();
 
     expect(deleteSecureTokenMock).toHaveBeenCalled();
+    expect(deleteSecureRefreshTokenMock).toHaveBeenCalled();
     expect(useCloudSyncStore.getState().session).toBeNull();
   });
 
@@ -215,8 +257,14 @@ describe('useCloudSyncStore', () => {
       authStatus: 'authenticated',
     });
 
+    tauriFetchJsonMock.mockResolvedValueOnce({ ok: true, data: { ok: true } });
+
     useCloudSyncStore.getState().logout();
 
+    expect(tauriFetchJsonMock).toHaveBeenCalledWith(
+      'https://sync.example.com/auth/logout',
+      expect.objectContaining({ method: 'POST', headers: expect.objectContaining({ Authorization: 'Bearer token-1' }) }),
+    );
     expect(useCloudSyncStore.getState().session).toBeNull();
     expect(useCloudSyncStore.getState().password).toBe('');
     expect(useWebDAVStore.getState().config.server_url).toBe('');
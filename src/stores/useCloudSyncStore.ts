@@ -3,12 +3,20 @@ import { persist } from 'zustand/middleware';
 import type { AuthSession, WorkspaceSummary } from '@lumina/shared';
 import { DEFAULT_SYNC_INTERVAL_SECS } from '@lumina/shared';
 import { useWebDAVStore } from '@/stores/useWebDAVStore';
-import { getSecureToken, setSecureToken, deleteSecureToken } from '@/lib/secureStore';
+import {
+  getSecureToken,
+  setSecureToken,
+  deleteSecureToken,
+  getSecureRefreshToken,
+  setSecureRefreshToken,
+  deleteSecureRefreshToken,
+} from '@/lib/secureStore';
 import {
   buildCloudWebDavConfig,
   createCloudWorkspace,
   listCloudWorkspaces,
   loginCloudAccount,
+  logoutCloudSession,
   normalizeCloudBaseUrl,
   refreshCloudToken,
   registerCloudAccount,
@@ -84,8 +92,10 @@ async function authenticate(
       mode === 'register'
         ? await registerCloudAccount(credentials)
         : await loginCloudAccount(credentials);
-    const session = deriveNextSession(response);
+    const { refresh_token: refreshToken, ...auth } = response;
+    const session = deriveNextSession(auth);
     await setSecureToken(session.token);
+    await setSecureRefreshToken(refreshToken);
     set({ session, authStatus: 'authenticated', isLoading: false, error: null });
     syncDerivedWebDav({ ...get(), session });
     return session;
@@ -127,15 +137,21 @@ export const useCloudSyncStore = create<CloudSyncState>()(
         const { session, serverBaseUrl } = get();
         if (!session) return null;
         try {
-          const response = await refreshCloudToken(serverBaseUrl, session.token);
+          const refreshToken = await getSecureRefreshToken();
+          if (!refreshToken) {
+            throw new Error('Session expired, please sign in again');
+          }
+          const response = await refreshCloudToken(serverBaseUrl, refreshToken);
           const nextSession = { ...session, token: response.token };
           await setSecureToken(response.token);
+          await setSecureRefreshToken(response.refresh_token);
           set({ session: nextSession, authStatus: 'authenticated', error: null });
           return response.token;
         } catch (error) {
           const message = error instanceof Error ? error.message : String(error);
           set({ error: message, authStatus: 'anonymous', session: null });
           deleteSecureToken().catch(() => {});
+          deleteSecureRefreshToken().catch(() => {});
           return null;
         }
       },
@@ -184,9 +200,15 @@ export const useCloudSyncStore = create<CloudSyncState>()(
         syncDerivedWebDav({ ...get(), session: nextSession });
       },
       logout: () => {
+        const { session, serverBaseUrl } = get();
+        // Revoke the session on the server too; if that fails it expires there on its own.
+        if (session?.token && serverBaseUrl) {
+          logoutCloudSession(serverBaseUrl, session.token).catch(() => {});
+        }
         set({ session: null, authStatus: 'anonymous', password: '', error: null });
         useWebDAVStore.getState().resetConfig();
         deleteSecureToken().catch(() => {});
+        deleteSecureRefreshToken().catch(() => {});
       },
       rehydrateToken: async () => {
         const session = get().session;